@@ -0,0 +1,25 @@
+; Multiply two numbers by repeated addition.
+; Run the tests with: cargo run test examples/multiply.toml
+
+        .ORIG x3000
+        LD R0, A
+        LD R1, B
+        JSR MULTIPLY
+        ST R2, PRODUCT
+        HALT
+A       .FILL #6
+B       .FILL #7
+PRODUCT .BLKW 1
+
+; R2 = R0 * R1, R1 must not be negative
+MULTIPLY
+        AND R2, R2, #0
+        ADD R3, R1, #0
+        BRz MULTIPLY_DONE
+MULTIPLY_LOOP
+        ADD R2, R2, R0
+        ADD R3, R3, #-1
+        BRp MULTIPLY_LOOP
+MULTIPLY_DONE
+        RET
+        .END
@@ -0,0 +1,17 @@
+// Print the primes below 100 with a sieve
+char composite[100];
+
+int main() {
+    int count = 0;
+    for (int i = 2; i < 100; i++) {
+        if (composite[i])
+            continue;
+        print_int(i);
+        putchar(' ');
+        count++;
+        for (int j = i * i; j < 100; j += i)
+            composite[j] = 1;
+    }
+    putchar('\n');
+    return count;
+}
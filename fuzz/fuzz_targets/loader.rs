@@ -0,0 +1,17 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use little_computer_3::hardware::vm::VM;
+use little_computer_3::loader;
+
+fuzz_target!(|data: &[u8]| {
+    // Malformed images must be rejected, not crash
+    if let Ok(image) = loader::parse(data) {
+        let mut vm = VM::with_console(Box::new(std::io::empty()), Box::new(std::io::sink()));
+        image.load(&mut vm);
+    }
+    if let Ok(text) = std::str::from_utf8(data) {
+        let _ = loader::parse_hex(text);
+        let _ = loader::parse_bin(text);
+        let _ = loader::parse_symbols(text);
+    }
+});
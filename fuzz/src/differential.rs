@@ -0,0 +1,174 @@
+//! Differential testing of `execute_instruction`
+//! against the reference model.
+//!
+//! The fuzzer input is decoded into an initial
+//! machine state and an instruction stream:
+//!
+//! | bytes     | content                                   |
+//! |-----------|-------------------------------------------|
+//! | 0..16     | R0-R7, big endian                         |
+//! | 16..18    | PC                                        |
+//! | 18        | [7] user mode, [6:4] priority, [1:0] cond |
+//! | 19..23    | saved SSP, saved USP                      |
+//! | 23        | number of memory pokes                    |
+//! | 4 each    | poke address and value                    |
+//! | remaining | instruction words stored at PC            |
+//!
+//! Both machines execute the stream until it stops,
+//! reaches a TRAP or runs out of steps. After every
+//! instruction the registers, condition codes and
+//! the whole memory must be the same.
+use crate::reference::{self, Machine, Outcome};
+use little_computer_3::hardware::bus::Bus;
+use little_computer_3::hardware::device::mcr::Mcr;
+use little_computer_3::hardware::instruction::execute_instruction;
+use little_computer_3::hardware::vm::{Fault, VM};
+use std::fmt::Write;
+
+/// Upper bound on executed instructions, loops
+/// are fine but must not stall the fuzzer
+const MAX_STEPS: usize = 256;
+
+struct Input<'a> {
+    data: &'a [u8],
+}
+
+impl Input<'_> {
+    fn byte(&mut self) -> u8 {
+        match self.data.split_first() {
+            Some((&byte, rest)) => {
+                self.data = rest;
+                byte
+            }
+            None => 0,
+        }
+    }
+
+    fn word(&mut self) -> u16 {
+        u16::from_be_bytes([self.byte(), self.byte()])
+    }
+}
+
+/// Decode the initial state into both machines
+fn setup(data: &[u8]) -> (Box<VM>, Machine) {
+    let mut input = Input { data };
+    let mut model = Machine::new();
+    for r in model.r.iter_mut() {
+        *r = input.word();
+    }
+    model.pc = input.word();
+    let flags = input.byte();
+    model.user = flags & 0x80 != 0;
+    model.priority = ((flags >> 4) & 0x7) as u16;
+    model.cc = [reference::N, reference::Z, reference::P, reference::Z][(flags & 0x3) as usize];
+    model.saved_ssp = input.word();
+    model.saved_usp = input.word();
+
+    let pokes = input.byte();
+    for _ in 0..pokes {
+        let address = input.word();
+        let value = input.word();
+        // The MCR must start running
+        if address != reference::MCR {
+            model.mem[address as usize] = value;
+        }
+    }
+    let mut address = model.pc;
+    while input.data.len() >= 2 {
+        let word = input.word();
+        if address != reference::MCR {
+            model.mem[address as usize] = word;
+        }
+        address = address.wrapping_add(1);
+    }
+
+    // Only the MCR is attached: every other
+    // address behaves as plain memory
+    let mut vm = Box::new(VM::with_console(Box::new(std::io::empty()), Box::new(std::io::sink())));
+    vm.bus = Bus::new();
+    vm.bus.attach(Box::new(Mcr::new()));
+    vm.memory.copy_from_slice(&model.mem);
+    let registers = &mut vm.registers;
+    for (i, &value) in model.r.iter().enumerate() {
+        registers.update(i as u16, value);
+    }
+    registers.pc = model.pc;
+    registers.user_mode = model.user;
+    registers.priority = model.priority;
+    registers.cond = model.cc;
+    registers.saved_ssp = model.saved_ssp;
+    registers.saved_usp = model.saved_usp;
+    (vm, model)
+}
+
+/// Describe every difference between the machines
+fn compare(vm: &mut VM, model: &Machine) -> String {
+    let mut report = String::new();
+    let registers = &vm.registers;
+    for i in 0..8 {
+        let value = registers.get(i);
+        if value != model.r[i as usize] {
+            let _ = writeln!(report, "R{}: x{:04X}, expected x{:04X}", i, value, model.r[i as usize]);
+        }
+    }
+    let pairs = [
+        ("PC", registers.pc, model.pc),
+        ("cond", registers.cond, model.cc),
+        ("user mode", registers.user_mode as u16, model.user as u16),
+        ("priority", registers.priority, model.priority),
+        ("saved SSP", registers.saved_ssp, model.saved_ssp),
+        ("saved USP", registers.saved_usp, model.saved_usp),
+    ];
+    for (name, value, expected) in pairs {
+        if value != expected {
+            let _ = writeln!(report, "{}: x{:04X}, expected x{:04X}", name, value, expected);
+        }
+    }
+    if vm.memory[..] != model.mem[..] {
+        for (address, (&value, &expected)) in vm.memory.iter().zip(&model.mem).enumerate() {
+            if value != expected && address != reference::MCR as usize {
+                let _ = writeln!(report, "x{:04X}: x{:04X}, expected x{:04X}", address, value, expected);
+            }
+        }
+    }
+    let mcr = vm.read_memory(reference::MCR);
+    if mcr != model.mem[reference::MCR as usize] {
+        let _ = writeln!(report, "MCR: x{:04X}, expected x{:04X}", mcr, model.mem[reference::MCR as usize]);
+    }
+    report
+}
+
+/// Run the input through both implementations,
+/// panics on the first divergence
+pub fn check(data: &[u8]) {
+    let (mut vm, mut model) = setup(data);
+    for step in 0..MAX_STEPS {
+        if !vm.is_running() {
+            break;
+        }
+        let pc = model.pc;
+        let instruction = vm.read_memory(vm.registers.pc);
+        let outcome = model.step();
+        if outcome == Outcome::Trap {
+            break;
+        }
+
+        vm.registers.pc = vm.registers.pc.wrapping_add(1);
+        execute_instruction(instruction, &mut vm);
+
+        let mut report = compare(&mut vm, &model);
+        let fault = vm.fault.map(|fault| match fault {
+            Fault::Exception { vector, pc } => Outcome::Fault { vector: vector as u16, pc },
+            Fault::InvalidTrap { .. } => Outcome::Trap,
+        });
+        if fault.is_some_and(|f| f != outcome) || (fault.is_none() && outcome != Outcome::Executed) {
+            let _ = writeln!(report, "fault: {:?}, expected {:?}", vm.fault, outcome);
+        }
+        if !report.is_empty() {
+            panic!(
+                "divergence at step {} executing x{:04X} at x{:04X}\n{}",
+                step, instruction, pc, report
+            );
+        }
+    }
+}
@@ -0,0 +1,3 @@
+//! Fuzzing support for the emulator.
+pub mod differential;
+pub mod reference;
@@ -0,0 +1,228 @@
+//! A deliberately simple model of the LC-3 ISA.
+//! It shares no code with the emulator: every
+//! instruction is written out straight from the
+//! specification, favouring clarity over speed.
+//! Memory is plain, except for the MCR whose
+//! clock enable bit stops the machine.
+
+pub const MCR: u16 = 0xFFFE;
+const VECTOR_TABLE: u16 = 0x0100;
+const PRIVILEGE_VIOLATION: u16 = 0x00;
+const ILLEGAL_OPCODE: u16 = 0x01;
+
+pub const N: u16 = 0b100;
+pub const Z: u16 = 0b010;
+pub const P: u16 = 0b001;
+
+/// What happened when executing an instruction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Executed,
+    /// An exception without handler stopped the machine
+    Fault { vector: u16, pc: u16 },
+    /// TRAP routines are native in the emulator,
+    /// they are not modelled here
+    Trap,
+}
+
+pub struct Machine {
+    pub r: [u16; 8],
+    pub pc: u16,
+    pub user: bool,
+    pub priority: u16,
+    pub cc: u16,
+    pub saved_ssp: u16,
+    pub saved_usp: u16,
+    pub mem: Vec<u16>,
+}
+
+impl Machine {
+    pub fn new() -> Machine {
+        let mut mem = vec![0; 0x10000];
+        mem[MCR as usize] = 0x8000;
+        Machine {
+            r: [0; 8],
+            pc: 0x3000,
+            user: true,
+            priority: 0,
+            cc: Z,
+            saved_ssp: 0x3000,
+            saved_usp: 0,
+            mem,
+        }
+    }
+
+    pub fn running(&self) -> bool {
+        self.mem[MCR as usize] & 0x8000 != 0
+    }
+
+    pub fn psr(&self) -> u16 {
+        let mut psr = self.cc | (self.priority << 8);
+        if self.user {
+            psr |= 0x8000;
+        }
+        psr
+    }
+
+    fn read(&self, address: u16) -> u16 {
+        self.mem[address as usize]
+    }
+
+    fn write(&mut self, address: u16, value: u16) {
+        self.mem[address as usize] = value;
+    }
+
+    fn set_cc(&mut self, value: u16) {
+        let signed = value as i16;
+        self.cc = if signed < 0 {
+            N
+        } else if signed == 0 {
+            Z
+        } else {
+            P
+        };
+    }
+
+    fn set_reg(&mut self, r: u16, value: u16) {
+        self.r[r as usize] = value;
+        self.set_cc(value);
+    }
+
+    /// Fetch and execute the instruction at PC
+    pub fn step(&mut self) -> Outcome {
+        let ir = self.read(self.pc);
+        if ir >> 12 == 0xF {
+            return Outcome::Trap;
+        }
+        self.pc = self.pc.wrapping_add(1);
+
+        let dr = (ir >> 9) & 7;
+        let sr1 = (ir >> 6) & 7;
+        let sr2 = ir & 7;
+        let imm5 = sext(ir, 5);
+        let offset6 = sext(ir, 6);
+        let offset9 = sext(ir, 9);
+        let offset11 = sext(ir, 11);
+        let immediate = ir & 0x20 != 0;
+
+        match ir >> 12 {
+            // BR
+            0x0 => {
+                if dr & self.cc != 0 {
+                    self.pc = self.pc.wrapping_add(offset9);
+                }
+            }
+            // ADD
+            0x1 => {
+                let b = if immediate { imm5 } else { self.r[sr2 as usize] };
+                self.set_reg(dr, self.r[sr1 as usize].wrapping_add(b));
+            }
+            // LD
+            0x2 => {
+                let value = self.read(self.pc.wrapping_add(offset9));
+                self.set_reg(dr, value);
+            }
+            // ST
+            0x3 => self.write(self.pc.wrapping_add(offset9), self.r[dr as usize]),
+            // JSR, JSRR
+            0x4 => {
+                let target = if ir & 0x800 != 0 {
+                    self.pc.wrapping_add(offset11)
+                } else {
+                    self.r[sr1 as usize]
+                };
+                self.r[7] = self.pc;
+                self.pc = target;
+            }
+            // AND
+            0x5 => {
+                let b = if immediate { imm5 } else { self.r[sr2 as usize] };
+                self.set_reg(dr, self.r[sr1 as usize] & b);
+            }
+            // LDR
+            0x6 => {
+                let value = self.read(self.r[sr1 as usize].wrapping_add(offset6));
+                self.set_reg(dr, value);
+            }
+            // STR
+            0x7 => self.write(self.r[sr1 as usize].wrapping_add(offset6), self.r[dr as usize]),
+            // RTI
+            0x8 => {
+                if self.user {
+                    return self.exception(PRIVILEGE_VIOLATION);
+                }
+                self.pc = self.read(self.r[6]);
+                let psr = self.read(self.r[6].wrapping_add(1));
+                self.r[6] = self.r[6].wrapping_add(2);
+                self.user = psr & 0x8000 != 0;
+                self.priority = (psr >> 8) & 7;
+                self.cc = psr & 7;
+                if self.user {
+                    self.saved_ssp = self.r[6];
+                    self.r[6] = self.saved_usp;
+                }
+            }
+            // NOT
+            0x9 => self.set_reg(dr, !self.r[sr1 as usize]),
+            // LDI
+            0xA => {
+                let pointer = self.read(self.pc.wrapping_add(offset9));
+                let value = self.read(pointer);
+                self.set_reg(dr, value);
+            }
+            // STI
+            0xB => {
+                let pointer = self.read(self.pc.wrapping_add(offset9));
+                self.write(pointer, self.r[dr as usize]);
+            }
+            // JMP, RET
+            0xC => self.pc = self.r[sr1 as usize],
+            // reserved
+            0xD => return self.exception(ILLEGAL_OPCODE),
+            // LEA
+            0xE => self.set_reg(dr, self.pc.wrapping_add(offset9)),
+            _ => unreachable!(),
+        }
+        Outcome::Executed
+    }
+
+    /// Push PSR and PC on the supervisor stack and
+    /// jump to the handler, the priority is kept
+    fn exception(&mut self, vector: u16) -> Outcome {
+        let handler = self.read(VECTOR_TABLE + vector);
+        if handler == 0 {
+            // Without a handler the emulator clears the
+            // clock enable bit and reports a fault
+            self.mem[MCR as usize] &= 0x7FFF;
+            return Outcome::Fault {
+                vector,
+                pc: self.pc.wrapping_sub(1),
+            };
+        }
+        let psr = self.psr();
+        if self.user {
+            self.saved_usp = self.r[6];
+            self.r[6] = self.saved_ssp;
+        }
+        self.r[6] = self.r[6].wrapping_sub(1);
+        self.write(self.r[6], psr);
+        self.r[6] = self.r[6].wrapping_sub(1);
+        self.write(self.r[6], self.pc);
+        self.user = false;
+        self.cc = 0;
+        self.pc = handler;
+        Outcome::Executed
+    }
+}
+
+impl Default for Machine {
+    fn default() -> Machine {
+        Machine::new()
+    }
+}
+
+/// Sign extend the lowest `bits` bits of `ir`
+fn sext(ir: u16, bits: u32) -> u16 {
+    let shift = 16 - bits;
+    (((ir << shift) as i16) >> shift) as u16
+}
@@ -0,0 +1,156 @@
+//! Words decoded back to instructions, with the
+//! registers they use, for the static analyses.
+use crate::assembler::{DEVICE_TRAPS, TRAP_ALIASES};
+use crate::hardware::instruction::sign_extend;
+use std::collections::BTreeMap;
+
+/// The second operand of ADD and AND
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operand {
+    Register(u16),
+    Immediate(i16),
+}
+
+/// A decoded instruction, PC-relative operands
+/// are resolved to addresses
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    Add { dr: u16, sr1: u16, sr2: Operand },
+    And { dr: u16, sr1: u16, sr2: Operand },
+    Not { dr: u16, sr: u16 },
+    /// `nzp` is the 3-bit condition mask
+    Br { nzp: u16, target: u16 },
+    /// RET is JMP R7
+    Jmp { base: u16 },
+    Jsr { target: u16 },
+    Jsrr { base: u16 },
+    Ld { dr: u16, target: u16 },
+    Ldi { dr: u16, target: u16 },
+    Ldr { dr: u16, base: u16, offset: i16 },
+    Lea { dr: u16, target: u16 },
+    St { sr: u16, target: u16 },
+    Sti { sr: u16, target: u16 },
+    Str { sr: u16, base: u16, offset: i16 },
+    Rti,
+    Trap(u8),
+    /// Opcode 13, or RTI/JMP/NOT with wrong bits
+    Reserved(u16),
+}
+
+use Instruction::*;
+
+fn register(word: u16, shift: u16) -> u16 {
+    (word >> shift) & 0x7
+}
+
+fn offset(word: u16, bits: u8) -> i16 {
+    sign_extend(word & ((1 << bits) - 1), bits) as i16
+}
+
+impl Instruction {
+    /// Decode the word stored at `address`
+    pub fn decode(address: u16, word: u16) -> Instruction {
+        let (dr, sr1) = (register(word, 9), register(word, 6));
+        let relative = |bits| address.wrapping_add(1).wrapping_add(offset(word, bits) as u16);
+        let sr2 = if word & 0x20 != 0 {
+            Operand::Immediate(offset(word, 5))
+        } else {
+            Operand::Register(register(word, 0))
+        };
+        match word >> 12 {
+            0 => Br { nzp: dr, target: relative(9) },
+            1 => Add { dr, sr1, sr2 },
+            2 => Ld { dr, target: relative(9) },
+            3 => St { sr: dr, target: relative(9) },
+            4 if word & 0x0800 != 0 => Jsr { target: relative(11) },
+            4 => Jsrr { base: sr1 },
+            5 => And { dr, sr1, sr2 },
+            6 => Ldr { dr, base: sr1, offset: offset(word, 6) },
+            7 => Str { sr: dr, base: sr1, offset: offset(word, 6) },
+            8 if word == 0x8000 => Rti,
+            9 if word & 0x3F == 0x3F => Not { dr, sr: sr1 },
+            10 => Ldi { dr, target: relative(9) },
+            11 => Sti { sr: dr, target: relative(9) },
+            12 => Jmp { base: sr1 },
+            14 => Lea { dr, target: relative(9) },
+            15 => Trap(word as u8),
+            _ => Reserved(word),
+        }
+    }
+
+    /// Registers whose value the instruction uses
+    pub fn reads(&self) -> Vec<u16> {
+        match *self {
+            Add { sr1, sr2, .. } | And { sr1, sr2, .. } => match sr2 {
+                Operand::Register(sr2) => vec![sr1, sr2],
+                Operand::Immediate(_) => vec![sr1],
+            },
+            Not { sr, .. } => vec![sr],
+            Jmp { base } | Jsrr { base } | Ldr { base, .. } => vec![base],
+            St { sr, .. } | Sti { sr, .. } => vec![sr],
+            Str { sr, base, .. } => vec![sr, base],
+            // OUT, PUTS and PUTSP print from R0
+            Trap(0x21) | Trap(0x22) | Trap(0x24) => vec![0],
+            // DREAD and DWRITE take the sector and buffer
+            Trap(0x30) | Trap(0x31) => vec![0, 1],
+            _ => vec![],
+        }
+    }
+
+    /// Registers the instruction writes, R7 for the
+    /// return address of JSR, JSRR and TRAP
+    pub fn writes(&self) -> Vec<u16> {
+        match *self {
+            Add { dr, .. } | And { dr, .. } | Not { dr, .. } => vec![dr],
+            Ld { dr, .. } | Ldi { dr, .. } | Ldr { dr, .. } | Lea { dr, .. } => vec![dr],
+            Jsr { .. } | Jsrr { .. } => vec![7],
+            // GETC and IN read a character to R0, the
+            // disk traps return a status and RAND a number
+            Trap(0x20) | Trap(0x23) | Trap(0x30..=0x32) => vec![0, 7],
+            Trap(_) => vec![7],
+            _ => vec![],
+        }
+    }
+
+    /// The instruction in assembler syntax, with
+    /// addresses replaced by their label in `names`
+    pub fn format(&self, names: &BTreeMap<u16, String>) -> String {
+        let at = |address: u16| names.get(&address).cloned().unwrap_or_else(|| format!("x{:04X}", address));
+        let operand = |sr2: Operand| match sr2 {
+            Operand::Register(r) => format!("R{}", r),
+            Operand::Immediate(n) => format!("#{}", n),
+        };
+        match *self {
+            Add { dr, sr1, sr2 } => format!("ADD R{}, R{}, {}", dr, sr1, operand(sr2)),
+            And { dr, sr1, sr2 } => format!("AND R{}, R{}, {}", dr, sr1, operand(sr2)),
+            Not { dr, sr } => format!("NOT R{}, R{}", dr, sr),
+            // No condition bits, never taken
+            Br { nzp: 0, .. } => "NOP".to_string(),
+            Br { nzp, target } => {
+                let flags: String = [(4, 'n'), (2, 'z'), (1, 'p')]
+                    .iter()
+                    .filter(|(bit, _)| nzp & bit != 0)
+                    .map(|&(_, flag)| flag)
+                    .collect();
+                format!("BR{} {}", flags, at(target))
+            }
+            Jmp { base: 7 } => "RET".to_string(),
+            Jmp { base } => format!("JMP R{}", base),
+            Jsr { target } => format!("JSR {}", at(target)),
+            Jsrr { base } => format!("JSRR R{}", base),
+            Ld { dr, target } => format!("LD R{}, {}", dr, at(target)),
+            Ldi { dr, target } => format!("LDI R{}, {}", dr, at(target)),
+            Ldr { dr, base, offset } => format!("LDR R{}, R{}, #{}", dr, base, offset),
+            Lea { dr, target } => format!("LEA R{}, {}", dr, at(target)),
+            St { sr, target } => format!("ST R{}, {}", sr, at(target)),
+            Sti { sr, target } => format!("STI R{}, {}", sr, at(target)),
+            Str { sr, base, offset } => format!("STR R{}, R{}, #{}", sr, base, offset),
+            Rti => "RTI".to_string(),
+            Trap(vector) => match TRAP_ALIASES.iter().chain(&DEVICE_TRAPS).find(|(_, v)| *v == vector as u16) {
+                Some((name, _)) => name.to_string(),
+                None => format!("TRAP x{:02X}", vector),
+            },
+            Reserved(word) => format!(".FILL x{:04X}", word),
+        }
+    }
+}
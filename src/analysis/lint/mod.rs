@@ -0,0 +1,388 @@
+//! Bugs found without running the program, over
+//! the control flow graph of `Cfg`.
+//!
+//! Registers are checked only in the program
+//! itself: subroutines take their arguments in
+//! registers. A call counts as writing every
+//! register. R6 is taken as the stack pointer,
+//! `ADD R6, R6, #n` pushes or pops n words and any
+//! other write to R6 stops the stack check. A call
+//! moves R6 by what the subroutine leaves on the
+//! stack, like the return value of compiled C.
+use super::decode::{Instruction, Operand};
+use super::{Block, Callee, Cfg, EdgeKind, Subroutine};
+use crate::runner;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Check {
+    /// A register read before any write
+    Uninitialized,
+    /// RET after R7 was overwritten
+    ClobberedReturn,
+    /// BR without condition bits
+    NeverTaken,
+    /// Pushes and pops that do not match
+    UnbalancedStack,
+    /// Execution leaves the program
+    MissingHalt,
+}
+
+impl Check {
+    pub fn name(self) -> &'static str {
+        match self {
+            Check::Uninitialized => "uninitialized",
+            Check::ClobberedReturn => "clobbered-r7",
+            Check::NeverTaken => "never-taken",
+            Check::UnbalancedStack => "unbalanced-stack",
+            Check::MissingHalt => "missing-halt",
+        }
+    }
+}
+
+/// A finding at `address`, `line` is known when
+/// the program was assembled
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Lint {
+    pub address: u16,
+    pub line: Option<usize>,
+    pub check: Check,
+    pub message: String,
+    pub help: Option<String>,
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "x{:04X}: warning[{}]: {}", self.address, self.check.name(), self.message)
+    }
+}
+
+impl Lint {
+    /// The message followed by the source line when
+    /// there is one, like the assembler warnings
+    pub fn render(&self, path: &str, source: Option<&str>) -> String {
+        let text = self.line.and_then(|line| Some((line, source?.lines().nth(line.wrapping_sub(1))?)));
+        let mut out = match self.line {
+            Some(line) => format!("{}:{}: {}\n", path, line, self),
+            None => format!("{}: {}\n", path, self),
+        };
+        let margin = match text {
+            Some((line, text)) => {
+                let number = line.to_string();
+                let margin = " ".repeat(number.len());
+                out += &format!("{} |\n{} | {}\n", margin, number, text);
+                margin
+            }
+            None => String::new(),
+        };
+        if let Some(help) = &self.help {
+            out += &format!("{} = help: {}\n", margin, help);
+        }
+        out
+    }
+}
+
+/// Where R6 is, in words pushed since the entry
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Depth {
+    Known(i32),
+    Unknown,
+}
+
+/// What every subroutine leaves on the stack, by
+/// entry
+type Effects = BTreeMap<u16, Depth>;
+
+/// What every check follows along the paths
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct State {
+    /// Bit r is set when Rr is written on every path
+    written: u8,
+    /// The instructions that may have overwritten R7
+    clobbered: BTreeSet<u16>,
+    depth: Depth,
+}
+
+impl State {
+    /// The state when two paths meet
+    fn merge(&self, other: &State) -> State {
+        State {
+            written: self.written & other.written,
+            clobbered: self.clobbered.union(&other.clobbered).copied().collect(),
+            depth: if self.depth == other.depth { self.depth } else { Depth::Unknown },
+        }
+    }
+
+    fn step(&mut self, address: u16, instruction: &Instruction, cfg: &Cfg, effects: &Effects) {
+        let writes = instruction.writes();
+        match *instruction {
+            Instruction::Jsr { .. } | Instruction::Jsrr { .. } => self.written = 0xFF,
+            _ => self.written |= writes.iter().fold(0, |bits, r| bits | 1 << r),
+        }
+        if writes.contains(&7) {
+            match instruction {
+                // Restored from memory
+                Instruction::Ld { .. } | Instruction::Ldi { .. } | Instruction::Ldr { .. } => self.clobbered.clear(),
+                _ => self.clobbered = BTreeSet::from([address]),
+            }
+        }
+        if writes.contains(&6) {
+            self.depth = match (*instruction, self.depth) {
+                (Instruction::Add { sr1: 6, sr2: Operand::Immediate(n), .. }, Depth::Known(depth)) => {
+                    Depth::Known(depth - n as i32)
+                }
+                _ => Depth::Unknown,
+            };
+        }
+        if let (Instruction::Jsr { .. } | Instruction::Jsrr { .. }, Depth::Known(depth)) = (instruction, self.depth) {
+            self.depth = match cfg.calls.get(&address).and_then(|callee| match callee {
+                Callee::Subroutine(entry) => effects.get(entry),
+                _ => None,
+            }) {
+                Some(Depth::Known(left)) => Depth::Known(depth + left),
+                _ => Depth::Unknown,
+            };
+        }
+    }
+}
+
+/// Run every check over the program
+pub fn lint(cfg: &Cfg) -> Vec<Lint> {
+    let mut lints = Vec::new();
+    let mut report = |address: u16, check: Check, message: String, help: Option<&str>| {
+        lints.push(Lint {
+            address,
+            line: cfg.lines.get(&address).copied(),
+            check,
+            message,
+            help: help.map(str::to_string),
+        });
+    };
+
+    for block in cfg.blocks.values() {
+        for &(address, instruction) in &block.instructions {
+            if let Instruction::Br { nzp: 0, .. } = instruction {
+                let help = "add n, z or p, or remove the branch";
+                report(address, Check::NeverTaken, "BR without condition bits is never taken".to_string(), Some(help));
+            }
+            if let Instruction::Reserved(word) = instruction {
+                let message = format!("x{:04X} is not an instruction, execution runs into data", word);
+                report(address, Check::MissingHalt, message, Some("end the program with HALT"));
+            }
+        }
+        for edge in &block.successors {
+            if cfg.blocks.contains_key(&edge.to) {
+                continue;
+            }
+            let data = cfg.data.contains(&edge.to);
+            let (address, message) = match edge.kind {
+                // Pointed at the data, where the program ends up
+                EdgeKind::Fallthrough if data => (edge.to, "execution runs into data".to_string()),
+                EdgeKind::Fallthrough => {
+                    (block.end(), format!("execution runs past the end of the program at x{:04X}", edge.to))
+                }
+                _ if data => (block.end(), format!("jump to x{:04X}, into data", edge.to)),
+                _ => (block.end(), format!("jump to x{:04X}, outside the program", edge.to)),
+            };
+            report(address, Check::MissingHalt, message, Some("end the program with HALT"));
+        }
+    }
+
+    let effects = effects(cfg);
+    for subroutine in cfg.subroutines.values() {
+        let main = subroutine.entry == cfg.entry;
+        let states = flow(cfg, subroutine, &effects);
+        for start in &subroutine.blocks {
+            let block = &cfg.blocks[start];
+            let mut state = match states.get(start) {
+                Some(state) => state.clone(),
+                None => continue,
+            };
+            for &(address, instruction) in &block.instructions {
+                let reads = match instruction {
+                    // Clearing reads nothing
+                    Instruction::And { sr2: Operand::Immediate(0), .. } => vec![],
+                    _ => instruction.reads(),
+                };
+                for r in reads {
+                    if main && state.written & (1 << r) == 0 {
+                        let message = format!("R{} is read before it is written", r);
+                        report(address, Check::Uninitialized, message, Some("set it first, its value is whatever ran before"));
+                        // Once is enough
+                        state.written |= 1 << r;
+                    }
+                }
+                if instruction == (Instruction::Jmp { base: 7 }) && !main {
+                    if let Some(&clobber) = state.clobbered.iter().next() {
+                        let text = instruction_at(cfg, clobber).map_or(String::new(), |i| i.format(&cfg.names));
+                        let message = format!(
+                            "RET after R7 was overwritten at x{:04X} ({}), it does not return to the caller",
+                            clobber, text
+                        );
+                        report(address, Check::ClobberedReturn, message, Some("save R7 before and restore it after"));
+                    }
+                    if let Depth::Known(depth) = state.depth {
+                        if depth != 0 {
+                            let message = format!("RET with {} words left on the stack", depth);
+                            report(address, Check::UnbalancedStack, message, Some("pop what the subroutine pushes"));
+                        }
+                    }
+                }
+                let before = state.depth;
+                state.step(address, &instruction, cfg, &effects);
+                if let (Depth::Known(from), Depth::Known(depth)) = (before, state.depth) {
+                    if depth < 0 && from >= 0 && !main {
+                        let message = format!("pops {} words more than it pushed", -depth);
+                        report(address, Check::UnbalancedStack, message, Some("pop only what was pushed"));
+                    }
+                }
+            }
+        }
+        for (start, depths) in meeting_depths(cfg, subroutine, &states, &effects) {
+            if depths.len() > 1 && !main {
+                let list: Vec<String> = depths.iter().map(|d| d.to_string()).collect();
+                let message = format!("paths meet with different stack depths ({})", list.join(", "));
+                report(start, Check::UnbalancedStack, message, Some("push and pop the same on every path"));
+            }
+        }
+    }
+    lints.sort_by_key(|lint| (lint.address, lint.check));
+    lints.dedup();
+    lints
+}
+
+/// The instruction at `address`, if it is code
+fn instruction_at(cfg: &Cfg, address: u16) -> Option<Instruction> {
+    let (_, block) = cfg.blocks.range(..=address).next_back()?;
+    block.instructions.iter().find(|(a, _)| *a == address).map(|&(_, instruction)| instruction)
+}
+
+fn run(block: &Block, mut state: State, cfg: &Cfg, effects: &Effects) -> State {
+    for (address, instruction) in &block.instructions {
+        state.step(*address, instruction, cfg, effects);
+    }
+    state
+}
+
+/// Find what every subroutine leaves on the stack
+/// when it returns, assuming first that nothing is
+/// left, until the calls agree
+fn effects(cfg: &Cfg) -> Effects {
+    let mut effects: Effects = cfg.subroutines.keys().map(|&entry| (entry, Depth::Known(0))).collect();
+    // Recursion may not settle, give up after a while
+    for _ in 0..=cfg.subroutines.len() {
+        let mut changed = false;
+        for subroutine in cfg.subroutines.values() {
+            let states = flow(cfg, subroutine, &effects);
+            let mut left: Option<Depth> = None;
+            for (start, state) in &states {
+                let mut state = state.clone();
+                for (address, instruction) in &cfg.blocks[start].instructions {
+                    if *instruction == (Instruction::Jmp { base: 7 }) {
+                        left = Some(match left {
+                            Some(depth) if depth != state.depth => Depth::Unknown,
+                            _ => state.depth,
+                        });
+                    }
+                    state.step(*address, instruction, cfg, &effects);
+                }
+            }
+            let left = left.unwrap_or(Depth::Known(0));
+            if effects.insert(subroutine.entry, left) != Some(left) {
+                changed = true;
+            }
+        }
+        if !changed {
+            return effects;
+        }
+    }
+    cfg.subroutines.keys().map(|&entry| (entry, Depth::Unknown)).collect()
+}
+
+/// The edges that stay in the subroutine
+fn inside<'a>(block: &'a Block, subroutine: &'a Subroutine) -> impl Iterator<Item = u16> + 'a {
+    block.successors.iter().map(|edge| edge.to).filter(|to| subroutine.blocks.contains(to))
+}
+
+/// The state at the start of every block of the
+/// subroutine, merged over all the paths to it
+fn flow(cfg: &Cfg, subroutine: &Subroutine, effects: &Effects) -> BTreeMap<u16, State> {
+    let entry = State {
+        written: 0,
+        clobbered: BTreeSet::new(),
+        depth: Depth::Known(0),
+    };
+    let mut states = BTreeMap::from([(subroutine.entry, entry)]);
+    let mut work = vec![subroutine.entry];
+    while let Some(start) = work.pop() {
+        let block = &cfg.blocks[&start];
+        let out = run(block, states[&start].clone(), cfg, effects);
+        for to in inside(block, subroutine) {
+            let merged = match states.get(&to) {
+                Some(state) => state.merge(&out),
+                None => out.clone(),
+            };
+            if states.get(&to) != Some(&merged) {
+                states.insert(to, merged);
+                work.push(to);
+            }
+        }
+    }
+    states
+}
+
+/// The known stack depths of the paths that lead
+/// to every block
+fn meeting_depths(
+    cfg: &Cfg,
+    subroutine: &Subroutine,
+    states: &BTreeMap<u16, State>,
+    effects: &Effects,
+) -> BTreeMap<u16, BTreeSet<i32>> {
+    let mut depths: BTreeMap<u16, BTreeSet<i32>> = BTreeMap::new();
+    depths.entry(subroutine.entry).or_default().insert(0);
+    for start in &subroutine.blocks {
+        let block = &cfg.blocks[start];
+        if let Some(state) = states.get(start) {
+            if let Depth::Known(depth) = run(block, state.clone(), cfg, effects).depth {
+                for to in inside(block, subroutine) {
+                    depths.entry(to).or_default().insert(depth);
+                }
+            }
+        }
+    }
+    depths
+}
+
+const USAGE: &str = "Usage: cargo run lint <program>";
+
+/// Entry point of `cargo run lint ...`, returns the
+/// exit code: 1 when something is found
+pub fn main(args: Vec<String>) -> i32 {
+    let program = match args.as_slice() {
+        [program] => program,
+        _ => {
+            eprintln!("{}", USAGE);
+            return 1;
+        }
+    };
+    let image = match runner::read(program, Default::default()) {
+        Ok(image) => image,
+        Err(message) => {
+            eprintln!("{}", message);
+            return 1;
+        }
+    };
+    let source = image.source.as_ref().and_then(|path| std::fs::read_to_string(path).ok());
+    let lints = lint(&Cfg::build(&image));
+    for lint in &lints {
+        print!("{}", lint.render(program, source.as_deref()));
+    }
+    if lints.is_empty() {
+        0
+    } else {
+        println!("{} warning{}", lints.len(), if lints.len() == 1 { "" } else { "s" });
+        1
+    }
+}
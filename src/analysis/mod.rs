@@ -0,0 +1,507 @@
+//! Static analysis of a loaded image: the control
+//! flow graph and the call graph.
+//!
+//! Instructions are followed from the entry point
+//! through BR, JMP, JSR and TRAP without running
+//! them, up to the words assembled as data. JMP and JSRR targets are known when the
+//! register was set by LEA, LD or ADD earlier in
+//! the same block, like `LD R2, F_main; JSRR R2`.
+//! Every JSR target starts a subroutine, which
+//! owns the blocks it reaches without calls.
+use crate::loader::Image;
+use crate::runner;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet};
+
+pub mod decode;
+pub mod lint;
+
+use decode::{Instruction, Operand};
+
+/// How control gets from a block to the next
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EdgeKind {
+    /// The next address
+    Fallthrough,
+    /// A taken BR
+    Branch,
+    /// JMP with a known target
+    Jump,
+}
+
+impl EdgeKind {
+    fn name(self) -> &'static str {
+        match self {
+            EdgeKind::Fallthrough => "fallthrough",
+            EdgeKind::Branch => "branch",
+            EdgeKind::Jump => "jump",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Edge {
+    pub to: u16,
+    pub kind: EdgeKind,
+}
+
+/// What JSR, JSRR or TRAP calls
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Callee {
+    Subroutine(u16),
+    Trap(u8),
+    /// JSRR with a register that is not known
+    Unknown,
+}
+
+/// Instructions that run one after the other,
+/// only the first one is the target of a jump
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Block {
+    pub start: u16,
+    pub instructions: Vec<(u16, Instruction)>,
+    /// Edges may lead outside the image, where
+    /// there is no block
+    pub successors: Vec<Edge>,
+}
+
+impl Block {
+    /// Address of the last instruction
+    pub fn end(&self) -> u16 {
+        self.instructions.last().map_or(self.start, |&(address, _)| address)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Subroutine {
+    pub entry: u16,
+    pub name: String,
+    /// Start of every block it owns, sorted
+    pub blocks: Vec<u16>,
+    /// Every callee once, sorted
+    pub calls: Vec<Callee>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Cfg {
+    pub entry: u16,
+    pub blocks: BTreeMap<u16, Block>,
+    /// By entry, the program itself is the first
+    pub subroutines: BTreeMap<u16, Subroutine>,
+    /// The callee of every call instruction
+    pub calls: BTreeMap<u16, Callee>,
+    /// JMP and JSRR whose target is not known
+    pub unresolved: Vec<u16>,
+    /// A label for addresses that have one
+    pub names: BTreeMap<u16, String>,
+    /// Source line of addresses, when assembled
+    pub lines: BTreeMap<u16, usize>,
+    /// Words assembled as data, the walk stops there
+    pub data: BTreeSet<u16>,
+}
+
+/// An instruction and where control goes next
+struct Step {
+    instruction: Instruction,
+    successors: Vec<Edge>,
+    /// Control does not simply go to the next word
+    ends: bool,
+}
+
+impl Cfg {
+    /// Follow the image from its entry point, or
+    /// from its first segment
+    pub fn build(image: &Image) -> Cfg {
+        let mut memory = BTreeMap::new();
+        for segment in image.segments() {
+            for (i, &word) in segment.words.iter().enumerate() {
+                memory.insert(segment.origin.wrapping_add(i as u16), word);
+            }
+        }
+        let mut names = BTreeMap::new();
+        for (name, &address) in &image.symbols {
+            names.entry(address).or_insert_with(|| name.clone());
+        }
+        let entry = image.entry.or(image.segments().first().map(|s| s.origin)).unwrap_or(0);
+        let mut cfg = Cfg {
+            entry,
+            names,
+            lines: image.lines.clone(),
+            data: image.data.clone(),
+            ..Cfg::default()
+        };
+        if !memory.contains_key(&entry) || cfg.data.contains(&entry) {
+            return cfg;
+        }
+
+        // Walk straight lines of code from every
+        // target, with the registers known so far
+        let mut steps: BTreeMap<u16, Step> = BTreeMap::new();
+        let mut leaders = BTreeSet::from([entry]);
+        let mut roots = vec![entry];
+        let mut work = vec![entry];
+        while let Some(start) = work.pop() {
+            let mut known: [Option<u16>; 8] = [None; 8];
+            let mut address = start;
+            while let Some(&word) = memory.get(&address) {
+                if cfg.data.contains(&address) {
+                    break;
+                }
+                if steps.contains_key(&address) {
+                    // Joined code walked before
+                    leaders.insert(address);
+                    break;
+                }
+                let instruction = Instruction::decode(address, word);
+                let next = address.wrapping_add(1);
+                let fallthrough = Edge { to: next, kind: EdgeKind::Fallthrough };
+                let branch = |to| Edge { to, kind: EdgeKind::Branch };
+                let mut callee = None;
+                let (successors, ends) = match instruction {
+                    Instruction::Br { nzp: 0, .. } => (vec![fallthrough], false),
+                    Instruction::Br { nzp: 7, target } => (vec![branch(target)], true),
+                    Instruction::Br { target, .. } => (vec![branch(target), fallthrough], true),
+                    Instruction::Jmp { base: 7 } | Instruction::Rti | Instruction::Reserved(_) => (vec![], true),
+                    Instruction::Jmp { base } => match known[base as usize] {
+                        Some(to) => (vec![Edge { to, kind: EdgeKind::Jump }], true),
+                        None => {
+                            cfg.unresolved.push(address);
+                            (vec![], true)
+                        }
+                    },
+                    Instruction::Jsr { target } => {
+                        callee = Some(Callee::Subroutine(target));
+                        (vec![fallthrough], false)
+                    }
+                    Instruction::Jsrr { base } => {
+                        callee = Some(known[base as usize].map_or(Callee::Unknown, Callee::Subroutine));
+                        if callee == Some(Callee::Unknown) {
+                            cfg.unresolved.push(address);
+                        }
+                        (vec![fallthrough], false)
+                    }
+                    Instruction::Trap(0x25) => {
+                        callee = Some(Callee::Trap(0x25));
+                        (vec![], true)
+                    }
+                    Instruction::Trap(vector) => {
+                        callee = Some(Callee::Trap(vector));
+                        (vec![fallthrough], false)
+                    }
+                    _ => (vec![fallthrough], false),
+                };
+                if let Some(callee) = callee {
+                    cfg.calls.insert(address, callee);
+                    if let Callee::Subroutine(target) = callee {
+                        let code = memory.contains_key(&target) && !cfg.data.contains(&target);
+                        if code && !roots.contains(&target) {
+                            roots.push(target);
+                            leaders.insert(target);
+                            work.push(target);
+                        }
+                    }
+                }
+
+                let value = match instruction {
+                    Instruction::Lea { target, .. } => Some(target),
+                    Instruction::Ld { target, .. } => memory.get(&target).copied(),
+                    Instruction::Add { sr1, sr2: Operand::Immediate(n), .. } => {
+                        known[sr1 as usize].map(|v| v.wrapping_add(n as u16))
+                    }
+                    _ => None,
+                };
+                for r in instruction.writes() {
+                    known[r as usize] = value;
+                }
+
+                if ends {
+                    for edge in &successors {
+                        leaders.insert(edge.to);
+                        work.push(edge.to);
+                    }
+                }
+                steps.insert(address, Step { instruction, successors, ends });
+                if ends {
+                    break;
+                }
+                address = next;
+            }
+        }
+
+        cfg.split(&steps, &leaders);
+        cfg.owners(&roots);
+        cfg.unresolved.sort();
+        cfg
+    }
+
+    /// Cut the walked instructions in blocks
+    fn split(&mut self, steps: &BTreeMap<u16, Step>, leaders: &BTreeSet<u16>) {
+        for &start in leaders {
+            let mut block = Block {
+                start,
+                instructions: Vec::new(),
+                successors: Vec::new(),
+            };
+            let mut address = start;
+            while let Some(step) = steps.get(&address) {
+                block.instructions.push((address, step.instruction));
+                block.successors = step.successors.clone();
+                let next = address.wrapping_add(1);
+                if step.ends || leaders.contains(&next) || next == 0 {
+                    break;
+                }
+                address = next;
+            }
+            if !block.instructions.is_empty() {
+                self.blocks.insert(start, block);
+            }
+        }
+    }
+
+    /// Give every subroutine the blocks it reaches
+    fn owners(&mut self, roots: &[u16]) {
+        for &entry in roots {
+            let mut blocks = BTreeSet::new();
+            let mut work = vec![entry];
+            while let Some(start) = work.pop() {
+                if let Some(block) = self.blocks.get(&start) {
+                    if blocks.insert(start) {
+                        work.extend(block.successors.iter().map(|e| e.to));
+                    }
+                }
+            }
+            let calls: BTreeSet<Callee> = blocks
+                .iter()
+                .flat_map(|start| &self.blocks[start].instructions)
+                .filter_map(|(address, _)| self.calls.get(address).copied())
+                .collect();
+            let subroutine = Subroutine {
+                entry,
+                name: self.name(entry),
+                blocks: blocks.into_iter().collect(),
+                calls: calls.into_iter().collect(),
+            };
+            self.subroutines.insert(entry, subroutine);
+        }
+    }
+
+    /// The label of `address`, or the address
+    pub fn name(&self, address: u16) -> String {
+        self.names.get(&address).cloned().unwrap_or_else(|| format!("x{:04X}", address))
+    }
+
+    fn callee_name(&self, callee: Callee) -> String {
+        match callee {
+            Callee::Subroutine(address) => self.name(address),
+            Callee::Trap(vector) => Instruction::Trap(vector).format(&self.names),
+            Callee::Unknown => "?".to_string(),
+        }
+    }
+
+    /// The subroutines of a block, by entry
+    pub fn owners_of(&self, start: u16) -> Vec<u16> {
+        self.subroutines.values().filter(|s| s.blocks.contains(&start)).map(|s| s.entry).collect()
+    }
+
+    /// The graph of blocks for Graphviz, every
+    /// subroutine in a cluster and calls dashed
+    pub fn dot(&self) -> String {
+        let mut out = String::from("digraph cfg {\n    node [shape=box, fontname=\"monospace\"];\n");
+        let mut placed = BTreeSet::new();
+        for (i, subroutine) in self.subroutines.values().enumerate() {
+            out += &format!("    subgraph cluster_{} {{\n        label=\"{}\";\n", i, escape(&subroutine.name));
+            for start in &subroutine.blocks {
+                // Shared blocks are drawn in the first owner
+                if placed.insert(*start) {
+                    out += &format!("        {}\n", self.node(&self.blocks[start]));
+                }
+            }
+            out += "    }\n";
+        }
+        for block in self.blocks.values() {
+            if placed.insert(block.start) {
+                out += &format!("    {}\n", self.node(block));
+            }
+            for edge in &block.successors {
+                if !self.blocks.contains_key(&edge.to) && placed.insert(edge.to) {
+                    let what = if self.data.contains(&edge.to) { "data" } else { "not loaded" };
+                    out += &format!(
+                        "    \"x{:04X}\" [shape=plaintext, label=\"x{:04X} ({})\"];\n",
+                        edge.to, edge.to, what
+                    );
+                }
+                let style = match edge.kind {
+                    EdgeKind::Fallthrough => "",
+                    EdgeKind::Branch => " [label=\"taken\"]",
+                    EdgeKind::Jump => " [label=\"jump\"]",
+                };
+                out += &format!("    \"x{:04X}\" -> \"x{:04X}\"{};\n", block.start, edge.to, style);
+            }
+            for (address, _) in &block.instructions {
+                if let Some(Callee::Subroutine(target)) = self.calls.get(address) {
+                    if self.blocks.contains_key(target) {
+                        out += &format!("    \"x{:04X}\" -> \"x{:04X}\" [style=dashed];\n", block.start, target);
+                    }
+                }
+            }
+        }
+        out + "}\n"
+    }
+
+    fn node(&self, block: &Block) -> String {
+        let mut label = String::new();
+        for &(address, instruction) in &block.instructions {
+            if let Some(name) = self.names.get(&address) {
+                label += &format!("{}:\\l", escape(name));
+            }
+            label += &format!("x{:04X}  {}\\l", address, escape(&instruction.format(&self.names)));
+        }
+        format!("\"x{:04X}\" [label=\"{}\"];", block.start, label)
+    }
+
+    /// The graph of subroutines and the traps they
+    /// use for Graphviz
+    pub fn call_dot(&self) -> String {
+        let mut out = String::from("digraph calls {\n");
+        for subroutine in self.subroutines.values() {
+            out += &format!("    \"{}\" [shape=box];\n", escape(&subroutine.name));
+        }
+        for subroutine in self.subroutines.values() {
+            for &callee in &subroutine.calls {
+                let style = if let Callee::Subroutine(_) = callee { "" } else { " [style=dashed]" };
+                out += &format!(
+                    "    \"{}\" -> \"{}\"{};\n",
+                    escape(&subroutine.name),
+                    escape(&self.callee_name(callee)),
+                    style
+                );
+            }
+        }
+        out + "}\n"
+    }
+
+    /// Blocks, subroutines and calls as JSON,
+    /// addresses are written like `x3000`
+    pub fn json(&self) -> Value {
+        let hex = |address: u16| format!("x{:04X}", address);
+        let blocks: Vec<Value> = self
+            .blocks
+            .values()
+            .map(|block| {
+                let instructions: Vec<Value> = block
+                    .instructions
+                    .iter()
+                    .map(|&(address, instruction)| {
+                        json!({
+                            "address": hex(address),
+                            "text": instruction.format(&self.names),
+                            "line": self.lines.get(&address),
+                        })
+                    })
+                    .collect();
+                let successors: Vec<Value> = block
+                    .successors
+                    .iter()
+                    .map(|edge| json!({ "to": hex(edge.to), "kind": edge.kind.name() }))
+                    .collect();
+                json!({
+                    "start": hex(block.start),
+                    "end": hex(block.end()),
+                    "instructions": instructions,
+                    "successors": successors,
+                })
+            })
+            .collect();
+        let subroutines: Vec<Value> = self
+            .subroutines
+            .values()
+            .map(|subroutine| {
+                let calls: Vec<String> = subroutine.calls.iter().map(|&c| self.callee_name(c)).collect();
+                json!({
+                    "name": subroutine.name,
+                    "entry": hex(subroutine.entry),
+                    "blocks": subroutine.blocks.iter().map(|&b| hex(b)).collect::<Vec<_>>(),
+                    "calls": calls,
+                })
+            })
+            .collect();
+        json!({
+            "entry": hex(self.entry),
+            "blocks": blocks,
+            "subroutines": subroutines,
+            "unresolved": self.unresolved.iter().map(|&a| hex(a)).collect::<Vec<_>>(),
+        })
+    }
+}
+
+/// Quote `text` for a Graphviz string
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+const USAGE: &str = "Usage: cargo run cfg [--calls] [--json] [-o <file>] <program>";
+
+/// Entry point of `cargo run cfg ...`, returns the exit code
+pub fn main(args: Vec<String>) -> i32 {
+    let mut program = None;
+    let mut output = None;
+    let (mut calls, mut json) = (false, false);
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--calls" => calls = true,
+            "--json" => json = true,
+            "-o" | "--output" => match args.next() {
+                Some(path) => output = Some(path),
+                None => {
+                    eprintln!("Missing value for {}\n{}", arg, USAGE);
+                    return 1;
+                }
+            },
+            _ if arg.starts_with('-') => {
+                eprintln!("Unknown argument `{}`\n{}", arg, USAGE);
+                return 1;
+            }
+            _ if program.is_some() => {
+                eprintln!("Unexpected argument `{}`\n{}", arg, USAGE);
+                return 1;
+            }
+            _ => program = Some(arg),
+        }
+    }
+    let program = match program {
+        Some(program) => program,
+        None => {
+            eprintln!("{}", USAGE);
+            return 1;
+        }
+    };
+
+    let image = match runner::read(&program, Default::default()) {
+        Ok(image) => image,
+        Err(message) => {
+            eprintln!("{}", message);
+            return 1;
+        }
+    };
+    let cfg = Cfg::build(&image);
+    let text = if json {
+        let value = cfg.json();
+        let value = if calls { value["subroutines"].clone() } else { value };
+        serde_json::to_string_pretty(&value).expect("Failed to serialize the graph") + "\n"
+    } else if calls {
+        cfg.call_dot()
+    } else {
+        cfg.dot()
+    };
+    match output {
+        Some(path) => {
+            if let Err(e) = std::fs::write(&path, text) {
+                eprintln!("{}: {}", path, e);
+                return 1;
+            }
+        }
+        None => print!("{}", text),
+    }
+    0
+}
@@ -0,0 +1,200 @@
+//! Errors and warnings pointing into the source.
+//!
+//! ```text
+//! loop.asm:4:11: error: undefined label `LOOOP`
+//!   |
+//! 4 |     BRnzp LOOOP
+//!   |           ^^^^^
+//!   = help: did you mean `LOOP`?
+//! ```
+use super::Expansion;
+use std::fmt;
+use std::ops::Range;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// An error or a warning, `line` starts at 1.
+/// Inside a macro `line` is in the macro definition
+/// and `expansion` lists the calls and includes,
+/// innermost first.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AsmError {
+    pub severity: Severity,
+    pub line: usize,
+    /// The included file `line` is in, None for the
+    /// assembled source
+    pub file: Option<String>,
+    /// Columns of the faulty text, starting at 1
+    pub columns: Option<Range<usize>>,
+    pub message: String,
+    pub help: Option<String>,
+    pub expansion: Vec<Expansion>,
+}
+
+impl AsmError {
+    pub fn new(line: usize, message: String, expansion: Vec<Expansion>) -> AsmError {
+        AsmError {
+            severity: Severity::Error,
+            line,
+            file: None,
+            columns: None,
+            message,
+            help: None,
+            expansion,
+        }
+    }
+
+    /// Line of the outermost macro call, or of the error
+    pub(super) fn source_line(&self) -> usize {
+        self.expansion.last().map_or(self.line, |e| e.line)
+    }
+
+    /// The message followed by the source line, the
+    /// faulty text underlined, and the help if any.
+    /// `source` is the text of `path`, lines of an
+    /// included file are read from it.
+    pub fn render(&self, path: &str, source: &str) -> String {
+        let included = self.file.as_deref().map(|file| (file, super::preprocess::read(file).unwrap_or_default()));
+        let (path, source) = match &included {
+            Some((file, text)) => (*file, text.as_str()),
+            None => (path, source),
+        };
+        let mut out = format!("{}:{}\n", path, self);
+        let text = match source.lines().nth(self.line.wrapping_sub(1)) {
+            Some(text) => text,
+            None => return out,
+        };
+        let number = self.line.to_string();
+        let margin = " ".repeat(number.len());
+        out += &format!("{} |\n{} | {}\n", margin, number, text);
+        if let Some(columns) = &self.columns {
+            // Keep the tabs so the carets line up
+            let indent: String = text
+                .chars()
+                .take(columns.start - 1)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            let carets = "^".repeat(columns.len().max(1));
+            out += &format!("{} | {}{}\n", margin, indent, carets);
+        }
+        if let Some(help) = &self.help {
+            out += &format!("{} = help: {}\n", margin, help);
+        }
+        out
+    }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:", self.line)?;
+        if let Some(columns) = &self.columns {
+            write!(f, "{}:", columns.start)?;
+        }
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, " {}: {}", severity, self.message)?;
+        for expansion in &self.expansion {
+            if expansion.include {
+                write!(f, " (in {} included at line {})", expansion.name, expansion.line)?;
+            } else {
+                write!(f, " (in macro {} called at line {})", expansion.name, expansion.line)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An error message not yet placed in the source,
+/// `span` are the columns of the line, from 0
+#[derive(Debug)]
+pub(super) struct Fault {
+    pub message: String,
+    pub span: Option<Range<usize>>,
+    pub help: Option<String>,
+}
+
+impl Fault {
+    pub fn at(mut self, span: Option<Range<usize>>) -> Fault {
+        if self.span.is_none() {
+            self.span = span;
+        }
+        self
+    }
+
+    pub fn help(mut self, help: impl Into<String>) -> Fault {
+        self.help = Some(help.into());
+        self
+    }
+}
+
+impl From<String> for Fault {
+    fn from(message: String) -> Fault {
+        Fault {
+            message,
+            span: None,
+            help: None,
+        }
+    }
+}
+
+impl From<&str> for Fault {
+    fn from(message: &str) -> Fault {
+        Fault::from(message.to_string())
+    }
+}
+
+/// The error for a name that is neither a register,
+/// a label nor a constant
+pub(super) fn undefined<'a>(name: &str, known: impl Iterator<Item = &'a String>) -> Fault {
+    let upper = name.to_ascii_uppercase();
+    if let Some(number) = upper.strip_prefix('R') {
+        if !number.is_empty() && number.chars().all(|c| c.is_ascii_digit() || c == 'O') {
+            let fault = Fault::from(format!("invalid register `{}`", name));
+            return match number {
+                "O" => fault.help("did you mean `R0`?"),
+                _ => fault.help("registers are R0 to R7"),
+            };
+        }
+    }
+    let fault = Fault::from(format!("undefined label `{}`", name));
+    match nearest(name, known) {
+        Some(candidate) => fault.help(format!("did you mean `{}`?", candidate)),
+        None => fault,
+    }
+}
+
+/// The known name closest to `name`, if it is close
+/// enough to be a typo
+fn nearest<'a>(name: &str, known: impl Iterator<Item = &'a String>) -> Option<&'a String> {
+    let limit = (name.len() / 3).max(1);
+    known
+        .map(|candidate| {
+            let distance = if candidate.eq_ignore_ascii_case(name) { 0 } else { distance(name, candidate) };
+            (distance, candidate)
+        })
+        .filter(|&(distance, _)| distance <= limit)
+        .min_by_key(|&(distance, _)| distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Levenshtein distance, ignoring case
+fn distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.to_ascii_uppercase().chars().collect();
+    let b: Vec<char> = b.to_ascii_uppercase().chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
@@ -0,0 +1,210 @@
+//! Constant expressions in operands.
+//!
+//! From the lowest to the highest precedence:
+//! `|`, `^`, `&`, `<<` `>>`, `+` `-`, `*` `/` `%`,
+//! then the unary `-`, `+` and `~`. Operands are
+//! numbers (`#10`, `x3000`, `0x3000`, `b1010`, `10`),
+//! characters (`'A'`, `'\n'`), labels, `.EQU` constants
+//! and parenthesised expressions.
+use super::{is_symbol, parse_number};
+
+#[derive(Clone, Debug, PartialEq)]
+pub(super) enum Expr {
+    Number(i32),
+    Symbol(String),
+    Unary(char, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+}
+
+/// The result of an expression. Addresses are
+/// tracked so that `LABEL+1` is a target address
+/// while `END-START` is a plain number.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) struct Value {
+    pub value: i32,
+    pub address: bool,
+}
+
+impl Value {
+    pub fn number(value: i32) -> Value {
+        Value {
+            value,
+            address: false,
+        }
+    }
+}
+
+const LEVELS: [&[&str]; 6] = [&["|"], &["^"], &["&"], &["<<", ">>"], &["+", "-"], &["*", "/", "%"]];
+
+impl Expr {
+    pub fn parse(text: &str) -> Result<Expr, String> {
+        let mut parser = Parser { text, pos: 0 };
+        let expr = parser.level(0)?;
+        parser.skip_spaces();
+        if parser.pos < text.len() {
+            return Err(format!("unexpected `{}` in `{}`", &text[parser.pos..], text));
+        }
+        Ok(expr)
+    }
+
+    /// Names of the symbols used by the expression
+    pub fn symbols(&self) -> Vec<&str> {
+        match self {
+            Expr::Number(_) => Vec::new(),
+            Expr::Symbol(name) => vec![name],
+            Expr::Unary(_, e) => e.symbols(),
+            Expr::Binary(_, a, b) => {
+                let mut symbols = a.symbols();
+                symbols.extend(b.symbols());
+                symbols
+            }
+        }
+    }
+
+    /// Evaluate, `lookup` gives the value of a symbol
+    pub fn eval(&self, lookup: &dyn Fn(&str) -> Option<Value>) -> Result<Value, String> {
+        Ok(match self {
+            Expr::Number(n) => Value::number(*n),
+            Expr::Symbol(name) => lookup(name).ok_or_else(|| format!("undefined label `{}`", name))?,
+            Expr::Unary(op, e) => {
+                let v = e.eval(lookup)?.value;
+                Value::number(match op {
+                    '-' => v.wrapping_neg(),
+                    '~' => !v,
+                    _ => v,
+                })
+            }
+            Expr::Binary(op, a, b) => {
+                let (a, b) = (a.eval(lookup)?, b.eval(lookup)?);
+                let (x, y) = (a.value, b.value);
+                let value = match *op {
+                    "+" => x.wrapping_add(y),
+                    "-" => x.wrapping_sub(y),
+                    "*" => x.wrapping_mul(y),
+                    "/" | "%" if y == 0 => return Err("division by zero".to_string()),
+                    "/" => x.wrapping_div(y),
+                    "%" => x.wrapping_rem(y),
+                    "&" => x & y,
+                    "|" => x | y,
+                    "^" => x ^ y,
+                    "<<" => x.checked_shl(y as u32).unwrap_or(0),
+                    _ => x.checked_shr(y as u32).unwrap_or(0),
+                };
+                // address + number and address - number stay addresses
+                let address = match *op {
+                    "+" => a.address != b.address,
+                    "-" => a.address && !b.address,
+                    _ => false,
+                };
+                Value { value, address }
+            }
+        })
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn rest(&self) -> &str {
+        &self.text[self.pos..]
+    }
+
+    fn skip_spaces(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_spaces();
+        if self.rest().starts_with(token) {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn level(&mut self, level: usize) -> Result<Expr, String> {
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut expr = self.level(level + 1)?;
+        'outer: loop {
+            for &op in LEVELS[level] {
+                if self.eat(op) {
+                    let right = self.level(level + 1)?;
+                    expr = Expr::Binary(op, Box::new(expr), Box::new(right));
+                    continue 'outer;
+                }
+            }
+            return Ok(expr);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        for op in ['-', '+', '~'] {
+            if self.eat(&op.to_string()) {
+                return Ok(Expr::Unary(op, Box::new(self.unary()?)));
+            }
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        self.skip_spaces();
+        if self.eat("(") {
+            let expr = self.level(0)?;
+            if !self.eat(")") {
+                return Err(format!("missing `)` in `{}`", self.text));
+            }
+            return Ok(expr);
+        }
+        if self.rest().starts_with('\'') {
+            return self.character();
+        }
+
+        // A number or a symbol, `#-3` keeps its sign
+        let rest = self.rest();
+        let start = if rest.starts_with("#-") { 2 } else { 0 };
+        let length = rest[start..]
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '#' || c == '@'))
+            .map_or(rest.len(), |n| n + start);
+        let word = &self.text[self.pos..self.pos + length];
+        self.pos += length;
+        if let Some(n) = parse_number(word) {
+            Ok(Expr::Number(n))
+        } else if is_symbol(word) {
+            Ok(Expr::Symbol(word.to_string()))
+        } else if word.is_empty() {
+            Err(format!("missing operand in `{}`", self.text))
+        } else {
+            Err(format!("invalid operand `{}`", word))
+        }
+    }
+
+    /// 'A', '\n', '\''
+    fn character(&mut self) -> Result<Expr, String> {
+        let mut chars = self.rest()[1..].chars();
+        let c = match chars.next() {
+            Some('\\') => match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('r') => '\r',
+                Some('0') => '\0',
+                Some('e') => '\x1b',
+                Some(c) => c,
+                None => return Err("unterminated character".to_string()),
+            },
+            Some(c) => c,
+            None => return Err("unterminated character".to_string()),
+        };
+        if chars.next() != Some('\'') {
+            return Err(format!("invalid character in `{}`", self.text));
+        }
+        self.pos = self.text.len() - chars.as_str().len();
+        Ok(Expr::Number(c as i32))
+    }
+}
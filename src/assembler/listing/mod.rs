@@ -0,0 +1,178 @@
+//! Listing files: what every source line became.
+//!
+//! ```text
+//! Line   Addr   Hex    Binary                Source
+//!    4   x3000  x1027  0001 000 000 1 00111  ADD R0, R0, (SIZE*2)-1  [SIZE=#4]
+//!    5+  x3001  x1DBF  0001 110 110 1 11111  ADD R6, R6, #-1
+//! ```
+//!
+//! Lines produced by a macro follow the call and
+//! carry its line number followed by `+`. Words
+//! after the first one of a `.BLKW` or `.STRINGZ`
+//! get their own rows.
+//! The listing ends with a cross-reference of
+//! every symbol: its value, the line defining it
+//! and the lines using it.
+use super::Expansion;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+/// What one statement became
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Listed {
+    pub line: usize,
+    pub expansion: Vec<Expansion>,
+    pub text: String,
+    pub address: Option<u16>,
+    pub words: Vec<u16>,
+    /// The words are an instruction, not data
+    pub instruction: bool,
+    /// Values of the symbols used by the operands
+    pub values: Vec<(String, Symbol)>,
+}
+
+impl Listed {
+    /// Line of the outermost macro call, or of the statement
+    pub fn source_line(&self) -> usize {
+        self.expansion.last().map_or(self.line, |e| e.line)
+    }
+}
+
+/// A label or an `.EQU` constant
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Symbol {
+    Address(u16),
+    Constant(i32),
+}
+
+impl std::fmt::Display for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Symbol::Address(address) => write!(f, "x{:04X}", address),
+            Symbol::Constant(value) => write!(f, "#{}", value),
+        }
+    }
+}
+
+/// Definition and uses of a symbol, as source lines
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CrossReference {
+    pub value: Symbol,
+    pub defined: usize,
+    pub uses: Vec<usize>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Listing {
+    pub statements: Vec<Listed>,
+    pub symbols: BTreeMap<String, CrossReference>,
+}
+
+impl Listing {
+    /// Write the listing of `source`, the text the
+    /// program was assembled from
+    pub fn write(&self, out: &mut dyn Write, source: &str) -> io::Result<()> {
+        writeln!(out, "Line   Addr   Hex    {:<20}  Source", "Binary")?;
+        let mut statements = self.statements.iter().peekable();
+        for (i, text) in source.lines().enumerate() {
+            let line = format!("{:>4}", i + 1);
+            let mut group = Vec::new();
+            while let Some(statement) = statements.next_if(|s| s.source_line() == i + 1) {
+                group.push(statement);
+            }
+            if group.iter().any(|s| !s.expansion.is_empty()) {
+                // A macro call, followed by the expanded lines
+                write_row(out, &line, group[0].address, None, text.trim())?;
+                for statement in group.iter().filter(|s| !s.expansion.is_empty()) {
+                    write_statement(out, statement)?;
+                }
+            } else if group.is_empty() {
+                write_row(out, &line, None, None, text.trim())?;
+            } else {
+                for statement in group {
+                    write_statement(out, statement)?;
+                }
+            }
+        }
+
+        writeln!(out)?;
+        writeln!(out, "{:<20}  {:<7}  {:<7}  Used", "Symbol", "Value", "Defined")?;
+        for (name, reference) in &self.symbols {
+            let uses: Vec<String> = reference.uses.iter().map(|line| line.to_string()).collect();
+            let row = format!(
+                "{:<20}  {:<7}  {:<7}  {}",
+                name,
+                reference.value.to_string(),
+                reference.defined,
+                uses.join(", ")
+            );
+            writeln!(out, "{}", row.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+fn write_statement(out: &mut dyn Write, statement: &Listed) -> io::Result<()> {
+    let line = format!(
+        "{:>4}{}",
+        statement.source_line(),
+        if statement.expansion.is_empty() { "" } else { "+" }
+    );
+    let mut text = statement.text.clone();
+    if !statement.values.is_empty() {
+        let values: Vec<String> = statement
+            .values
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect();
+        text = format!("{}  [{}]", text, values.join(" "));
+    }
+    let first = statement.words.first().map(|&word| {
+        let binary = if statement.instruction { fields(word) } else { format!("{:016b}", word) };
+        (word, binary)
+    });
+    write_row(out, &line, statement.address, first, &text)?;
+    for (i, &word) in statement.words.iter().enumerate().skip(1) {
+        let address = statement.address.map(|a| a.wrapping_add(i as u16));
+        write_row(out, "", address, Some((word, format!("{:016b}", word))), "")?;
+    }
+    Ok(())
+}
+
+fn write_row(out: &mut dyn Write, line: &str, address: Option<u16>, word: Option<(u16, String)>, text: &str) -> io::Result<()> {
+    let address = address.map_or(String::new(), |a| format!("x{:04X}", a));
+    let (hex, binary) = word.map_or((String::new(), String::new()), |(w, b)| (format!("x{:04X}", w), b));
+    let row = format!("{:<5}  {:<5}  {:<5}  {:<20}  {}", line, address, hex, binary, text);
+    writeln!(out, "{}", row.trim_end())
+}
+
+/// The bits of an instruction grouped by field,
+/// `0001 000 000 1 00111` for `ADD R0, R0, #7`
+pub fn fields(word: u16) -> String {
+    let widths: &[u32] = match word >> 12 {
+        // BR, LD, ST, LDI, STI, LEA: DR/nzp and PCoffset9
+        0b0000 | 0b0010 | 0b0011 | 0b1010 | 0b1011 | 0b1110 => &[4, 3, 9],
+        // ADD, AND
+        0b0001 | 0b0101 if word & 0x20 != 0 => &[4, 3, 3, 1, 5],
+        0b0001 | 0b0101 => &[4, 3, 3, 1, 2, 3],
+        // JSR, JSRR
+        0b0100 if word & 0x800 != 0 => &[4, 1, 11],
+        0b0100 => &[4, 1, 2, 3, 6],
+        // LDR, STR
+        0b0110 | 0b0111 => &[4, 3, 3, 6],
+        // NOT, JMP
+        0b1001 | 0b1100 => &[4, 3, 3, 6],
+        // TRAP
+        0b1111 => &[4, 4, 8],
+        // RTI, reserved
+        _ => &[4, 12],
+    };
+    let bits = format!("{:016b}", word);
+    let mut groups = Vec::new();
+    let mut start = 0;
+    for &width in widths {
+        groups.push(&bits[start..start + width as usize]);
+        start += width as usize;
+    }
+    groups.join(" ")
+}
@@ -0,0 +1,1547 @@
+use crate::hardware::instruction::sign_extend;
+use crate::hardware::register::PC_START;
+use crate::linker::{Definition, Field, Object, Relocation, Section, Target};
+use crate::loader::Image;
+use byteorder::{BigEndian, WriteBytesExt};
+use std::cell::Cell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+use std::ops::Range;
+
+mod diagnostic;
+mod expr;
+pub mod listing;
+mod preprocess;
+pub mod stdlib;
+
+pub use diagnostic::{AsmError, Severity};
+use diagnostic::Fault;
+use expr::{Expr, Value};
+use listing::{CrossReference, Listed, Listing, Symbol};
+
+/// One segment for every `.ORIG`
+pub use crate::loader::Segment;
+
+/// The result of assembling a source file
+#[derive(Clone, Debug, Default)]
+pub struct Program {
+    pub segments: Vec<Segment>,
+    pub symbols: BTreeMap<String, u16>,
+    /// Source line of the first word of every statement
+    pub lines: BTreeMap<u16, usize>,
+    pub listing: Listing,
+    /// Suspicious code that still assembles
+    pub warnings: Vec<AsmError>,
+    /// `.SECTION` name of every segment, None for
+    /// an `.ORIG` block
+    pub sections: Vec<Option<String>>,
+    /// Words left for the linker, with their segment
+    pub relocations: Vec<(usize, Relocation)>,
+    /// Segment of every label
+    pub label_segments: BTreeMap<String, usize>,
+    pub globals: BTreeSet<String>,
+    pub externals: BTreeSet<String>,
+}
+
+impl Program {
+    /// Where execution starts: x3000 when a segment
+    /// starts there, otherwise the first origin
+    pub fn entry(&self) -> Option<u16> {
+        self.segments
+            .iter()
+            .find(|s| s.origin == PC_START)
+            .or(self.segments.first())
+            .map(|s| s.origin)
+    }
+
+    /// Write the program in the object format read by
+    /// `loader::parse`
+    pub fn write_object(&self, out: &mut dyn Write, source: Option<&str>) -> io::Result<()> {
+        let mut image = Image::unchecked(self.segments.clone());
+        image.entry = self.entry();
+        image.symbols = self.symbols.clone();
+        image.source = source.map(str::to_string);
+        image.lines = self.lines.clone();
+        image.write_object(out)
+    }
+
+    /// Addresses of the words that are data, like
+    /// `.FILL`, `.STRINGZ` and `.BLKW`
+    pub fn data(&self) -> BTreeSet<u16> {
+        self.listing
+            .statements
+            .iter()
+            .filter(|listed| !listed.instruction)
+            .filter_map(|listed| Some((listed.address?, listed.words.len())))
+            .flat_map(|(address, n)| (0..n).map(move |i| address.wrapping_add(i as u16)))
+            .collect()
+    }
+
+    /// Whether the program has sections or externals,
+    /// which only the linker can place and resolve
+    pub fn is_relocatable(&self) -> bool {
+        self.sections.iter().any(Option::is_some) || !self.externals.is_empty()
+    }
+
+    /// The relocatable object of the program,
+    /// written by `assemble -c` for the linker
+    pub fn object(&self, source: Option<&str>) -> Object {
+        let mut sections: Vec<Section> = self
+            .segments
+            .iter()
+            .zip(&self.sections)
+            .map(|(segment, name)| Section {
+                name: name.clone(),
+                origin: segment.origin,
+                words: segment.words.clone(),
+                relocations: Vec::new(),
+            })
+            .collect();
+        for (segment, relocation) in &self.relocations {
+            sections[*segment].relocations.push(relocation.clone());
+        }
+        let symbols = self
+            .label_segments
+            .iter()
+            .map(|(name, &section)| Definition {
+                name: name.clone(),
+                section,
+                offset: self.symbols[name].wrapping_sub(sections[section].origin),
+                global: self.globals.contains(name),
+            })
+            .collect();
+        Object {
+            source: source.map(str::to_string),
+            sections,
+            symbols,
+            externals: self.externals.iter().cloned().collect(),
+        }
+    }
+
+    /// Write the legacy `.obj` format: the origin
+    /// followed by the words. It only holds one segment.
+    pub fn write_legacy(&self, out: &mut dyn Write) -> io::Result<()> {
+        let segment = match self.segments.as_slice() {
+            [segment] => segment,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "the legacy format holds exactly one .ORIG block",
+                ))
+            }
+        };
+        out.write_u16::<BigEndian>(segment.origin)?;
+        for &word in &segment.words {
+            out.write_u16::<BigEndian>(word)?;
+        }
+        Ok(())
+    }
+}
+
+/// A macro call or an `.INCLUDE`, `line` is the
+/// line of the call
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Expansion {
+    /// The macro, or the included file
+    pub name: String,
+    pub line: usize,
+    pub include: bool,
+}
+
+/// How a source file is assembled
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Options {
+    /// Accept the pseudo-instructions PUSH, POP,
+    /// MOV, CLR, SUB and LDIMM, `.POOL` and the
+    /// device traps DREAD, DWRITE and RAND
+    pub extended: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Operand {
+    Register(u16),
+    Expr(Expr),
+    String(String),
+}
+
+/// Labels and `.EQU` constants
+#[derive(Default)]
+struct SymbolTable {
+    labels: BTreeMap<String, u16>,
+    constants: BTreeMap<String, Value>,
+    /// Names declared with `.EXTERNAL`, their
+    /// address is only known after linking
+    externals: BTreeSet<String>,
+    globals: BTreeSet<String>,
+    /// Every `.ORIG` and `.SECTION`, in order
+    blocks: Vec<Block>,
+    /// Block of every label
+    label_blocks: BTreeMap<String, usize>,
+    /// Address of every literal pool entry,
+    /// by statement
+    literals: BTreeMap<usize, u16>,
+}
+
+/// An `.ORIG` block or a `.SECTION`
+struct Block {
+    /// Index of the statement opening the block
+    statement: usize,
+    /// 0 for a section, which labels are relative to
+    origin: u16,
+    /// None for an `.ORIG` block
+    section: Option<String>,
+}
+
+impl SymbolTable {
+    fn lookup(&self, name: &str) -> Option<Value> {
+        match self.labels.get(name) {
+            Some(&address) => Some(Value {
+                value: address as i32,
+                address: true,
+            }),
+            None if self.externals.contains(name) => Some(Value { value: 0, address: true }),
+            None => self.constants.get(name).copied(),
+        }
+    }
+
+    /// The label or external an address expression
+    /// is based on, with the block of the label
+    /// (None for an external)
+    fn anchor<'e>(&self, e: &'e Expr) -> Option<(&'e str, Option<usize>)> {
+        e.symbols().into_iter().find_map(|name| match self.label_blocks.get(name) {
+            Some(&block) => Some((name, Some(block))),
+            None if self.externals.contains(name) => Some((name, None)),
+            None => None,
+        })
+    }
+
+    /// What the linker must add to an address used
+    /// as a whole word, None if it is already known
+    fn absolute(&self, e: &Expr, value: i32) -> Option<(Target, i32)> {
+        match self.anchor(e)? {
+            (name, None) => Some((Target::Symbol(name.to_string()), value)),
+            (_, Some(block)) if self.blocks[block].section.is_some() => Some((Target::Section(block), value)),
+            _ => None,
+        }
+    }
+
+    /// What the linker must add to an address used
+    /// PC relative from `block`, None if the distance
+    /// is already known
+    fn relative(&self, e: &Expr, value: i32, block: usize) -> Option<(Target, i32)> {
+        match self.anchor(e)? {
+            (name, None) => Some((Target::Symbol(name.to_string()), value)),
+            (_, Some(b)) if b == block => None,
+            (_, Some(b)) if self.blocks[b].section.is_none() && self.blocks[block].section.is_none() => None,
+            (_, Some(b)) => Some((Target::Section(b), value - self.blocks[b].origin as i32)),
+        }
+    }
+
+    fn symbol(&self, name: &str) -> Option<Symbol> {
+        if self.externals.contains(name) {
+            return None;
+        }
+        match self.lookup(name)? {
+            Value { value, address: true } => Some(Symbol::Address(value as u16)),
+            Value { value, .. } => Some(Symbol::Constant(value)),
+        }
+    }
+
+    fn contains(&self, name: &str) -> bool {
+        self.labels.contains_key(name) || self.constants.contains_key(name) || self.externals.contains(name)
+    }
+
+    fn names(&self) -> impl Iterator<Item = &String> {
+        self.labels.keys().chain(self.constants.keys()).chain(&self.externals)
+    }
+
+    /// The fault of operand `i`: a name it uses
+    /// that is not defined, otherwise `message`
+    fn fault(&self, statement: &Statement, i: usize, message: String) -> Fault {
+        let undefined = match statement.operands.get(i) {
+            Some(Operand::Expr(e)) => e.symbols().into_iter().find(|name| !self.contains(name)),
+            _ => None,
+        };
+        let fault = match undefined {
+            Some(name) => diagnostic::undefined(name, self.names()),
+            None => Fault::from(message),
+        };
+        fault.at(statement.spans.get(i).cloned())
+    }
+
+    /// Fail on an address only known after linking,
+    /// where a plain number is needed
+    fn fixed(&self, e: &Expr, value: Value) -> Result<Value, Fault> {
+        match (value.address, self.absolute(e, value.value), self.anchor(e)) {
+            (true, Some(_), Some((name, _))) => Err(Fault::from(format!(
+                "`{}` is relocatable, its address is only known after linking",
+                name
+            ))),
+            _ => Ok(value),
+        }
+    }
+
+    fn eval(&self, operand: Option<&Operand>) -> Option<Result<Value, String>> {
+        match operand {
+            Some(Operand::Expr(e)) => Some(e.eval(&|name| self.lookup(name))),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Statement {
+    line: usize,
+    /// The included file of the line, if any
+    file: Option<String>,
+    expansion: Vec<Expansion>,
+    text: String,
+    label: Option<String>,
+    /// Upper case mnemonic or directive, None for a lone label
+    op: Option<String>,
+    operands: Vec<Operand>,
+    /// Columns of the label, the op and every operand
+    label_span: Option<Range<usize>>,
+    op_span: Option<Range<usize>>,
+    spans: Vec<Range<usize>>,
+    /// Statement of the pool entry of an LDIMM
+    literal: Option<usize>,
+}
+
+impl Statement {
+    fn source_line(&self) -> usize {
+        self.expansion.last().map_or(self.line, |e| e.line)
+    }
+
+    /// Symbols used by the operands
+    fn symbols(&self) -> Vec<&str> {
+        // Literals are listed at their LDIMM
+        if matches!(self.op.as_deref(), Some(".SECTION" | ".LITERAL")) {
+            return Vec::new();
+        }
+        self.operands
+            .iter()
+            .flat_map(|operand| match operand {
+                Operand::Expr(e) => e.symbols(),
+                _ => Vec::new(),
+            })
+            .collect()
+    }
+
+    fn error(&self, fault: impl Into<Fault>) -> AsmError {
+        at(self.line, self.file.as_deref(), &self.expansion, fault.into())
+    }
+}
+
+/// Place a fault on a line. Lines expanded from a
+/// macro differ from the source, so they get no
+/// columns.
+fn at(line: usize, file: Option<&str>, expansion: &[Expansion], fault: Fault) -> AsmError {
+    let columns = fault.span.filter(|_| expansion.iter().all(|e| e.include));
+    AsmError {
+        columns: columns.map(|span| span.start + 1..span.end + 1),
+        help: fault.help,
+        file: file.map(str::to_string),
+        ..AsmError::new(line, fault.message, expansion.to_vec())
+    }
+}
+
+pub(crate) const TRAP_ALIASES: [(&str, u16); 6] = [
+    ("GETC", 0x20),
+    ("OUT", 0x21),
+    ("PUTS", 0x22),
+    ("IN", 0x23),
+    ("PUTSP", 0x24),
+    ("HALT", 0x25),
+];
+
+/// Traps of the devices, only known with
+/// `Options::extended`
+pub(crate) const DEVICE_TRAPS: [(&str, u16); 3] = [("DREAD", 0x30), ("DWRITE", 0x31), ("RAND", 0x32)];
+
+const OPCODES: [&str; 18] = [
+    "ADD", "AND", "NOT", "BR", "JMP", "RET", "JSR", "JSRR", "LD", "LDI", "LDR", "LEA", "ST",
+    "STI", "STR", "TRAP", "RTI", "RES",
+];
+
+const DIRECTIVES: [&str; 9] = [
+    ".ORIG", ".END", ".FILL", ".BLKW", ".STRINGZ", ".EQU", ".SECTION", ".GLOBAL", ".EXTERNAL",
+];
+
+/// Only known with `Options::extended`
+const PSEUDO: [&str; 7] = ["PUSH", "POP", "MOV", "CLR", "SUB", "LDIMM", ".POOL"];
+
+/// Assemble LC-3 source code. Every error in the
+/// file is reported, not only the first one.
+/// Warnings are kept in `Program::warnings`.
+/// Included files are looked up from the current
+/// directory.
+pub fn assemble(source: &str) -> Result<Program, Vec<AsmError>> {
+    assemble_with(source, None, Options::default())
+}
+
+/// Assemble `source`, the text of the file at `path`,
+/// included files are looked up next to it
+pub fn assemble_file(path: &str, source: &str) -> Result<Program, Vec<AsmError>> {
+    assemble_with(source, Some(path), Options::default())
+}
+
+/// Assemble `source` with `options`, `path` is the
+/// file it was read from, if any
+pub fn assemble_with(source: &str, path: Option<&str>, options: Options) -> Result<Program, Vec<AsmError>> {
+    let mut errors = Vec::new();
+    let (lines, conditions) = preprocess::expand(source, path, &mut errors);
+    let statements: Vec<Statement> = lines
+        .into_iter()
+        .filter_map(|line| match parse_line(line.line, &line.text, options.extended) {
+            Ok(statement) => statement.map(|s| Statement {
+                file: line.file,
+                expansion: line.expansion,
+                ..s
+            }),
+            Err(fault) => {
+                errors.push(at(line.line, line.file.as_deref(), &line.expansion, fault));
+                None
+            }
+        })
+        .collect();
+    let statements = literal_pools(statements);
+
+    let symbols = collect_symbols(&statements, &mut errors);
+    let mut program = Program {
+        symbols: symbols.labels.clone(),
+        label_segments: symbols.label_blocks.clone(),
+        globals: symbols.globals.clone(),
+        externals: symbols.externals.clone(),
+        ..Program::default()
+    };
+    encode(&statements, &symbols, &mut program, &mut errors);
+    program.listing.symbols = cross_reference(&statements, &conditions, &symbols);
+    errors.extend(unreachable(&statements, &program.listing.statements));
+
+    let (warnings, errors): (Vec<_>, Vec<_>) = errors.into_iter().partition(|e| e.severity == Severity::Warning);
+    if errors.is_empty() {
+        program.warnings = warnings;
+        Ok(program)
+    } else {
+        let mut errors = errors;
+        errors.extend(warnings);
+        errors.sort_by_key(|e| e.source_line());
+        Err(errors)
+    }
+}
+
+fn is_branch(op: &str) -> bool {
+    op.strip_prefix("BR").is_some_and(|flags| parse_branch_flags(flags).is_some())
+}
+
+/// BR, BRn, BRzp, BRnzp, ... the flags come in n, z, p order
+fn parse_branch_flags(flags: &str) -> Option<u16> {
+    if flags.is_empty() {
+        return Some(0b111);
+    }
+    let mut mask = 0;
+    let mut rest = flags;
+    for (c, bit) in [('N', 0b100), ('Z', 0b010), ('P', 0b001)] {
+        if let Some(r) = rest.strip_prefix(c) {
+            mask |= bit;
+            rest = r;
+        }
+    }
+    if rest.is_empty() {
+        Some(mask)
+    } else {
+        None
+    }
+}
+
+fn is_mnemonic(word: &str) -> bool {
+    let upper = word.to_ascii_uppercase();
+    OPCODES.contains(&upper.as_str())
+        || DIRECTIVES.contains(&upper.as_str())
+        || TRAP_ALIASES.iter().any(|(name, _)| *name == upper)
+        || is_branch(&upper)
+}
+
+/// A pseudo-instruction or device trap, whether or
+/// not they are enabled
+fn is_pseudo(word: &str) -> bool {
+    let upper = word.to_ascii_uppercase();
+    PSEUDO.contains(&upper.as_str()) || DEVICE_TRAPS.iter().any(|(name, _)| *name == upper)
+}
+
+fn parse_line(line: usize, text: &str, extended: bool) -> Result<Option<Statement>, Fault> {
+    let tokens = tokenize_spans(text)?;
+    let mut tokens = tokens.into_iter().peekable();
+    let is_op = |word: &str| is_mnemonic(word) || (extended && is_pseudo(word));
+
+    let mut label = None;
+    let mut label_span = None;
+    if let Some((Token::Word(word), span)) = tokens.peek() {
+        if !is_op(word) {
+            if !is_symbol(word) {
+                let fault = Fault::from(format!("invalid label `{}`", word)).at(Some(span.clone()));
+                return Err(if is_pseudo(word) {
+                    fault.help(format!("`{}` needs --extended", word.to_ascii_uppercase()))
+                } else {
+                    fault
+                });
+            }
+            label = Some(word.clone());
+            label_span = Some(span.clone());
+            tokens.next();
+        }
+    }
+
+    let (op, op_span) = match tokens.next() {
+        Some((Token::Word(word), span)) if is_op(&word) => (Some(word.to_ascii_uppercase()), Some(span)),
+        Some((Token::Word(word), span)) => {
+            let fault = Fault::from(format!("unknown instruction `{}`", word)).at(Some(span));
+            let pseudo = [Some(&word), label.as_ref()].into_iter().flatten().find(|w| is_pseudo(w));
+            let pseudo = pseudo.map(|w| w.to_ascii_uppercase());
+            return Err(match (pseudo, label) {
+                (Some(pseudo), _) => {
+                    let kind = if PSEUDO.contains(&pseudo.as_str()) {
+                        "a pseudo-instruction"
+                    } else {
+                        "a device trap"
+                    };
+                    fault.help(format!("`{}` is {}, assemble with --extended", pseudo, kind))
+                }
+                (None, Some(label)) => fault.help(format!("`{}` was read as a label", label)),
+                (None, None) => fault,
+            });
+        }
+        Some((Token::String(_), span)) => return Err(Fault::from("unexpected string").at(Some(span))),
+        None => (None, None),
+    };
+    if op.is_none() && label.is_none() {
+        return Ok(None);
+    }
+
+    let mut operands = Vec::new();
+    let mut spans = Vec::new();
+    for (token, span) in tokens {
+        operands.push(match token {
+            Token::String(s) => Operand::String(s),
+            Token::Word(word) => parse_operand(&word).map_err(|m| Fault::from(m).at(Some(span.clone())))?,
+        });
+        spans.push(span);
+    }
+
+    Ok(Some(Statement {
+        line,
+        file: None,
+        expansion: Vec::new(),
+        text: text.trim().to_string(),
+        label,
+        op,
+        operands,
+        label_span,
+        op_span,
+        spans,
+        literal: None,
+    }))
+}
+
+enum Token {
+    Word(String),
+    String(String),
+}
+
+/// Characters that join the words around them
+/// into a single expression
+const OPERATORS: &str = "+-*/%&|^~<>()";
+
+/// Split a line in words and string literals,
+/// dropping commas and comments. Expressions are
+/// single words: spaces only separate words
+/// between two operands (`R0 #1`), not around an
+/// operator (`SIZE * 2`) or inside parentheses.
+/// A sign right before an operand after a space
+/// starts a new word (`R0 -1`).
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    tokenize_spans(text)
+        .map(|tokens| tokens.into_iter().map(|(token, _)| token).collect())
+        .map_err(|fault| fault.message)
+}
+
+/// Like `tokenize`, with the columns of every token
+fn tokenize_spans(text: &str) -> Result<Vec<(Token, Range<usize>)>, Fault> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    let mut span = 0..0;
+    // The word ends with an operand, not an operator
+    let mut operand = false;
+    let mut space = false;
+    let mut depth = 0;
+    let flush = |word: &mut String, span: &Range<usize>, tokens: &mut Vec<(Token, Range<usize>)>| {
+        if !word.is_empty() {
+            tokens.push((Token::Word(std::mem::take(word)), span.clone()));
+        }
+    };
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        i += 1;
+        if word.is_empty() {
+            span = i - 1..i;
+        }
+        if c == ';' {
+            break;
+        } else if c == ',' {
+            flush(&mut word, &span, &mut tokens);
+            depth = 0;
+        } else if c.is_whitespace() {
+            space = !word.is_empty();
+            continue;
+        } else if c == '"' {
+            flush(&mut word, &span, &mut tokens);
+            let start = i - 1;
+            let unterminated = || Fault::from("unterminated string").at(Some(start..chars.len()));
+            let mut s = String::new();
+            loop {
+                let c = *chars.get(i).ok_or_else(unterminated)?;
+                i += 1;
+                match c {
+                    '"' => break,
+                    '\\' => {
+                        let c = *chars.get(i).ok_or_else(unterminated)?;
+                        i += 1;
+                        s.push(match c {
+                            'n' => '\n',
+                            't' => '\t',
+                            'r' => '\r',
+                            '0' => '\0',
+                            'e' => '\x1b',
+                            c => c,
+                        })
+                    }
+                    c => s.push(c),
+                }
+            }
+            tokens.push((Token::String(s), start..i));
+        } else if OPERATORS.contains(c) {
+            let sign = (c == '-' || c == '+') && chars.get(i).is_some_and(|n| !n.is_whitespace());
+            if space && operand && depth == 0 && (sign || c == '(') {
+                flush(&mut word, &span, &mut tokens);
+                span = i - 1..i;
+            }
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                _ => {}
+            }
+            word.push(c);
+            operand = c == ')';
+        } else {
+            if space && operand && depth == 0 {
+                flush(&mut word, &span, &mut tokens);
+                span = i - 1..i;
+            }
+            word.push(c);
+            if c == '\'' {
+                // A character literal, which may be a space
+                let mut escaped = false;
+                while i < chars.len() {
+                    let c = chars[i];
+                    i += 1;
+                    word.push(c);
+                    match c {
+                        '\'' if !escaped => break,
+                        '\\' => escaped = !escaped,
+                        _ => escaped = false,
+                    }
+                }
+            }
+            operand = true;
+        }
+        span.end = i;
+        space = false;
+    }
+    flush(&mut word, &span, &mut tokens);
+    Ok(tokens)
+}
+
+fn is_label(word: &str) -> bool {
+    let mut chars = word.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// A label, or a local label renamed by a macro expansion
+fn is_symbol(word: &str) -> bool {
+    match word.split_once('@') {
+        Some((name, n)) => is_label(name) && !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()),
+        None => is_label(word),
+    }
+}
+
+fn parse_register(word: &str) -> Option<u16> {
+    let digit = word.strip_prefix(['R', 'r'])?;
+    match digit.parse::<u16>() {
+        Ok(n) if n < 8 && digit.len() == 1 => Some(n),
+        _ => None,
+    }
+}
+
+/// #10, #-3, x3000, b1010, 0x3000 or a plain decimal
+pub fn parse_number(word: &str) -> Option<i32> {
+    if let Some(dec) = word.strip_prefix('#') {
+        return dec.parse().ok();
+    }
+    let (negative, body) = match word.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, word),
+    };
+    let value = if let Some(hex) = body.strip_prefix("0x").or_else(|| body.strip_prefix("0X")) {
+        i32::from_str_radix(hex, 16).ok()?
+    } else if let Some(hex) = body.strip_prefix(['x', 'X']) {
+        i32::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = body.strip_prefix(['b', 'B']) {
+        i32::from_str_radix(bin, 2).ok()?
+    } else if !body.is_empty() && body.chars().all(|c| c.is_ascii_digit()) {
+        body.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn parse_operand(word: &str) -> Result<Operand, String> {
+    match parse_register(word) {
+        Some(register) => Ok(Operand::Register(register)),
+        None => Expr::parse(word).map(Operand::Expr),
+    }
+}
+
+/// Number of words a statement occupies,
+/// `.BLKW` counts must already be defined
+fn size(statement: &Statement, symbols: &SymbolTable) -> Result<u16, String> {
+    let op = match statement.op.as_deref() {
+        Some(op) => op,
+        None => return Ok(0),
+    };
+    match op {
+        ".ORIG" | ".END" | ".EQU" | ".SECTION" | ".GLOBAL" | ".EXTERNAL" | ".POOL" => Ok(0),
+        "PUSH" | "POP" => Ok(2),
+        // Rd - Rd is a CLR
+        "SUB" => match statement.operands.as_slice() {
+            [Operand::Register(d), Operand::Register(s), Operand::Register(t)] if d == s && s == t => Ok(1),
+            [_, _, Operand::Register(_)] => Ok(3),
+            _ => Ok(1),
+        },
+        ".BLKW" => match symbols.eval(statement.operands.first()) {
+            Some(Ok(n)) if (0..=0xFFFF).contains(&n.value) => Ok(n.value as u16),
+            Some(Err(message)) => Err(message),
+            _ => Err(".BLKW expects a word count".to_string()),
+        },
+        ".STRINGZ" => match statement.operands.first() {
+            Some(Operand::String(s)) => Ok(s.chars().count() as u16 + 1),
+            _ => Err(".STRINGZ expects a string".to_string()),
+        },
+        _ => Ok(1),
+    }
+}
+
+/// First pass: assign an address to every label
+/// and a value to every constant
+fn collect_symbols(statements: &[Statement], errors: &mut Vec<AsmError>) -> SymbolTable {
+    let mut symbols = SymbolTable::default();
+    // Constants that use symbols defined later
+    let mut pending = Vec::new();
+    let mut globals = Vec::new();
+    let mut address: Option<u32> = None;
+    for (index, statement) in statements.iter().enumerate() {
+        let error = |message: String, span: &Option<Range<usize>>| statement.error(Fault::from(message).at(span.clone()));
+        let operand = statement.spans.first().cloned().or(statement.op_span.clone());
+        let label_span = &statement.label_span;
+        match statement.op.as_deref() {
+            Some(".ORIG") => match (statement.operands.len(), symbols.eval(statement.operands.first())) {
+                (1, Some(Ok(n))) if (0..=0xFFFF).contains(&n.value) => {
+                    address = Some(n.value as u32);
+                    symbols.blocks.push(Block {
+                        statement: index,
+                        origin: n.value as u16,
+                        section: None,
+                    });
+                }
+                (1, Some(Err(message))) => errors.push(statement.error(symbols.fault(statement, 0, message))),
+                _ => errors.push(error(".ORIG expects an address".to_string(), &operand)),
+            },
+            // Labels of a section are relative to its start
+            Some(".SECTION") => match statement.operands.as_slice() {
+                [Operand::Expr(Expr::Symbol(name))] => {
+                    address = Some(0);
+                    symbols.blocks.push(Block {
+                        statement: index,
+                        origin: 0,
+                        section: Some(name.clone()),
+                    });
+                }
+                _ => errors.push(error(".SECTION expects a name".to_string(), &operand)),
+            },
+            Some(directive @ (".GLOBAL" | ".EXTERNAL")) => {
+                if statement.operands.is_empty() {
+                    errors.push(error(format!("{} expects names", directive), &operand));
+                }
+                for (i, name) in statement.operands.iter().enumerate() {
+                    let span = statement.spans.get(i).cloned();
+                    match name {
+                        Operand::Expr(Expr::Symbol(name)) if directive == ".GLOBAL" => {
+                            symbols.globals.insert(name.clone());
+                            globals.push((statement, i, name));
+                        }
+                        Operand::Expr(Expr::Symbol(name)) if symbols.labels.contains_key(name) || symbols.constants.contains_key(name) => {
+                            errors.push(error(format!("`{}` is defined in this file, it cannot be .EXTERNAL", name), &span))
+                        }
+                        Operand::Expr(Expr::Symbol(name)) => {
+                            symbols.externals.insert(name.clone());
+                        }
+                        _ => errors.push(error(format!("{} expects names", directive), &span)),
+                    }
+                }
+            }
+            Some(".LITERAL") => {
+                if let Some(a) = address {
+                    symbols.literals.insert(index, a as u16);
+                }
+            }
+            Some(".EQU") => {
+                match (&statement.label, statement.operands.as_slice()) {
+                    (Some(name), [Operand::Expr(_)]) if symbols.contains(name) => {
+                        errors.push(error(format!("duplicate label `{}`", name), label_span))
+                    }
+                    (Some(name), [Operand::Expr(e)]) => match e.eval(&|n| symbols.lookup(n)) {
+                        Ok(value) => {
+                            if let Err(fault) = symbols.fixed(e, value) {
+                                errors.push(statement.error(fault.at(statement.spans.first().cloned())));
+                            }
+                            symbols.constants.insert(name.clone(), value);
+                        }
+                        Err(_) => pending.push((statement, name, e)),
+                    },
+                    (None, _) => errors.push(error(".EQU needs a name: NAME .EQU value".to_string(), &statement.op_span)),
+                    _ => errors.push(error(".EQU expects one value".to_string(), &operand)),
+                }
+                continue;
+            }
+            _ => {}
+        }
+        if let Some(label) = &statement.label {
+            match address {
+                Some(a) => {
+                    if symbols.externals.contains(label) {
+                        errors.push(error(format!("`{}` is declared .EXTERNAL but defined here", label), label_span));
+                    } else if symbols.contains(label) {
+                        errors.push(error(format!("duplicate label `{}`", label), label_span));
+                    } else {
+                        symbols.labels.insert(label.clone(), a as u16);
+                        symbols.label_blocks.insert(label.clone(), symbols.blocks.len() - 1);
+                    }
+                }
+                None => errors.push(error("label outside of an .ORIG block".to_string(), label_span)),
+            }
+        }
+        match size(statement, &symbols) {
+            Ok(n) => {
+                if n > 0 && address.is_none() {
+                    errors.push(error("code outside of an .ORIG block".to_string(), &statement.op_span));
+                }
+                if let Some(a) = address.as_mut() {
+                    *a += n as u32;
+                    if *a > 0x10000 {
+                        errors.push(error("program runs past the end of memory".to_string(), &statement.op_span));
+                        address = None;
+                    }
+                }
+            }
+            Err(message) => errors.push(statement.error(symbols.fault(statement, 0, message))),
+        }
+        if statement.op.as_deref() == Some(".END") {
+            address = None;
+        }
+    }
+
+    // Resolve the constants that refer to later
+    // symbols, until no more can be resolved
+    while !pending.is_empty() {
+        let before = pending.len();
+        pending.retain(|(statement, name, e)| match e.eval(&|n| symbols.lookup(n)) {
+            Ok(value) => {
+                if let Err(fault) = symbols.fixed(e, value) {
+                    errors.push(statement.error(fault.at(statement.spans.first().cloned())));
+                }
+                symbols.constants.insert(name.to_string(), value);
+                false
+            }
+            Err(_) => true,
+        });
+        if pending.len() == before {
+            let names: Vec<&str> = pending.iter().map(|(_, name, _)| name.as_str()).collect();
+            for (statement, _, e) in &pending {
+                let fault = match e.symbols().into_iter().find(|n| !symbols.contains(n)) {
+                    Some(n) if names.contains(&n) => {
+                        Fault::from(format!("circular .EQU through `{}`", n)).at(statement.spans.first().cloned())
+                    }
+                    Some(_) => symbols.fault(statement, 0, String::new()),
+                    None => continue,
+                };
+                errors.push(statement.error(fault));
+            }
+            break;
+        }
+    }
+
+    // Only labels can be used by other files
+    for (statement, i, name) in globals {
+        if !symbols.labels.contains_key(name) {
+            let message = format!("`{}` is not a label of this file", name);
+            errors.push(statement.error(symbols.fault(statement, i, message)));
+        }
+    }
+    symbols
+}
+
+/// Second pass: encode every statement
+fn encode(statements: &[Statement], symbols: &SymbolTable, program: &mut Program, errors: &mut Vec<AsmError>) {
+    // The open block and its segment
+    let mut current: Option<(usize, Segment)> = None;
+    let close = |current: &mut Option<(usize, Segment)>, program: &mut Program| {
+        if let Some((block, segment)) = current.take() {
+            program.segments.push(segment);
+            program.sections.push(symbols.blocks[block].section.clone());
+        }
+    };
+    for (index, statement) in statements.iter().enumerate() {
+        let mut listed = Listed {
+            line: statement.line,
+            expansion: statement.expansion.clone(),
+            text: statement.text.clone(),
+            address: current.as_ref().map(|(_, s)| s.origin.wrapping_add(s.words.len() as u16)),
+            values: statement
+                .symbols()
+                .into_iter()
+                .filter_map(|name| Some((name.to_string(), symbols.symbol(name)?)))
+                .collect(),
+            ..Listed::default()
+        };
+        let mut seen = Vec::new();
+        listed.values.retain(|(name, _)| {
+            let first = !seen.contains(name);
+            seen.push(name.clone());
+            first
+        });
+        match statement.op.as_deref() {
+            Some(".ORIG" | ".SECTION") => {
+                close(&mut current, program);
+                // Opened by the first pass if it is valid
+                if let Some(block) = symbols.blocks.iter().position(|b| b.statement == index) {
+                    let origin = symbols.blocks[block].origin;
+                    current = Some((
+                        block,
+                        Segment {
+                            origin,
+                            words: Vec::new(),
+                        },
+                    ));
+                    listed.address = Some(origin);
+                }
+            }
+            Some(".END") => close(&mut current, program),
+            // Resolved by the first pass
+            Some(".EQU" | ".GLOBAL" | ".EXTERNAL") => listed.address = None,
+            Some(op) => {
+                if let Some((block, segment)) = current.as_mut() {
+                    listed.instruction = !op.starts_with('.');
+                    listed.words = encode_words(op, statement, (*block, segment), symbols, program, errors);
+                }
+            }
+            None => {}
+        }
+        program.listing.statements.push(listed);
+    }
+    close(&mut current, program);
+}
+
+/// A relocation before its offset is known
+type Pending = (Field, Target, i32);
+
+/// Append the words of a statement to its segment
+fn encode_words(
+    op: &str,
+    statement: &Statement,
+    (block, segment): (usize, &mut Segment),
+    symbols: &SymbolTable,
+    program: &mut Program,
+    errors: &mut Vec<AsmError>,
+) -> Vec<u16> {
+    let pc = segment.origin.wrapping_add(segment.words.len() as u16);
+    let relocation = Cell::new(None);
+    let words = match encode_statement(op, statement, pc, block, symbols, &relocation) {
+        Ok(words) => {
+            if let Some((field, target, addend)) = relocation.take() {
+                let relocation = Relocation {
+                    offset: segment.words.len() as u16,
+                    field,
+                    target,
+                    addend,
+                };
+                program.relocations.push((block, relocation));
+            }
+            // Sections only get their address when linked
+            if !words.is_empty() && symbols.blocks[block].section.is_none() {
+                // Lines of included files map to the `.INCLUDE`
+                let line = if statement.file.is_some() { statement.source_line() } else { statement.line };
+                program.lines.insert(pc, line);
+            }
+            words
+        }
+        Err(message) => {
+            // A literal fails like its LDIMM, which reports it
+            if op != ".LITERAL" {
+                errors.push(statement.error(message));
+            }
+            // Keep the addresses of the following lines right
+            let n = size(statement, symbols).unwrap_or(1);
+            vec![0; n as usize]
+        }
+    };
+    segment.words.extend(&words);
+    words
+}
+
+/// Move the values of LDIMM to a literal pool:
+/// `.LITERAL` words at the next `.POOL`, or before
+/// the end of the block. Equal values share a word.
+fn literal_pools(statements: Vec<Statement>) -> Vec<Statement> {
+    let mut out: Vec<Statement> = Vec::with_capacity(statements.len());
+    let mut pool: Vec<Statement> = Vec::new();
+    // Every LDIMM waiting for the pool, and its entry
+    let mut users: Vec<(usize, usize)> = Vec::new();
+    let mut open = false;
+    let flush = |out: &mut Vec<Statement>, pool: &mut Vec<Statement>, users: &mut Vec<(usize, usize)>, at: &Statement| {
+        let base = out.len();
+        for (user, entry) in users.drain(..) {
+            out[user].literal = Some(base + entry);
+        }
+        out.extend(pool.drain(..).map(|entry| Statement {
+            line: at.line,
+            file: at.file.clone(),
+            expansion: at.expansion.clone(),
+            ..entry
+        }));
+    };
+    for statement in statements {
+        match statement.op.as_deref() {
+            Some(".ORIG" | ".SECTION" | ".END") => {
+                flush(&mut out, &mut pool, &mut users, &statement);
+                open = statement.op.as_deref() != Some(".END");
+            }
+            Some("LDIMM") if open => {
+                if let [Operand::Register(_), Operand::Expr(e)] = statement.operands.as_slice() {
+                    let entry = pool.iter().position(|p| p.operands[0] == Operand::Expr(e.clone()));
+                    let entry = entry.unwrap_or_else(|| {
+                        // The operand as written, for the listing
+                        let start = statement.label_span.as_ref().or(statement.op_span.as_ref()).map_or(0, |s| s.start);
+                        let written = statement.spans.get(1).and_then(|s| statement.text.get(s.start - start..s.end - start));
+                        pool.push(Statement {
+                            text: format!(".FILL {}", written.unwrap_or("")),
+                            label: None,
+                            op: Some(".LITERAL".to_string()),
+                            operands: vec![Operand::Expr(e.clone())],
+                            label_span: None,
+                            op_span: None,
+                            spans: Vec::new(),
+                            ..statement.clone()
+                        });
+                        pool.len() - 1
+                    });
+                    users.push((out.len(), entry));
+                }
+            }
+            _ => {}
+        }
+        let pool_here = statement.op.as_deref() == Some(".POOL");
+        out.push(statement);
+        if pool_here {
+            let at = out[out.len() - 1].clone();
+            flush(&mut out, &mut pool, &mut users, &at);
+        }
+    }
+    if let Some(last) = out.last().cloned() {
+        flush(&mut out, &mut pool, &mut users, &last);
+    }
+    out
+}
+
+/// Warn about instructions after HALT, RET, JMP,
+/// RTI or an unconditional branch, that neither a
+/// label, a branch nor a data word (a jump table)
+/// makes reachable. Data in between is skipped.
+fn unreachable(statements: &[Statement], listed: &[Listed]) -> Vec<AsmError> {
+    let mut targets = std::collections::HashSet::new();
+    for l in listed {
+        let (address, word) = match (l.address, l.words.first()) {
+            (Some(address), Some(&word)) => (address, word),
+            _ => continue,
+        };
+        let next = address.wrapping_add(1);
+        let offset = match word >> 12 {
+            _ if !l.instruction => {
+                targets.extend(l.words.iter().copied());
+                continue;
+            }
+            0b0000 => sign_extend(word & 0x1FF, 9),
+            0b0100 if word & 0x800 != 0 => sign_extend(word & 0x7FF, 11),
+            _ => continue,
+        };
+        targets.insert(next.wrapping_add(offset));
+    }
+    let mut warnings = Vec::new();
+    // The last instruction that does not fall through,
+    // and whether the code after it was reported
+    let mut after: Option<(&str, bool)> = None;
+    for (statement, listed) in statements.iter().zip(listed) {
+        if statement.label.is_some() || listed.address.is_some_and(|a| targets.contains(&a)) {
+            after = None;
+        }
+        let op = match statement.op.as_deref() {
+            Some(".ORIG" | ".END") => {
+                after = None;
+                continue;
+            }
+            Some(op) if !op.starts_with('.') => op,
+            _ => continue,
+        };
+        if let Some((previous, false)) = after {
+            let fault = Fault::from(format!("unreachable instruction after {}", previous))
+                .at(statement.op_span.clone())
+                .help("add a label if something jumps here");
+            warnings.push(AsmError {
+                severity: Severity::Warning,
+                ..statement.error(fault)
+            });
+            after = Some((previous, true));
+        }
+        let halt = match (op, statement.operands.as_slice()) {
+            ("HALT" | "RET" | "JMP" | "RTI", _) => true,
+            ("TRAP", [Operand::Expr(Expr::Number(0x25))]) => true,
+            _ => is_branch(op) && parse_branch_flags(&op[2..]) == Some(0b111),
+        };
+        if halt && after.is_none() {
+            after = Some((op, false));
+        }
+    }
+    warnings
+}
+
+/// Where every symbol is defined and used, `conditions`
+/// are the uses in `.IF` lines, which leave no statement
+fn cross_reference(
+    statements: &[Statement],
+    conditions: &[(String, usize)],
+    symbols: &SymbolTable,
+) -> BTreeMap<String, CrossReference> {
+    let mut references: BTreeMap<String, CrossReference> = BTreeMap::new();
+    for statement in statements {
+        if let Some(label) = &statement.label {
+            if let Some(value) = symbols.symbol(label) {
+                references.entry(label.clone()).or_insert(CrossReference {
+                    value,
+                    defined: statement.source_line(),
+                    uses: Vec::new(),
+                });
+            }
+        }
+    }
+    for statement in statements {
+        for name in statement.symbols() {
+            if let Some(reference) = references.get_mut(name) {
+                if reference.uses.last() != Some(&statement.source_line()) {
+                    reference.uses.push(statement.source_line());
+                }
+            }
+        }
+    }
+    for (name, line) in conditions {
+        if let Some(reference) = references.get_mut(name) {
+            reference.uses.push(*line);
+        }
+    }
+    for reference in references.values_mut() {
+        reference.uses.sort();
+        reference.uses.dedup();
+    }
+    references
+}
+
+/// Encode a statement at `pc` in `block`. An address
+/// only known after linking is encoded as 0 and
+/// the linker is told how to fill it in `relocation`.
+fn encode_statement(
+    op: &str,
+    statement: &Statement,
+    pc: u16,
+    block: usize,
+    symbols: &SymbolTable,
+    relocation: &Cell<Option<Pending>>,
+) -> Result<Vec<u16>, Fault> {
+    let operands = statement.operands.as_slice();
+    let span = |i: usize| statement.spans.get(i).cloned();
+    for i in 0..operands.len() {
+        if let Some(Err(message)) = symbols.eval(operands.get(i)) {
+            return Err(symbols.fault(statement, i, message));
+        }
+    }
+    let expect = |n: usize| -> Result<(), Fault> {
+        if operands.len() == n {
+            Ok(())
+        } else {
+            let message = format!("{} expects {} operand(s), found {}", op, n, operands.len());
+            Err(Fault::from(message).at(span(n).or(statement.op_span.clone())))
+        }
+    };
+    let reg = |i: usize| -> Result<u16, Fault> {
+        match operands.get(i) {
+            Some(Operand::Register(r)) => Ok(*r),
+            _ => Err(Fault::from(format!("{} expects a register as operand {}", op, i + 1))
+                .at(span(i))
+                .help("registers are R0 to R7")),
+        }
+    };
+    let value = |i: usize| symbols.eval(operands.get(i)).transpose();
+    // A value that must be known before linking
+    let number = |i: usize| -> Result<Option<Value>, Fault> {
+        match (operands.get(i), value(i)?) {
+            (Some(Operand::Expr(e)), Some(v)) => symbols.fixed(e, v).map(Some).map_err(|f| f.at(span(i))),
+            (_, v) => Ok(v),
+        }
+    };
+    // Immediate value that must fit in the `field`
+    let imm = |i: usize, field: &str| -> Result<u16, Fault> {
+        let help = match field {
+            "imm5" => "load larger values from a .FILL with LD",
+            _ => "add to the base register first",
+        };
+        match number(i)? {
+            Some(v) => fit_signed(v.value, field).map_err(|m| Fault::from(m).at(span(i)).help(help)),
+            None => Err(Fault::from(format!("{} expects an immediate as operand {}", op, i + 1)).at(span(i))),
+        }
+    };
+    // PC relative offset to an address, or an explicit offset
+    let offset = |i: usize, field: &str| -> Result<u16, Fault> {
+        let help = match op {
+            "LD" => "use LDI or LEA+LDR",
+            "ST" => "use STI or LEA+STR",
+            "LEA" => "load the address from a nearby .FILL with LD",
+            "LDI" | "STI" => "move the pointer closer to the instruction",
+            "JSR" => "use JSRR with the address in a register",
+            _ => "branch to a nearby JMP, or load the address with LD and JMP",
+        };
+        match value(i)? {
+            Some(v) if v.address => {
+                if let Operand::Expr(e) = &operands[i] {
+                    if let Some((target, addend)) = symbols.relative(e, v.value, block) {
+                        let field = if field == "PCoffset11" { Field::PcOffset11 } else { Field::PcOffset9 };
+                        relocation.set(Some((field, target, addend)));
+                        return Ok(0);
+                    }
+                }
+                let distance = v.value - (pc as i32 + 1);
+                fit_signed(distance, field).map_err(|message| {
+                    let target = match &operands[i] {
+                        Operand::Expr(Expr::Symbol(name)) => format!("`{}`", name),
+                        _ => format!("x{:04X}", v.value as u16),
+                    };
+                    Fault::from(format!("{} is too far: offset {}", target, message)).at(span(i)).help(help)
+                })
+            }
+            Some(v) => fit_signed(v.value, field)
+                .map_err(|message| Fault::from(format!("offset {}", message)).at(span(i)).help(help)),
+            None => Err(Fault::from(format!("{} expects a label or an offset as operand {}", op, i + 1)).at(span(i))),
+        }
+    };
+
+    if let Some((_, vector)) = TRAP_ALIASES.iter().chain(&DEVICE_TRAPS).find(|(name, _)| *name == op) {
+        expect(0)?;
+        return Ok(vec![0xF000 | vector]);
+    }
+    if let Some(flags) = op.strip_prefix("BR").and_then(parse_branch_flags) {
+        expect(1)?;
+        return Ok(vec![(flags << 9) | offset(0, "PCoffset9")?]);
+    }
+
+    let word = match op {
+        "ADD" | "AND" => {
+            expect(3)?;
+            let opcode = if op == "ADD" { 0b0001 } else { 0b0101 };
+            let base = (opcode << 12) | (reg(0)? << 9) | (reg(1)? << 6);
+            match operands[2] {
+                Operand::Register(sr2) => base | sr2,
+                _ => base | (1 << 5) | imm(2, "imm5")?,
+            }
+        }
+        "NOT" => {
+            expect(2)?;
+            (0b1001 << 12) | (reg(0)? << 9) | (reg(1)? << 6) | 0x3F
+        }
+        "JMP" => {
+            expect(1)?;
+            (0b1100 << 12) | (reg(0)? << 6)
+        }
+        "RET" => {
+            expect(0)?;
+            (0b1100 << 12) | (7 << 6)
+        }
+        "JSR" => {
+            expect(1)?;
+            (0b0100 << 12) | (1 << 11) | offset(0, "PCoffset11")?
+        }
+        "JSRR" => {
+            expect(1)?;
+            (0b0100 << 12) | (reg(0)? << 6)
+        }
+        "LD" | "LDI" | "LEA" | "ST" | "STI" => {
+            expect(2)?;
+            let opcode = match op {
+                "LD" => 0b0010,
+                "LDI" => 0b1010,
+                "LEA" => 0b1110,
+                "ST" => 0b0011,
+                _ => 0b1011,
+            };
+            (opcode << 12) | (reg(0)? << 9) | offset(1, "PCoffset9")?
+        }
+        "LDR" | "STR" => {
+            expect(3)?;
+            let opcode = if op == "LDR" { 0b0110 } else { 0b0111 };
+            (opcode << 12) | (reg(0)? << 9) | (reg(1)? << 6) | imm(2, "offset6")?
+        }
+        "TRAP" => {
+            expect(1)?;
+            match number(0)? {
+                Some(v) if (0..=0xFF).contains(&v.value) => 0xF000 | v.value as u16,
+                _ => return Err(Fault::from("TRAP expects a vector between x00 and xFF").at(span(0))),
+            }
+        }
+        "RTI" => {
+            expect(0)?;
+            0x8000
+        }
+        // Pseudo-instructions, R6 is the stack pointer
+        "PUSH" | "POP" => {
+            expect(1)?;
+            let r = reg(0)?;
+            if r == 6 {
+                return Err(Fault::from(format!("{} cannot use R6, the stack pointer", op)).at(span(0)));
+            }
+            return Ok(match op {
+                "PUSH" => vec![add_imm(6, 6, -1), (0b0111 << 12) | (r << 9) | (6 << 6)],
+                _ => vec![(0b0110 << 12) | (r << 9) | (6 << 6), add_imm(6, 6, 1)],
+            });
+        }
+        "MOV" => {
+            expect(2)?;
+            add_imm(reg(0)?, reg(1)?, 0)
+        }
+        "CLR" => {
+            expect(1)?;
+            let r = reg(0)?;
+            (0b0101 << 12) | (r << 9) | (r << 6) | (1 << 5)
+        }
+        "SUB" => {
+            expect(3)?;
+            let (d, s) = (reg(0)?, reg(1)?);
+            let not = |d: u16, s: u16| (0b1001 << 12) | (d << 9) | (s << 6) | 0x3F;
+            let add = |d: u16, s: u16, t: u16| (0b0001 << 12) | (d << 9) | (s << 6) | t;
+            return Ok(match operands[2] {
+                Operand::Register(t) if d == s && s == t => vec![(0b0101 << 12) | (d << 9) | (d << 6) | (1 << 5)],
+                // -t + s, s is read last
+                Operand::Register(t) if d != s => vec![not(d, t), add_imm(d, d, 1), add(d, s, d)],
+                // ~(~s + t), t is read before d changes
+                Operand::Register(t) => vec![not(d, s), add(d, d, t), not(d, d)],
+                _ => match number(2)? {
+                    Some(v) if (-15..=16).contains(&v.value) => vec![add_imm(d, s, -v.value)],
+                    Some(v) => {
+                        return Err(Fault::from(format!("SUB immediate {} is out of range (-15 to 16)", v.value))
+                            .at(span(2))
+                            .help("subtract a register loaded with LDIMM"))
+                    }
+                    None => return Err(Fault::from("SUB expects a register or an immediate as operand 3").at(span(2))),
+                },
+            });
+        }
+        "LDIMM" => {
+            expect(2)?;
+            let r = reg(0)?;
+            match value(1)? {
+                Some(v) if (-0x8000..=0xFFFF).contains(&v.value) => {}
+                Some(v) => return Err(Fault::from(format!("LDIMM value {} does not fit in 16 bits", v.value)).at(span(1))),
+                None => return Err(Fault::from("LDIMM expects a number or a label").at(span(1))),
+            }
+            let pool = statement.literal.and_then(|i| symbols.literals.get(&i));
+            let pool = pool.ok_or_else(|| Fault::from("LDIMM outside of an .ORIG block").at(statement.op_span.clone()))?;
+            let offset = fit_signed(*pool as i32 - (pc as i32 + 1), "PCoffset9").map_err(|message| {
+                Fault::from(format!("the literal pool is too far: offset {}", message))
+                    .at(statement.op_span.clone())
+                    .help("add a .POOL after a nearby HALT, RET or branch")
+            })?;
+            (0b0010 << 12) | (r << 9) | offset
+        }
+        ".POOL" => {
+            expect(0)?;
+            return Ok(Vec::new());
+        }
+        "RES" => {
+            expect(0)?;
+            0xD000
+        }
+        ".FILL" | ".LITERAL" => {
+            expect(1)?;
+            let absolute = match (&operands[0], value(0)?) {
+                (Operand::Expr(e), Some(v)) if v.address => symbols.absolute(e, v.value),
+                _ => None,
+            };
+            if let Some((target, addend)) = absolute {
+                relocation.set(Some((Field::Word, target, addend)));
+                return Ok(vec![0]);
+            }
+            match value(0)? {
+                Some(v) if (-0x8000..=0xFFFF).contains(&v.value) => v.value as u16,
+                Some(v) => return Err(Fault::from(format!(".FILL value {} does not fit in 16 bits", v.value)).at(span(0))),
+                None => return Err(Fault::from(".FILL expects a number or a label").at(span(0))),
+            }
+        }
+        ".BLKW" => {
+            let count = size(statement, symbols)? as usize;
+            let fill = number(1)?.map_or(0, |v| v.value as u16);
+            return Ok(vec![fill; count]);
+        }
+        ".STRINGZ" => {
+            expect(1)?;
+            let mut words: Vec<u16> = match &operands[0] {
+                Operand::String(s) => s.chars().map(|c| c as u16).collect(),
+                _ => return Err(Fault::from(".STRINGZ expects a string").at(span(0))),
+            };
+            words.push(0);
+            return Ok(words);
+        }
+        _ => return Err(Fault::from(format!("unknown instruction `{}`", op)).at(statement.op_span.clone())),
+    };
+    Ok(vec![word])
+}
+
+/// ADD DR, SR, #imm
+fn add_imm(dr: u16, sr: u16, imm: i32) -> u16 {
+    (0b0001 << 12) | (dr << 9) | (sr << 6) | (1 << 5) | (imm as u16 & 0x1F)
+}
+
+/// Two's complement encoding of `value` in an
+/// instruction field such as imm5 or PCoffset9
+fn fit_signed(value: i32, field: &str) -> Result<u16, String> {
+    let bits: u32 = field.trim_start_matches(char::is_alphabetic).parse().unwrap();
+    let min = -(1 << (bits - 1));
+    let max = (1 << (bits - 1)) - 1;
+    if value < min || value > max {
+        return Err(format!("{} does not fit in {} ({} to {})", value, field, min, max));
+    }
+    Ok((value as u16) & ((1 << bits) - 1) as u16)
+}
+
+const USAGE: &str =
+    "Usage: cargo run assemble [--legacy | -c] [--extended] [-o <file.obj>] [-l <file.lst>] <program.asm>";
+
+/// Entry point of `cargo run assemble ...`, returns the exit code
+pub fn main(args: Vec<String>) -> i32 {
+    let mut source = None;
+    let mut output = None;
+    let mut listing = None;
+    let mut legacy = false;
+    let mut relocatable = false;
+    let mut options = Options::default();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" | "-l" | "--listing" => match args.next() {
+                Some(path) if arg.starts_with("-o") || arg == "--output" => output = Some(path),
+                Some(path) => listing = Some(path),
+                None => {
+                    eprintln!("Missing value for {}\n{}", arg, USAGE);
+                    return 1;
+                }
+            },
+            "--legacy" => legacy = true,
+            "-c" | "--relocatable" => relocatable = true,
+            "--extended" => options.extended = true,
+            _ if arg.starts_with('-') => {
+                eprintln!("Unknown argument `{}`\n{}", arg, USAGE);
+                return 1;
+            }
+            _ if source.is_some() => {
+                eprintln!("Unexpected argument `{}`\n{}", arg, USAGE);
+                return 1;
+            }
+            _ => source = Some(arg),
+        }
+    }
+    let source = match source {
+        Some(source) => source,
+        None => {
+            eprintln!("{}", USAGE);
+            return 1;
+        }
+    };
+    let output = output.unwrap_or_else(|| {
+        std::path::Path::new(&source)
+            .with_extension(if relocatable { "o" } else { "obj" })
+            .to_string_lossy()
+            .into_owned()
+    });
+
+    let text = match std::fs::read_to_string(&source) {
+        Ok(text) => text,
+        Err(e) => {
+            eprintln!("{}: {}", source, e);
+            return 1;
+        }
+    };
+    let program = match assemble_with(&text, Some(&source), options) {
+        Ok(program) => program,
+        Err(errors) => {
+            for e in errors {
+                eprint!("{}", e.render(&source, &text));
+            }
+            return 1;
+        }
+    };
+    for warning in &program.warnings {
+        eprint!("{}", warning.render(&source, &text));
+    }
+    if program.is_relocatable() && !relocatable {
+        eprintln!("{}: uses .SECTION or .EXTERNAL, assemble it with -c and link it", source);
+        return 1;
+    }
+    let mut bytes = Vec::new();
+    let written = if relocatable {
+        crate::linker::object::write(&program.object(Some(&source)), &mut bytes)
+    } else if legacy {
+        program.write_legacy(&mut bytes)
+    } else {
+        program.write_object(&mut bytes, Some(&source))
+    };
+    if let Err(e) = written.and_then(|()| std::fs::write(&output, bytes)) {
+        eprintln!("{}: {}", output, e);
+        return 1;
+    }
+    if let Some(path) = listing {
+        let mut bytes = Vec::new();
+        let written = program.listing.write(&mut bytes, &text);
+        if let Err(e) = written.and_then(|()| std::fs::write(&path, bytes)) {
+            eprintln!("{}: {}", path, e);
+            return 1;
+        }
+    }
+    0
+}
@@ -0,0 +1,508 @@
+//! Macro expansion and conditional assembly,
+//! applied to the source lines before parsing.
+//!
+//! ```text
+//! .MACRO PUSH reg
+//!     ADD R6, R6, #-1
+//!     STR \reg, R6, #0
+//! .ENDM
+//! ```
+//!
+//! Parameters are referenced as `\name`. Labels
+//! written `@name` inside a macro are local: every
+//! expansion renames them to `name@N`, so a macro
+//! with a loop can be expanded many times.
+//! `.IF value`, `.ELSE` and `.ENDIF` keep or drop
+//! lines depending on whether the value is not zero.
+//! The value is an expression of numbers and the
+//! `.EQU` constants defined above it.
+//! `.INCLUDE "file.asm"` inserts a file, looked up
+//! next to the including one, and `.INCLUDE <name.asm>`
+//! a file of the standard library. A file is only
+//! included once, later `.INCLUDE`s of it are skipped.
+use super::expr::{Expr, Value};
+use super::{is_mnemonic, stdlib, tokenize, AsmError, Expansion, Token};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::Path;
+
+/// Limit on nested expansions, a macro that
+/// expands itself would never stop
+const MAX_DEPTH: usize = 64;
+
+/// A source line after expansion
+pub(super) struct Line {
+    pub line: usize,
+    /// The included file the line is in, None for
+    /// the assembled source
+    pub file: Option<String>,
+    pub text: String,
+    /// Macro calls that produced the line, innermost first
+    pub expansion: Vec<Expansion>,
+}
+
+struct Macro {
+    /// Where the macro is defined
+    file: Option<String>,
+    params: Vec<String>,
+    /// Source line number and text
+    body: Vec<(usize, String)>,
+}
+
+/// One open `.IF`
+struct Conditional {
+    line: usize,
+    /// The enclosing block is kept
+    outer: bool,
+    value: bool,
+    in_else: bool,
+}
+
+impl Conditional {
+    fn active(&self) -> bool {
+        self.outer && (self.value != self.in_else)
+    }
+}
+
+struct Preprocessor<'a> {
+    macros: HashMap<String, Macro>,
+    /// Number of expansions so far, names local labels
+    expansions: usize,
+    /// Path of the assembled source, if known
+    path: Option<String>,
+    /// Files included so far
+    included: HashSet<String>,
+    /// `.EQU` constants so far, for `.IF`
+    constants: HashMap<String, i32>,
+    /// Names in `.IF` conditions and their source line
+    conditions: Vec<(String, usize)>,
+    out: Vec<Line>,
+    errors: &'a mut Vec<AsmError>,
+}
+
+/// Expand the includes, macros and conditionals of
+/// `source`, read from `path` if it is known. The
+/// names used by `.IF` conditions are returned with
+/// the source line of each use.
+pub(super) fn expand(
+    source: &str,
+    path: Option<&str>,
+    errors: &mut Vec<AsmError>,
+) -> (Vec<Line>, Vec<(String, usize)>) {
+    let lines: Vec<(usize, String)> = source
+        .lines()
+        .enumerate()
+        .map(|(i, text)| (i + 1, text.to_string()))
+        .collect();
+    let mut preprocessor = Preprocessor {
+        macros: HashMap::new(),
+        expansions: 0,
+        path: path.map(str::to_string),
+        included: path.map(key).into_iter().collect(),
+        constants: HashMap::new(),
+        conditions: Vec::new(),
+        out: Vec::new(),
+        errors,
+    };
+    preprocessor.process(&lines, None, &[]);
+    (preprocessor.out, preprocessor.conditions)
+}
+
+/// The text of an included file, `<name.asm>`
+/// for the standard library
+pub(super) fn read(file: &str) -> io::Result<String> {
+    match file.strip_prefix('<').and_then(|name| name.strip_suffix('>')) {
+        Some(name) => stdlib::source(name)
+            .map(str::to_string)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such file in the standard library")),
+        None => std::fs::read_to_string(file),
+    }
+}
+
+/// What follows `.INCLUDE`, if the line is one.
+/// `<` would join the file name to `.INCLUDE` as
+/// an expression, so the line is not tokenized.
+fn include(text: &str) -> Option<&str> {
+    let text = text.trim_start();
+    let rest = text.get(..8).filter(|d| d.eq_ignore_ascii_case(".INCLUDE")).map(|_| &text[8..])?;
+    match rest.chars().next() {
+        None | Some('"' | '<' | ';') => {}
+        Some(c) if c.is_whitespace() => {}
+        _ => return None,
+    }
+    Some(rest.split(';').next().unwrap_or_default().trim())
+}
+
+/// Identifies a file, whatever path it is included by
+fn key(file: &str) -> String {
+    match std::fs::canonicalize(file) {
+        Ok(path) => path.to_string_lossy().into_owned(),
+        Err(_) => file.to_string(),
+    }
+}
+
+/// Upper case words of a line, strings are left out
+fn words(text: &str) -> Vec<String> {
+    match tokenize(text) {
+        Ok(tokens) => tokens
+            .into_iter()
+            .map(|token| match token {
+                Token::Word(word) => word,
+                Token::String(_) => "\"".to_string(),
+            })
+            .collect(),
+        // Reported by the parser
+        Err(_) => Vec::new(),
+    }
+}
+
+impl Preprocessor<'_> {
+    fn error(&mut self, line: usize, file: Option<&str>, expansion: &[Expansion], message: String) {
+        self.errors.push(AsmError {
+            file: file.map(str::to_string),
+            ..AsmError::new(line, message, expansion.to_vec())
+        });
+    }
+
+    /// Process the `lines` of `file`
+    fn process(&mut self, lines: &[(usize, String)], file: Option<&str>, expansion: &[Expansion]) {
+        let mut conditionals: Vec<Conditional> = Vec::new();
+        let mut definition: Option<(usize, String, Macro)> = None;
+        for (line, text) in lines {
+            let line = *line;
+            let words = words(text);
+            let first = words.first().map(|w| w.to_ascii_uppercase());
+            let first = first.as_deref();
+
+            if let Some((_, _, body)) = definition.as_mut() {
+                match first {
+                    Some(".ENDM") => {
+                        let (_, name, body) = definition.take().unwrap();
+                        self.macros.insert(name, body);
+                    }
+                    Some(".MACRO") => {
+                        self.error(line, file, expansion, "nested .MACRO definition".to_string())
+                    }
+                    _ => body.body.push((line, text.clone())),
+                }
+                continue;
+            }
+
+            let active = conditionals.last().is_none_or(|c| c.active());
+            match first {
+                Some(".IF") => {
+                    let value = if !active {
+                        false
+                    } else {
+                        match words.as_slice() {
+                            [_, value] => match self.condition(value, line, expansion) {
+                                Ok(n) => n != 0,
+                                Err(message) => {
+                                    self.error(line, file, expansion, message);
+                                    false
+                                }
+                            },
+                            _ => {
+                                self.error(line, file, expansion, ".IF expects one value".to_string());
+                                false
+                            }
+                        }
+                    };
+                    conditionals.push(Conditional {
+                        line,
+                        outer: active,
+                        value,
+                        in_else: false,
+                    });
+                }
+                Some(".ELSE") => match conditionals.last_mut() {
+                    Some(c) if !c.in_else => c.in_else = true,
+                    Some(_) => self.error(line, file, expansion, "duplicate .ELSE".to_string()),
+                    None => self.error(line, file, expansion, ".ELSE without .IF".to_string()),
+                },
+                Some(".ENDIF") => {
+                    if conditionals.pop().is_none() {
+                        self.error(line, file, expansion, ".ENDIF without .IF".to_string());
+                    }
+                }
+                _ if !active => {}
+                Some(".MACRO") => {
+                    if expansion.iter().any(|e| !e.include) {
+                        self.error(line, file, expansion, ".MACRO inside a macro".to_string());
+                        continue;
+                    }
+                    match self.definition(&words) {
+                        Ok((name, params)) => {
+                            definition = Some((
+                                line,
+                                name,
+                                Macro {
+                                    file: file.map(str::to_string),
+                                    params,
+                                    body: Vec::new(),
+                                },
+                            ))
+                        }
+                        Err(message) => self.error(line, file, expansion, message),
+                    }
+                }
+                Some(".ENDM") => self.error(line, file, expansion, ".ENDM without .MACRO".to_string()),
+                _ if include(text).is_some() => self.include(line, text, file, expansion),
+                _ => {
+                    if let [name, equ, value] = words.as_slice() {
+                        if equ.eq_ignore_ascii_case(".EQU") && !self.constants.contains_key(name) {
+                            // Errors are left to the assembler
+                            if let Ok(n) = self.constant(value) {
+                                self.constants.insert(name.clone(), n);
+                            }
+                        }
+                    }
+                    self.line(line, text, &words, file, expansion)
+                }
+            }
+        }
+        if let Some((line, name, _)) = definition {
+            self.error(line, file, expansion, format!("macro `{}` has no .ENDM", name));
+        }
+        for conditional in conditionals {
+            self.error(conditional.line, file, expansion, ".IF has no .ENDIF".to_string());
+        }
+    }
+
+    /// Evaluate `value` with the constants so far
+    /// Value of an `.IF` condition, its names are
+    /// recorded as used on `line`
+    fn condition(&mut self, value: &str, line: usize, expansion: &[Expansion]) -> Result<i32, String> {
+        let line = expansion.last().map_or(line, |e| e.line);
+        if let Ok(expr) = Expr::parse(value) {
+            let names = expr.symbols().into_iter().map(|name| (name.to_string(), line));
+            self.conditions.extend(names);
+        }
+        self.constant(value)
+    }
+
+    fn constant(&self, value: &str) -> Result<i32, String> {
+        let expr = Expr::parse(value)?;
+        let lookup = |name: &str| self.constants.get(name).map(|n| Value::number(*n));
+        match expr.eval(&lookup) {
+            Ok(v) => Ok(v.value),
+            Err(_) => Err(format!("`{}` is not a constant", value)),
+        }
+    }
+
+    /// Name and parameters of a `.MACRO` line
+    fn definition(&self, words: &[String]) -> Result<(String, Vec<String>), String> {
+        let name = words.get(1).ok_or(".MACRO expects a name")?;
+        if !super::is_label(name) || is_mnemonic(name) {
+            return Err(format!("invalid macro name `{}`", name));
+        }
+        let params = words[2..].to_vec();
+        for (i, param) in params.iter().enumerate() {
+            if !super::is_label(param) {
+                return Err(format!("invalid parameter `{}`", param));
+            }
+            if params[..i].contains(param) {
+                return Err(format!("duplicate parameter `{}`", param));
+            }
+        }
+        Ok((name.to_ascii_uppercase(), params))
+    }
+
+    /// Insert the file named by an `.INCLUDE` line
+    fn include(&mut self, line: usize, text: &str, file: Option<&str>, expansion: &[Expansion]) {
+        if expansion.iter().any(|e| !e.include) {
+            self.error(line, file, expansion, ".INCLUDE inside a macro".to_string());
+            return;
+        }
+        let name = include(text).unwrap_or_default();
+        let included = match name.strip_prefix('"').and_then(|n| n.strip_suffix('"')) {
+            Some(name) if !name.is_empty() => {
+                // Relative to the including file
+                let including = file.or(self.path.as_deref());
+                let directory = including.filter(|f| !f.starts_with('<')).map_or(Path::new(""), |f| {
+                    Path::new(f).parent().unwrap_or(Path::new(""))
+                });
+                directory.join(name).to_string_lossy().into_owned()
+            }
+            _ if name.len() > 2 && name.starts_with('<') && name.ends_with('>') => name.to_string(),
+            _ => {
+                let message = ".INCLUDE expects a \"file\" or a <library> file".to_string();
+                self.error(line, file, expansion, message);
+                return;
+            }
+        };
+        if !self.included.insert(key(&included)) {
+            return;
+        }
+        let source = match read(&included) {
+            Ok(source) => source,
+            Err(e) => {
+                self.error(line, file, expansion, format!("cannot include `{}`: {}", included, e));
+                return;
+            }
+        };
+        let lines: Vec<(usize, String)> = source
+            .lines()
+            .enumerate()
+            .map(|(i, text)| (i + 1, text.to_string()))
+            .collect();
+        let mut inner = vec![Expansion {
+            name: included.clone(),
+            line,
+            include: true,
+        }];
+        inner.extend_from_slice(expansion);
+        self.process(&lines, Some(&included), &inner);
+    }
+
+    /// Emit a line, expanding it if it calls a macro
+    fn line(&mut self, line: usize, text: &str, words: &[String], file: Option<&str>, expansion: &[Expansion]) {
+        let is_macro = |w: &String| self.macros.contains_key(&w.to_ascii_uppercase());
+        let (label, call) = match words {
+            [name, ..] if is_macro(name) => (None, 0),
+            [label, name, ..] if is_macro(name) && !is_mnemonic(label) => (Some(label), 1),
+            _ => {
+                self.out.push(Line {
+                    line,
+                    file: file.map(str::to_string),
+                    text: text.to_string(),
+                    expansion: expansion.to_vec(),
+                });
+                return;
+            }
+        };
+        let name = words[call].to_ascii_uppercase();
+
+        if expansion.len() >= MAX_DEPTH {
+            self.error(line, file, expansion, format!("macro `{}` expands too deeply", name));
+            return;
+        }
+        let args = match tokenize(text) {
+            Ok(tokens) => tokens
+                .into_iter()
+                .skip(call + 1)
+                .map(|token| match token {
+                    Token::Word(word) => word,
+                    Token::String(s) => quote(&s),
+                })
+                .collect::<Vec<_>>(),
+            Err(_) => return,
+        };
+        let definition = &self.macros[&name];
+        if args.len() != definition.params.len() {
+            let message = format!(
+                "macro `{}` expects {} argument(s), found {}",
+                name,
+                definition.params.len(),
+                args.len()
+            );
+            self.error(line, file, expansion, message);
+            return;
+        }
+
+        self.expansions += 1;
+        let mut body = Vec::new();
+        let mut errors = Vec::new();
+        for (body_line, body_text) in &definition.body {
+            match substitute(body_text, &definition.params, &args, self.expansions) {
+                Ok(text) => body.push((*body_line, text)),
+                Err(message) => errors.push((*body_line, message)),
+            }
+        }
+
+        let mut inner = vec![Expansion {
+            name,
+            line,
+            include: false,
+        }];
+        inner.extend_from_slice(expansion);
+        let defined = definition.file.clone();
+        for (body_line, message) in errors {
+            self.error(body_line, defined.as_deref(), &inner, message);
+        }
+        if let Some(label) = label {
+            // The label names the first expanded word
+            self.out.push(Line {
+                line,
+                file: file.map(str::to_string),
+                text: label.clone(),
+                expansion: expansion.to_vec(),
+            });
+        }
+        self.process(&body, defined.as_deref(), &inner);
+    }
+}
+
+/// Replace the parameters and local labels of a
+/// macro body line, strings, characters and
+/// comments are kept
+fn substitute(text: &str, params: &[String], args: &[String], expansion: usize) -> Result<String, String> {
+    let mut out = String::new();
+    let mut chars = text.chars().peekable();
+    // The closing quote of the string or character
+    let mut quoted = None;
+    while let Some(c) = chars.next() {
+        if let Some(quote) = quoted {
+            out.push(c);
+            if c == '\\' {
+                out.extend(chars.next());
+            } else if c == quote {
+                quoted = None;
+            }
+            continue;
+        }
+        match c {
+            '"' | '\'' => {
+                quoted = Some(c);
+                out.push(c);
+            }
+            ';' => {
+                out.push(c);
+                out.extend(chars.by_ref());
+            }
+            '\\' | '@' => {
+                let mut name = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_ascii_alphanumeric() || c == '_') {
+                        break;
+                    }
+                    name.push(c);
+                    chars.next();
+                }
+                if c == '@' {
+                    if !super::is_label(&name) {
+                        return Err(format!("invalid local label `@{}`", name));
+                    }
+                    out.push_str(&format!("{}@{}", name, expansion));
+                } else {
+                    let i = params
+                        .iter()
+                        .position(|p| *p == name)
+                        .ok_or_else(|| format!("unknown parameter `\\{}`", name))?;
+                    out.push_str(&args[i]);
+                }
+            }
+            _ => out.push(c),
+        }
+    }
+    Ok(out)
+}
+
+/// Write a string argument back as a literal
+fn quote(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            '\0' => out.push_str("\\0"),
+            '\x1b' => out.push_str("\\e"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
@@ -0,0 +1,26 @@
+//! The standard library, LC-3 routines kept in
+//! `stdlib/` and built into the assembler. A file
+//! is included with `.INCLUDE <math.asm>`, or all
+//! of them are linked with `link --stdlib`.
+
+/// Name and source of every file
+pub const FILES: [(&str, &str); 4] = [
+    ("math.asm", include_str!("../../../stdlib/math.asm")),
+    ("print.asm", include_str!("../../../stdlib/print.asm")),
+    ("string.asm", include_str!("../../../stdlib/string.asm")),
+    ("stack.asm", include_str!("../../../stdlib/stack.asm")),
+];
+
+pub fn source(name: &str) -> Option<&'static str> {
+    FILES.iter().find(|(file, _)| *file == name).map(|(_, source)| *source)
+}
+
+/// A relocatable source with every file in the
+/// `STDLIB` section, for the linker
+pub fn linkable() -> String {
+    let mut source = String::from(".SECTION STDLIB\n");
+    for (name, _) in FILES {
+        source += &format!(".INCLUDE <{}>\n", name);
+    }
+    source
+}
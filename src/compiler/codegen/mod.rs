@@ -0,0 +1,1089 @@
+//! Generate LC-3 assembly from the syntax tree.
+//!
+//! Every expression leaves its value in R0, R1 and
+//! R2 are scratch registers and intermediate values
+//! are pushed on the R6 stack. Frames follow the
+//! calling convention of the module documentation.
+use super::parser::{constant, Declaration, Expr, ExprKind, Function, Init, Item, Pos, Stmt, Type};
+use super::CompileError;
+use crate::hardware::device::framebuffer::VIDEO_START;
+use std::collections::{BTreeMap, HashMap};
+
+/// Where the stack starts, it grows down from
+/// the word below the framebuffer
+const STACK: u16 = VIDEO_START;
+
+/// Words of code after which the literal pool is
+/// written, well in reach of the first LDIMM
+const POOL_DISTANCE: usize = 150;
+
+/// Functions provided by the compiler, with their
+/// number of arguments. A function of the program
+/// with the same name replaces them.
+const BUILTINS: [(&str, usize); 5] = [
+    ("putchar", 1),
+    ("getchar", 0),
+    ("puts", 1),
+    ("print_string", 1),
+    ("print_int", 1),
+];
+
+struct Signature {
+    params: usize,
+    ret: Type,
+    defined: bool,
+}
+
+/// A local variable or a parameter
+#[derive(Clone)]
+struct Local {
+    /// From R5
+    offset: i32,
+    ty: Type,
+}
+
+struct Generator<'a> {
+    source: Vec<&'a str>,
+    /// Code of the function being compiled
+    code: Vec<String>,
+    /// Last source line written as a comment
+    commented: usize,
+    /// After an unconditional jump, until a label
+    dead: bool,
+    /// Words since the last literal pool
+    words: usize,
+    labels: usize,
+    strings: Vec<String>,
+    functions: BTreeMap<String, Signature>,
+    globals: HashMap<String, Type>,
+    scopes: Vec<HashMap<String, Local>>,
+    /// Words of locals in the current frame
+    frame: i32,
+    /// Break and continue labels of the open loops
+    loops: Vec<(String, String)>,
+    /// Epilogue label and return type
+    ret: (String, Type),
+    math: bool,
+    print: bool,
+    errors: Vec<CompileError>,
+}
+
+fn error(pos: Pos, message: String) -> CompileError {
+    CompileError {
+        line: pos.0,
+        column: pos.1,
+        message,
+    }
+}
+
+pub(super) fn generate(items: &[Item], source: &str) -> Result<String, Vec<CompileError>> {
+    let mut generator = Generator {
+        source: source.lines().collect(),
+        code: Vec::new(),
+        commented: 0,
+        dead: false,
+        words: 0,
+        labels: 0,
+        strings: Vec::new(),
+        functions: BTreeMap::new(),
+        globals: HashMap::new(),
+        scopes: Vec::new(),
+        frame: 0,
+        loops: Vec::new(),
+        ret: (String::new(), Type::Void),
+        math: false,
+        print: false,
+        errors: Vec::new(),
+    };
+    let output = generator.unit(items);
+    if generator.errors.is_empty() {
+        Ok(output)
+    } else {
+        generator.errors.sort_by_key(|e| (e.line, e.column));
+        Err(generator.errors)
+    }
+}
+
+impl Generator<'_> {
+    fn unit(&mut self, items: &[Item]) -> String {
+        // Functions may be called before their definition
+        for item in items {
+            let (name, pos) = match item {
+                Item::Function(f) => (&f.name, f.pos),
+                Item::Global(g) => (&g.name, g.pos),
+            };
+            let previous = self.functions.get(name);
+            let duplicate = match item {
+                Item::Function(f) => previous.is_some_and(|p| p.defined && f.body.is_some()) || self.globals.contains_key(name),
+                Item::Global(_) => previous.is_some() || self.globals.contains_key(name),
+            };
+            if duplicate {
+                self.errors.push(error(pos, format!("`{}` is defined twice", name)));
+                continue;
+            }
+            match item {
+                Item::Function(f) => {
+                    let defined = f.body.is_some() || previous.is_some_and(|p| p.defined);
+                    self.functions.insert(
+                        name.clone(),
+                        Signature {
+                            params: f.params.len(),
+                            ret: f.ret.clone(),
+                            defined,
+                        },
+                    );
+                }
+                Item::Global(g) => {
+                    self.globals.insert(name.clone(), g.ty.clone());
+                }
+            }
+        }
+        match self.functions.get("main") {
+            Some(main) if main.params > 0 => {
+                let pos = items.iter().find_map(|item| match item {
+                    Item::Function(f) if f.name == "main" => Some(f.pos),
+                    _ => None,
+                });
+                self.errors.push(error(pos.unwrap_or((1, 1)), "`main` takes no arguments".to_string()));
+            }
+            Some(_) => {}
+            None => self.errors.push(error((1, 1), "no `main` function".to_string())),
+        }
+
+        let mut out = vec![
+            super::HEADER.to_string(),
+            "        .ORIG x3000".to_string(),
+            format!("        LDIMM R6, x{:04X}", STACK),
+            "        MOV R5, R6".to_string(),
+            "        LDIMM R2, F_main".to_string(),
+            "        JSRR R2".to_string(),
+            "        LDR R0, R6, #0          ; what main returned".to_string(),
+            "        HALT".to_string(),
+            "        .POOL".to_string(),
+        ];
+        for item in items {
+            if let Item::Function(f) = item {
+                if f.body.is_some() {
+                    out.push(String::new());
+                    out.extend(self.function(f));
+                }
+            }
+        }
+        let mut data = Vec::new();
+        for item in items {
+            if let Item::Global(g) = item {
+                data.extend(self.global(g));
+            }
+        }
+        for (i, s) in std::mem::take(&mut self.strings).iter().enumerate() {
+            data.push(format!("S_{}", i));
+            data.extend(string_data(s, s.chars().count() + 1));
+        }
+        if !data.is_empty() {
+            out.push(String::new());
+            out.extend(data);
+        }
+        if self.math || self.print {
+            out.push(String::new());
+        }
+        if self.math {
+            out.push("        .INCLUDE <math.asm>".to_string());
+        }
+        if self.print {
+            out.push("        .INCLUDE <print.asm>".to_string());
+        }
+        out.push("        .END".to_string());
+        out.join("\n") + "\n"
+    }
+
+    fn global(&mut self, g: &Declaration) -> Vec<String> {
+        let mut out = vec![format!("G_{}", g.name)];
+        let number = |e: &Expr, errors: &mut Vec<CompileError>| match constant(e) {
+            Some(n) => format!("        .FILL #{}", n as i16),
+            None => {
+                errors.push(error(e.pos, "globals can only be initialized with constants".to_string()));
+                String::new()
+            }
+        };
+        match (&g.ty, &g.init) {
+            (ty, None) if ty.size() == 1 => out.push("        .FILL #0".to_string()),
+            (ty, None) => out.push(format!("        .BLKW #{}", ty.size())),
+            (Type::Array(element, length), Some(Init::Expr(e))) => match &e.kind {
+                ExprKind::Str(s) if element.size() == 1 && s.chars().count() < *length => out.extend(string_data(s, *length)),
+                ExprKind::Str(_) if element.size() == 1 => {
+                    self.errors.push(error(e.pos, format!("the string does not fit in `{}`", g.name)))
+                }
+                _ => self.errors.push(error(e.pos, "arrays are initialized with a list or a string".to_string())),
+            },
+            (Type::Array(element, length), Some(Init::List(list))) => {
+                if element.size() != 1 {
+                    self.errors.push(error(g.pos, "only arrays of int, char or pointers can be initialized".to_string()));
+                } else if list.len() > *length {
+                    self.errors.push(error(g.pos, format!("too many initializers for `{}`", g.name)));
+                } else {
+                    for e in list {
+                        out.push(number(e, &mut self.errors));
+                    }
+                    if list.len() < *length {
+                        out.push(format!("        .BLKW #{}", length - list.len()));
+                    }
+                }
+            }
+            (_, Some(Init::List(_))) => self.errors.push(error(g.pos, format!("`{}` is not an array", g.name))),
+            (_, Some(Init::Expr(e))) => match &e.kind {
+                ExprKind::Str(s) => {
+                    let label = self.string(s);
+                    out.push(format!("        .FILL {}", label));
+                }
+                _ => out.push(number(e, &mut self.errors)),
+            },
+        }
+        out
+    }
+
+    /// Label of a string literal
+    fn string(&mut self, s: &str) -> String {
+        let index = self.strings.iter().position(|t| t == s).unwrap_or_else(|| {
+            self.strings.push(s.to_string());
+            self.strings.len() - 1
+        });
+        format!("S_{}", index)
+    }
+
+    fn function(&mut self, f: &Function) -> Vec<String> {
+        self.code.clear();
+        self.frame = 0;
+        self.dead = false;
+        self.words = 0;
+        let ret = self.label();
+        self.ret = (ret.clone(), f.ret.clone());
+        // Arguments are above the return value
+        let params = f
+            .params
+            .iter()
+            .enumerate()
+            .map(|(i, (name, ty))| {
+                let local = Local {
+                    offset: 4 + i as i32,
+                    ty: ty.clone(),
+                };
+                (name.clone(), local)
+            })
+            .collect();
+        self.scopes = vec![params];
+        for statement in f.body.as_deref().unwrap_or_default() {
+            self.statement(statement);
+        }
+
+        let params: Vec<String> = f.params.iter().map(|(name, ty)| format!("{} {}", ty, name)).collect();
+        let mut out = vec![
+            format!("; {} {}({})", f.ret, f.name, params.join(", ")),
+            format!("F_{}", f.name),
+            "        ADD R6, R6, #-1         ; return value".to_string(),
+            "        PUSH R7".to_string(),
+            "        PUSH R5".to_string(),
+            "        ADD R5, R6, #-1         ; first local".to_string(),
+        ];
+        out.extend(stack_adjust(-self.frame).into_iter().map(|line| format!("        {}", line)));
+        out.append(&mut self.code);
+        out.extend([
+            ret,
+            "        ADD R6, R5, #1".to_string(),
+            "        POP R5".to_string(),
+            "        POP R7".to_string(),
+            "        RET".to_string(),
+            "        .POOL".to_string(),
+        ]);
+        out
+    }
+
+    fn label(&mut self) -> String {
+        self.labels += 1;
+        format!("L{}", self.labels)
+    }
+
+    fn emit(&mut self, line: impl AsRef<str>) {
+        if self.dead {
+            return;
+        }
+        let line = line.as_ref();
+        self.words += match line.split(' ').next() {
+            Some("PUSH" | "POP") => 2,
+            Some("SUB") => 3,
+            Some(";") => 0,
+            _ => 1,
+        };
+        self.code.push(format!("        {}", line));
+    }
+
+    /// Write the literal pool if the code since the
+    /// last one is long, jumping over it
+    fn pool(&mut self) {
+        if self.words < POOL_DISTANCE {
+            return;
+        }
+        if !self.dead {
+            let skip = self.label();
+            self.jump_near(&skip);
+            self.code.push("        .POOL".to_string());
+            self.place(&skip);
+        } else {
+            self.code.push("        .POOL".to_string());
+        }
+        self.words = 0;
+    }
+
+    fn place(&mut self, label: &str) {
+        self.code.push(label.to_string());
+        self.dead = false;
+    }
+
+    /// Jump through R2, a BR only reaches 256 words
+    fn jump(&mut self, label: &str) {
+        self.emit(format!("LDIMM R2, {}", label));
+        self.emit("JMP R2");
+        self.dead = true;
+    }
+
+    /// Jump to a label a few words ahead
+    fn jump_near(&mut self, label: &str) {
+        self.emit(format!("BR {}", label));
+        self.dead = true;
+    }
+
+    /// Go to `label` when the condition codes match
+    /// `nzp`, by jumping unless the others match
+    fn branch(&mut self, nzp: &str, label: &str) {
+        let others: String = "nzp".chars().filter(|c| !nzp.contains(*c)).collect();
+        let skip = self.label();
+        self.emit(format!("BR{} {}", others, skip));
+        self.jump(label);
+        self.place(&skip);
+    }
+
+    /// The source line as a comment, once
+    fn comment(&mut self, pos: Pos) {
+        if pos.0 != self.commented {
+            self.commented = pos.0;
+            let text = self.source.get(pos.0 - 1).map_or("", |t| t.trim());
+            self.emit(format!("; {}: {}", pos.0, text));
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<Local> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name)).cloned()
+    }
+
+    /// Compile `e` to find its type, without keeping the code
+    fn type_of(&mut self, e: &Expr) -> Type {
+        let (code, errors, strings, labels) = (self.code.len(), self.errors.len(), self.strings.len(), self.labels);
+        let (math, print, dead) = (self.math, self.print, self.dead);
+        let ty = self.expr(e);
+        self.code.truncate(code);
+        self.errors.truncate(errors);
+        self.strings.truncate(strings);
+        (self.labels, self.math, self.print, self.dead) = (labels, math, print, dead);
+        ty
+    }
+
+    fn statement(&mut self, statement: &Stmt) {
+        self.pool();
+        match statement {
+            Stmt::Expr(e) => {
+                self.comment(e.pos);
+                self.expr(e);
+            }
+            Stmt::Declaration(d) => {
+                self.comment(d.pos);
+                self.declare(d);
+            }
+            Stmt::Block(statements) => {
+                self.scopes.push(HashMap::new());
+                for statement in statements {
+                    self.statement(statement);
+                }
+                self.scopes.pop();
+            }
+            Stmt::If(condition, then, otherwise) => {
+                self.comment(condition.pos);
+                let skip = self.label();
+                self.branch_if_zero(condition, &skip);
+                self.statement(then);
+                match otherwise {
+                    Some(otherwise) => {
+                        let end = self.label();
+                        self.jump(&end);
+                        self.place(&skip);
+                        self.statement(otherwise);
+                        self.place(&end);
+                    }
+                    None => self.place(&skip),
+                }
+            }
+            Stmt::While(condition, body) => {
+                let (top, end) = (self.label(), self.label());
+                self.place(&top);
+                self.comment(condition.pos);
+                self.branch_if_zero(condition, &end);
+                self.loop_body(body, &end, &top);
+                self.jump(&top);
+                self.place(&end);
+            }
+            Stmt::DoWhile(body, condition) => {
+                let (top, next, end) = (self.label(), self.label(), self.label());
+                self.place(&top);
+                self.loop_body(body, &end, &next);
+                self.place(&next);
+                self.comment(condition.pos);
+                self.expr(condition);
+                self.emit("ADD R0, R0, #0");
+                self.branch("np", &top);
+                self.place(&end);
+            }
+            Stmt::For(init, condition, step, body) => {
+                self.scopes.push(HashMap::new());
+                for statement in init {
+                    self.statement(statement);
+                }
+                let (top, next, end) = (self.label(), self.label(), self.label());
+                self.place(&top);
+                if let Some(condition) = condition {
+                    self.comment(condition.pos);
+                    self.branch_if_zero(condition, &end);
+                }
+                self.loop_body(body, &end, &next);
+                self.place(&next);
+                if let Some(step) = step {
+                    self.expr(step);
+                }
+                self.jump(&top);
+                self.place(&end);
+                self.scopes.pop();
+            }
+            Stmt::Return(value, pos) => {
+                self.comment(*pos);
+                match (value, &self.ret.1) {
+                    (Some(value), Type::Void) => {
+                        self.errors.push(error(value.pos, "a void function cannot return a value".to_string()))
+                    }
+                    (Some(value), _) => {
+                        self.expr(value);
+                        self.emit("STR R0, R5, #3");
+                    }
+                    (None, _) => {}
+                }
+                let ret = self.ret.0.clone();
+                self.jump(&ret);
+            }
+            Stmt::Break(pos) | Stmt::Continue(pos) => {
+                let target = match (statement, self.loops.last()) {
+                    (Stmt::Break(_), Some((end, _))) => end.clone(),
+                    (_, Some((_, next))) => next.clone(),
+                    (_, None) => {
+                        let keyword = if matches!(statement, Stmt::Break(_)) { "break" } else { "continue" };
+                        self.errors.push(error(*pos, format!("`{}` outside of a loop", keyword)));
+                        return;
+                    }
+                };
+                self.comment(*pos);
+                self.jump(&target);
+            }
+            Stmt::Empty => {}
+        }
+    }
+
+    fn loop_body(&mut self, body: &Stmt, end: &str, next: &str) {
+        self.loops.push((end.to_string(), next.to_string()));
+        self.statement(body);
+        self.loops.pop();
+    }
+
+    fn branch_if_zero(&mut self, condition: &Expr, label: &str) {
+        self.expr(condition);
+        self.emit("ADD R0, R0, #0");
+        self.branch("z", label);
+    }
+
+    fn declare(&mut self, d: &Declaration) {
+        let scope = self.scopes.last_mut().expect("a function has a scope");
+        if scope.contains_key(&d.name) {
+            self.errors.push(error(d.pos, format!("`{}` is already declared", d.name)));
+            return;
+        }
+        let size = d.ty.size() as i32;
+        let offset = -(self.frame + size - 1);
+        self.frame += size;
+        scope.insert(
+            d.name.clone(),
+            Local {
+                offset,
+                ty: d.ty.clone(),
+            },
+        );
+        // Elements past the initializer are zero
+        let words: Vec<Option<&Expr>> = match (&d.ty, &d.init) {
+            (_, None) => return,
+            (Type::Array(element, length), Some(init)) if element.size() == 1 => match init {
+                Init::List(list) if list.len() <= *length => {
+                    list.iter().map(Some).chain(std::iter::repeat(None)).take(*length).collect()
+                }
+                Init::Expr(Expr {
+                    kind: ExprKind::Str(s), ..
+                }) if s.chars().count() < *length => {
+                    let chars: Vec<i32> = s.chars().map(|c| c as i32).collect();
+                    for i in 0..*length {
+                        self.load_constant("R0", chars.get(i).copied().unwrap_or(0));
+                        self.store_local(offset + i as i32);
+                    }
+                    return;
+                }
+                Init::List(_) => {
+                    self.errors.push(error(d.pos, format!("too many initializers for `{}`", d.name)));
+                    return;
+                }
+                Init::Expr(e) => {
+                    self.errors.push(error(e.pos, format!("`{}` is initialized with a list or a string that fits", d.name)));
+                    return;
+                }
+            },
+            (Type::Array(..), Some(_)) => {
+                self.errors.push(error(d.pos, "only arrays of int, char or pointers can be initialized".to_string()));
+                return;
+            }
+            (_, Some(Init::Expr(e))) => vec![Some(e)],
+            (_, Some(Init::List(_))) => {
+                self.errors.push(error(d.pos, format!("`{}` is not an array", d.name)));
+                return;
+            }
+        };
+        for (i, word) in words.into_iter().enumerate() {
+            match word {
+                Some(e) => {
+                    self.expr(e);
+                }
+                None => self.emit("AND R0, R0, #0"),
+            }
+            self.store_local(offset + i as i32);
+        }
+    }
+
+    /// `r` = `n`
+    fn load_constant(&mut self, r: &str, n: i32) {
+        if (-16..=15).contains(&n) {
+            self.emit(format!("AND {}, {}, #0", r, r));
+            if n != 0 {
+                self.emit(format!("ADD {}, {}, #{}", r, r, n));
+            }
+        } else {
+            self.emit(format!("LDIMM {}, #{}", r, n as i16));
+        }
+    }
+
+    /// `r` = R5 + `offset`
+    fn frame_address(&mut self, r: &str, offset: i32) {
+        if (-16..=15).contains(&offset) {
+            self.emit(format!("ADD {}, R5, #{}", r, offset));
+        } else {
+            self.emit(format!("LDIMM {}, #{}", r, offset));
+            self.emit(format!("ADD {}, R5, {}", r, r));
+        }
+    }
+
+    fn load_local(&mut self, offset: i32) {
+        if (-32..=31).contains(&offset) {
+            self.emit(format!("LDR R0, R5, #{}", offset));
+        } else {
+            self.frame_address("R0", offset);
+            self.emit("LDR R0, R0, #0");
+        }
+    }
+
+    fn store_local(&mut self, offset: i32) {
+        if (-32..=31).contains(&offset) {
+            self.emit(format!("STR R0, R5, #{}", offset));
+        } else {
+            self.frame_address("R1", offset);
+            self.emit("STR R0, R1, #0");
+        }
+    }
+
+    /// R0 = R0 * `n`, R1 is kept
+    fn scale(&mut self, n: usize) {
+        if n.is_power_of_two() {
+            for _ in 0..n.trailing_zeros() {
+                self.emit("ADD R0, R0, R0");
+            }
+        } else {
+            self.emit("PUSH R1");
+            self.load_constant("R1", n as i32);
+            self.call_library("MULTIPLY");
+            self.emit("POP R1");
+        }
+    }
+
+    fn call_library(&mut self, routine: &str) {
+        self.math |= ["MULTIPLY", "DIVIDE", "MODULO"].contains(&routine);
+        self.print |= routine == "PRINT_DECIMAL";
+        self.emit(format!("LDIMM R2, {}", routine));
+        self.emit("JSRR R2");
+    }
+
+    fn swap(&mut self) {
+        self.emit("MOV R2, R0");
+        self.emit("MOV R0, R1");
+        self.emit("MOV R1, R2");
+    }
+
+    /// Set R1 to a number with the sign of R1 - R0.
+    /// The difference overflows when the top bits of
+    /// the operands differ, so then it is decided by
+    /// which one has it set: that one is the smaller
+    /// int, or the larger pointer.
+    fn order(&mut self, unsigned: bool) {
+        let (negative, same, done) = (self.label(), self.label(), self.label());
+        let (below, above) = if unsigned { ("#1", "#-1") } else { ("#-1", "#1") };
+        self.emit("ADD R1, R1, #0");
+        self.emit(format!("BRn {}", negative));
+        self.emit("ADD R0, R0, #0");
+        self.emit(format!("BRzp {}", same));
+        self.emit("AND R1, R1, #0");
+        self.emit(format!("ADD R1, R1, {}", above));
+        self.jump_near(&done);
+        self.place(&negative);
+        self.emit("ADD R0, R0, #0");
+        self.emit(format!("BRn {}", same));
+        self.emit("AND R1, R1, #0");
+        self.emit(format!("ADD R1, R1, {}", below));
+        self.jump_near(&done);
+        self.place(&same);
+        self.emit("SUB R1, R1, R0");
+        self.place(&done);
+    }
+
+    /// R0 = R1 `op` R0, where R1 has type `left`
+    /// and R0 type `right`
+    fn operate(&mut self, op: &str, left: &Type, right: &Type, pos: Pos) -> Type {
+        let (left, right) = (left.clone().decay(), right.clone().decay());
+        let size = |t: &Type| t.target().map_or(1, |t| t.size().max(1));
+        match (op, &left, &right) {
+            ("+" | "-", Type::Pointer(_), Type::Pointer(_)) if op == "-" => {
+                self.emit("SUB R0, R1, R0");
+                let n = size(&left);
+                if n > 1 {
+                    self.load_constant("R1", n as i32);
+                    self.call_library("DIVIDE");
+                }
+                return Type::Int;
+            }
+            ("+" | "-", Type::Pointer(_), Type::Pointer(_)) => {
+                self.errors.push(error(pos, "two pointers cannot be added".to_string()));
+                return Type::Int;
+            }
+            ("+" | "-", Type::Pointer(_), _) => {
+                self.scale(size(&left));
+                self.emit(if op == "+" { "ADD R0, R1, R0" } else { "SUB R0, R1, R0" });
+                return left;
+            }
+            ("+", _, Type::Pointer(_)) => {
+                self.swap();
+                self.scale(size(&right));
+                self.emit("ADD R0, R1, R0");
+                return right;
+            }
+            ("-", _, Type::Pointer(_)) => {
+                self.errors.push(error(pos, "a pointer cannot be subtracted from a number".to_string()));
+                return Type::Int;
+            }
+            _ => {}
+        }
+        match op {
+            "+" => self.emit("ADD R0, R1, R0"),
+            "-" => self.emit("SUB R0, R1, R0"),
+            "&" => self.emit("AND R0, R1, R0"),
+            "|" => {
+                // ~(~a & ~b)
+                self.emit("NOT R1, R1");
+                self.emit("NOT R0, R0");
+                self.emit("AND R0, R1, R0");
+                self.emit("NOT R0, R0");
+            }
+            "^" => {
+                // (a | b) & ~(a & b)
+                self.emit("AND R2, R1, R0");
+                self.emit("NOT R2, R2");
+                self.emit("NOT R1, R1");
+                self.emit("NOT R0, R0");
+                self.emit("AND R0, R1, R0");
+                self.emit("NOT R0, R0");
+                self.emit("AND R0, R0, R2");
+            }
+            "*" => self.call_library("MULTIPLY"),
+            "/" | "%" => {
+                self.swap();
+                self.call_library(if op == "/" { "DIVIDE" } else { "MODULO" });
+            }
+            _ => {
+                // A comparison: the flags of R1 - R0
+                if matches!(op, "==" | "!=") {
+                    self.emit("SUB R1, R1, R0");
+                } else {
+                    let unsigned = matches!(left, Type::Pointer(_)) || matches!(right, Type::Pointer(_));
+                    self.order(unsigned);
+                }
+                let skip = match op {
+                    "==" => "BRnp",
+                    "!=" => "BRz",
+                    "<" => "BRzp",
+                    "<=" => "BRp",
+                    ">" => "BRnz",
+                    _ => "BRn",
+                };
+                self.emit("AND R0, R0, #0");
+                self.emit("ADD R1, R1, #0");
+                self.emit(format!("{} #1", skip));
+                self.emit("ADD R0, R0, #1");
+            }
+        }
+        Type::Int
+    }
+
+    /// The address of `e` in R0, and the type of
+    /// what is there
+    fn address(&mut self, e: &Expr) -> Type {
+        match &e.kind {
+            ExprKind::Name(name) => {
+                if let Some(local) = self.lookup(name) {
+                    self.frame_address("R0", local.offset);
+                    local.ty
+                } else if let Some(ty) = self.globals.get(name).cloned() {
+                    self.emit(format!("LDIMM R0, G_{}", name));
+                    ty
+                } else {
+                    self.undefined(name, e.pos)
+                }
+            }
+            ExprKind::Unary("*", pointer) => {
+                let ty = self.expr(pointer).decay();
+                match ty.target() {
+                    Some(Type::Void) | None => {
+                        self.errors.push(error(e.pos, format!("cannot dereference a value of type {}", ty)));
+                        Type::Int
+                    }
+                    Some(target) => target.clone(),
+                }
+            }
+            ExprKind::Index(base, index) => {
+                let ty = self.expr(base).decay();
+                let element = match ty.target() {
+                    Some(Type::Void) | None => {
+                        self.errors.push(error(e.pos, format!("cannot index a value of type {}", ty)));
+                        Type::Int
+                    }
+                    Some(target) => target.clone(),
+                };
+                self.emit("PUSH R0");
+                self.expr(index);
+                self.scale(element.size().max(1));
+                self.emit("POP R1");
+                self.emit("ADD R0, R1, R0");
+                element
+            }
+            _ => {
+                self.errors.push(error(e.pos, "this expression has no address".to_string()));
+                Type::Int
+            }
+        }
+    }
+
+    fn undefined(&mut self, name: &str, pos: Pos) -> Type {
+        let message = if self.functions.contains_key(name) {
+            format!("`{}` is a function, it can only be called", name)
+        } else {
+            format!("undefined variable `{}`", name)
+        };
+        self.errors.push(error(pos, message));
+        Type::Int
+    }
+
+    /// Load what is at the address in R0, unless
+    /// it is an array which is used as its address
+    fn load(&mut self, ty: Type) -> Type {
+        if !matches!(ty, Type::Array(..)) {
+            self.emit("LDR R0, R0, #0");
+        }
+        ty
+    }
+
+    /// The value of `e` in R0, and its type
+    fn expr(&mut self, e: &Expr) -> Type {
+        // Long expressions need a pool of their own
+        self.pool();
+        match &e.kind {
+            ExprKind::Number(n) => {
+                self.load_constant("R0", *n);
+                Type::Int
+            }
+            ExprKind::Str(s) => {
+                let label = self.string(s);
+                self.emit(format!("LDIMM R0, {}", label));
+                Type::Array(Box::new(Type::Char), s.chars().count() + 1)
+            }
+            ExprKind::Name(name) => match self.lookup(name) {
+                Some(Local { ty: ty @ Type::Array(..), offset }) => {
+                    self.frame_address("R0", offset);
+                    ty
+                }
+                Some(local) => {
+                    self.load_local(local.offset);
+                    local.ty
+                }
+                None => {
+                    let ty = self.address(e);
+                    self.load(ty)
+                }
+            },
+            ExprKind::Unary(op @ ("*" | "&"), operand) => {
+                let ty = self.address(if *op == "*" { e } else { operand });
+                if *op == "&" {
+                    return Type::Pointer(Box::new(ty));
+                }
+                self.load(ty)
+            }
+            ExprKind::Index(..) => {
+                let ty = self.address(e);
+                self.load(ty)
+            }
+            ExprKind::Unary("-", operand) => {
+                self.expr(operand);
+                self.emit("NOT R0, R0");
+                self.emit("ADD R0, R0, #1");
+                Type::Int
+            }
+            ExprKind::Unary("~", operand) => {
+                self.expr(operand);
+                self.emit("NOT R0, R0");
+                Type::Int
+            }
+            ExprKind::Unary("!", operand) => {
+                self.expr(operand);
+                self.emit("ADD R0, R0, #0");
+                self.emit("BRnp #2");
+                self.emit("ADD R0, R0, #1");
+                self.emit("BR #1");
+                self.emit("AND R0, R0, #0");
+                Type::Int
+            }
+            ExprKind::Unary(op, operand) | ExprKind::Postfix(op, operand) => {
+                // ++ and --
+                let ty = self.address(operand);
+                self.assignable(&ty, operand.pos);
+                let step = ty.target().map_or(1, |t| t.size().max(1)) as i32;
+                let step = if *op == "++" { step } else { -step };
+                self.emit("MOV R1, R0");
+                self.emit("LDR R0, R1, #0");
+                self.add_constant(step);
+                self.emit("STR R0, R1, #0");
+                if matches!(e.kind, ExprKind::Postfix(..)) {
+                    self.add_constant(-step);
+                }
+                ty
+            }
+            ExprKind::Binary(op @ ("&&" | "||"), left, right) => {
+                let (short, end) = (self.label(), self.label());
+                let nzp = if *op == "&&" { "z" } else { "np" };
+                for operand in [left, right] {
+                    self.expr(operand);
+                    self.emit("ADD R0, R0, #0");
+                    self.branch(nzp, &short);
+                }
+                // The value when both operands were tested
+                let (tested, shorted) = if *op == "&&" { (1, 0) } else { (0, 1) };
+                self.load_constant("R0", tested);
+                self.jump_near(&end);
+                self.place(&short);
+                self.load_constant("R0", shorted);
+                self.place(&end);
+                Type::Int
+            }
+            ExprKind::Binary(op, left, right) => {
+                let left_type = self.expr(left);
+                self.emit("PUSH R0");
+                let right_type = self.expr(right);
+                self.emit("POP R1");
+                self.operate(op, &left_type, &right_type, e.pos)
+            }
+            ExprKind::Assign(op, target, value) => {
+                if let Some(op) = op.strip_suffix('=').filter(|op| !op.is_empty()) {
+                    let ty = self.address(target);
+                    self.assignable(&ty, target.pos);
+                    self.emit("PUSH R0");
+                    self.emit("LDR R0, R0, #0");
+                    self.emit("PUSH R0");
+                    let value_type = self.expr(value);
+                    self.emit("POP R1");
+                    self.operate(op, &ty, &value_type, e.pos);
+                    self.emit("POP R1");
+                    self.emit("STR R0, R1, #0");
+                    return ty;
+                }
+                if let ExprKind::Name(name) = &target.kind {
+                    if let Some(local) = self.lookup(name) {
+                        self.assignable(&local.ty, target.pos);
+                        self.expr(value);
+                        self.store_local(local.offset);
+                        return local.ty;
+                    }
+                }
+                let ty = self.address(target);
+                self.assignable(&ty, target.pos);
+                self.emit("PUSH R0");
+                self.expr(value);
+                self.emit("POP R1");
+                self.emit("STR R0, R1, #0");
+                ty
+            }
+            ExprKind::Call(name, args) => self.call(name, args, e.pos),
+            ExprKind::Conditional(condition, then, otherwise) => {
+                let (other, end) = (self.label(), self.label());
+                self.branch_if_zero(condition, &other);
+                let ty = self.expr(then);
+                self.jump(&end);
+                self.place(&other);
+                self.expr(otherwise);
+                self.place(&end);
+                ty
+            }
+            ExprKind::SizeofType(ty) => {
+                self.load_constant("R0", ty.size() as i32);
+                Type::Int
+            }
+            ExprKind::SizeofExpr(operand) => {
+                let ty = self.type_of(operand);
+                self.load_constant("R0", ty.size() as i32);
+                Type::Int
+            }
+        }
+    }
+
+    /// R0 += `n`, R1 is kept
+    fn add_constant(&mut self, n: i32) {
+        if (-16..=15).contains(&n) {
+            self.emit(format!("ADD R0, R0, #{}", n));
+        } else {
+            self.load_constant("R2", n);
+            self.emit("ADD R0, R0, R2");
+        }
+    }
+
+    /// Only scalars can be assigned
+    fn assignable(&mut self, ty: &Type, pos: Pos) {
+        if matches!(ty, Type::Array(..)) {
+            self.errors.push(error(pos, "cannot assign to an array".to_string()));
+        }
+    }
+
+    fn call(&mut self, name: &str, args: &[Expr], pos: Pos) -> Type {
+        let (params, ret, defined) = match self.functions.get(name) {
+            Some(f) => (f.params, f.ret.clone(), f.defined),
+            None => match BUILTINS.iter().find(|(builtin, _)| *builtin == name) {
+                Some(&(_, params)) => {
+                    if args.len() != params {
+                        let message = format!("`{}` expects {} argument(s), found {}", name, params, args.len());
+                        self.errors.push(error(pos, message));
+                        return Type::Int;
+                    }
+                    return self.builtin(name, args);
+                }
+                None => {
+                    let message = if self.lookup(name).is_some() || self.globals.contains_key(name) {
+                        format!("`{}` is a variable, not a function", name)
+                    } else {
+                        format!("undefined function `{}`", name)
+                    };
+                    self.errors.push(error(pos, message));
+                    return Type::Int;
+                }
+            },
+        };
+        if args.len() != params {
+            self.errors.push(error(pos, format!("`{}` expects {} argument(s), found {}", name, params, args.len())));
+        }
+        if !defined {
+            self.errors.push(error(pos, format!("`{}` is declared but never defined", name)));
+        }
+        // The first argument ends up on top
+        for arg in args.iter().rev() {
+            self.expr(arg);
+            self.emit("PUSH R0");
+        }
+        self.emit(format!("LDIMM R2, F_{}", name));
+        self.emit("JSRR R2");
+        self.emit("LDR R0, R6, #0");
+        for line in stack_adjust(args.len() as i32 + 1) {
+            self.emit(line);
+        }
+        ret
+    }
+
+    fn builtin(&mut self, name: &str, args: &[Expr]) -> Type {
+        if let Some(arg) = args.first() {
+            self.expr(arg);
+        }
+        match name {
+            "putchar" => self.emit("OUT"),
+            "getchar" => self.emit("GETC"),
+            "print_string" => self.emit("PUTS"),
+            "puts" => {
+                self.emit("PUTS");
+                self.load_constant("R0", '\n' as i32);
+                self.emit("OUT");
+            }
+            _ => self.call_library("PRINT_DECIMAL"),
+        }
+        Type::Int
+    }
+}
+
+/// R6 += `n`
+fn stack_adjust(n: i32) -> Vec<String> {
+    let mut lines = Vec::new();
+    if n.abs() > 64 {
+        lines.push(format!("LDIMM R1, #{}", n));
+        lines.push("ADD R6, R6, R1".to_string());
+        return lines;
+    }
+    let mut left = n;
+    while left != 0 {
+        let step = left.clamp(-16, 15);
+        lines.push(format!("ADD R6, R6, #{}", step));
+        left -= step;
+    }
+    lines
+}
+
+/// A string in `length` words: `.STRINGZ` when it
+/// can be written so, then zeros
+fn string_data(s: &str, length: usize) -> Vec<String> {
+    let printable = s.chars().all(|c| (' '..='~').contains(&c) || c == '\n' || c == '\t');
+    let mut lines = Vec::new();
+    if printable {
+        let escaped: String = s
+            .chars()
+            .map(|c| match c {
+                '\n' => "\\n".to_string(),
+                '\t' => "\\t".to_string(),
+                '"' | '\\' => format!("\\{}", c),
+                c => c.to_string(),
+            })
+            .collect();
+        lines.push(format!("        .STRINGZ \"{}\"", escaped));
+    } else {
+        lines.extend(s.chars().map(|c| format!("        .FILL #{}", c as u32 as u16 as i16)));
+        lines.push("        .FILL #0".to_string());
+    }
+    let rest = length - s.chars().count() - 1;
+    if rest > 0 {
+        lines.push(format!("        .BLKW #{}", rest));
+    }
+    lines
+}
@@ -0,0 +1,137 @@
+//! Split C source in tokens.
+use super::CompileError;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(super) enum Tok {
+    Number(i32),
+    Str(String),
+    Ident(String),
+    Punct(&'static str),
+    Eof,
+}
+
+/// A token and where it starts, line and column from 1
+#[derive(Clone, Debug)]
+pub(super) struct Token {
+    pub tok: Tok,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Longest first, so `<=` is not read as `<` `=`
+const PUNCTUATION: [&str; 46] = [
+    "<<=", ">>=", "==", "!=", "<=", ">=", "&&", "||", "++", "--", "+=", "-=", "*=", "/=", "%=", "&=", "|=",
+    "^=", "<<", ">>", "->", "+", "-", "*", "/", "%", "&", "|", "^", "~", "!", "=", "<", ">", "(", ")", "[",
+    "]", "{", "}", ",", ";", "?", ":", ".", "#",
+];
+
+pub(super) fn tokenize(source: &str) -> Result<Vec<Token>, CompileError> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let (mut i, mut line, mut column) = (0, 1, 1);
+    // Move past `n` characters
+    let advance = |i: &mut usize, line: &mut usize, column: &mut usize, n: usize| {
+        for _ in 0..n {
+            if chars.get(*i) == Some(&'\n') {
+                *line += 1;
+                *column = 1;
+            } else {
+                *column += 1;
+            }
+            *i += 1;
+        }
+    };
+    while i < chars.len() {
+        let c = chars[i];
+        let rest: String = chars[i..chars.len().min(i + 3)].iter().collect();
+        let error = |message: String| CompileError {
+            line,
+            column,
+            message,
+        };
+        if c.is_whitespace() {
+            advance(&mut i, &mut line, &mut column, 1);
+            continue;
+        }
+        if rest.starts_with("//") {
+            let n = chars[i..].iter().position(|&c| c == '\n').unwrap_or(chars.len() - i);
+            advance(&mut i, &mut line, &mut column, n);
+            continue;
+        }
+        if rest.starts_with("/*") {
+            let end = (i + 2..chars.len().saturating_sub(1)).find(|&j| chars[j] == '*' && chars[j + 1] == '/');
+            let end = end.ok_or_else(|| error("unterminated comment".to_string()))?;
+            let n = end + 2 - i;
+            advance(&mut i, &mut line, &mut column, n);
+            continue;
+        }
+
+        let (tok, length) = if c.is_ascii_digit() {
+            let n = chars[i..].iter().take_while(|c| c.is_ascii_alphanumeric()).count();
+            let text: String = chars[i..i + n].iter().collect();
+            let value = match text.strip_prefix("0x").or(text.strip_prefix("0X")) {
+                Some(hex) => i64::from_str_radix(hex, 16),
+                None => text.parse::<i64>(),
+            };
+            match value {
+                Ok(value) if value <= 0xFFFF => (Tok::Number(value as i32), n),
+                Ok(_) => return Err(error(format!("{} does not fit in 16 bits", text))),
+                Err(_) => return Err(error(format!("invalid number `{}`", text))),
+            }
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let n = chars[i..].iter().take_while(|c| c.is_ascii_alphanumeric() || **c == '_').count();
+            (Tok::Ident(chars[i..i + n].iter().collect()), n)
+        } else if c == '\'' || c == '"' {
+            // A character or a string, with escapes
+            let mut text = String::new();
+            let mut j = i + 1;
+            loop {
+                match chars.get(j) {
+                    None | Some('\n') => {
+                        return Err(error(format!("unterminated {}", if c == '"' { "string" } else { "character" })))
+                    }
+                    Some(&end) if end == c => break,
+                    Some('\\') => {
+                        let escaped = chars.get(j + 1).copied().unwrap_or('\\');
+                        text.push(match escaped {
+                            'n' => '\n',
+                            't' => '\t',
+                            'r' => '\r',
+                            '0' => '\0',
+                            'e' => '\x1b',
+                            '\\' | '\'' | '"' => escaped,
+                            _ => return Err(error(format!("unknown escape `\\{}`", escaped))),
+                        });
+                        j += 2;
+                    }
+                    Some(&other) => {
+                        text.push(other);
+                        j += 1;
+                    }
+                }
+            }
+            if c == '"' {
+                (Tok::Str(text), j + 1 - i)
+            } else {
+                let mut chars = text.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => (Tok::Number(c as i32), j + 1 - i),
+                    _ => return Err(error("a character literal holds one character".to_string())),
+                }
+            }
+        } else {
+            match PUNCTUATION.iter().find(|p| rest.starts_with(*p)) {
+                Some(p) => (Tok::Punct(p), p.len()),
+                None => return Err(error(format!("unexpected character `{}`", c))),
+            }
+        };
+        tokens.push(Token { tok, line, column });
+        advance(&mut i, &mut line, &mut column, length);
+    }
+    tokens.push(Token {
+        tok: Tok::Eof,
+        line,
+        column,
+    });
+    Ok(tokens)
+}
@@ -0,0 +1,162 @@
+//! A compiler for a small subset of C, which
+//! writes assembly for the extended assembler.
+//!
+//! The language has `int` and `char` (both one
+//! word), pointers, arrays, functions with
+//! recursion, globals, `if`, `while`, `do`, `for`,
+//! `break`, `continue` and the C operators except
+//! shifts. There is no preprocessor and no struct.
+//! `putchar`, `getchar`, `puts`, `print_string`
+//! and `print_int` are built in.
+//!
+//! Calling convention: R6 is the stack pointer and
+//! R5 the frame pointer. The caller pushes the
+//! arguments from the last to the first and calls
+//! with JSRR. The callee pushes a slot for the
+//! return value, R7 and R5, points R5 at its first
+//! local and reserves its locals below:
+//!
+//! ```text
+//! R5+4...  arguments, the first one at R5+4
+//! R5+3     return value
+//! R5+2     return address (R7)
+//! R5+1     frame pointer of the caller (R5)
+//! R5-0...  locals
+//! ```
+//!
+//! On return R6 points at the return value, which
+//! the caller reads before popping it and the
+//! arguments. R0 to R2 are not preserved.
+use crate::assembler::{self, Options, Program};
+use std::fmt;
+
+mod codegen;
+mod lexer;
+mod parser;
+
+/// First line of the compiled assembly, the runner
+/// recognizes it to assemble with the extensions
+pub const HEADER: &str = "; Compiled from C, assemble with --extended";
+
+/// An error in the C source, `line` and `column`
+/// start at 1
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompileError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: error: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+impl CompileError {
+    /// The message followed by the source line, with
+    /// the column marked
+    pub fn render(&self, path: &str, source: &str) -> String {
+        let mut out = format!("{}:{}\n", path, self);
+        if let Some(text) = source.lines().nth(self.line.wrapping_sub(1)) {
+            let number = self.line.to_string();
+            let margin = " ".repeat(number.len());
+            let indent: String = text
+                .chars()
+                .take(self.column - 1)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            out += &format!("{} |\n{} | {}\n{} | {}^\n", margin, number, text, margin, indent);
+        }
+        out
+    }
+}
+
+/// Compile C source to assembly. Compiling stops
+/// at the first syntax error, other errors are
+/// all reported.
+pub fn compile(source: &str) -> Result<String, Vec<CompileError>> {
+    let tokens = lexer::tokenize(source).map_err(|e| vec![e])?;
+    let items = parser::parse(tokens).map_err(|e| vec![e])?;
+    codegen::generate(&items, source)
+}
+
+/// Compile and assemble `source`, the text of the
+/// file at `path`, errors are rendered
+pub fn build(path: &str, source: &str) -> Result<Program, String> {
+    let render = |errors: Vec<String>| errors.concat().trim_end().to_string();
+    let assembly = compile(source).map_err(|errors| render(errors.iter().map(|e| e.render(path, source)).collect()))?;
+    // Only limits of the assembler can fail here,
+    // like a branch farther than 256 words
+    let options = Options { extended: true };
+    assembler::assemble_with(&assembly, None, options).map_err(|errors| {
+        let name = format!("{} (compiled)", path);
+        render(errors.iter().map(|e| e.render(&name, &assembly)).collect())
+    })
+}
+
+const USAGE: &str = "Usage: cargo run compile [-o <file.asm>] <program.c>";
+
+/// Entry point of `cargo run compile ...`, returns the exit code
+pub fn main(args: Vec<String>) -> i32 {
+    let mut source = None;
+    let mut output = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => match args.next() {
+                Some(path) => output = Some(path),
+                None => {
+                    eprintln!("Missing value for {}\n{}", arg, USAGE);
+                    return 1;
+                }
+            },
+            _ if arg.starts_with('-') => {
+                eprintln!("Unknown argument `{}`\n{}", arg, USAGE);
+                return 1;
+            }
+            _ if source.is_some() => {
+                eprintln!("Unexpected argument `{}`\n{}", arg, USAGE);
+                return 1;
+            }
+            _ => source = Some(arg),
+        }
+    }
+    let source = match source {
+        Some(source) => source,
+        None => {
+            eprintln!("{}", USAGE);
+            return 1;
+        }
+    };
+    let output = output.unwrap_or_else(|| {
+        std::path::Path::new(&source)
+            .with_extension("asm")
+            .to_string_lossy()
+            .into_owned()
+    });
+
+    let text = match std::fs::read_to_string(&source) {
+        Ok(text) => text,
+        Err(e) => {
+            eprintln!("{}: {}", source, e);
+            return 1;
+        }
+    };
+    let assembly = match compile(&text) {
+        Ok(assembly) => assembly,
+        Err(errors) => {
+            for e in errors {
+                eprint!("{}", e.render(&source, &text));
+            }
+            return 1;
+        }
+    };
+    if let Err(e) = std::fs::write(&output, assembly) {
+        eprintln!("{}: {}", output, e);
+        return 1;
+    }
+    0
+}
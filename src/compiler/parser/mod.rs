@@ -0,0 +1,711 @@
+//! Parse tokens into a syntax tree.
+use super::lexer::{Tok, Token};
+use super::CompileError;
+
+/// Every type is a whole number of words, `char`
+/// takes a word like `int`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(super) enum Type {
+    Void,
+    Int,
+    Char,
+    Pointer(Box<Type>),
+    Array(Box<Type>, usize),
+}
+
+impl Type {
+    /// Size in words
+    pub fn size(&self) -> usize {
+        match self {
+            Type::Void => 0,
+            Type::Int | Type::Char | Type::Pointer(_) => 1,
+            Type::Array(element, n) => element.size() * n,
+        }
+    }
+
+    /// What a pointer or an array points to
+    pub fn target(&self) -> Option<&Type> {
+        match self {
+            Type::Pointer(t) | Type::Array(t, _) => Some(t),
+            _ => None,
+        }
+    }
+
+    /// Arrays are used as a pointer to their first element
+    pub fn decay(self) -> Type {
+        match self {
+            Type::Array(t, _) => Type::Pointer(t),
+            t => t,
+        }
+    }
+}
+
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Type::Void => write!(f, "void"),
+            Type::Int => write!(f, "int"),
+            Type::Char => write!(f, "char"),
+            Type::Pointer(t) => write!(f, "{}*", t),
+            Type::Array(t, n) => write!(f, "{}[{}]", t, n),
+        }
+    }
+}
+
+/// Line and column
+pub(super) type Pos = (usize, usize);
+
+#[derive(Clone, Debug)]
+pub(super) struct Expr {
+    pub kind: ExprKind,
+    pub pos: Pos,
+}
+
+#[derive(Clone, Debug)]
+pub(super) enum ExprKind {
+    Number(i32),
+    Str(String),
+    Name(String),
+    /// `-`, `!`, `~`, `*`, `&`, prefix `++` and `--`
+    Unary(&'static str, Box<Expr>),
+    /// Postfix `++` and `--`
+    Postfix(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    /// `=` or a compound assignment like `+=`
+    Assign(&'static str, Box<Expr>, Box<Expr>),
+    Index(Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+    Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
+    SizeofType(Type),
+    SizeofExpr(Box<Expr>),
+}
+
+#[derive(Clone, Debug)]
+pub(super) enum Init {
+    Expr(Expr),
+    List(Vec<Expr>),
+}
+
+#[derive(Clone, Debug)]
+pub(super) struct Declaration {
+    pub name: String,
+    pub ty: Type,
+    pub init: Option<Init>,
+    pub pos: Pos,
+}
+
+#[derive(Clone, Debug)]
+pub(super) enum Stmt {
+    Expr(Expr),
+    Declaration(Declaration),
+    Block(Vec<Stmt>),
+    If(Expr, Box<Stmt>, Option<Box<Stmt>>),
+    While(Expr, Box<Stmt>),
+    DoWhile(Box<Stmt>, Expr),
+    /// The declarations of the first part are
+    /// local to the loop
+    For(Vec<Stmt>, Option<Expr>, Option<Expr>, Box<Stmt>),
+    Return(Option<Expr>, Pos),
+    Break(Pos),
+    Continue(Pos),
+    Empty,
+}
+
+#[derive(Clone, Debug)]
+pub(super) struct Function {
+    pub name: String,
+    pub ret: Type,
+    pub params: Vec<(String, Type)>,
+    /// None for a prototype
+    pub body: Option<Vec<Stmt>>,
+    pub pos: Pos,
+}
+
+#[derive(Clone, Debug)]
+pub(super) enum Item {
+    Global(Declaration),
+    Function(Function),
+}
+
+/// From the lowest to the highest precedence
+const BINARY: [&[&str]; 8] = [
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", "<=", ">", ">="],
+    &["+", "-"],
+];
+
+const ASSIGNMENTS: [&str; 9] = ["=", "+=", "-=", "*=", "/=", "%=", "&=", "|=", "^="];
+
+pub(super) fn parse(tokens: Vec<Token>) -> Result<Vec<Item>, CompileError> {
+    let mut parser = Parser { tokens, pos: 0 };
+    let mut items = Vec::new();
+    while parser.peek() != &Tok::Eof {
+        items.extend(parser.item()?);
+    }
+    Ok(items)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Tok {
+        &self.tokens[self.pos].tok
+    }
+
+    fn here(&self) -> Pos {
+        (self.tokens[self.pos].line, self.tokens[self.pos].column)
+    }
+
+    fn next(&mut self) -> Tok {
+        let tok = self.tokens[self.pos].tok.clone();
+        if tok != Tok::Eof {
+            self.pos += 1;
+        }
+        tok
+    }
+
+    fn error<T>(&self, message: String) -> Result<T, CompileError> {
+        let (line, column) = self.here();
+        Err(CompileError { line, column, message })
+    }
+
+    fn describe(&self) -> String {
+        match self.peek() {
+            Tok::Number(n) => format!("`{}`", n),
+            Tok::Str(_) => "a string".to_string(),
+            Tok::Ident(name) => format!("`{}`", name),
+            Tok::Punct(p) => format!("`{}`", p),
+            Tok::Eof => "the end of the file".to_string(),
+        }
+    }
+
+    fn is(&self, punct: &str) -> bool {
+        matches!(self.peek(), Tok::Punct(p) if *p == punct)
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Tok::Ident(name) if name == keyword)
+    }
+
+    /// Skip `punct` if it is next
+    fn eat(&mut self, punct: &str) -> bool {
+        let found = self.is(punct);
+        if found {
+            self.next();
+        }
+        found
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<(), CompileError> {
+        if self.eat(punct) {
+            Ok(())
+        } else {
+            self.error(format!("expected `{}`, found {}", punct, self.describe()))
+        }
+    }
+
+    fn name(&mut self) -> Result<String, CompileError> {
+        match self.peek() {
+            Tok::Ident(name) if !is_keyword(name) => {
+                let name = name.clone();
+                self.next();
+                Ok(name)
+            }
+            _ => self.error(format!("expected a name, found {}", self.describe())),
+        }
+    }
+
+    fn is_type(&self) -> bool {
+        ["int", "char", "void"].iter().any(|t| self.is_keyword(t))
+    }
+
+    fn base_type(&mut self) -> Result<Type, CompileError> {
+        let ty = match self.peek() {
+            Tok::Ident(name) if name == "int" => Type::Int,
+            Tok::Ident(name) if name == "char" => Type::Char,
+            Tok::Ident(name) if name == "void" => Type::Void,
+            Tok::Ident(name) if ["unsigned", "long", "short", "float", "double", "struct"].contains(&name.as_str()) => {
+                return self.error(format!("`{}` is not supported, only int, char and void", name))
+            }
+            _ => return self.error(format!("expected a type, found {}", self.describe())),
+        };
+        self.next();
+        Ok(ty)
+    }
+
+    /// Stars, a name and array sizes: `*p`, `a[10]`
+    fn declarator(&mut self, mut ty: Type) -> Result<(String, Type, Pos), CompileError> {
+        while self.eat("*") {
+            ty = Type::Pointer(Box::new(ty));
+        }
+        let pos = self.here();
+        let name = self.name()?;
+        let mut sizes = Vec::new();
+        while self.eat("[") {
+            if self.eat("]") {
+                // Sized by the initializer
+                sizes.push(0);
+                continue;
+            }
+            let size = self.expr()?;
+            match constant(&size) {
+                Some(n) if n > 0 => sizes.push(n as usize),
+                _ => return Err(at(size.pos, "array sizes must be positive constants".to_string())),
+            }
+            self.expect("]")?;
+        }
+        for size in sizes.into_iter().rev() {
+            ty = Type::Array(Box::new(ty), size);
+        }
+        Ok((name, ty, pos))
+    }
+
+    /// A function, or global variables
+    fn item(&mut self) -> Result<Vec<Item>, CompileError> {
+        if self.is("#") {
+            return self.error("preprocessor directives are not supported".to_string());
+        }
+        let base = self.base_type()?;
+        let (name, ty, pos) = self.declarator(base.clone())?;
+        if !self.eat("(") {
+            let mut items = vec![Item::Global(self.declaration_rest(name, ty, pos)?)];
+            while self.eat(",") {
+                let (name, ty, pos) = self.declarator(base.clone())?;
+                items.push(Item::Global(self.declaration_rest(name, ty, pos)?));
+            }
+            self.expect(";")?;
+            return Ok(items);
+        }
+
+        let mut params = Vec::new();
+        if self.is_keyword("void") && self.tokens[self.pos + 1].tok == Tok::Punct(")") {
+            self.next();
+        }
+        if !self.is(")") {
+            loop {
+                let base = self.base_type()?;
+                let (name, ty, _) = self.declarator(base)?;
+                params.push((name, ty.decay()));
+                if !self.eat(",") {
+                    break;
+                }
+            }
+        }
+        self.expect(")")?;
+        let body = if self.eat(";") { None } else { Some(self.block()?) };
+        Ok(vec![Item::Function(Function {
+            name,
+            ret: ty,
+            params,
+            body,
+            pos,
+        })])
+    }
+
+    /// The initializer of a declaration, if any
+    fn declaration_rest(&mut self, name: String, mut ty: Type, pos: Pos) -> Result<Declaration, CompileError> {
+        if ty == Type::Void {
+            return Err(at(pos, format!("`{}` cannot be void", name)));
+        }
+        let init = if self.eat("=") {
+            Some(if self.eat("{") {
+                let mut list = Vec::new();
+                while !self.eat("}") {
+                    list.push(self.assignment()?);
+                    if !self.eat(",") {
+                        self.expect("}")?;
+                        break;
+                    }
+                }
+                Init::List(list)
+            } else {
+                Init::Expr(self.assignment()?)
+            })
+        } else {
+            None
+        };
+        // `[]` takes the length of the initializer
+        if let Type::Array(element, 0) = &ty {
+            let length = match &init {
+                Some(Init::List(list)) => list.len(),
+                Some(Init::Expr(Expr {
+                    kind: ExprKind::Str(s), ..
+                })) => s.chars().count() + 1,
+                _ => return Err(at(pos, format!("`{}` needs a size or an initializer", name))),
+            };
+            ty = Type::Array(element.clone(), length);
+        }
+        if matches!(&ty, Type::Array(element, _) if element.size() == 0) {
+            return Err(at(pos, format!("`{}` needs a size", name)));
+        }
+        Ok(Declaration { name, ty, init, pos })
+    }
+
+    fn block(&mut self) -> Result<Vec<Stmt>, CompileError> {
+        self.expect("{")?;
+        let mut statements = Vec::new();
+        while !self.eat("}") {
+            if self.peek() == &Tok::Eof {
+                return self.error("expected `}`, found the end of the file".to_string());
+            }
+            statements.extend(self.statement()?);
+        }
+        Ok(statements)
+    }
+
+    /// Local declarations, `int a, b;`, give several statements
+    fn declarations(&mut self) -> Result<Vec<Stmt>, CompileError> {
+        let base = self.base_type()?;
+        let mut statements = Vec::new();
+        loop {
+            let (name, ty, pos) = self.declarator(base.clone())?;
+            statements.push(Stmt::Declaration(self.declaration_rest(name, ty, pos)?));
+            if !self.eat(",") {
+                break;
+            }
+        }
+        self.expect(";")?;
+        Ok(statements)
+    }
+
+    fn statement(&mut self) -> Result<Vec<Stmt>, CompileError> {
+        if self.is_type() {
+            return self.declarations();
+        }
+        Ok(vec![self.single()?])
+    }
+
+    /// A statement where a declaration is not allowed
+    fn single(&mut self) -> Result<Stmt, CompileError> {
+        let pos = self.here();
+        let keyword = match self.peek() {
+            Tok::Ident(name) if is_keyword(name) => name.clone(),
+            Tok::Punct("{") => return Ok(Stmt::Block(self.block()?)),
+            Tok::Punct(";") => {
+                self.next();
+                return Ok(Stmt::Empty);
+            }
+            _ => {
+                let e = self.expr()?;
+                self.expect(";")?;
+                return Ok(Stmt::Expr(e));
+            }
+        };
+        self.next();
+        let body = |parser: &mut Parser| -> Result<Box<Stmt>, CompileError> {
+            match parser.statement()?.as_slice() {
+                [single] => Ok(Box::new(single.clone())),
+                several => Ok(Box::new(Stmt::Block(several.to_vec()))),
+            }
+        };
+        Ok(match keyword.as_str() {
+            "if" => {
+                let condition = self.condition()?;
+                let then = body(self)?;
+                let otherwise = if self.is_keyword("else") {
+                    self.next();
+                    Some(body(self)?)
+                } else {
+                    None
+                };
+                Stmt::If(condition, then, otherwise)
+            }
+            "while" => {
+                let condition = self.condition()?;
+                Stmt::While(condition, body(self)?)
+            }
+            "do" => {
+                let statement = body(self)?;
+                if !self.is_keyword("while") {
+                    return self.error(format!("expected `while`, found {}", self.describe()));
+                }
+                self.next();
+                let condition = self.condition()?;
+                self.expect(";")?;
+                Stmt::DoWhile(statement, condition)
+            }
+            "for" => {
+                self.expect("(")?;
+                let init = if self.eat(";") {
+                    Vec::new()
+                } else if self.is_type() {
+                    self.declarations()?
+                } else {
+                    let e = self.expr()?;
+                    self.expect(";")?;
+                    vec![Stmt::Expr(e)]
+                };
+                let condition = if self.is(";") { None } else { Some(self.expr()?) };
+                self.expect(";")?;
+                let step = if self.is(")") { None } else { Some(self.expr()?) };
+                self.expect(")")?;
+                Stmt::For(init, condition, step, body(self)?)
+            }
+            "return" => {
+                let value = if self.is(";") { None } else { Some(self.expr()?) };
+                self.expect(";")?;
+                Stmt::Return(value, pos)
+            }
+            "break" | "continue" => {
+                self.expect(";")?;
+                if keyword == "break" {
+                    Stmt::Break(pos)
+                } else {
+                    Stmt::Continue(pos)
+                }
+            }
+            other => return Err(at(pos, format!("unexpected `{}`", other))),
+        })
+    }
+
+    fn condition(&mut self) -> Result<Expr, CompileError> {
+        self.expect("(")?;
+        let e = self.expr()?;
+        self.expect(")")?;
+        Ok(e)
+    }
+
+    /// Expressions separated by commas are not
+    /// supported, so this is an assignment
+    fn expr(&mut self) -> Result<Expr, CompileError> {
+        self.assignment()
+    }
+
+    fn assignment(&mut self) -> Result<Expr, CompileError> {
+        let left = self.conditional()?;
+        if let Tok::Punct(op) = *self.peek() {
+            if ASSIGNMENTS.contains(&op) {
+                let pos = self.here();
+                self.next();
+                let right = self.assignment()?;
+                return Ok(Expr {
+                    kind: ExprKind::Assign(op, Box::new(left), Box::new(right)),
+                    pos,
+                });
+            }
+            if op == "<<=" || op == ">>=" {
+                return self.error(format!("`{}` is not supported", op));
+            }
+        }
+        Ok(left)
+    }
+
+    fn conditional(&mut self) -> Result<Expr, CompileError> {
+        let condition = self.binary(0)?;
+        if !self.is("?") {
+            return Ok(condition);
+        }
+        let pos = self.here();
+        self.next();
+        let then = self.expr()?;
+        self.expect(":")?;
+        let otherwise = self.conditional()?;
+        Ok(Expr {
+            kind: ExprKind::Conditional(Box::new(condition), Box::new(then), Box::new(otherwise)),
+            pos,
+        })
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, CompileError> {
+        if level == BINARY.len() {
+            return self.multiplicative();
+        }
+        let mut left = self.binary(level + 1)?;
+        while let Tok::Punct(op) = *self.peek() {
+            if !BINARY[level].contains(&op) {
+                break;
+            }
+            let pos = self.here();
+            self.next();
+            let right = self.binary(level + 1)?;
+            left = Expr {
+                kind: ExprKind::Binary(op, Box::new(left), Box::new(right)),
+                pos,
+            };
+        }
+        if level == BINARY.len() - 2 && (self.is("<<") || self.is(">>")) {
+            return self.error(format!("{} is not supported", self.describe()));
+        }
+        Ok(left)
+    }
+
+    fn multiplicative(&mut self) -> Result<Expr, CompileError> {
+        let mut left = self.unary()?;
+        while let Tok::Punct(op @ ("*" | "/" | "%")) = *self.peek() {
+            let pos = self.here();
+            self.next();
+            let right = self.unary()?;
+            left = Expr {
+                kind: ExprKind::Binary(op, Box::new(left), Box::new(right)),
+                pos,
+            };
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, CompileError> {
+        let pos = self.here();
+        if let Tok::Punct(op @ ("-" | "+" | "!" | "~" | "*" | "&" | "++" | "--")) = *self.peek() {
+            self.next();
+            let operand = self.unary()?;
+            if op == "+" {
+                return Ok(operand);
+            }
+            return Ok(Expr {
+                kind: ExprKind::Unary(op, Box::new(operand)),
+                pos,
+            });
+        }
+        if self.is_keyword("sizeof") {
+            self.next();
+            // sizeof(type) or sizeof expression
+            let typed = self.is("(") && matches!(&self.tokens[self.pos + 1].tok, Tok::Ident(t) if ["int", "char", "void"].contains(&t.as_str()));
+            let kind = if typed {
+                self.next();
+                let mut ty = self.base_type()?;
+                while self.eat("*") {
+                    ty = Type::Pointer(Box::new(ty));
+                }
+                self.expect(")")?;
+                ExprKind::SizeofType(ty)
+            } else {
+                ExprKind::SizeofExpr(Box::new(self.unary()?))
+            };
+            return Ok(Expr { kind, pos });
+        }
+        self.postfix()
+    }
+
+    fn postfix(&mut self) -> Result<Expr, CompileError> {
+        let mut e = self.primary()?;
+        loop {
+            let pos = self.here();
+            e = match self.peek() {
+                Tok::Punct("[") => {
+                    self.next();
+                    let index = self.expr()?;
+                    self.expect("]")?;
+                    Expr {
+                        kind: ExprKind::Index(Box::new(e), Box::new(index)),
+                        pos,
+                    }
+                }
+                Tok::Punct(op @ ("++" | "--")) => {
+                    let op = *op;
+                    self.next();
+                    Expr {
+                        kind: ExprKind::Postfix(op, Box::new(e)),
+                        pos,
+                    }
+                }
+                Tok::Punct("(") => {
+                    let name = match &e.kind {
+                        ExprKind::Name(name) => name.clone(),
+                        _ => return self.error("only functions can be called, by their name".to_string()),
+                    };
+                    self.next();
+                    let mut args = Vec::new();
+                    if !self.eat(")") {
+                        loop {
+                            args.push(self.assignment()?);
+                            if !self.eat(",") {
+                                break;
+                            }
+                        }
+                        self.expect(")")?;
+                    }
+                    Expr {
+                        kind: ExprKind::Call(name, args),
+                        pos: e.pos,
+                    }
+                }
+                Tok::Punct(op @ ("." | "->")) => return self.error(format!("`{}` is not supported, there are no structs", op)),
+                _ => return Ok(e),
+            };
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, CompileError> {
+        let pos = self.here();
+        let kind = match self.peek().clone() {
+            Tok::Number(n) => ExprKind::Number(n),
+            Tok::Str(mut s) => {
+                // Adjacent strings are joined
+                self.next();
+                while let Tok::Str(more) = self.peek() {
+                    s.push_str(more);
+                    self.next();
+                }
+                return Ok(Expr {
+                    kind: ExprKind::Str(s),
+                    pos,
+                });
+            }
+            Tok::Ident(name) if !is_keyword(&name) => ExprKind::Name(name),
+            Tok::Punct("(") => {
+                self.next();
+                let e = self.expr()?;
+                self.expect(")")?;
+                return Ok(e);
+            }
+            _ => return self.error(format!("expected an expression, found {}", self.describe())),
+        };
+        self.next();
+        Ok(Expr { kind, pos })
+    }
+}
+
+fn is_keyword(name: &str) -> bool {
+    [
+        "int", "char", "void", "if", "else", "while", "do", "for", "return", "break", "continue", "sizeof",
+    ]
+    .contains(&name)
+}
+
+fn at(pos: Pos, message: String) -> CompileError {
+    CompileError {
+        line: pos.0,
+        column: pos.1,
+        message,
+    }
+}
+
+/// The value of an expression made of numbers
+pub(super) fn constant(e: &Expr) -> Option<i32> {
+    Some(match &e.kind {
+        ExprKind::Number(n) => *n,
+        ExprKind::SizeofType(t) => t.size() as i32,
+        ExprKind::Unary(op, e) => {
+            let v = constant(e)?;
+            match *op {
+                "-" => v.wrapping_neg(),
+                "~" => !v,
+                "!" => (v == 0) as i32,
+                _ => return None,
+            }
+        }
+        ExprKind::Binary(op, a, b) => {
+            let (a, b) = (constant(a)?, constant(b)?);
+            match *op {
+                "+" => a.wrapping_add(b),
+                "-" => a.wrapping_sub(b),
+                "*" => a.wrapping_mul(b),
+                "/" if b != 0 => a / b,
+                "%" if b != 0 => a % b,
+                "&" => a & b,
+                "|" => a | b,
+                "^" => a ^ b,
+                _ => return None,
+            }
+        }
+        _ => return None,
+    })
+}
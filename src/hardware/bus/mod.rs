@@ -0,0 +1,247 @@
+use std::any::Any;
+use std::ops::RangeInclusive;
+
+/// An interrupt request raised by a device.
+/// The vector selects the entry in the interrupt
+/// vector table (x0100 + vector) and the priority
+/// is compared against the priority level of the
+/// running program (PSR[10:8]).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Interrupt {
+    pub vector: u8,
+    pub priority: u16,
+}
+
+/// A memory mapped peripheral.
+/// Every access to an address inside `range` is
+/// routed to the device instead of the main memory.
+pub trait Device: Any {
+    /// Human readable name of the device
+    fn name(&self) -> &str;
+
+    /// Addresses claimed by the device
+    fn range(&self) -> RangeInclusive<u16>;
+
+    /// Called when the CPU reads a claimed address
+    fn read(&mut self, address: u16) -> u16;
+
+    /// Like `read`, with the number of instructions
+    /// executed so far, for devices that log reads
+    fn read_at(&mut self, address: u16, _instructions: u64) -> u16 {
+        self.read(address)
+    }
+
+    /// Read a claimed address for a debugger or a
+    /// report, without the side effects of `read`
+    fn peek(&mut self, address: u16) -> u16 {
+        self.read(address)
+    }
+
+    /// Called when the CPU writes a claimed address
+    fn write(&mut self, address: u16, value: u16);
+
+    /// Called once after every executed instruction.
+    /// The device can access the memory (DMA) and
+    /// request an interrupt. The request is level
+    /// triggered: the device should keep returning
+    /// it until the handler acknowledges it.
+    fn tick(&mut self, _dma: &mut Dma) -> Option<Interrupt> {
+        None
+    }
+
+    /// Internal state of the device, used by snapshots
+    fn save(&self) -> Vec<u16> {
+        Vec::new()
+    }
+
+    /// Restore a state returned by `save`
+    fn restore(&mut self, _state: &[u16]) {}
+}
+
+/// Memory as seen by a device during DMA. Like
+/// accesses of the CPU, addresses claimed by the
+/// other devices are routed to them.
+pub struct Dma<'a> {
+    memory: &'a mut [u16],
+    others: [&'a mut [Box<dyn Device>]; 2],
+    instructions: u64,
+}
+
+impl<'a> Dma<'a> {
+    /// Plain memory without devices
+    pub fn new(memory: &'a mut [u16]) -> Dma<'a> {
+        Dma {
+            memory,
+            others: [&mut [], &mut []],
+            instructions: 0,
+        }
+    }
+
+    fn device(&mut self, address: u16) -> Option<&mut Box<dyn Device>> {
+        self.others
+            .iter_mut()
+            .flat_map(|devices| devices.iter_mut())
+            .find(|d| d.range().contains(&address))
+    }
+
+    pub fn read(&mut self, address: u16) -> u16 {
+        let instructions = self.instructions;
+        match self.device(address) {
+            Some(device) => device.read_at(address, instructions),
+            None => self.memory[address as usize],
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u16) {
+        match self.device(address) {
+            Some(device) => device.write(address, value),
+            None => self.memory[address as usize] = value,
+        }
+    }
+}
+
+/// The bus connects the devices to the CPU.
+pub struct Bus {
+    devices: Vec<Box<dyn Device>>,
+}
+
+impl Default for Bus {
+    fn default() -> Bus {
+        Bus::new()
+    }
+}
+
+impl Bus {
+    pub fn new() -> Bus {
+        Bus {
+            devices: Vec::new(),
+        }
+    }
+
+    /// Register a device on the bus.
+    /// Panics if the device claims addresses that
+    /// already belong to another device.
+    pub fn attach(&mut self, device: Box<dyn Device>) {
+        let range = device.range();
+        for other in &self.devices {
+            let taken = other.range();
+            if range.start() <= taken.end() && taken.start() <= range.end() {
+                panic!(
+                    "Device {} overlaps with {} at x{:04X}-x{:04X}",
+                    device.name(),
+                    other.name(),
+                    taken.start(),
+                    taken.end()
+                );
+            }
+        }
+        self.devices.push(device);
+    }
+
+    /// Returns true if a device claims the address
+    pub fn claims(&self, address: u16) -> bool {
+        self.devices.iter().any(|d| d.range().contains(&address))
+    }
+
+    /// Read from the device that claims the address,
+    /// None if the address is plain memory
+    pub fn read(&mut self, address: u16, instructions: u64) -> Option<u16> {
+        self.devices
+            .iter_mut()
+            .find(|d| d.range().contains(&address))
+            .map(|d| d.read_at(address, instructions))
+    }
+
+    /// Like `read`, with `Device::peek`
+    pub fn peek(&mut self, address: u16) -> Option<u16> {
+        self.devices
+            .iter_mut()
+            .find(|d| d.range().contains(&address))
+            .map(|d| d.peek(address))
+    }
+
+    /// Write to the device that claims the address,
+    /// returns false if the address is plain memory
+    pub fn write(&mut self, address: u16, value: u16) -> bool {
+        match self.devices.iter_mut().find(|d| d.range().contains(&address)) {
+            Some(device) => {
+                device.write(address, value);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Tick every device and return the pending
+    /// interrupt with the highest priority
+    pub fn tick(&mut self, memory: &mut [u16], instructions: u64) -> Option<Interrupt> {
+        let mut pending: Option<Interrupt> = None;
+        for i in 0..self.devices.len() {
+            let interrupt = self.dma(i, memory, instructions, |device, dma| device.tick(dma));
+            if let Some(interrupt) = interrupt {
+                if pending.is_none_or(|p| interrupt.priority > p.priority) {
+                    pending = Some(interrupt);
+                }
+            }
+        }
+        pending
+    }
+
+    /// Run `f` on the first device of type T, with
+    /// DMA access to the memory and the other devices
+    pub fn with_dma<T: Device, R>(
+        &mut self,
+        memory: &mut [u16],
+        instructions: u64,
+        f: impl FnOnce(&mut T, &mut Dma) -> R,
+    ) -> Option<R> {
+        let index = self.devices.iter().position(|d| (d.as_ref() as &dyn Any).is::<T>())?;
+        self.dma(index, memory, instructions, |device, dma| {
+            (device as &mut dyn Any).downcast_mut::<T>().map(|device| f(device, dma))
+        })
+    }
+
+    fn dma<R>(
+        &mut self,
+        index: usize,
+        memory: &mut [u16],
+        instructions: u64,
+        f: impl FnOnce(&mut dyn Device, &mut Dma) -> R,
+    ) -> R {
+        let (before, rest) = self.devices.split_at_mut(index);
+        let (device, after) = rest.split_first_mut().expect("device index out of range");
+        let mut dma = Dma {
+            memory,
+            others: [before, after],
+            instructions,
+        };
+        f(device.as_mut(), &mut dma)
+    }
+
+    /// Iterate over the attached devices
+    pub fn devices(&self) -> impl Iterator<Item = &dyn Device> {
+        self.devices.iter().map(|d| d.as_ref())
+    }
+
+    /// Find an attached device by name
+    pub fn device_named_mut(&mut self, name: &str) -> Option<&mut dyn Device> {
+        self.devices
+            .iter_mut()
+            .find(|d| d.name() == name)
+            .map(|d| d.as_mut() as &mut dyn Device)
+    }
+
+    /// Find the first attached device of type T
+    pub fn device<T: Device>(&self) -> Option<&T> {
+        self.devices
+            .iter()
+            .find_map(|d| (d.as_ref() as &dyn Any).downcast_ref::<T>())
+    }
+
+    /// Find the first attached device of type T
+    pub fn device_mut<T: Device>(&mut self) -> Option<&mut T> {
+        self.devices
+            .iter_mut()
+            .find_map(|d| (d.as_mut() as &mut dyn Any).downcast_mut::<T>())
+    }
+}
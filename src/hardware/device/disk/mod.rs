@@ -0,0 +1,204 @@
+use crate::hardware::bus::{Device, Dma, Interrupt};
+use crate::hardware::vm::MemoryMappedReg;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+/// Number of 16-bit words in a sector
+pub const SECTOR_SIZE: usize = 256;
+
+/// DSKSR bit set when the controller is idle
+pub const DISK_READY: u16 = 1 << 15;
+/// DSKSR bit enabling the completion interrupt
+pub const DISK_INTERRUPT_ENABLE: u16 = 1 << 14;
+/// DSKSR bit set when the last command failed
+pub const DISK_ERROR: u16 = 1 << 0;
+
+/// Commands accepted by DSKCMD
+pub const DISK_READ: u16 = 1;
+pub const DISK_WRITE: u16 = 2;
+
+/// Entry in the interrupt vector table, x0182
+pub const DISK_VECTOR: u8 = 0x82;
+/// Priority level of the completion interrupt, PSR[10:8]
+pub const DISK_PRIORITY: u16 = 4;
+
+/// Block storage controller backed by a host file.
+/// The image is a sequence of 256-word sectors
+/// stored big endian, like the `.obj` files.
+///
+/// DSKSR  status: ready [15], interrupt enable [14],
+///        error [0]
+/// DSKSEC sector number
+/// DSKADR memory address of the DMA buffer
+/// DSKCMD writing a command starts the transfer,
+///        which completes on the next cycle
+pub struct Disk {
+    image: File,
+    status: u16,
+    sector: u16,
+    address: u16,
+    command: Option<u16>,
+    done: bool,
+}
+
+impl Disk {
+    /// Open the image, creating it if missing
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Disk> {
+        let image = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        Ok(Disk {
+            image,
+            status: DISK_READY,
+            sector: 0,
+            address: 0,
+            command: None,
+            done: false,
+        })
+    }
+
+    /// Run the pending command, if any. The buffer
+    /// is accessed like the CPU would, so a sector
+    /// can be read straight into the framebuffer.
+    pub fn execute(&mut self, dma: &mut Dma) {
+        let command = match self.command.take() {
+            Some(command) => command,
+            None => return,
+        };
+        let result = match command {
+            DISK_READ => self.read_sector(dma),
+            DISK_WRITE => self.write_sector(dma),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "unknown command")),
+        };
+        self.status |= DISK_READY;
+        if result.is_err() {
+            self.status |= DISK_ERROR;
+        }
+        self.done = true;
+    }
+
+    fn seek(&mut self) -> io::Result<()> {
+        let offset = self.sector as u64 * SECTOR_SIZE as u64 * 2;
+        self.image.seek(SeekFrom::Start(offset))?;
+        Ok(())
+    }
+
+    fn read_sector(&mut self, dma: &mut Dma) -> io::Result<()> {
+        self.seek()?;
+        let mut bytes = [0u8; SECTOR_SIZE * 2];
+        // Sectors past the end of the image read as zeros
+        let mut filled = 0;
+        loop {
+            let n = self.image.read(&mut bytes[filled..])?;
+            if n == 0 {
+                break;
+            }
+            filled += n;
+        }
+        for i in 0..SECTOR_SIZE {
+            let word = u16::from_be_bytes([bytes[2 * i], bytes[2 * i + 1]]);
+            dma.write(self.address.wrapping_add(i as u16), word);
+        }
+        Ok(())
+    }
+
+    fn write_sector(&mut self, dma: &mut Dma) -> io::Result<()> {
+        self.seek()?;
+        let mut bytes = [0u8; SECTOR_SIZE * 2];
+        for i in 0..SECTOR_SIZE {
+            let word = dma.read(self.address.wrapping_add(i as u16));
+            bytes[2 * i..2 * i + 2].copy_from_slice(&word.to_be_bytes());
+        }
+        self.image.write_all(&bytes)?;
+        self.image.flush()
+    }
+}
+
+impl Device for Disk {
+    fn name(&self) -> &str {
+        "disk"
+    }
+
+    fn range(&self) -> RangeInclusive<u16> {
+        MemoryMappedReg::Dsksr as u16..=MemoryMappedReg::Dskcmd as u16 + 1
+    }
+
+    fn read(&mut self, address: u16) -> u16 {
+        match address {
+            a if a == MemoryMappedReg::Dsksr as u16 => {
+                // Reading the status acknowledges the interrupt
+                self.done = false;
+                self.status
+            }
+            a if a == MemoryMappedReg::Dsksec as u16 => self.sector,
+            a if a == MemoryMappedReg::Dskadr as u16 => self.address,
+            _ => 0,
+        }
+    }
+
+    /// The status without acknowledging the interrupt
+    fn peek(&mut self, address: u16) -> u16 {
+        match address {
+            a if a == MemoryMappedReg::Dsksr as u16 => self.status,
+            _ => self.read(address),
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u16) {
+        match address {
+            a if a == MemoryMappedReg::Dsksr as u16 => {
+                self.status = (self.status & !DISK_INTERRUPT_ENABLE)
+                    | (value & DISK_INTERRUPT_ENABLE);
+            }
+            a if a == MemoryMappedReg::Dsksec as u16 => self.sector = value,
+            a if a == MemoryMappedReg::Dskadr as u16 => self.address = value,
+            // Commands are ignored while a transfer is running
+            a if a == MemoryMappedReg::Dskcmd as u16 && self.status & DISK_READY != 0 => {
+                self.status &= !(DISK_READY | DISK_ERROR);
+                self.command = Some(value);
+                self.done = false;
+            }
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self, dma: &mut Dma) -> Option<Interrupt> {
+        self.execute(dma);
+        if self.done && self.status & DISK_INTERRUPT_ENABLE != 0 {
+            Some(Interrupt {
+                vector: DISK_VECTOR,
+                priority: DISK_PRIORITY,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// The image itself lives on the host and is
+    /// not part of the state
+    fn save(&self) -> Vec<u16> {
+        vec![
+            self.status,
+            self.sector,
+            self.address,
+            self.command.is_some() as u16,
+            self.command.unwrap_or(0),
+            self.done as u16,
+        ]
+    }
+
+    fn restore(&mut self, state: &[u16]) {
+        if let [status, sector, address, pending, command, done] = *state {
+            self.status = status;
+            self.sector = sector;
+            self.address = address;
+            self.command = if pending != 0 { Some(command) } else { None };
+            self.done = done != 0;
+        }
+    }
+}
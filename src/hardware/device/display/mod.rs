@@ -0,0 +1,46 @@
+use crate::hardware::bus::Device;
+use crate::hardware::vm::MemoryMappedReg;
+use std::io::Write;
+use std::ops::RangeInclusive;
+
+/// Display status (DSR) and data (DDR) registers.
+/// The display is always ready, every character
+/// written to DDR is sent to the output.
+pub struct Display {
+    output: Box<dyn Write>,
+}
+
+impl Display {
+    pub fn new(output: Box<dyn Write>) -> Display {
+        Display { output }
+    }
+
+    pub fn stdout() -> Display {
+        Display::new(Box::new(std::io::stdout()))
+    }
+}
+
+impl Device for Display {
+    fn name(&self) -> &str {
+        "display"
+    }
+
+    fn range(&self) -> RangeInclusive<u16> {
+        MemoryMappedReg::Dsr as u16..=MemoryMappedReg::Ddr as u16 + 1
+    }
+
+    fn read(&mut self, address: u16) -> u16 {
+        if address == MemoryMappedReg::Dsr as u16 {
+            1 << 15
+        } else {
+            0
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u16) {
+        if address == MemoryMappedReg::Ddr as u16 {
+            self.output.write_all(&[value as u8]).expect("Failed to write");
+            self.output.flush().expect("Failed to flush");
+        }
+    }
+}
@@ -0,0 +1,254 @@
+use crate::hardware::bus::Device;
+#[cfg(feature = "window")]
+use crate::hardware::bus::{Dma, Interrupt};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+pub const WIDTH: usize = 128;
+pub const HEIGHT: usize = 124;
+
+/// Video memory, one word per pixel, row major
+pub const VIDEO_START: u16 = 0xC000;
+pub const VIDEO_END: u16 = VIDEO_START + (WIDTH * HEIGHT) as u16 - 1;
+
+/// Instructions executed between two window refreshes
+#[cfg(feature = "window")]
+const REFRESH_INTERVAL: u32 = 50_000;
+
+/// Bitmap display in the style of PennSim.
+/// Each word in xC000-xFDFF is a pixel with 15-bit
+/// colour: red [14:10], green [9:5], blue [4:0].
+/// Frames are always kept in memory so they can be
+/// saved as PNG or PPM images, and are shown in a
+/// window when the crate is built with the `window`
+/// feature.
+pub struct Framebuffer {
+    pixels: Vec<u16>,
+    #[cfg(feature = "window")]
+    window: Option<minifb::Window>,
+    #[cfg(feature = "window")]
+    ticks: u32,
+    #[cfg(feature = "window")]
+    dirty: bool,
+}
+
+impl Framebuffer {
+    /// A framebuffer that is never shown on screen
+    pub fn headless() -> Framebuffer {
+        Framebuffer {
+            pixels: vec![0; WIDTH * HEIGHT],
+            #[cfg(feature = "window")]
+            window: None,
+            #[cfg(feature = "window")]
+            ticks: 0,
+            #[cfg(feature = "window")]
+            dirty: false,
+        }
+    }
+
+    /// A framebuffer rendered to a window
+    #[cfg(feature = "window")]
+    pub fn windowed() -> Result<Framebuffer, String> {
+        let options = minifb::WindowOptions {
+            scale: minifb::Scale::X4,
+            ..minifb::WindowOptions::default()
+        };
+        let window = minifb::Window::new("LC-3", WIDTH, HEIGHT, options)
+            .map_err(|e| e.to_string())?;
+        let mut framebuffer = Framebuffer::headless();
+        framebuffer.window = Some(window);
+        framebuffer.dirty = true;
+        Ok(framebuffer)
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> u16 {
+        self.pixels[y * WIDTH + x]
+    }
+
+    /// The colour of a pixel with 8 bits per channel
+    pub fn rgb(&self, x: usize, y: usize) -> [u8; 3] {
+        let pixel = self.pixel(x, y);
+        let expand = |c: u16| ((c << 3) | (c >> 2)) as u8;
+        [
+            expand((pixel >> 10) & 0x1F),
+            expand((pixel >> 5) & 0x1F),
+            expand(pixel & 0x1F),
+        ]
+    }
+
+    /// Write the current frame as a binary PPM (P6)
+    pub fn write_ppm<W: Write>(&self, mut out: W) -> io::Result<()> {
+        write!(out, "P6\n{} {}\n255\n", WIDTH, HEIGHT)?;
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                out.write_all(&self.rgb(x, y))?;
+            }
+        }
+        out.flush()
+    }
+
+    /// Write the current frame as an RGB PNG.
+    /// The image data is stored without compression.
+    pub fn write_png<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let mut raw = Vec::with_capacity(HEIGHT * (1 + WIDTH * 3));
+        for y in 0..HEIGHT {
+            raw.push(0); // filter type: none
+            for x in 0..WIDTH {
+                raw.extend_from_slice(&self.rgb(x, y));
+            }
+        }
+
+        let mut header = Vec::new();
+        header.extend_from_slice(&(WIDTH as u32).to_be_bytes());
+        header.extend_from_slice(&(HEIGHT as u32).to_be_bytes());
+        // bit depth 8, colour type RGB, deflate, no filter, no interlace
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+        out.write_all(b"\x89PNG\r\n\x1a\n")?;
+        write_png_chunk(&mut out, b"IHDR", &header)?;
+        write_png_chunk(&mut out, b"IDAT", &zlib_stored(&raw))?;
+        write_png_chunk(&mut out, b"IEND", &[])?;
+        out.flush()
+    }
+
+    /// Save the current frame, the format is chosen
+    /// from the extension (`.png` or `.ppm`)
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let out = BufWriter::new(File::create(path)?);
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("ppm") => self.write_ppm(out),
+            Some("png") => self.write_png(out),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "unsupported image format, use .png or .ppm",
+            )),
+        }
+    }
+
+    /// Keep showing the last frame until the
+    /// window is closed
+    #[cfg(feature = "window")]
+    pub fn wait_close(&mut self) {
+        self.refresh();
+        while self.window.as_ref().is_some_and(|w| w.is_open()) {
+            if let Some(window) = self.window.as_mut() {
+                window.update();
+            }
+            std::thread::sleep(std::time::Duration::from_millis(16));
+        }
+    }
+
+    #[cfg(feature = "window")]
+    fn refresh(&mut self) {
+        let buffer: Vec<u32> = (0..WIDTH * HEIGHT)
+            .map(|i| {
+                let [r, g, b] = self.rgb(i % WIDTH, i / WIDTH);
+                ((r as u32) << 16) | ((g as u32) << 8) | b as u32
+            })
+            .collect();
+        if let Some(window) = self.window.as_mut() {
+            // The window keeps the last frame if it was closed
+            let _ = window.update_with_buffer(&buffer, WIDTH, HEIGHT);
+        }
+        self.dirty = false;
+    }
+}
+
+fn write_png_chunk<W: Write>(out: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    let crc = crc32(kind.iter().chain(data.iter()));
+    out.write_all(&crc.to_be_bytes())
+}
+
+/// Wrap data in a zlib stream made of stored deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(u16::MAX as usize).peekable();
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        out.push(last as u8);
+        out.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32<'a, I: Iterator<Item = &'a u8>>(bytes: I) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+impl Device for Framebuffer {
+    fn name(&self) -> &str {
+        "framebuffer"
+    }
+
+    fn range(&self) -> RangeInclusive<u16> {
+        VIDEO_START..=VIDEO_END
+    }
+
+    fn read(&mut self, address: u16) -> u16 {
+        self.pixels[(address - VIDEO_START) as usize]
+    }
+
+    fn write(&mut self, address: u16, value: u16) {
+        self.pixels[(address - VIDEO_START) as usize] = value;
+        #[cfg(feature = "window")]
+        {
+            self.dirty = true;
+        }
+    }
+
+    fn save(&self) -> Vec<u16> {
+        self.pixels.clone()
+    }
+
+    fn restore(&mut self, state: &[u16]) {
+        if state.len() == self.pixels.len() {
+            self.pixels.copy_from_slice(state);
+            #[cfg(feature = "window")]
+            {
+                self.dirty = true;
+            }
+        }
+    }
+
+    #[cfg(feature = "window")]
+    fn tick(&mut self, _dma: &mut Dma) -> Option<Interrupt> {
+        if self.window.is_some() {
+            self.ticks += 1;
+            if self.ticks >= REFRESH_INTERVAL {
+                self.ticks = 0;
+                if self.dirty {
+                    self.refresh();
+                } else if let Some(window) = self.window.as_mut() {
+                    window.update();
+                }
+            }
+        }
+        None
+    }
+}
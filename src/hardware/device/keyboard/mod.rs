@@ -0,0 +1,201 @@
+use crate::hardware::bus::Device;
+use crate::hardware::replay::{self, InputEvent, InputLog, ReplayError};
+use crate::hardware::vm::MemoryMappedReg;
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::ops::RangeInclusive;
+
+/// Keyboard status (KBSR) and data (KBDR) registers.
+/// Reading KBSR blocks until a byte is available on
+/// the input, then bit [15] of KBSR tells whether
+/// KBDR holds a new character.
+///
+/// Every register read can be recorded in an input
+/// log, stamped with the instruction count of the
+/// VM, and a log can be replayed instead of reading
+/// the input so that a run is reproduced exactly.
+pub struct Keyboard {
+    input: Box<dyn Read>,
+    status: u16,
+    data: u16,
+    recorder: Option<Box<dyn Write>>,
+    replay: Option<VecDeque<InputEvent>>,
+    /// The first divergence or log write failure
+    error: Option<ReplayError>,
+}
+
+impl Keyboard {
+    pub fn new(input: Box<dyn Read>) -> Keyboard {
+        Keyboard {
+            input,
+            status: 0,
+            data: 0,
+            recorder: None,
+            replay: None,
+            error: None,
+        }
+    }
+
+    pub fn stdin() -> Keyboard {
+        Keyboard::new(Box::new(std::io::stdin()))
+    }
+
+    /// Log every register read to `out`,
+    /// the header must already be written
+    pub fn record(&mut self, out: Box<dyn Write>) {
+        self.recorder = Some(out);
+    }
+
+    /// Answer register reads from the log
+    /// instead of reading the input
+    pub fn replay(&mut self, log: InputLog) {
+        self.replay = Some(log.events);
+    }
+
+    /// Set when the replay diverged from the log or
+    /// the log could not be written, the run should
+    /// stop as it is no longer reproducible
+    pub fn error(&self) -> Option<&ReplayError> {
+        self.error.as_ref()
+    }
+
+    fn poll(&mut self) {
+        let mut buffer = [0; 1];
+        match self.input.read_exact(&mut buffer) {
+            Ok(()) if buffer[0] != 0 => {
+                self.status |= 1 << 15;
+                self.data = buffer[0] as u16;
+            }
+            _ => self.status &= !(1 << 15),
+        }
+    }
+
+    fn live_read(&mut self, address: u16) -> u16 {
+        if address == MemoryMappedReg::Kbsr as u16 {
+            self.poll();
+            self.status
+        } else if address == MemoryMappedReg::Kbdr as u16 {
+            // Reading the data clears the ready bit
+            self.status &= !(1 << 15);
+            self.data
+        } else {
+            0
+        }
+    }
+
+    fn replay_read(&mut self, address: u16, instructions: u64) -> u16 {
+        let events = match self.replay.as_mut() {
+            Some(events) => events,
+            None => return 0,
+        };
+        match events.pop_front() {
+            Some(event) if event.address == address => {
+                if event.instruction != instructions {
+                    self.diverge(address, instructions);
+                }
+                event.value
+            }
+            Some(event) => {
+                // Keep the event, the program may catch up
+                events.push_front(event);
+                self.diverge(address, instructions);
+                0
+            }
+            // The recording is over: no more input
+            None => 0,
+        }
+    }
+
+    fn diverge(&mut self, address: u16, instruction: u64) {
+        self.error.get_or_insert(ReplayError::Diverged { instruction, address });
+    }
+}
+
+impl Device for Keyboard {
+    fn name(&self) -> &str {
+        "keyboard"
+    }
+
+    fn range(&self) -> RangeInclusive<u16> {
+        MemoryMappedReg::Kbsr as u16..=MemoryMappedReg::Kbdr as u16 + 1
+    }
+
+    /// A read without the instruction count is
+    /// logged as the first instruction
+    fn read(&mut self, address: u16) -> u16 {
+        self.read_at(address, 0)
+    }
+
+    fn read_at(&mut self, address: u16, instructions: u64) -> u16 {
+        if address != MemoryMappedReg::Kbsr as u16 && address != MemoryMappedReg::Kbdr as u16 {
+            return 0;
+        }
+        let value = if self.replay.is_some() {
+            self.replay_read(address, instructions)
+        } else {
+            self.live_read(address)
+        };
+        if let Some(out) = self.recorder.as_mut() {
+            let event = InputEvent {
+                instruction: instructions,
+                address,
+                value,
+            };
+            if let Err(e) = replay::write_event(out.as_mut(), &event) {
+                // Stop recording, the log is incomplete
+                self.recorder = None;
+                self.error.get_or_insert(ReplayError::Io(e));
+            }
+        }
+        value
+    }
+
+    /// The registers as they are, without polling
+    /// the input or logging
+    fn peek(&mut self, address: u16) -> u16 {
+        match address {
+            a if a == MemoryMappedReg::Kbsr as u16 => self.status,
+            a if a == MemoryMappedReg::Kbdr as u16 => self.data,
+            _ => 0,
+        }
+    }
+
+    /// The registers are read only. Reading the input
+    /// blocks, so the keyboard raises no interrupt and
+    /// KBSR has no interrupt enable bit.
+    fn write(&mut self, _address: u16, _value: u16) {}
+
+    /// Status, data, then whether a replay is running
+    /// and its remaining events: the instruction in
+    /// four words, most significant first, address
+    /// and value
+    fn save(&self) -> Vec<u16> {
+        let mut state = vec![self.status, self.data, self.replay.is_some() as u16];
+        for event in self.replay.iter().flatten() {
+            let i = event.instruction;
+            state.extend([(i >> 48) as u16, (i >> 32) as u16, (i >> 16) as u16, i as u16]);
+            state.extend([event.address, event.value]);
+        }
+        state
+    }
+
+    /// A replay started before the restore goes on
+    /// if the snapshot was taken without one
+    fn restore(&mut self, state: &[u16]) {
+        let (status, data, events) = match *state {
+            [status, data] | [status, data, 0] => (status, data, None),
+            [status, data, 1, ref events @ ..] if events.len() % 6 == 0 => (status, data, Some(events)),
+            _ => return,
+        };
+        self.status = status;
+        self.data = data;
+        if let Some(events) = events {
+            let events = events.chunks(6).map(|e| InputEvent {
+                instruction: e[..4].iter().fold(0, |i, &word| (i << 16) | word as u64),
+                address: e[4],
+                value: e[5],
+            });
+            self.replay = Some(events.collect());
+        }
+    }
+}
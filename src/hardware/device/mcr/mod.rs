@@ -0,0 +1,54 @@
+use crate::hardware::bus::Device;
+use crate::hardware::vm::MemoryMappedReg;
+use std::ops::RangeInclusive;
+
+/// Machine control register (MCR).
+/// Bit [15] is the clock enable, the machine
+/// stops executing instructions when it is cleared.
+pub struct Mcr {
+    value: u16,
+}
+
+impl Default for Mcr {
+    fn default() -> Mcr {
+        Mcr::new()
+    }
+}
+
+impl Mcr {
+    pub fn new() -> Mcr {
+        Mcr { value: 1 << 15 }
+    }
+
+    pub fn clock_enabled(&self) -> bool {
+        self.value & (1 << 15) != 0
+    }
+}
+
+impl Device for Mcr {
+    fn name(&self) -> &str {
+        "mcr"
+    }
+
+    fn range(&self) -> RangeInclusive<u16> {
+        MemoryMappedReg::Mcr as u16..=MemoryMappedReg::Mcr as u16
+    }
+
+    fn read(&mut self, _address: u16) -> u16 {
+        self.value
+    }
+
+    fn write(&mut self, _address: u16, value: u16) {
+        self.value = value;
+    }
+
+    fn save(&self) -> Vec<u16> {
+        vec![self.value]
+    }
+
+    fn restore(&mut self, state: &[u16]) {
+        if let [value] = *state {
+            self.value = value;
+        }
+    }
+}
@@ -0,0 +1,7 @@
+pub mod disk;
+pub mod display;
+pub mod framebuffer;
+pub mod keyboard;
+pub mod mcr;
+pub mod rng;
+pub mod timer;
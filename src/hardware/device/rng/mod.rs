@@ -0,0 +1,125 @@
+use crate::hardware::bus::Device;
+use crate::hardware::vm::MemoryMappedReg;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::ops::RangeInclusive;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// RNGCR bit selecting the deterministic mode
+pub const RNG_DETERMINISTIC: u16 = 1 << 15;
+
+/// Random number generator.
+/// In deterministic mode the sequence only depends
+/// on the seed, so runs can be reproduced. In
+/// entropy mode the generator is reseeded from the
+/// host every time it is switched on.
+///
+/// RNGDR random word, every read returns a new one
+/// RNGCR control: deterministic mode [15]
+/// RNGSD seed, writing it reseeds the generator and
+///       selects the deterministic mode
+pub struct Rng {
+    state: u64,
+    seed: u16,
+    control: u16,
+}
+
+impl Rng {
+    /// A generator seeded from the host
+    pub fn entropy() -> Rng {
+        Rng {
+            state: host_entropy(),
+            seed: 0,
+            control: 0,
+        }
+    }
+
+    /// A reproducible generator
+    pub fn seeded(seed: u16) -> Rng {
+        Rng {
+            state: seed as u64,
+            seed,
+            control: RNG_DETERMINISTIC,
+        }
+    }
+
+    /// splitmix64
+    pub fn next_word(&mut self) -> u16 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        (z >> 48) as u16
+    }
+}
+
+fn host_entropy() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    if let Ok(now) = SystemTime::now().duration_since(UNIX_EPOCH) {
+        hasher.write_u128(now.as_nanos());
+    }
+    hasher.finish()
+}
+
+impl Device for Rng {
+    fn name(&self) -> &str {
+        "rng"
+    }
+
+    fn range(&self) -> RangeInclusive<u16> {
+        MemoryMappedReg::Rngdr as u16..=MemoryMappedReg::Rngsd as u16 + 1
+    }
+
+    fn read(&mut self, address: u16) -> u16 {
+        match address {
+            a if a == MemoryMappedReg::Rngdr as u16 => self.next_word(),
+            a if a == MemoryMappedReg::Rngcr as u16 => self.control,
+            a if a == MemoryMappedReg::Rngsd as u16 => self.seed,
+            _ => 0,
+        }
+    }
+
+    /// The next random word, without advancing
+    fn peek(&mut self, address: u16) -> u16 {
+        let state = self.state;
+        let value = self.read(address);
+        self.state = state;
+        value
+    }
+
+    fn write(&mut self, address: u16, value: u16) {
+        match address {
+            a if a == MemoryMappedReg::Rngcr as u16 => {
+                if value & RNG_DETERMINISTIC != 0 {
+                    *self = Rng::seeded(self.seed);
+                } else {
+                    // The seed is kept for the next switch back
+                    self.state = host_entropy();
+                    self.control = 0;
+                }
+            }
+            a if a == MemoryMappedReg::Rngsd as u16 => *self = Rng::seeded(value),
+            _ => {}
+        }
+    }
+
+    fn save(&self) -> Vec<u16> {
+        vec![
+            (self.state >> 48) as u16,
+            (self.state >> 32) as u16,
+            (self.state >> 16) as u16,
+            self.state as u16,
+            self.seed,
+            self.control,
+        ]
+    }
+
+    fn restore(&mut self, state: &[u16]) {
+        if let [s3, s2, s1, s0, seed, control] = *state {
+            self.state = ((s3 as u64) << 48) | ((s2 as u64) << 32) | ((s1 as u64) << 16) | s0 as u64;
+            self.seed = seed;
+            self.control = control;
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use crate::hardware::bus::{Device, Dma, Interrupt};
+use crate::hardware::vm::MemoryMappedReg;
+use std::ops::RangeInclusive;
+
+/// TMCR bit set when the counter expires,
+/// write a 1 to acknowledge it
+pub const TIMER_EXPIRED: u16 = 1 << 15;
+/// TMCR bit enabling the interrupt
+pub const TIMER_INTERRUPT_ENABLE: u16 = 1 << 14;
+/// TMCR bit starting the counter
+pub const TIMER_ENABLE: u16 = 1 << 0;
+/// Default entry in the interrupt vector table
+pub const TIMER_VECTOR: u16 = 0x81;
+/// Default priority, TMCR[10:8]
+pub const TIMER_PRIORITY: u16 = 6;
+
+/// Programmable interval timer.
+/// The VM executes one instruction per cycle,
+/// so the counter is decremented once per
+/// instruction. When it reaches zero it is
+/// reloaded from TMIR, TMCR[15] is set and, if
+/// enabled, an interrupt is requested until the
+/// handler acknowledges it.
+///
+/// TMCR  control/status: expired [15], interrupt
+///       enable [14], priority [10:8], enable [0]
+/// TMIR  reload interval in instructions
+/// TMCNT current counter
+/// TMIV  interrupt vector
+pub struct Timer {
+    control: u16,
+    reload: u16,
+    count: u16,
+    vector: u16,
+}
+
+impl Default for Timer {
+    fn default() -> Timer {
+        Timer::new()
+    }
+}
+
+impl Timer {
+    pub fn new() -> Timer {
+        Timer {
+            control: TIMER_PRIORITY << 8,
+            reload: 0,
+            count: 0,
+            vector: TIMER_VECTOR,
+        }
+    }
+}
+
+impl Device for Timer {
+    fn name(&self) -> &str {
+        "timer"
+    }
+
+    fn range(&self) -> RangeInclusive<u16> {
+        MemoryMappedReg::Tmcr as u16..=MemoryMappedReg::Tmiv as u16 + 1
+    }
+
+    fn read(&mut self, address: u16) -> u16 {
+        match address {
+            a if a == MemoryMappedReg::Tmcr as u16 => self.control,
+            a if a == MemoryMappedReg::Tmir as u16 => self.reload,
+            a if a == MemoryMappedReg::Tmcnt as u16 => self.count,
+            a if a == MemoryMappedReg::Tmiv as u16 => self.vector,
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u16) {
+        match address {
+            a if a == MemoryMappedReg::Tmcr as u16 => {
+                let mut expired = self.control & TIMER_EXPIRED;
+                if value & TIMER_EXPIRED != 0 {
+                    expired = 0;
+                }
+                let enabled = self.control & TIMER_ENABLE;
+                self.control = expired
+                    | (value & (TIMER_INTERRUPT_ENABLE | 0x0700 | TIMER_ENABLE));
+                // Restart the count when the timer is switched on
+                if enabled == 0 && value & TIMER_ENABLE != 0 {
+                    self.count = self.reload;
+                }
+            }
+            a if a == MemoryMappedReg::Tmir as u16 => {
+                self.reload = value;
+                self.count = value;
+            }
+            a if a == MemoryMappedReg::Tmcnt as u16 => self.count = value,
+            a if a == MemoryMappedReg::Tmiv as u16 => self.vector = value & 0xFF,
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self, _dma: &mut Dma) -> Option<Interrupt> {
+        if self.control & TIMER_ENABLE != 0 && self.reload != 0 {
+            self.count = self.count.saturating_sub(1);
+            if self.count == 0 {
+                self.count = self.reload;
+                self.control |= TIMER_EXPIRED;
+            }
+        }
+
+        if self.control & TIMER_EXPIRED != 0 && self.control & TIMER_INTERRUPT_ENABLE != 0 {
+            Some(Interrupt {
+                vector: self.vector as u8,
+                priority: (self.control >> 8) & 0x7,
+            })
+        } else {
+            None
+        }
+    }
+
+    fn save(&self) -> Vec<u16> {
+        vec![self.control, self.reload, self.count, self.vector]
+    }
+
+    fn restore(&mut self, state: &[u16]) {
+        if let [control, reload, count, vector] = *state {
+            self.control = control;
+            self.reload = reload;
+            self.count = count;
+            self.vector = vector;
+        }
+    }
+}
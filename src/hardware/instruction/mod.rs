@@ -1,19 +1,20 @@
+use crate::hardware::device::disk::{self, Disk};
 use crate::hardware::vm::*;
-use std::process;
-use std::io::{self, Write, Read};
 
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum OpCode {
     BR = 0, // branch
     ADD,    // add
     LD,     // load
+    ST,     // store
     JSR,    // jump register
     AND,    // bitwise and
     LDR,    // load register
     STR,    // store register
-    RTI,    // unused
+    RTI,    // return from interrupt
     NOT,    // bitwise not
     LDI,    // load indirect
-    ST,     // store
     STI,    // store indirect
     JMP,    // jump
     RES,    // reserved (unused)
@@ -22,7 +23,7 @@ pub enum OpCode {
 }
 
 pub fn get_op_code(instr: &u16) -> Option<OpCode> {
-    let op_code = (instr >> 12);
+    let op_code = instr >> 12;
     match op_code {
         0 => Some(OpCode::BR),
         1 => Some(OpCode::ADD),
@@ -53,7 +54,7 @@ pub fn execute_instruction(instr: u16, vm: &mut VM) {
         Some(OpCode::NOT)  => not(instr, vm),
         Some(OpCode::BR)   => br(instr, vm),
         Some(OpCode::JMP)  => jmp(instr, vm),
-        Some(OpCode::RTI)  => println!("RTI is not implemented"),
+        Some(OpCode::RTI)  => rti(instr, vm),
         Some(OpCode::JSR)  => jsr(instr, vm),
         Some(OpCode::LD)   => ld(instr, vm),
         Some(OpCode::LDI)  => ldi(instr, vm),
@@ -63,12 +64,15 @@ pub fn execute_instruction(instr: u16, vm: &mut VM) {
         Some(OpCode::STI)  => sti(instr, vm),
         Some(OpCode::STR)  => str(instr, vm),
         Some(OpCode::TRAP) => trap(instr, vm),
-        _ => println!("Invalid instruction"),
+        _ => vm.exception(ILLEGAL_OPCODE_EXCEPTION),
     }
 }
 
-fn sign_extend(mut x: u16, bit_count: u8) -> u16 {
-    // If positive
+/// Extend the two's complement number in the
+/// lowest `bit_count` bits of `x` to 16 bits.
+/// `x` must not have bits set above `bit_count`.
+pub fn sign_extend(mut x: u16, bit_count: u8) -> u16 {
+    // If negative
     if (x >> (bit_count - 1)) & 1 == 1 {
         x |= 0xFFFF << bit_count;
     }
@@ -92,106 +96,185 @@ fn trap(instr: u16, vm: &mut VM) {
 
     match trap_vector {
         0x20 => {
-            /// GETC
-            /// Read a single character from the keyboard.
-            /// The character is not echoed onto the console.
-            /// Its ASCII code is copied into R0. The high
-            /// eight bits of R0 are cleared.
-            let mut buffer = [0; 1];
-            std::io::stdin().read_exact(&mut buffer).unwrap();
-            vm.registers.r0 = buffer[0] as u16;
+            // GETC
+            // Read a single character from the keyboard.
+            // The character is not echoed onto the console.
+            // Its ASCII code is copied into R0. The high
+            // eight bits of R0 are cleared.
+            let char = get_char(vm);
+            vm.registers.update(0, char);
         }
         0x21 => {
-            /// OUT
-            /// Write a character in R0[7:0] to the console
-            /// display.
-            print!("{}", (vm.registers.get(0) & 0xFF) as u8 as char);
+            // OUT
+            // Write a character in R0[7:0] to the console
+            // display.
+            put_char(vm, (vm.registers.get(0) & 0xFF) as u8);
         }
         0x22 => {
-            /// PUTS
-            /// Write a string of ASCII characters to the 
-            /// console display. The characters are 
-            /// contained in consecutive memory locations, 
-            /// one character per memory location, starting
-            /// with the address specified in R0. Writing 
-            /// terminates with the occurrence of x0000 in
-            /// a memory location.
+            // PUTS
+            // Write a string of ASCII characters to the 
+            // console display. The characters are 
+            // contained in consecutive memory locations, 
+            // one character per memory location, starting
+            // with the address specified in R0. Writing 
+            // terminates with the occurrence of x0000 in
+            // a memory location.
             let mut index = vm.registers.get(0);
             loop {
                 let c = vm.read_memory(index);
-                index += 1;
+                index = index.wrapping_add(1);
                 if c == 0x0000 {
                     break;
                 }
-                print!("{}", (c as u8) as char);
+                put_char(vm, c as u8);
             }
-            io::stdout().flush().expect("Failed to flush");
         }
         0x23 => {
-            /// IN
-            /// Print a prompt on the screen and read a
-            /// single character from the keyboard.
-            /// The character is echoed onto the console
-            /// monitor, and its ASCII code is copied
-            /// into R0. The high eight bits of R0 are
-            /// cleared
-            print!("Enter a  character : ");
-            io::stdout().flush().expect("failed to flush");
-            let char = std::io::stdin()
-                .bytes()
-                .next()
-                .and_then(|result| result.ok())
-                .map(|byte| byte as u16)
-                .unwrap();
+            // IN
+            // Print a prompt on the screen and read a
+            // single character from the keyboard.
+            // The character is echoed onto the console
+            // monitor, and its ASCII code is copied
+            // into R0. The high eight bits of R0 are
+            // cleared
+            put_str(vm, "Enter a  character : ");
+            let char = get_char(vm);
+            if char != 0 {
+                put_char(vm, char as u8);
+            }
             vm.registers.update(0, char);
         }
         0x24 => {
-            /// PUTSP
-            /// Write a string of ASCII characters to the 
-            /// console. The characters are contained in
-            /// consecutive memory locations, two characters
-            /// per memory location, starting with the
-            /// address specified in R0. The ASCII code
-            /// contained in bits [7:0] of a memory
-            /// location is written to the console first.
-            /// Then the ASCII code contained in bits
-            /// [15:8] of that memory location is written
-            /// to the console. (A character string
-            /// consisting of an odd number of characters
-            /// to be written will have x00 in bits
-            /// [15:8] of the memory location containing
-            /// the last character to be written.) Writing
-            /// terminates with the occurrence of x0000 in
-            /// a memory location.
+            // PUTSP
+            // Write a string of ASCII characters to the 
+            // console. The characters are contained in
+            // consecutive memory locations, two characters
+            // per memory location, starting with the
+            // address specified in R0. The ASCII code
+            // contained in bits [7:0] of a memory
+            // location is written to the console first.
+            // Then the ASCII code contained in bits
+            // [15:8] of that memory location is written
+            // to the console. (A character string
+            // consisting of an odd number of characters
+            // to be written will have x00 in bits
+            // [15:8] of the memory location containing
+            // the last character to be written.) Writing
+            // terminates with the occurrence of x0000 in
+            // a memory location.
             let mut index = vm.registers.r0;
             let mut c = vm.read_memory(index);
             while c != 0x0000 {
-                let c1 = ((c & 0xFF) as u8) as char;
-                print!("{}", c1);
-                let c2 = ((c >> 8) as u8) as char;
-                if c2 != '\0' {
-                    print!("{}", c2);
+                put_char(vm, (c & 0xFF) as u8);
+                let c2 = (c >> 8) as u8;
+                if c2 != 0 {
+                    put_char(vm, c2);
                 }
-                index += 1;
+                index = index.wrapping_add(1);
                 c = vm.read_memory(index);
             }
-            io::stdout().flush().expect("Failed to flush");
         }
         0x25 => {
-            /// HALT
-            /// Halt execution and print a message on
-            /// the console.
-            println!("HALT detected");
-            io::stdout().flush().expect("Failed to flush");
-            process::exit(0);
+            // HALT
+            // Halt execution and print a message on
+            // the console.
+            put_str(vm, "HALT detected\n");
+            vm.halt();
+        }
+        0x30 => {
+            // DREAD
+            // Read the disk sector whose number is in R0
+            // into the 256 words starting at the address
+            // in R1. R0 is set to 0 on success and to -1
+            // if the transfer failed or no disk is attached.
+            disk_transfer(vm, disk::DISK_READ);
+        }
+        0x31 => {
+            // DWRITE
+            // Write the 256 words starting at the address
+            // in R1 to the disk sector whose number is in
+            // R0. R0 is set to 0 on success and to -1 if
+            // the transfer failed or no disk is attached.
+            disk_transfer(vm, disk::DISK_WRITE);
+        }
+        0x32 => {
+            // RAND
+            // Read a random word from the random number
+            // generator into R0.
+            let value = vm.read_memory(MemoryMappedReg::Rngdr as u16);
+            vm.registers.update(0, value);
         }
         _ => {
-            println!("Invalid trap vector");
-            process::exit(1);
+            vm.stop(Fault::InvalidTrap {
+                vector: trap_vector as u8,
+                pc: vm.registers.pc.wrapping_sub(1),
+            });
         }
     }
 }
 
+/// Read a character through the keyboard registers,
+/// 0 if no character is available
+fn get_char(vm: &mut VM) -> u16 {
+    if vm.read_memory(MemoryMappedReg::Kbsr as u16) & (1 << 15) != 0 {
+        vm.read_memory(MemoryMappedReg::Kbdr as u16)
+    } else {
+        0
+    }
+}
+
+/// Program the disk controller and wait for
+/// the command to complete
+fn disk_transfer(vm: &mut VM, command: u16) {
+    if vm.bus.device::<Disk>().is_none() {
+        vm.registers.update(0, 0xFFFF);
+        return;
+    }
+    vm.write_memory(MemoryMappedReg::Dsksec as usize, vm.registers.get(0));
+    vm.write_memory(MemoryMappedReg::Dskadr as usize, vm.registers.get(1));
+    vm.write_memory(MemoryMappedReg::Dskcmd as usize, command);
+    let instructions = vm.instructions;
+    vm.bus.with_dma(&mut vm.memory, instructions, |disk: &mut Disk, dma| disk.execute(dma));
+
+    let status = vm.read_memory(MemoryMappedReg::Dsksr as u16);
+    let result = if status & disk::DISK_ERROR != 0 { 0xFFFF } else { 0 };
+    vm.registers.update(0, result);
+}
+
+/// Write a character through the display registers
+fn put_char(vm: &mut VM, c: u8) {
+    vm.write_memory(MemoryMappedReg::Ddr as usize, c as u16);
+}
+
+fn put_str(vm: &mut VM, s: &str) {
+    for c in s.bytes() {
+        put_char(vm, c);
+    }
+}
+
+/// RTI
+/// If the processor is running in Supervisor mode,
+/// the top two elements on the Supervisor Stack are
+/// popped and loaded into PC, PSR. If the processor
+/// is returning to User mode, the Supervisor stack
+/// pointer is saved and R6 is loaded with the User
+/// stack pointer. If the processor is running in
+/// User mode, a privilege mode violation exception
+/// occurs.
+pub fn rti(_instruction: u16, vm: &mut VM) {
+    if vm.registers.user_mode {
+        vm.exception(PRIVILEGE_EXCEPTION);
+        return;
+    }
+    vm.registers.pc = vm.pop();
+    let psr = vm.pop();
+    vm.registers.set_psr(psr);
+    if vm.registers.user_mode {
+        vm.registers.saved_ssp = vm.registers.r6;
+        vm.registers.r6 = vm.registers.saved_usp;
+    }
+}
+
 /// LEA
 /// OPCODE u4, DR u3, PCOffset9 u9
 /// An address is computed by sign-extending bits
@@ -200,10 +283,10 @@ fn trap(instr: u16, vm: &mut VM) {
 /// The condition codes are set, based on whether
 /// the value loaded is negative, zero, or positive.
 fn lea(instr: u16, vm: &mut VM) {
-    let mut dr = (instr >> 9) & 0x7;
-    let mut pc_offset = sign_extend(instr & 0x1FF, 9);
-    pc_offset += vm.registers.pc;
-    vm.registers.update(dr, pc_offset);
+    let dr = (instr >> 9) & 0x7;
+    let pc_offset = sign_extend(instr & 0x1FF, 9);
+    let address = vm.registers.pc.wrapping_add(pc_offset);
+    vm.registers.update(dr, address);
     vm.registers.update_r_cond_register(dr);
 }
 
@@ -248,7 +331,7 @@ fn add(instr: u16, vm: &mut VM) {
 pub fn ldi(instruction: u16, vm: &mut VM) {
     let dr = (instruction >> 9) & 0x7;
     let pc_offset = sign_extend(instruction & 0x1ff, 9);
-    let first_read = vm.read_memory(vm.registers.pc + pc_offset);
+    let first_read = vm.read_memory(vm.registers.pc.wrapping_add(pc_offset));
     let resulting_address = vm.read_memory(first_read);
     vm.registers.update(dr, resulting_address);
     vm.registers.update_r_cond_register(dr);
@@ -344,7 +427,9 @@ pub fn jsr(instruction: u16, vm: &mut VM) {
 
     let long_flag = (instruction >> 11) & 1;
 
-    vm.registers.r7 = vm.registers.pc;
+    // The base register is read before R7 is written,
+    // so JSRR R7 jumps to the old value of R7
+    let return_address = vm.registers.pc;
 
     if long_flag != 0 {
         let val: u32 = vm.registers.pc as u32 + long_pc_offset as u32;
@@ -352,6 +437,7 @@ pub fn jsr(instruction: u16, vm: &mut VM) {
     } else {
         vm.registers.pc = vm.registers.get(base_reg);
     }
+    vm.registers.r7 = return_address;
 }
 
 /// LD
@@ -386,7 +472,7 @@ pub fn ldr(instruction: u16, vm: &mut VM) {
     let base_reg = (instruction >> 6) & 0x7;
     let offset = sign_extend(instruction & 0x3F, 6);
     let val: u32 = vm.registers.get(base_reg) as u32 + offset as u32;
-    let mem_value = vm.read_memory(val as u16).clone();
+    let mem_value = vm.read_memory(val as u16);
 
     vm.registers.update(dr, mem_value);
     vm.registers.update_r_cond_register(dr);
@@ -1,3 +1,7 @@
+pub mod bus;
+pub mod device;
 pub mod instruction;
 pub mod register;
+pub mod replay;
+pub mod snapshot;
 pub mod vm;
@@ -1,5 +1,10 @@
+/// Conventional start of user programs
 pub const PC_START: u16 = 0x3000;
+/// Initial supervisor stack pointer, the supervisor
+/// stack grows down from the start of user space
+pub const SSP_START: u16 = 0x3000;
 
+#[allow(clippy::upper_case_acronyms)]
 pub enum ConditionFlag {
     // We are bit shifting to the left
     // for the value of each flag
@@ -19,6 +24,16 @@ pub struct Registers {
     pub r7: u16,
     pub pc: u16,   // Program Counter
     pub cond: u16, // Condition flags
+    pub user_mode: bool, // PSR[15] privilege
+    pub priority: u16,   // PSR[10:8] priority level
+    pub saved_ssp: u16,  // Saved supervisor stack pointer
+    pub saved_usp: u16,  // Saved user stack pointer
+}
+
+impl Default for Registers {
+    fn default() -> Registers {
+        Registers::new()
+    }
 }
 
 impl Registers {
@@ -32,11 +47,30 @@ impl Registers {
             r5: 0,
             r6: 0,
             r7: 0,
+            // The loader moves it to the entry point of
+            // images that have one
             pc: PC_START,
-            cond: 0,
+            // The machine starts with Z set, like PSR x8002
+            cond: ConditionFlag::ZRO as u16,
+            user_mode: true,
+            priority: 0,
+            saved_ssp: SSP_START,
+            saved_usp: 0,
         }
     }
 
+    /// Processor status register:
+    /// privilege [15], priority [10:8], condition codes [2:0]
+    pub fn psr(&self) -> u16 {
+        ((self.user_mode as u16) << 15) | ((self.priority & 0x7) << 8) | (self.cond & 0x7)
+    }
+
+    pub fn set_psr(&mut self, psr: u16) {
+        self.user_mode = (psr >> 15) & 1 == 1;
+        self.priority = (psr >> 8) & 0x7;
+        self.cond = psr & 0x7;
+    }
+
     pub fn update(&mut self, index: u16, value: u16) {
         match index {
             0 => self.r0 = value,
@@ -0,0 +1,102 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+/// First line of every input log
+pub const LOG_HEADER: &str = "# lc3 input log v1";
+
+/// A keyboard register read, stamped with the
+/// number of instructions executed before it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InputEvent {
+    pub instruction: u64,
+    pub address: u16,
+    pub value: u16,
+}
+
+/// Everything needed to reproduce the input of a run.
+///
+/// The log is a text file:
+///
+/// # lc3 input log v1
+/// seed 1234
+/// 1523 xFE00 x8000
+/// 1524 xFE02 x0061
+///
+/// The seed line is present when the random number
+/// generator was seeded for the recording.
+#[derive(Debug, Default)]
+pub struct InputLog {
+    pub seed: Option<u16>,
+    pub events: VecDeque<InputEvent>,
+}
+
+impl InputLog {
+    pub fn read<R: BufRead>(input: R) -> io::Result<InputLog> {
+        let mut lines = input.lines();
+        match lines.next() {
+            Some(Ok(line)) if line.trim() == LOG_HEADER => {}
+            _ => return Err(invalid("not an input log")),
+        }
+
+        let mut log = InputLog::default();
+        for (number, line) in lines.enumerate() {
+            let line = line?;
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let error = || invalid(&format!("invalid input log line {}: {}", number + 2, line));
+            match fields.as_slice() {
+                [] => {}
+                ["seed", seed] => log.seed = Some(seed.parse().map_err(|_| error())?),
+                [instruction, address, value] => log.events.push_back(InputEvent {
+                    instruction: instruction.parse().map_err(|_| error())?,
+                    address: parse_hex(address).ok_or_else(error)?,
+                    value: parse_hex(value).ok_or_else(error)?,
+                }),
+                _ => return Err(error()),
+            }
+        }
+        Ok(log)
+    }
+}
+
+/// Why the keyboard stopped replaying or recording
+#[derive(Debug)]
+pub enum ReplayError {
+    /// The program read a keyboard register at
+    /// another time or address than in the log
+    Diverged { instruction: u64, address: u16 },
+    /// The input log could not be written
+    Io(io::Error),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Diverged { instruction, address } => {
+                write!(f, "Replay diverged at instruction {} reading x{:04X}", instruction, address)
+            }
+            ReplayError::Io(e) => write!(f, "Unable to write input log: {}", e),
+        }
+    }
+}
+
+pub fn write_header<W: Write>(out: &mut W, seed: Option<u16>) -> io::Result<()> {
+    writeln!(out, "{}", LOG_HEADER)?;
+    if let Some(seed) = seed {
+        writeln!(out, "seed {}", seed)?;
+    }
+    out.flush()
+}
+
+pub fn write_event<W: Write + ?Sized>(out: &mut W, event: &InputEvent) -> io::Result<()> {
+    writeln!(out, "{} x{:04X} x{:04X}", event.instruction, event.address, event.value)?;
+    out.flush()
+}
+
+fn parse_hex(s: &str) -> Option<u16> {
+    u16::from_str_radix(s.strip_prefix('x')?, 16).ok()
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,139 @@
+use crate::hardware::vm::{Fault, VM};
+use crate::MEMORY_SIZE;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+/// Magic bytes at the start of every snapshot
+pub const SNAPSHOT_MAGIC: &[u8; 4] = b"LC3S";
+/// Version of the snapshot layout
+pub const SNAPSHOT_VERSION: u16 = 2;
+
+/// Snapshot layout, every number is big endian:
+///
+/// magic "LC3S", version u16
+/// R0-R7, PC, PSR, saved SSP, saved USP (12 x u16)
+/// executed instructions u64
+/// fault kind u16 (0 none, 1 exception, 2 invalid
+///   trap), vector u16, PC u16
+/// memory (65536 x u16)
+/// device count u16, then for every device:
+///   name length u16, name (UTF-8),
+///   state length u32, state (u16 each)
+impl VM {
+    pub fn save_snapshot<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        out.write_all(SNAPSHOT_MAGIC)?;
+        out.write_u16::<BigEndian>(SNAPSHOT_VERSION)?;
+
+        let r = &self.registers;
+        for value in [
+            r.r0, r.r1, r.r2, r.r3, r.r4, r.r5, r.r6, r.r7,
+            r.pc, r.psr(), r.saved_ssp, r.saved_usp,
+        ] {
+            out.write_u16::<BigEndian>(value)?;
+        }
+        out.write_u64::<BigEndian>(self.instructions)?;
+        let fault = match self.fault {
+            None => [0, 0, 0],
+            Some(Fault::Exception { vector, pc }) => [1, vector as u16, pc],
+            Some(Fault::InvalidTrap { vector, pc }) => [2, vector as u16, pc],
+        };
+        for value in fault {
+            out.write_u16::<BigEndian>(value)?;
+        }
+
+        for &word in self.memory.iter() {
+            out.write_u16::<BigEndian>(word)?;
+        }
+
+        let devices: Vec<_> = self.bus.devices().collect();
+        out.write_u16::<BigEndian>(devices.len() as u16)?;
+        for device in devices {
+            let name = device.name().as_bytes();
+            out.write_u16::<BigEndian>(name.len() as u16)?;
+            out.write_all(name)?;
+            let state = device.save();
+            out.write_u32::<BigEndian>(state.len() as u32)?;
+            for word in state {
+                out.write_u16::<BigEndian>(word)?;
+            }
+        }
+        out.flush()
+    }
+
+    /// Restore the machine from a snapshot. Devices in
+    /// the snapshot that are not attached to this VM
+    /// are ignored.
+    pub fn load_snapshot<R: Read>(&mut self, input: &mut R) -> io::Result<()> {
+        let mut magic = [0; 4];
+        input.read_exact(&mut magic)?;
+        if &magic != SNAPSHOT_MAGIC {
+            return Err(invalid("not a snapshot file"));
+        }
+        let version = input.read_u16::<BigEndian>()?;
+        if version != SNAPSHOT_VERSION {
+            return Err(invalid(&format!("unsupported snapshot version {}", version)));
+        }
+
+        let mut registers = [0; 12];
+        input.read_u16_into::<BigEndian>(&mut registers)?;
+        let instructions = input.read_u64::<BigEndian>()?;
+        let mut words = [0; 3];
+        input.read_u16_into::<BigEndian>(&mut words)?;
+        let [kind, vector, pc] = words;
+        let vector = u8::try_from(vector).map_err(|_| invalid("invalid fault vector"))?;
+        let fault = match kind {
+            0 => None,
+            1 => Some(Fault::Exception { vector, pc }),
+            2 => Some(Fault::InvalidTrap { vector, pc }),
+            _ => return Err(invalid("invalid fault")),
+        };
+        let mut memory = vec![0; MEMORY_SIZE];
+        input.read_u16_into::<BigEndian>(&mut memory)?;
+
+        let mut devices = Vec::new();
+        for _ in 0..input.read_u16::<BigEndian>()? {
+            let mut name = vec![0; input.read_u16::<BigEndian>()? as usize];
+            input.read_exact(&mut name)?;
+            let name = String::from_utf8(name).map_err(|_| invalid("invalid device name"))?;
+            let length = input.read_u32::<BigEndian>()? as usize;
+            if length > MEMORY_SIZE {
+                return Err(invalid("device state too large"));
+            }
+            let mut state = vec![0; length];
+            input.read_u16_into::<BigEndian>(&mut state)?;
+            devices.push((name, state));
+        }
+
+        // Only touch the machine once the whole file is valid
+        let r = &mut self.registers;
+        let [r0, r1, r2, r3, r4, r5, r6, r7, pc, psr, ssp, usp] = registers;
+        (r.r0, r.r1, r.r2, r.r3, r.r4, r.r5, r.r6, r.r7) = (r0, r1, r2, r3, r4, r5, r6, r7);
+        r.pc = pc;
+        r.set_psr(psr);
+        r.saved_ssp = ssp;
+        r.saved_usp = usp;
+        self.instructions = instructions;
+        self.fault = fault;
+        self.memory.copy_from_slice(&memory);
+        for (name, state) in devices {
+            if let Some(device) = self.bus.device_named_mut(&name) {
+                device.restore(&state);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn save_snapshot_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.save_snapshot(&mut BufWriter::new(File::create(path)?))
+    }
+
+    pub fn load_snapshot_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        self.load_snapshot(&mut BufReader::new(File::open(path)?))
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
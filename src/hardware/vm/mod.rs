@@ -1,45 +1,230 @@
+use crate::hardware::bus::{Bus, Interrupt};
+use crate::hardware::device::display::Display;
+use crate::hardware::device::framebuffer::Framebuffer;
+use crate::hardware::device::keyboard::Keyboard;
+use crate::hardware::device::mcr::Mcr;
+use crate::hardware::device::rng::Rng;
+use crate::hardware::device::timer::Timer;
+use crate::hardware::instruction;
 use crate::hardware::register::*;
 use crate::MEMORY_SIZE;
-use std::io::Read;
+use std::fmt;
+use std::io::{Read, Write};
+
+/// Base address of the interrupt vector table.
+/// Exceptions use vectors x00-x7F and
+/// interrupts use vectors x80-xFF.
+pub const INTERRUPT_TABLE: u16 = 0x0100;
+
+/// Exception raised by RTI in user mode
+pub const PRIVILEGE_EXCEPTION: u8 = 0x00;
+/// Exception raised by the reserved opcode
+pub const ILLEGAL_OPCODE_EXCEPTION: u8 = 0x01;
+
+/// An error that stops the machine
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// An exception was raised but no handler
+    /// is installed in the vector table
+    Exception { vector: u8, pc: u16 },
+    /// TRAP to a vector without a service routine
+    InvalidTrap { vector: u8, pc: u16 },
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Fault::Exception { vector, pc } => {
+                write!(f, "Unhandled exception x{:02X} at x{:04X}", vector, pc)
+            }
+            Fault::InvalidTrap { vector, pc } => {
+                write!(f, "Invalid trap vector x{:02X} at x{:04X}", vector, pc)
+            }
+        }
+    }
+}
 
 /// The memory is just a bit array of 16-bit unsigned integers.
+/// Addresses claimed by a device on the bus are
+/// routed to the device instead.
 pub struct VM  {
     pub memory: [u16; MEMORY_SIZE],
     pub registers: Registers,
+    pub bus: Bus,
+    /// Instructions executed so far
+    pub instructions: u64,
+    /// Set when the machine stopped because of an error
+    pub fault: Option<Fault>,
 }
 
 pub enum MemoryMappedReg {
     Kbsr = 0xFE00,    // Keyboard status
     Kbdr = 0xFE02,    // Keyboard data
+    Dsr = 0xFE04,     // Display status
+    Ddr = 0xFE06,     // Display data
+    Tmcr = 0xFE08,    // Timer control/status
+    Tmir = 0xFE0A,    // Timer reload interval
+    Tmcnt = 0xFE0C,   // Timer counter
+    Tmiv = 0xFE0E,    // Timer interrupt vector
+    Dsksr = 0xFE10,   // Disk status
+    Dsksec = 0xFE12,  // Disk sector
+    Dskadr = 0xFE14,  // Disk DMA buffer address
+    Dskcmd = 0xFE16,  // Disk command
+    Rngdr = 0xFE18,   // Random data
+    Rngcr = 0xFE1A,   // Random generator control
+    Rngsd = 0xFE1C,   // Random generator seed
+    Mcr = 0xFFFE,     // Machine control
+}
+
+impl Default for VM {
+    fn default() -> VM {
+        VM::new()
+    }
 }
 
 impl VM {
+    /// Create a VM attached to the terminal
     pub fn new() -> VM {
+        VM::with_console(Box::new(std::io::stdin()), Box::new(std::io::stdout()))
+    }
+
+    /// Create a VM with the keyboard reading from
+    /// `input` and the display writing to `output`
+    pub fn with_console(input: Box<dyn Read>, output: Box<dyn Write>) -> VM {
+        let mut bus = Bus::new();
+        bus.attach(Box::new(Keyboard::new(input)));
+        bus.attach(Box::new(Display::new(output)));
+        bus.attach(Box::new(Timer::new()));
+        bus.attach(Box::new(Framebuffer::headless()));
+        bus.attach(Box::new(Rng::entropy()));
+        bus.attach(Box::new(Mcr::new()));
         VM {
             memory: [0; MEMORY_SIZE],
             registers: Registers::new(),
+            bus,
+            instructions: 0,
+            fault: None,
         }
     }
-    
+
     pub fn write_memory(&mut self, address: usize, value: u16) {
-        self.memory[address] = value;
+        if !self.bus.write(address as u16, value) {
+            self.memory[address] = value;
+        }
     }
 
     pub fn read_memory(&mut self, address: u16) -> u16 {
-        if (address == MemoryMappedReg::Kbsr as u16) {
-            self.handle_keyboard();
+        match self.bus.read(address, self.instructions) {
+            Some(value) => value,
+            None => self.memory[address as usize],
+        }
+    }
+
+    /// Read memory or a device register without
+    /// changing the state of the device
+    pub fn peek_memory(&mut self, address: u16) -> u16 {
+        match self.bus.peek(address) {
+            Some(value) => value,
+            None => self.memory[address as usize],
+        }
+    }
+
+    /// The machine runs as long as the clock
+    /// enable bit of the MCR is set
+    pub fn is_running(&self) -> bool {
+        self.bus.device::<Mcr>().is_none_or(|mcr| mcr.clock_enabled())
+    }
+
+    /// Stop the clock by clearing MCR[15]
+    pub fn halt(&mut self) {
+        let mcr = self.read_memory(MemoryMappedReg::Mcr as u16);
+        self.write_memory(MemoryMappedReg::Mcr as usize, mcr & !(1 << 15));
+    }
+
+    /// Execute a single instruction, then let
+    /// the devices run and serve their interrupts
+    pub fn step(&mut self) {
+        // Read instruction
+        let instruction = self.read_memory(self.registers.pc);
+
+        // Increment PC
+        self.registers.pc = self.registers.pc.wrapping_add(1);
+
+        // Extract op_code and execute operation
+        instruction::execute_instruction(instruction, self);
+        self.instructions += 1;
+
+        // A halted machine stays where the program stopped
+        if !self.is_running() {
+            return;
+        }
+        if let Some(interrupt) = self.bus.tick(&mut self.memory, self.instructions) {
+            if interrupt.priority > self.registers.priority {
+                self.interrupt(interrupt);
+            }
+        }
+    }
+
+    /// Start the service routine of an interrupt.
+    /// Without a handler the machine stops with a fault.
+    pub fn interrupt(&mut self, interrupt: Interrupt) {
+        if self.read_memory(INTERRUPT_TABLE + interrupt.vector as u16) == 0 {
+            self.stop(Fault::Exception {
+                vector: interrupt.vector,
+                pc: self.registers.pc,
+            });
+            return;
         }
-        self.memory[address as usize]
+        self.enter_service_routine(interrupt.vector, interrupt.priority);
     }
 
-    fn handle_keyboard(&mut self) {
-        let mut buffer = [0; 1];
-        std::io::stdin().read_exact(&mut buffer).unwrap();
-        if buffer[0] != 0 {
-            self.write_memory(MemoryMappedReg::Kbsr as usize, 1 << 15);
-            self.write_memory(MemoryMappedReg::Kbdr as usize, buffer[0] as u16);
-        } else {
-           self.write_memory(MemoryMappedReg::Kbsr as usize, 0)
+    /// Start the handler of an exception, the
+    /// priority level is not changed. Without a
+    /// handler the machine stops with a fault.
+    pub fn exception(&mut self, vector: u8) {
+        if self.read_memory(INTERRUPT_TABLE + vector as u16) == 0 {
+            self.stop(Fault::Exception {
+                vector,
+                pc: self.registers.pc.wrapping_sub(1),
+            });
+            return;
         }
+        self.enter_service_routine(vector, self.registers.priority);
+    }
+
+    /// Record the fault and halt the machine
+    pub fn stop(&mut self, fault: Fault) {
+        self.fault = Some(fault);
+        self.halt();
+    }
+
+    /// Push PSR and PC on the supervisor stack and
+    /// jump to the address in the vector table
+    fn enter_service_routine(&mut self, vector: u8, priority: u16) {
+        let psr = self.registers.psr();
+        if self.registers.user_mode {
+            self.registers.saved_usp = self.registers.r6;
+            self.registers.r6 = self.registers.saved_ssp;
+        }
+        self.push(psr);
+        self.push(self.registers.pc);
+
+        self.registers.user_mode = false;
+        self.registers.priority = priority;
+        self.registers.cond = 0;
+        self.registers.pc = self.read_memory(INTERRUPT_TABLE + vector as u16);
+    }
+
+    /// Push a value on the stack pointed by R6
+    pub fn push(&mut self, value: u16) {
+        self.registers.r6 = self.registers.r6.wrapping_sub(1);
+        self.write_memory(self.registers.r6 as usize, value);
+    }
+
+    /// Pop a value from the stack pointed by R6
+    pub fn pop(&mut self) -> u16 {
+        let value = self.read_memory(self.registers.r6);
+        self.registers.r6 = self.registers.r6.wrapping_add(1);
+        value
     }
 }
@@ -0,0 +1,11 @@
+
+pub mod analysis;
+pub mod assembler;
+pub mod compiler;
+pub mod hardware;
+pub mod linker;
+pub mod loader;
+pub mod runner;
+pub mod testcase;
+
+pub const MEMORY_SIZE: usize = u16::MAX as usize + 1;
@@ -0,0 +1,358 @@
+//! Link relocatable objects into a loadable image.
+//!
+//! `.ORIG` blocks stay at their origin. Sections
+//! with the same name are put one after the other,
+//! in the order of the objects, and every name
+//! follows the previous one from `Options::base`,
+//! unless `Options::placements` gives its address.
+//! References to other sections and to `.GLOBAL`
+//! symbols of other objects are then filled in.
+use crate::assembler;
+use crate::hardware::register::PC_START;
+use crate::loader::{Image, LoadError, Segment};
+use std::collections::BTreeMap;
+use std::fmt;
+
+pub mod object;
+
+pub use object::{Definition, Field, Object, Relocation, Section, Target};
+
+#[derive(Debug)]
+pub enum LinkError {
+    /// Two objects export the same symbol
+    Duplicate { name: String, first: String, second: String },
+    /// An external that no object exports
+    Undefined { name: String, object: String },
+    /// A PC relative offset does not fit in its field
+    Overflow {
+        object: String,
+        address: u16,
+        field: Field,
+        offset: i32,
+        target: String,
+    },
+    /// A placement names a section no object has
+    UnknownSection(String),
+    /// The entry point is neither a symbol nor an address
+    UnknownEntry(String),
+    /// The placed sections do not fit in memory
+    Load(LoadError),
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LinkError::Duplicate { name, first, second } => {
+                write!(f, "`{}` is exported by both {} and {}", name, first, second)
+            }
+            LinkError::Undefined { name, object } => {
+                write!(f, "{}: undefined external `{}`", object, name)
+            }
+            LinkError::Overflow {
+                object,
+                address,
+                field,
+                offset,
+                target,
+            } => {
+                let bits = field.bits().unwrap_or(16);
+                write!(
+                    f,
+                    "{}: x{:04X}: offset {} to {} does not fit in {} ({} to {})",
+                    object,
+                    address,
+                    offset,
+                    target,
+                    field,
+                    -(1 << (bits - 1)),
+                    (1 << (bits - 1)) - 1
+                )
+            }
+            LinkError::UnknownSection(name) => write!(f, "no section named `{}`", name),
+            LinkError::UnknownEntry(entry) => write!(f, "unknown entry point `{}`", entry),
+            LinkError::Load(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for LinkError {}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Field::Word => write!(f, "a word"),
+            Field::PcOffset9 => write!(f, "PCoffset9"),
+            Field::PcOffset11 => write!(f, "PCoffset11"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    /// Address of the first section, x3000 by default
+    pub base: Option<u16>,
+    /// Address of named sections
+    pub placements: BTreeMap<String, u16>,
+    /// A symbol or an address, by default x3000 if
+    /// a segment starts there, otherwise the first one
+    pub entry: Option<String>,
+}
+
+/// Place the sections of the objects, resolve their
+/// references and build the image. Every error is
+/// reported, not only the first one.
+pub fn link(objects: &[Object], options: &Options) -> Result<Image, Vec<LinkError>> {
+    let mut errors = Vec::new();
+    let name = |i: usize| objects[i].source.clone().unwrap_or_else(|| format!("object {}", i + 1));
+
+    // Exported symbols: object and definition
+    let mut globals: BTreeMap<&str, (usize, &Definition)> = BTreeMap::new();
+    for (i, object) in objects.iter().enumerate() {
+        for definition in object.symbols.iter().filter(|d| d.global) {
+            match globals.get(definition.name.as_str()) {
+                Some(&(first, _)) => errors.push(LinkError::Duplicate {
+                    name: definition.name.clone(),
+                    first: name(first),
+                    second: name(i),
+                }),
+                None => {
+                    globals.insert(&definition.name, (i, definition));
+                }
+            }
+        }
+    }
+
+    let addresses = match place(objects, options) {
+        Ok(addresses) => addresses,
+        Err(e) => {
+            errors.push(e);
+            return Err(errors);
+        }
+    };
+    let address_of = |i: usize, definition: &Definition| {
+        addresses[i][definition.section].wrapping_add(definition.offset)
+    };
+
+    for (i, object) in objects.iter().enumerate() {
+        for external in object.externals.iter().filter(|e| !globals.contains_key(e.as_str())) {
+            errors.push(LinkError::Undefined {
+                name: external.clone(),
+                object: name(i),
+            });
+        }
+    }
+
+    let mut segments = Vec::new();
+    for (i, object) in objects.iter().enumerate() {
+        for (j, section) in object.sections.iter().enumerate() {
+            let origin = addresses[i][j];
+            let mut words = section.words.clone();
+            for relocation in &section.relocations {
+                let base = match &relocation.target {
+                    Target::Section(k) => addresses[i][*k],
+                    Target::Symbol(symbol) => match globals.get(symbol.as_str()) {
+                        Some(&(k, definition)) => address_of(k, definition),
+                        None => {
+                            if !object.externals.contains(symbol) {
+                                errors.push(LinkError::Undefined {
+                                    name: symbol.clone(),
+                                    object: name(i),
+                                });
+                            }
+                            continue;
+                        }
+                    },
+                };
+                let target = (base as i32 + relocation.addend) as u16;
+                let described = match &relocation.target {
+                    Target::Symbol(symbol) if relocation.addend == 0 => format!("`{}`", symbol),
+                    Target::Symbol(symbol) => format!("`{}`{:+}", symbol, relocation.addend),
+                    Target::Section(_) => format!("x{:04X}", target),
+                };
+                let address = origin.wrapping_add(relocation.offset);
+                let word = &mut words[relocation.offset as usize];
+                match relocation.field.bits() {
+                    None => *word = target,
+                    Some(bits) => {
+                        let offset = target as i32 - (address as i32 + 1);
+                        if offset < -(1 << (bits - 1)) || offset >= 1 << (bits - 1) {
+                            errors.push(LinkError::Overflow {
+                                object: name(i),
+                                address,
+                                field: relocation.field,
+                                offset,
+                                target: described,
+                            });
+                            continue;
+                        }
+                        let mask = (1u16 << bits) - 1;
+                        *word = (*word & !mask) | (offset as u16 & mask);
+                    }
+                }
+            }
+            segments.push(Segment { origin, words });
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let mut image = Image::new(segments).map_err(|e| vec![LinkError::Load(e)])?;
+    for (&symbol, &(i, definition)) in &globals {
+        image.symbols.insert(symbol.to_string(), address_of(i, definition));
+    }
+    // Local labels, when no other symbol has the name
+    for (i, object) in objects.iter().enumerate() {
+        for definition in &object.symbols {
+            image
+                .symbols
+                .entry(definition.name.clone())
+                .or_insert(address_of(i, definition));
+        }
+    }
+    if let [object] = objects {
+        image.source = object.source.clone();
+    }
+    image.entry = match &options.entry {
+        Some(entry) => Some(
+            globals
+                .get(entry.as_str())
+                .map(|&(i, definition)| address_of(i, definition))
+                .or_else(|| image.symbols.get(entry).copied())
+                .or_else(|| {
+                    assembler::parse_number(entry)
+                        .filter(|a| (0..=0xFFFF).contains(a))
+                        .map(|a| a as u16)
+                })
+                .ok_or_else(|| vec![LinkError::UnknownEntry(entry.clone())])?,
+        ),
+        None => image
+            .segments()
+            .iter()
+            .find(|s| s.origin == PC_START)
+            .or(image.segments().first())
+            .map(|s| s.origin),
+    };
+    Ok(image)
+}
+
+/// Address of every section of every object
+fn place(objects: &[Object], options: &Options) -> Result<Vec<Vec<u16>>, LinkError> {
+    let mut names: Vec<&str> = Vec::new();
+    for section in objects.iter().flat_map(|o| &o.sections) {
+        if let Some(name) = section.name.as_deref() {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+    }
+    if let Some(name) = options.placements.keys().find(|n| !names.contains(&n.as_str())) {
+        return Err(LinkError::UnknownSection(name.clone()));
+    }
+
+    let mut addresses: Vec<Vec<u16>> = objects
+        .iter()
+        .map(|o| o.sections.iter().map(|s| s.origin).collect())
+        .collect();
+    let mut next = options.base.unwrap_or(PC_START) as usize;
+    for name in names {
+        let start = options.placements.get(name).map_or(next, |&a| a as usize);
+        next = start;
+        for (i, object) in objects.iter().enumerate() {
+            for (j, section) in object.sections.iter().enumerate() {
+                if section.name.as_deref() == Some(name) {
+                    if next + section.words.len() > crate::MEMORY_SIZE {
+                        return Err(LinkError::Load(LoadError::PastEndOfMemory {
+                            origin: start as u16,
+                            length: next + section.words.len() - start,
+                        }));
+                    }
+                    addresses[i][j] = next as u16;
+                    next += section.words.len();
+                }
+            }
+        }
+    }
+    Ok(addresses)
+}
+
+const USAGE: &str = "Usage: cargo run link [-o <file.obj>] [--base <address>] \
+[--section <name>=<address>]... [--entry <symbol|address>] [--stdlib] [--extended] <file.o|file.asm>...";
+
+/// Entry point of `cargo run link ...`, returns the exit code
+pub fn main(args: Vec<String>) -> i32 {
+    match run(args) {
+        Ok(()) => 0,
+        Err(message) => {
+            eprintln!("{}", message);
+            1
+        }
+    }
+}
+
+fn run(args: Vec<String>) -> Result<(), String> {
+    let mut options = Options::default();
+    let mut output = None;
+    let mut inputs = Vec::new();
+    let mut stdlib = false;
+    let mut assembler_options = assembler::Options::default();
+    let address = |s: &str| {
+        assembler::parse_number(s)
+            .filter(|a| (0..=0xFFFF).contains(a))
+            .map(|a| a as u16)
+            .ok_or(format!("Invalid address `{}`", s))
+    };
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("Missing value for {}\n{}", arg, USAGE));
+        match arg.as_str() {
+            "-o" | "--output" => output = Some(value()?),
+            "--base" => options.base = Some(address(&value()?)?),
+            "--entry" => options.entry = Some(value()?),
+            "--stdlib" => stdlib = true,
+            "--extended" => assembler_options.extended = true,
+            "--section" => {
+                let v = value()?;
+                let (name, at) = v.split_once('=').ok_or(format!("Invalid placement `{}`", v))?;
+                options.placements.insert(name.to_string(), address(at)?);
+            }
+            _ if arg.starts_with('-') => return Err(format!("Unknown argument `{}`\n{}", arg, USAGE)),
+            _ => inputs.push(arg),
+        }
+    }
+    if inputs.is_empty() {
+        return Err(USAGE.to_string());
+    }
+
+    let mut objects = Vec::new();
+    for path in &inputs {
+        objects.push(if path.ends_with(".asm") {
+            let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+            let program = assembler::assemble_with(&text, Some(path), assembler_options)
+                .map_err(|errors| errors.iter().map(|e| e.render(path, &text)).collect::<String>())?;
+            program.object(Some(path))
+        } else {
+            let bytes = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+            object::parse(&bytes).map_err(|e| format!("{}: {}", path, e))?
+        });
+    }
+    if stdlib {
+        // Built in, so it assembles
+        let program = assembler::assemble(&assembler::stdlib::linkable()).unwrap();
+        objects.push(program.object(Some("<stdlib>")));
+    }
+    let image = link(&objects, &options)
+        .map_err(|errors| errors.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("\n"))?;
+
+    let output = output.unwrap_or_else(|| {
+        std::path::Path::new(&inputs[0])
+            .with_extension("obj")
+            .to_string_lossy()
+            .into_owned()
+    });
+    let mut bytes = Vec::new();
+    image
+        .write_object(&mut bytes)
+        .and_then(|()| std::fs::write(&output, bytes))
+        .map_err(|e| format!("{}: {}", output, e))
+}
@@ -0,0 +1,236 @@
+//! Relocatable object files, written by
+//! `assemble -c` and read by the linker.
+use crate::loader::{read_name, write_name, LoadError, RELOCATABLE_MAGIC};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{self, Write};
+
+pub const RELOCATABLE_VERSION: u16 = 1;
+
+/// The output of assembling one source file
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Object {
+    /// Source file the object was assembled from
+    pub source: Option<String>,
+    pub sections: Vec<Section>,
+    pub symbols: Vec<Definition>,
+    /// Names declared with `.EXTERNAL`
+    pub externals: Vec<String>,
+}
+
+/// A `.SECTION` placed by the linker, or an
+/// `.ORIG` block that stays at its origin
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Section {
+    /// None for an `.ORIG` block
+    pub name: Option<String>,
+    /// Address of an `.ORIG` block, 0 for a `.SECTION`
+    pub origin: u16,
+    pub words: Vec<u16>,
+    pub relocations: Vec<Relocation>,
+}
+
+/// A word the linker must fix once the
+/// addresses of the sections are known
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Relocation {
+    /// Offset of the word in its section
+    pub offset: u16,
+    pub field: Field,
+    pub target: Target,
+    /// Added to the address of the target
+    pub addend: i32,
+}
+
+/// The bits of the word to fill in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Field {
+    /// The whole word, `.FILL LABEL`
+    Word,
+    /// BR, LD, LDI, LEA, ST, STI
+    PcOffset9,
+    /// JSR
+    PcOffset11,
+}
+
+impl Field {
+    /// Width of a PC relative offset
+    pub fn bits(self) -> Option<u32> {
+        match self {
+            Field::Word => None,
+            Field::PcOffset9 => Some(9),
+            Field::PcOffset11 => Some(11),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Target {
+    /// The start of a section of the same object
+    Section(usize),
+    /// A `.GLOBAL` symbol of another object
+    Symbol(String),
+}
+
+/// A label, `global` ones are visible to other objects
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Definition {
+    pub name: String,
+    pub section: usize,
+    /// Offset of the label in its section
+    pub offset: u16,
+    pub global: bool,
+}
+
+/// Write an object after the magic bytes,
+/// every field is big endian:
+///
+/// | field     | content                                          |
+/// |-----------|--------------------------------------------------|
+/// | version   | u16, `RELOCATABLE_VERSION`                       |
+/// | source    | name                                             |
+/// | sections  | u16 count, then name, origin u16, u32 length,    |
+/// |           | words, u32 relocation count, relocations         |
+/// | symbols   | u32 count, then name, section u16, offset u16,   |
+/// |           | global u8                                        |
+/// | externals | u32 count, then name                             |
+///
+/// A relocation is offset u16, field u8 (0 word,
+/// 1 PCoffset9, 2 PCoffset11), target u8 (0 section
+/// followed by u16 index, 1 symbol followed by name)
+/// and addend i32. An `.ORIG` block has an empty name.
+pub fn write(object: &Object, out: &mut dyn Write) -> io::Result<()> {
+    out.write_all(RELOCATABLE_MAGIC)?;
+    out.write_u16::<BigEndian>(RELOCATABLE_VERSION)?;
+    write_name(out, object.source.as_deref().unwrap_or(""))?;
+
+    out.write_u16::<BigEndian>(object.sections.len() as u16)?;
+    for section in &object.sections {
+        write_name(out, section.name.as_deref().unwrap_or(""))?;
+        out.write_u16::<BigEndian>(section.origin)?;
+        out.write_u32::<BigEndian>(section.words.len() as u32)?;
+        for &word in &section.words {
+            out.write_u16::<BigEndian>(word)?;
+        }
+        out.write_u32::<BigEndian>(section.relocations.len() as u32)?;
+        for relocation in &section.relocations {
+            out.write_u16::<BigEndian>(relocation.offset)?;
+            out.write_u8(match relocation.field {
+                Field::Word => 0,
+                Field::PcOffset9 => 1,
+                Field::PcOffset11 => 2,
+            })?;
+            match &relocation.target {
+                Target::Section(index) => {
+                    out.write_u8(0)?;
+                    out.write_u16::<BigEndian>(*index as u16)?;
+                }
+                Target::Symbol(name) => {
+                    out.write_u8(1)?;
+                    write_name(out, name)?;
+                }
+            }
+            out.write_i32::<BigEndian>(relocation.addend)?;
+        }
+    }
+
+    out.write_u32::<BigEndian>(object.symbols.len() as u32)?;
+    for symbol in &object.symbols {
+        write_name(out, &symbol.name)?;
+        out.write_u16::<BigEndian>(symbol.section as u16)?;
+        out.write_u16::<BigEndian>(symbol.offset)?;
+        out.write_u8(symbol.global as u8)?;
+    }
+    out.write_u32::<BigEndian>(object.externals.len() as u32)?;
+    for name in &object.externals {
+        write_name(out, name)?;
+    }
+    Ok(())
+}
+
+/// Parse a relocatable object, see `write`
+pub fn parse(bytes: &[u8]) -> Result<Object, LoadError> {
+    let bytes = bytes.strip_prefix(RELOCATABLE_MAGIC).ok_or(LoadError::NotRelocatable)?;
+    parse_body(bytes).map_err(|e| match e {
+        LoadError::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof => LoadError::Truncated,
+        e => e,
+    })
+}
+
+fn parse_body(mut bytes: &[u8]) -> Result<Object, LoadError> {
+    let version = bytes.read_u16::<BigEndian>()?;
+    if version != RELOCATABLE_VERSION {
+        return Err(LoadError::UnsupportedVersion(version));
+    }
+    let source = read_name(&mut bytes)?;
+    let mut object = Object {
+        source: Some(source).filter(|s| !s.is_empty()),
+        ..Object::default()
+    };
+
+    let count = bytes.read_u16::<BigEndian>()? as usize;
+    for _ in 0..count {
+        let name = read_name(&mut bytes)?;
+        let origin = bytes.read_u16::<BigEndian>()?;
+        let length = bytes.read_u32::<BigEndian>()? as usize;
+        // Check before allocating
+        if bytes.len() / 2 < length {
+            return Err(LoadError::Truncated);
+        }
+        let mut words = vec![0; length];
+        bytes.read_u16_into::<BigEndian>(&mut words)?;
+        let mut relocations = Vec::new();
+        for _ in 0..bytes.read_u32::<BigEndian>()? {
+            let offset = bytes.read_u16::<BigEndian>()?;
+            let field = match bytes.read_u8()? {
+                0 => Field::Word,
+                1 => Field::PcOffset9,
+                2 => Field::PcOffset11,
+                _ => return Err(LoadError::InvalidReference),
+            };
+            let target = match bytes.read_u8()? {
+                0 => Target::Section(bytes.read_u16::<BigEndian>()? as usize),
+                1 => Target::Symbol(read_name(&mut bytes)?),
+                _ => return Err(LoadError::InvalidReference),
+            };
+            let addend = bytes.read_i32::<BigEndian>()?;
+            if offset as usize >= length || matches!(target, Target::Section(i) if i >= count) {
+                return Err(LoadError::InvalidReference);
+            }
+            relocations.push(Relocation {
+                offset,
+                field,
+                target,
+                addend,
+            });
+        }
+        object.sections.push(Section {
+            name: Some(name).filter(|n| !n.is_empty()),
+            origin,
+            words,
+            relocations,
+        });
+    }
+
+    for _ in 0..bytes.read_u32::<BigEndian>()? {
+        let name = read_name(&mut bytes)?;
+        let section = bytes.read_u16::<BigEndian>()? as usize;
+        let offset = bytes.read_u16::<BigEndian>()?;
+        let global = bytes.read_u8()? != 0;
+        if section >= count {
+            return Err(LoadError::InvalidReference);
+        }
+        object.symbols.push(Definition {
+            name,
+            section,
+            offset,
+            global,
+        });
+    }
+    for _ in 0..bytes.read_u32::<BigEndian>()? {
+        object.externals.push(read_name(&mut bytes)?);
+    }
+    if !bytes.is_empty() {
+        return Err(LoadError::TrailingData(bytes.len()));
+    }
+    Ok(object)
+}
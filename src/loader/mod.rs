@@ -0,0 +1,512 @@
+use crate::hardware::vm::VM;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// First address of the device registers,
+/// images must not write there
+pub const IO_PAGE_START: u16 = 0xFE00;
+
+/// Magic bytes of the object format
+pub const OBJECT_MAGIC: &[u8; 4] = b"LC3O";
+pub const OBJECT_VERSION: u16 = 1;
+/// Header flag: the entry point is set
+pub const FLAG_ENTRY: u16 = 1 << 0;
+/// Magic bytes of relocatable objects, see `linker`
+pub const RELOCATABLE_MAGIC: &[u8; 4] = b"LC3R";
+
+/// A block of consecutive words starting at `origin`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Segment {
+    pub origin: u16,
+    pub words: Vec<u16>,
+}
+
+impl Segment {
+    /// One past the last address, may be x10000
+    fn end(&self) -> usize {
+        self.origin as usize + self.words.len()
+    }
+}
+
+/// An error found while reading or validating an image
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    /// The file is too short to hold the origin
+    MissingOrigin,
+    /// The file ends in the middle of a word
+    OddLength(usize),
+    /// A segment runs past the end of memory
+    PastEndOfMemory { origin: u16, length: usize },
+    /// A segment writes into the device registers
+    IoPage { origin: u16, length: usize },
+    /// Two segments write the same addresses,
+    /// starting from the origin of the second
+    Overlap { first: u16, second: u16 },
+    /// The object file ends before its last field
+    Truncated,
+    /// The object file has bytes after its last field
+    TrailingData(usize),
+    UnsupportedVersion(u16),
+    /// A symbol or file name is not valid UTF-8
+    InvalidName,
+    /// Two files loaded together write the same address
+    FileOverlap {
+        first: String,
+        second: String,
+        address: u16,
+    },
+    /// An error in one of several files
+    File { path: String, error: Box<LoadError> },
+    /// A line of a text image or symbol table
+    /// that cannot be parsed, `line` starts at 1
+    InvalidLine { line: usize, text: String },
+    /// A relocatable object, which must be linked
+    Relocatable,
+    /// The linker was given something else
+    NotRelocatable,
+    /// A relocatable object refers to a section or
+    /// a word it does not have
+    InvalidReference,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "{}", e),
+            LoadError::MissingOrigin => write!(f, "the image has no origin"),
+            LoadError::OddLength(n) => write!(f, "the image has an odd length of {} bytes", n),
+            LoadError::PastEndOfMemory { origin, length } => write!(
+                f,
+                "segment at x{:04X} of {} words runs past the end of memory",
+                origin, length
+            ),
+            LoadError::IoPage { origin, length } => write!(
+                f,
+                "segment at x{:04X} of {} words overlaps the device registers at x{:04X}",
+                origin, length, IO_PAGE_START
+            ),
+            LoadError::Overlap { first, second } => write!(
+                f,
+                "segments at x{:04X} and x{:04X} overlap",
+                first, second
+            ),
+            LoadError::Truncated => write!(f, "the object file is truncated"),
+            LoadError::TrailingData(n) => {
+                write!(f, "the object file has {} unexpected trailing bytes", n)
+            }
+            LoadError::UnsupportedVersion(v) => {
+                write!(f, "unsupported object file version {}", v)
+            }
+            LoadError::InvalidName => write!(f, "the object file has a name that is not UTF-8"),
+            LoadError::FileOverlap {
+                first,
+                second,
+                address,
+            } => write!(f, "{} and {} overlap at x{:04X}", first, second, address),
+            LoadError::File { path, error } => write!(f, "{}: {}", path, error),
+            LoadError::InvalidLine { line, text } => write!(f, "line {}: invalid `{}`", line, text),
+            LoadError::Relocatable => {
+                write!(f, "relocatable object, link it first with `cargo run link`")
+            }
+            LoadError::NotRelocatable => write!(f, "not a relocatable object"),
+            LoadError::InvalidReference => {
+                write!(f, "the object file refers to a section or word it does not have")
+            }
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> LoadError {
+        LoadError::Io(e)
+    }
+}
+
+/// A validated program image: every segment fits
+/// in memory below the device registers and no
+/// two segments overlap.
+/// The debug information is only available when
+/// the image comes from an object file.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Image {
+    segments: Vec<Segment>,
+    /// Address of the first instruction
+    pub entry: Option<u16>,
+    pub symbols: BTreeMap<String, u16>,
+    /// Source file the image was assembled from
+    pub source: Option<String>,
+    /// Source line of the word at every address
+    pub lines: BTreeMap<u16, usize>,
+    /// Addresses assembled as data, not instructions.
+    /// Only known for images assembled from source.
+    pub data: BTreeSet<u16>,
+}
+
+impl Image {
+    pub fn new(segments: Vec<Segment>) -> Result<Image, LoadError> {
+        validate(&segments)?;
+        Ok(Image {
+            segments,
+            ..Image::default()
+        })
+    }
+
+    /// An image that is not validated, for writers only
+    pub(crate) fn unchecked(segments: Vec<Segment>) -> Image {
+        Image {
+            segments,
+            ..Image::default()
+        }
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    /// Write the image in the object format read by `parse`
+    pub fn write_object(&self, out: &mut dyn Write) -> io::Result<()> {
+        out.write_all(OBJECT_MAGIC)?;
+        out.write_u16::<BigEndian>(OBJECT_VERSION)?;
+        out.write_u16::<BigEndian>(if self.entry.is_some() { FLAG_ENTRY } else { 0 })?;
+        out.write_u16::<BigEndian>(self.entry.unwrap_or(0))?;
+        write_name(out, self.source.as_deref().unwrap_or(""))?;
+
+        out.write_u16::<BigEndian>(self.segments.len() as u16)?;
+        for segment in &self.segments {
+            out.write_u16::<BigEndian>(segment.origin)?;
+            out.write_u32::<BigEndian>(segment.words.len() as u32)?;
+            for &word in &segment.words {
+                out.write_u16::<BigEndian>(word)?;
+            }
+        }
+        out.write_u32::<BigEndian>(self.symbols.len() as u32)?;
+        for (name, &address) in &self.symbols {
+            out.write_u16::<BigEndian>(address)?;
+            write_name(out, name)?;
+        }
+        out.write_u32::<BigEndian>(self.lines.len() as u32)?;
+        for (&address, &line) in &self.lines {
+            out.write_u16::<BigEndian>(address)?;
+            out.write_u32::<BigEndian>(line as u32)?;
+        }
+        Ok(())
+    }
+
+    /// Write every segment into the memory of the VM
+    pub fn load(&self, vm: &mut VM) {
+        for segment in &self.segments {
+            for (i, &word) in segment.words.iter().enumerate() {
+                vm.write_memory(segment.origin as usize + i, word);
+            }
+        }
+    }
+}
+
+/// Check that the segments can be loaded together
+pub fn validate(segments: &[Segment]) -> Result<(), LoadError> {
+    for segment in segments {
+        let length = segment.words.len();
+        if segment.end() > crate::MEMORY_SIZE {
+            return Err(LoadError::PastEndOfMemory {
+                origin: segment.origin,
+                length,
+            });
+        }
+        if length > 0 && segment.end() > IO_PAGE_START as usize {
+            return Err(LoadError::IoPage {
+                origin: segment.origin,
+                length,
+            });
+        }
+    }
+
+    let mut sorted: Vec<&Segment> = segments.iter().filter(|s| !s.words.is_empty()).collect();
+    sorted.sort_by_key(|s| s.origin);
+    for pair in sorted.windows(2) {
+        if (pair[1].origin as usize) < pair[0].end() {
+            return Err(LoadError::Overlap {
+                first: pair[0].origin,
+                second: pair[1].origin,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Parse an image, either in the object format or
+/// in the legacy `.obj` format
+pub fn parse(bytes: &[u8]) -> Result<Image, LoadError> {
+    if bytes.starts_with(RELOCATABLE_MAGIC) {
+        return Err(LoadError::Relocatable);
+    }
+    match bytes.strip_prefix(OBJECT_MAGIC) {
+        Some(object) => parse_object(object).map_err(|e| match e {
+            LoadError::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof => LoadError::Truncated,
+            e => e,
+        }),
+        None => parse_legacy(bytes),
+    }
+}
+
+/// Parse a legacy `.obj` image: the origin
+/// followed by the words, all big endian.
+/// The entry point is the origin.
+pub fn parse_legacy(bytes: &[u8]) -> Result<Image, LoadError> {
+    if bytes.len() < 2 {
+        return Err(LoadError::MissingOrigin);
+    }
+    if !bytes.len().is_multiple_of(2) {
+        return Err(LoadError::OddLength(bytes.len()));
+    }
+    let mut words = bytes
+        .chunks_exact(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]));
+    let origin = words.next().unwrap_or_default();
+    let mut image = Image::new(vec![Segment {
+        origin,
+        words: words.collect(),
+    }])?;
+    image.entry = Some(origin);
+    Ok(image)
+}
+
+/// Parse the object format after the magic bytes,
+/// every field is big endian:
+///
+/// | field    | content                                        |
+/// |----------|------------------------------------------------|
+/// | version  | u16, `OBJECT_VERSION`                          |
+/// | flags    | u16, `FLAG_ENTRY`                              |
+/// | entry    | u16, meaningful with `FLAG_ENTRY`              |
+/// | source   | name                                           |
+/// | segments | u16 count, then origin u16, u32 length, words  |
+/// | symbols  | u32 count, then address u16, name              |
+/// | line map | u32 count, then address u16, line u32          |
+///
+/// A name is a u16 byte length followed by UTF-8 bytes,
+/// an empty source name means unknown.
+fn parse_object(mut bytes: &[u8]) -> Result<Image, LoadError> {
+    let version = bytes.read_u16::<BigEndian>()?;
+    if version != OBJECT_VERSION {
+        return Err(LoadError::UnsupportedVersion(version));
+    }
+    let flags = bytes.read_u16::<BigEndian>()?;
+    let entry = bytes.read_u16::<BigEndian>()?;
+    let source = read_name(&mut bytes)?;
+
+    let mut segments = Vec::new();
+    for _ in 0..bytes.read_u16::<BigEndian>()? {
+        let origin = bytes.read_u16::<BigEndian>()?;
+        let length = bytes.read_u32::<BigEndian>()? as usize;
+        // Check before allocating
+        if bytes.len() / 2 < length {
+            return Err(LoadError::Truncated);
+        }
+        let mut words = vec![0; length];
+        bytes.read_u16_into::<BigEndian>(&mut words)?;
+        segments.push(Segment { origin, words });
+    }
+    let mut image = Image::new(segments)?;
+    if flags & FLAG_ENTRY != 0 {
+        image.entry = Some(entry);
+    }
+    if !source.is_empty() {
+        image.source = Some(source);
+    }
+
+    for _ in 0..bytes.read_u32::<BigEndian>()? {
+        let address = bytes.read_u16::<BigEndian>()?;
+        let name = read_name(&mut bytes)?;
+        image.symbols.insert(name, address);
+    }
+    for _ in 0..bytes.read_u32::<BigEndian>()? {
+        let address = bytes.read_u16::<BigEndian>()?;
+        let line = bytes.read_u32::<BigEndian>()?;
+        image.lines.insert(address, line as usize);
+    }
+    if !bytes.is_empty() {
+        return Err(LoadError::TrailingData(bytes.len()));
+    }
+    Ok(image)
+}
+
+/// A u16 byte length followed by UTF-8 bytes
+pub(crate) fn write_name(out: &mut dyn Write, name: &str) -> io::Result<()> {
+    let length = name
+        .len()
+        .try_into()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "name too long"))?;
+    out.write_u16::<BigEndian>(length)?;
+    out.write_all(name.as_bytes())
+}
+
+pub(crate) fn read_name(bytes: &mut &[u8]) -> Result<String, LoadError> {
+    let length = bytes.read_u16::<BigEndian>()? as usize;
+    if bytes.len() < length {
+        return Err(LoadError::Truncated);
+    }
+    let (name, rest) = bytes.split_at(length);
+    *bytes = rest;
+    String::from_utf8(name.to_vec()).map_err(|_| LoadError::InvalidName)
+}
+
+/// Parse a `.hex` text image: one word per line
+/// written as 4 hex digits, the first is the origin
+pub fn parse_hex(text: &str) -> Result<Image, LoadError> {
+    parse_text(text, 16)
+}
+
+/// Parse a `.bin` text image: one word per line
+/// written as 16 binary digits, the first is the origin
+pub fn parse_bin(text: &str) -> Result<Image, LoadError> {
+    parse_text(text, 2)
+}
+
+fn parse_text(text: &str, radix: u32) -> Result<Image, LoadError> {
+    let digits = if radix == 16 { 4 } else { 16 };
+    let mut words = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let word = line.trim();
+        if word.is_empty() {
+            continue;
+        }
+        match u16::from_str_radix(word, radix) {
+            Ok(value) if word.len() == digits => words.push(value),
+            _ => {
+                return Err(LoadError::InvalidLine {
+                    line: i + 1,
+                    text: word.to_string(),
+                })
+            }
+        }
+    }
+    let (&origin, words) = words.split_first().ok_or(LoadError::MissingOrigin)?;
+    let mut image = Image::new(vec![Segment {
+        origin,
+        words: words.to_vec(),
+    }])?;
+    image.entry = Some(origin);
+    Ok(image)
+}
+
+/// Parse a PennSim/lc3tools `.sym` symbol table:
+///
+/// ```text
+/// // Symbol table
+/// // Scope level 0:
+/// //    Symbol Name       Page Address
+/// //    ----------------  ------------
+/// //    MAIN              3000
+/// ```
+pub fn parse_symbols(text: &str) -> Result<BTreeMap<String, u16>, LoadError> {
+    let mut symbols = BTreeMap::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        let entry = line.strip_prefix("//").unwrap_or(line).trim();
+        let fields: Vec<&str> = entry.split_whitespace().collect();
+        match fields.as_slice() {
+            [] | ["Symbol", "table"] | ["Scope", "level", _] | ["Symbol", "Name", "Page", "Address"] => {}
+            [dashes, _] if dashes.starts_with('-') => {}
+            [name, address] => {
+                let digits = address.strip_prefix(['x', 'X']).unwrap_or(address);
+                let address = u16::from_str_radix(digits, 16).map_err(|_| LoadError::InvalidLine {
+                    line: i + 1,
+                    text: line.to_string(),
+                })?;
+                symbols.insert(name.to_string(), address);
+            }
+            _ => {
+                return Err(LoadError::InvalidLine {
+                    line: i + 1,
+                    text: line.to_string(),
+                })
+            }
+        }
+    }
+    Ok(symbols)
+}
+
+/// Read and validate an image file: `.hex` and
+/// `.bin` are text images, anything else is an
+/// object file. The symbols of a `.sym` file next
+/// to the image are added to the image.
+pub fn read(path: impl AsRef<Path>) -> Result<Image, LoadError> {
+    let path = path.as_ref();
+    let extension = path.extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase);
+    let mut image = match extension.as_deref() {
+        Some("hex") => parse_hex(&std::fs::read_to_string(path)?)?,
+        Some("bin") => parse_bin(&std::fs::read_to_string(path)?)?,
+        _ => parse(&std::fs::read(path)?)?,
+    };
+    let sym = path.with_extension("sym");
+    if sym.is_file() {
+        let symbols = parse_symbols(&std::fs::read_to_string(&sym)?).map_err(|error| LoadError::File {
+            path: sym.display().to_string(),
+            error: Box::new(error),
+        })?;
+        for (name, address) in symbols {
+            image.symbols.entry(name).or_insert(address);
+        }
+    }
+    Ok(image)
+}
+
+/// Read an image file and load it into the VM
+pub fn load_file(vm: &mut VM, path: impl AsRef<Path>) -> Result<Image, LoadError> {
+    let image = read(path)?;
+    image.load(vm);
+    Ok(image)
+}
+
+/// Read image files that are loaded together,
+/// no two files may write the same address
+pub fn read_all<P: AsRef<Path>>(paths: &[P]) -> Result<Vec<Image>, LoadError> {
+    let mut images: Vec<Image> = Vec::new();
+    for path in paths {
+        let name = |path: &P| path.as_ref().display().to_string();
+        let image = read(path).map_err(|error| LoadError::File {
+            path: name(path),
+            error: Box::new(error),
+        })?;
+        for (j, other) in images.iter().enumerate() {
+            if let Some(address) = overlap(&image, other) {
+                return Err(LoadError::FileOverlap {
+                    first: name(&paths[j]),
+                    second: name(path),
+                    address,
+                });
+            }
+        }
+        images.push(image);
+    }
+    Ok(images)
+}
+
+/// First address written by both images
+fn overlap(a: &Image, b: &Image) -> Option<u16> {
+    let mut first: Option<u16> = None;
+    for x in &a.segments {
+        for y in &b.segments {
+            let start = x.origin.max(y.origin);
+            if !x.words.is_empty() && !y.words.is_empty() && (start as usize) < x.end().min(y.end()) {
+                first = Some(first.map_or(start, |f| f.min(start)));
+            }
+        }
+    }
+    first
+}
+
+/// Read image files and load them into the VM
+pub fn load_files<P: AsRef<Path>>(vm: &mut VM, paths: &[P]) -> Result<Vec<Image>, LoadError> {
+    let images = read_all(paths)?;
+    for image in &images {
+        image.load(vm);
+    }
+    Ok(images)
+}
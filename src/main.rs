@@ -1,62 +1,184 @@
-#![allow(unused)]
 use std::fs::File;
 use std::env::args;
-use byteorder::{BigEndian, ReadBytesExt};
-use crate::hardware::vm::*;
-use crate::hardware::instruction;
+use std::sync::atomic::{AtomicBool, Ordering};
+use little_computer_3::hardware::device::disk::Disk;
+use little_computer_3::hardware::device::framebuffer::Framebuffer;
+use little_computer_3::hardware::device::keyboard::Keyboard;
+use little_computer_3::hardware::device::rng::Rng;
+use little_computer_3::hardware::replay::{self, InputLog};
+use little_computer_3::hardware::vm::*;
+use little_computer_3::{analysis, assembler, compiler, linker, loader, runner, testcase};
 
-mod hardware;
-
-pub const MEMORY_SIZE: usize = std::u16::MAX as usize;
+/// Set by the signal handler to stop the machine
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
 
 fn main() {
 
-    // Open file
-    let args: Vec<String> = args().collect();
-    if args.len() < 2 {
-        println!("Usage: cargo run <filename>");
+    match args().nth(1).as_deref() {
+        Some("run") => std::process::exit(runner::main(args().skip(2).collect())),
+        Some("test") => std::process::exit(testcase::main(args().skip(2).collect())),
+        Some("assemble") => std::process::exit(assembler::main(args().skip(2).collect())),
+        Some("link") => std::process::exit(linker::main(args().skip(2).collect())),
+        Some("compile") => std::process::exit(compiler::main(args().skip(2).collect())),
+        Some("cfg") => std::process::exit(analysis::main(args().skip(2).collect())),
+        Some("lint") => std::process::exit(analysis::lint::main(args().skip(2).collect())),
+        _ => {}
+    }
+
+    // Parse arguments
+    let mut programs = Vec::new();
+    let mut entry = None;
+    let mut disk = None;
+    let mut window = false;
+    let mut screenshot = None;
+    let mut seed = None;
+    let mut snapshot = None;
+    let mut restore = None;
+    let mut record = None;
+    let mut replay = None;
+    let mut args = args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--disk" => disk = args.next(),
+            "--window" => window = true,
+            "--screenshot" => screenshot = args.next(),
+            "--seed" => seed = args.next(),
+            "--snapshot" => snapshot = args.next(),
+            "--restore" => restore = args.next(),
+            "--record" => record = args.next(),
+            "--replay" => replay = args.next(),
+            "--entry" => entry = args.next(),
+            _ if arg.starts_with('-') => {
+                eprintln!("Unknown argument `{}`", arg);
+                std::process::exit(1);
+            }
+            _ => programs.push(arg),
+        }
+    }
+    if programs.is_empty() && restore.is_none() {
+        println!("Usage: cargo run [--disk <image>] [--window] [--screenshot <file>] [--seed <n>] [--snapshot <file>] [--restore <file>] [--record <file>] [--replay <file>] [--entry <address>] <filename>...");
         std::process::exit(1);
     }
-    let mut f = File::open(args[1].clone()).expect("Unable to open file");
+    let entry: Option<u16> = entry.map(|e| {
+        assembler::parse_number(&e)
+            .filter(|a| (0..=0xFFFF).contains(a))
+            .expect("Invalid entry point") as u16
+    });
 
     // Create VM
     let mut vm = VM::new();
+    if let Some(path) = disk {
+        let disk = Disk::open(path).expect("Unable to open disk image");
+        vm.bus.attach(Box::new(disk));
+    }
+    if window {
+        attach_window(&mut vm);
+    }
+    let mut seed: Option<u16> = seed.map(|s| s.parse().expect("Invalid seed"));
+    if let Some(path) = replay {
+        let f = File::open(path).expect("Unable to open input log");
+        let log = InputLog::read(std::io::BufReader::new(f)).expect("Unable to read input log");
+        seed = log.seed.or(seed);
+        if let Some(keyboard) = vm.bus.device_mut::<Keyboard>() {
+            keyboard.replay(log);
+        }
+    } else if let Some(path) = record {
+        // Random numbers must be reproducible too
+        let seed = *seed.get_or_insert_with(|| Rng::entropy().next_word());
+        let mut f = File::create(path).expect("Unable to create input log");
+        replay::write_header(&mut f, Some(seed)).expect("Unable to write input log");
+        if let Some(keyboard) = vm.bus.device_mut::<Keyboard>() {
+            keyboard.record(Box::new(std::io::BufWriter::new(f)));
+        }
+    }
+    if let Some(seed) = seed {
+        if let Some(rng) = vm.bus.device_mut::<Rng>() {
+            *rng = Rng::seeded(seed);
+        }
+    }
 
-
-    // Read u16 instructions from file
-    let base_address = f.read_u16::<BigEndian>().expect("error");
-    // Starting from the base address
-    let mut address = base_address as usize;
-    loop {
-        match f.read_u16::<BigEndian>() {
-            Ok(instruction) => {
-                // println!("address: {:x} instruction: {:b}\n", address, instruction);
-                vm.write_memory(address, instruction);
-                address += 1;
-            },
-            Err(e) => {
-                if e.kind() == std::io::ErrorKind::UnexpectedEof {
-                    println!("OK");
-                } else {
-                    println!("failed: {}", e);
+    if let Some(path) = restore {
+        vm.load_snapshot_file(path).expect("Unable to restore snapshot");
+        // The snapshot may have been taken on HALT
+        // or on a fault
+        let mcr = vm.read_memory(MemoryMappedReg::Mcr as u16);
+        vm.write_memory(MemoryMappedReg::Mcr as usize, mcr | (1 << 15));
+        vm.fault = None;
+    }
+    if !programs.is_empty() {
+        match loader::load_files(&mut vm, &programs) {
+            Ok(images) => {
+                println!("OK");
+                // The last file is usually the user program
+                if let Some(pc) = entry.or(images.last().and_then(|i| i.entry)) {
+                    vm.registers.pc = pc;
                 }
-                break;
+            }
+            Err(e) => {
+                println!("failed: {}", e);
+                std::process::exit(1);
             }
         }
+    } else if let Some(pc) = entry {
+        vm.registers.pc = pc;
+    }
+    if snapshot.is_some() {
+        ctrlc::set_handler(|| INTERRUPTED.store(true, Ordering::SeqCst))
+            .expect("Unable to set the signal handler");
     }
 
     execute_program(&mut vm);
+    if let Some(fault) = vm.fault {
+        println!("{}", fault);
+    }
+    let replay_error = vm.bus.device::<Keyboard>().and_then(|k| k.error()).map(|e| e.to_string());
+    if let Some(e) = &replay_error {
+        println!("{}", e);
+    }
+
+    if let Some(path) = snapshot {
+        vm.save_snapshot_file(path).expect("Unable to save snapshot");
+    }
+    if let Some(framebuffer) = vm.bus.device_mut::<Framebuffer>() {
+        if let Some(path) = screenshot {
+            framebuffer.save(path).expect("Unable to save screenshot");
+        }
+        #[cfg(feature = "window")]
+        if window {
+            framebuffer.wait_close();
+        }
+    }
+    if vm.fault.is_some() || replay_error.is_some() {
+        std::process::exit(1);
+    }
 }
 
-pub fn execute_program(vm: &mut VM) {
-    while vm.registers.pc < MEMORY_SIZE as u16 {
-        // Read instruction
-        let instruction = vm.read_memory(vm.registers.pc);
+/// Replace the headless framebuffer with one
+/// rendered to a window, if possible
+#[cfg(feature = "window")]
+fn attach_window(vm: &mut VM) {
+    match Framebuffer::windowed() {
+        Ok(framebuffer) => {
+            if let Some(headless) = vm.bus.device_mut::<Framebuffer>() {
+                *headless = framebuffer;
+            }
+        }
+        Err(e) => println!("Unable to open window, running headless: {}", e),
+    }
+}
 
-        // Increment PC
-        vm.registers.pc += 1;
+#[cfg(not(feature = "window"))]
+fn attach_window(_vm: &mut VM) {
+    println!("Built without the `window` feature, running headless");
+}
 
-        // Extract op_code and execute operation
-        instruction::execute_instruction(instruction, vm);
+/// Run until the machine halts, is interrupted or
+/// the keyboard can no longer replay or record
+pub fn execute_program(vm: &mut VM) {
+    while vm.is_running() && !INTERRUPTED.load(Ordering::SeqCst) {
+        vm.step();
+        if vm.bus.device::<Keyboard>().is_some_and(|k| k.error().is_some()) {
+            break;
+        }
     }
 }
@@ -0,0 +1,324 @@
+use crate::assembler;
+use crate::compiler;
+use crate::hardware::device::rng::Rng;
+use crate::hardware::vm::VM;
+use crate::linker;
+use crate::loader::{self, Image};
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+
+/// Default cap on the executed instructions
+pub const DEFAULT_MAX_INSTRUCTIONS: u64 = 10_000_000;
+
+/// Process exit codes of the `run` command
+pub const EXIT_HALTED: i32 = 0;
+pub const EXIT_ERROR: i32 = 1;
+pub const EXIT_TIMEOUT: i32 = 2;
+pub const EXIT_FAULT: i32 = 3;
+
+/// An output shared between the display and the
+/// runner, so the console output can be captured
+#[derive(Clone, Default)]
+pub struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuffer {
+    pub fn contents(&self) -> Vec<u8> {
+        self.0.borrow().clone()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HaltReason {
+    /// The program executed HALT or cleared the MCR
+    Halted,
+    /// The instruction cap was reached
+    Timeout,
+    /// The machine stopped on an exception or an invalid trap
+    Fault,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RegisterDump {
+    pub r0: u16,
+    pub r1: u16,
+    pub r2: u16,
+    pub r3: u16,
+    pub r4: u16,
+    pub r5: u16,
+    pub r6: u16,
+    pub r7: u16,
+    pub pc: u16,
+    pub psr: u16,
+}
+
+impl RegisterDump {
+    pub fn new(vm: &VM) -> RegisterDump {
+        let r = &vm.registers;
+        RegisterDump {
+            r0: r.r0,
+            r1: r.r1,
+            r2: r.r2,
+            r3: r.r3,
+            r4: r.r4,
+            r5: r.r5,
+            r6: r.r6,
+            r7: r.r7,
+            pc: r.pc,
+            psr: r.psr(),
+        }
+    }
+}
+
+/// The JSON report of a batch run
+#[derive(Debug, Serialize)]
+pub struct RunResult {
+    pub halt_reason: HaltReason,
+    pub message: Option<String>,
+    pub instructions: u64,
+    pub output: String,
+    pub registers: RegisterDump,
+    pub memory: BTreeMap<String, u16>,
+}
+
+impl RunResult {
+    pub fn exit_code(&self) -> i32 {
+        match self.halt_reason {
+            HaltReason::Halted => EXIT_HALTED,
+            HaltReason::Timeout => EXIT_TIMEOUT,
+            HaltReason::Fault => EXIT_FAULT,
+        }
+    }
+}
+
+/// Run until the machine halts or `max_instructions`
+/// have been executed
+pub fn run(vm: &mut VM, max_instructions: u64) -> HaltReason {
+    run_until(vm, max_instructions, None)
+}
+
+/// Like `run`, also stopping when PC reaches `stop`
+pub fn run_until(vm: &mut VM, max_instructions: u64, stop: Option<u16>) -> HaltReason {
+    while vm.is_running() && Some(vm.registers.pc) != stop {
+        if vm.instructions >= max_instructions {
+            return HaltReason::Timeout;
+        }
+        vm.step();
+    }
+    if vm.fault.is_some() {
+        HaltReason::Fault
+    } else {
+        HaltReason::Halted
+    }
+}
+
+/// Read a `.obj` image, assemble a `.asm` source or
+/// compile a `.c` one. Only assembled images have
+/// source lines, compiled and assembled ones tell
+/// code from data. Compiler output is always
+/// assembled with the pseudo-instructions.
+pub fn read(path: &str, options: assembler::Options) -> Result<Image, String> {
+    let error = |e: loader::LoadError| format!("{}: {}", path, e);
+    if path.ends_with(".c") {
+        let source = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let program = compiler::build(path, &source)?;
+        let mut image = Image::new(program.segments.clone()).map_err(error)?;
+        image.entry = program.entry();
+        image.data = program.data();
+        image.symbols = program.symbols;
+        Ok(image)
+    } else if path.ends_with(".asm") {
+        let source = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let options = assembler::Options {
+            extended: options.extended || source.starts_with(compiler::HEADER),
+        };
+        let program = assembler::assemble_with(&source, Some(path), options).map_err(|errors| {
+            errors
+                .iter()
+                .map(|e| e.render(path, &source))
+                .collect::<String>()
+                .trim_end()
+                .to_string()
+        })?;
+        if program.is_relocatable() {
+            // Sections are placed as if linked alone
+            return linker::link(&[program.object(Some(path))], &linker::Options::default())
+                .map_err(|errors| errors.iter().map(|e| format!("{}: {}", path, e)).collect::<Vec<_>>().join("\n"));
+        }
+        let mut image = Image::new(program.segments.clone()).map_err(error)?;
+        image.entry = program.entry();
+        image.data = program.data();
+        image.symbols = program.symbols;
+        image.source = Some(path.to_string());
+        image.lines = program.lines;
+        Ok(image)
+    } else {
+        loader::read(path).map_err(error)
+    }
+}
+
+/// Read a program with `read` and load it,
+/// returns the symbols of the program
+pub fn load(vm: &mut VM, path: &str, options: assembler::Options) -> Result<BTreeMap<String, u16>, String> {
+    let image = read(path, options)?;
+    if let Some(entry) = image.entry {
+        vm.registers.pc = entry;
+    }
+    image.load(vm);
+    Ok(image.symbols)
+}
+
+struct RunOptions {
+    program: Option<String>,
+    input: Option<String>,
+    report: Option<String>,
+    max_instructions: u64,
+    memory: Vec<(u16, u16)>,
+    seed: Option<u16>,
+    assembler: assembler::Options,
+}
+
+const USAGE: &str = "Usage: cargo run run [--input <file>] [--max-instructions <n>] \
+[--memory <address>[:<count>]]... [--seed <n>] [--extended] [--report <file>] <program.obj|program.asm|program.c>";
+
+/// Entry point of `cargo run run ...`, returns the exit code
+pub fn main(args: Vec<String>) -> i32 {
+    let options = match parse_args(args) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n{}", message, USAGE);
+            return EXIT_ERROR;
+        }
+    };
+    let program = match options.program {
+        Some(program) => program,
+        None => {
+            eprintln!("{}", USAGE);
+            return EXIT_ERROR;
+        }
+    };
+
+    let input: Box<dyn Read> = match &options.input {
+        Some(path) => match File::open(path) {
+            Ok(f) => Box::new(f),
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                return EXIT_ERROR;
+            }
+        },
+        None => Box::new(io::empty()),
+    };
+    let output = SharedBuffer::default();
+    let mut vm = VM::with_console(input, Box::new(output.clone()));
+    if let Some(seed) = options.seed {
+        if let Some(rng) = vm.bus.device_mut::<Rng>() {
+            *rng = Rng::seeded(seed);
+        }
+    }
+    if let Err(message) = load(&mut vm, &program, options.assembler) {
+        eprintln!("{}", message);
+        return EXIT_ERROR;
+    }
+
+    let halt_reason = run(&mut vm, options.max_instructions);
+    let message = match halt_reason {
+        HaltReason::Timeout => Some(format!(
+            "Instruction limit of {} reached at x{:04X}",
+            options.max_instructions, vm.registers.pc
+        )),
+        _ => vm.fault.map(|fault| fault.to_string()),
+    };
+    let mut memory = BTreeMap::new();
+    for &(start, count) in &options.memory {
+        for i in 0..count {
+            // Device registers as they are, reading the
+            // keyboard would consume input
+            let address = start.wrapping_add(i);
+            memory.insert(format!("x{:04X}", address), vm.peek_memory(address));
+        }
+    }
+    let result = RunResult {
+        halt_reason,
+        message,
+        instructions: vm.instructions,
+        output: String::from_utf8_lossy(&output.contents()).into_owned(),
+        registers: RegisterDump::new(&vm),
+        memory,
+    };
+
+    let json = serde_json::to_string_pretty(&result).expect("Failed to serialize the result");
+    match &options.report {
+        Some(path) => {
+            if let Err(e) = std::fs::write(path, json + "\n") {
+                eprintln!("{}: {}", path, e);
+                return EXIT_ERROR;
+            }
+        }
+        None => println!("{}", json),
+    }
+    result.exit_code()
+}
+
+fn parse_args(args: Vec<String>) -> Result<RunOptions, String> {
+    let mut options = RunOptions {
+        program: None,
+        input: None,
+        report: None,
+        max_instructions: DEFAULT_MAX_INSTRUCTIONS,
+        memory: Vec::new(),
+        seed: None,
+        assembler: assembler::Options::default(),
+    };
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("Missing value for {}", arg));
+        match arg.as_str() {
+            "--input" => options.input = Some(value()?),
+            "--extended" => options.assembler.extended = true,
+            "--report" => options.report = Some(value()?),
+            "--max-instructions" => {
+                let v = value()?;
+                options.max_instructions = v.parse().map_err(|_| format!("Invalid count `{}`", v))?;
+            }
+            "--memory" => options.memory.push(parse_cells(&value()?)?),
+            "--seed" => {
+                let v = value()?;
+                options.seed = Some(v.parse().map_err(|_| format!("Invalid seed `{}`", v))?);
+            }
+            _ if arg.starts_with('-') => return Err(format!("Unknown argument `{}`", arg)),
+            _ if options.program.is_some() => return Err(format!("Unexpected argument `{}`", arg)),
+            _ => options.program = Some(arg),
+        }
+    }
+    Ok(options)
+}
+
+/// x4000 or x4000:8
+fn parse_cells(s: &str) -> Result<(u16, u16), String> {
+    let (address, count) = match s.split_once(':') {
+        Some((address, count)) => (address, count),
+        None => (s, "1"),
+    };
+    let address = assembler::parse_number(address)
+        .filter(|a| (0..=0xFFFF).contains(a))
+        .ok_or(format!("Invalid address `{}`", address))?;
+    let count = count
+        .parse::<u16>()
+        .map_err(|_| format!("Invalid count `{}`", count))?;
+    Ok((address as u16, count))
+}
@@ -0,0 +1,247 @@
+use crate::assembler;
+use crate::hardware::device::framebuffer::VIDEO_START;
+use crate::hardware::vm::VM;
+use crate::runner::{self, HaltReason, SharedBuffer, DEFAULT_MAX_INSTRUCTIONS};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::Cursor;
+use std::path::Path;
+
+/// Return address of the subroutine under test,
+/// the run stops when the subroutine returns there
+pub const HARNESS_ADDRESS: u16 = 0x0200;
+
+/// A test file: the program under test and its cases
+///
+/// ```toml
+/// program = "multiply.asm"
+///
+/// [[case]]
+/// name = "3 * 4"
+/// subroutine = "MULTIPLY"
+/// registers = { r0 = 3, r1 = 4 }
+/// expect = { registers = { r2 = 12 } }
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TestFile {
+    /// `.asm` or `.obj`, relative to the test file
+    pub program: String,
+    pub max_instructions: Option<u64>,
+    #[serde(rename = "case", default)]
+    pub cases: Vec<TestCase>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TestCase {
+    pub name: String,
+    /// Label or address called with R7 pointing to the
+    /// harness and R6 to an empty stack below the
+    /// framebuffer. Without it the program runs from
+    /// its entry point.
+    pub subroutine: Option<Value>,
+    #[serde(default)]
+    pub registers: BTreeMap<String, Value>,
+    /// Memory written before the run, keys are
+    /// addresses or labels
+    #[serde(default)]
+    pub memory: BTreeMap<String, Value>,
+    /// Keyboard input
+    #[serde(default)]
+    pub input: String,
+    pub max_instructions: Option<u64>,
+    #[serde(default)]
+    pub expect: Expect,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Expect {
+    #[serde(default)]
+    pub registers: BTreeMap<String, Value>,
+    #[serde(default)]
+    pub memory: BTreeMap<String, Value>,
+    pub output: Option<String>,
+}
+
+/// A number, or a string holding a literal
+/// (`"x4000"`, `"#-1"`) or a label
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Value {
+    Number(i64),
+    Text(String),
+}
+
+impl Value {
+    fn resolve(&self, symbols: &BTreeMap<String, u16>) -> Result<u16, String> {
+        let number = match self {
+            Value::Number(n) => *n,
+            Value::Text(text) => match assembler::parse_number(text) {
+                Some(n) => n as i64,
+                None => {
+                    return symbols
+                        .get(text)
+                        .copied()
+                        .ok_or(format!("unknown label `{}`", text))
+                }
+            },
+        };
+        if (-0x8000..=0xFFFF).contains(&number) {
+            Ok(number as u16)
+        } else {
+            Err(format!("{} does not fit in 16 bits", number))
+        }
+    }
+}
+
+/// The outcome of a single case, passed when
+/// there are no failures
+pub struct CaseResult {
+    pub name: String,
+    pub failures: Vec<String>,
+}
+
+fn register_index(name: &str) -> Result<u16, String> {
+    match name.to_ascii_lowercase().as_str() {
+        "r0" => Ok(0),
+        "r1" => Ok(1),
+        "r2" => Ok(2),
+        "r3" => Ok(3),
+        "r4" => Ok(4),
+        "r5" => Ok(5),
+        "r6" => Ok(6),
+        "r7" => Ok(7),
+        "pc" => Ok(8),
+        _ => Err(format!("unknown register `{}`", name)),
+    }
+}
+
+/// Run every case of a test file
+pub fn run_file(path: &Path) -> Result<Vec<CaseResult>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let file: TestFile = toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let program = path.parent().unwrap_or(Path::new("")).join(&file.program);
+    let program = program.to_string_lossy();
+
+    Ok(file
+        .cases
+        .iter()
+        .map(|case| {
+            let max = case
+                .max_instructions
+                .or(file.max_instructions)
+                .unwrap_or(DEFAULT_MAX_INSTRUCTIONS);
+            let failures = match run_case(&program, case, max) {
+                Ok(failures) => failures,
+                Err(message) => vec![message],
+            };
+            CaseResult {
+                name: case.name.clone(),
+                failures,
+            }
+        })
+        .collect())
+}
+
+fn run_case(program: &str, case: &TestCase, max_instructions: u64) -> Result<Vec<String>, String> {
+    let input = Cursor::new(case.input.clone().into_bytes());
+    let output = SharedBuffer::default();
+    let mut vm = VM::with_console(Box::new(input), Box::new(output.clone()));
+    let symbols = runner::load(&mut vm, program, assembler::Options::default())?;
+
+    let mut stop = None;
+    if let Some(subroutine) = &case.subroutine {
+        // Like JSR from the harness
+        stop = Some(HARNESS_ADDRESS);
+        vm.registers.r7 = HARNESS_ADDRESS;
+        vm.registers.r6 = VIDEO_START;
+        vm.registers.pc = subroutine.resolve(&symbols)?;
+    }
+    for (name, value) in &case.registers {
+        vm.registers.update(register_index(name)?, value.resolve(&symbols)?);
+    }
+    for (address, value) in &case.memory {
+        let address = Value::Text(address.clone()).resolve(&symbols)?;
+        vm.write_memory(address as usize, value.resolve(&symbols)?);
+    }
+
+    let mut failures = Vec::new();
+    match runner::run_until(&mut vm, max_instructions, stop) {
+        HaltReason::Halted => {}
+        HaltReason::Timeout => failures.push(format!(
+            "did not halt within {} instructions (PC x{:04X})",
+            max_instructions, vm.registers.pc
+        )),
+        HaltReason::Fault => failures.push(vm.fault.map(|f| f.to_string()).unwrap_or_default()),
+    }
+
+    for (name, value) in &case.expect.registers {
+        let expected = value.resolve(&symbols)?;
+        let found = vm.registers.get(register_index(name)?);
+        if expected != found {
+            failures.push(mismatch(name, expected, found));
+        }
+    }
+    for (address, value) in &case.expect.memory {
+        let location = Value::Text(address.clone()).resolve(&symbols)?;
+        let expected = value.resolve(&symbols)?;
+        let found = vm.peek_memory(location);
+        if expected != found {
+            failures.push(mismatch(address, expected, found));
+        }
+    }
+    if let Some(expected) = &case.expect.output {
+        let found = String::from_utf8_lossy(&output.contents()).into_owned();
+        if *expected != found {
+            failures.push(format!("output: expected {:?}, found {:?}", expected, found));
+        }
+    }
+    Ok(failures)
+}
+
+fn mismatch(what: &str, expected: u16, found: u16) -> String {
+    format!(
+        "{}: expected x{:04X} ({}), found x{:04X} ({})",
+        what, expected, expected as i16, found, found as i16
+    )
+}
+
+/// Entry point of `cargo run test <file.toml>...`,
+/// returns the exit code
+pub fn main(args: Vec<String>) -> i32 {
+    if args.is_empty() {
+        eprintln!("Usage: cargo run test <file.toml>...");
+        return runner::EXIT_ERROR;
+    }
+    let (mut passed, mut failed) = (0, 0);
+    for path in &args {
+        let results = match run_file(Path::new(path)) {
+            Ok(results) => results,
+            Err(message) => {
+                eprintln!("{}", message);
+                failed += 1;
+                continue;
+            }
+        };
+        for result in results {
+            if result.failures.is_empty() {
+                println!("PASS {}", result.name);
+                passed += 1;
+            } else {
+                println!("FAIL {}", result.name);
+                for failure in &result.failures {
+                    println!("     {}", failure);
+                }
+                failed += 1;
+            }
+        }
+    }
+    println!("{} passed, {} failed", passed, failed);
+    if failed == 0 {
+        0
+    } else {
+        runner::EXIT_ERROR
+    }
+}
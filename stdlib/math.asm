@@ -0,0 +1,127 @@
+; Integer arithmetic
+;
+; MULTIPLY  R0 = R0 * R1, the low 16 bits of the product
+; DIVIDE    R0 = R0 / R1 and R1 = R0 % R1, rounded towards
+;           zero: the remainder has the sign of the
+;           dividend. Dividing by zero gives 0 and 0.
+; MODULO    R0 = R0 % R1
+;
+; The other registers are preserved.
+
+        .GLOBAL MULTIPLY, DIVIDE, MODULO
+
+; Shift and add, one bit of R1 at a time
+MULTIPLY
+        ST R1, MATH_R1
+        ST R2, MATH_R2
+        ST R3, MATH_R3
+        ST R4, MATH_R4
+        AND R2, R2, #0          ; product
+        ADD R3, R2, #1          ; bit of R1
+MUL_LOOP
+        AND R4, R1, R3
+        BRz MUL_NEXT
+        ADD R2, R2, R0
+MUL_NEXT
+        ADD R0, R0, R0
+        ADD R3, R3, R3
+        BRnp MUL_LOOP
+        ADD R0, R2, #0
+        LD R1, MATH_R1
+        LD R2, MATH_R2
+        LD R3, MATH_R3
+        LD R4, MATH_R4
+        RET
+
+; Long division of the magnitudes, then the signs
+DIVIDE
+        ST R2, MATH_R2
+        ST R3, MATH_R3
+        ST R4, MATH_R4
+        ST R5, MATH_R5
+        ST R7, MATH_R7
+        AND R2, R2, #0          ; quotient
+        AND R3, R3, #0          ; remainder
+        ADD R1, R1, #0
+        BRz DIV_STORE
+        ST R0, DIV_DIVIDEND
+        AND R4, R4, #0          ; -1 if the quotient is negative
+        ADD R0, R0, #0
+        BRzp DIV_DIVIDEND_POSITIVE
+        NOT R0, R0
+        ADD R0, R0, #1
+        NOT R4, R4
+DIV_DIVIDEND_POSITIVE
+        ADD R1, R1, #0
+        BRzp DIV_DIVISOR_POSITIVE
+        NOT R1, R1
+        ADD R1, R1, #1
+        NOT R4, R4
+DIV_DIVISOR_POSITIVE
+        ST R4, DIV_NEGATE
+        NOT R5, R1
+        ADD R5, R5, #1          ; -divisor
+        AND R7, R7, #0
+        ADD R7, R7, #8
+        ADD R7, R7, #8          ; bits left
+DIV_LOOP
+        ADD R2, R2, R2
+        ADD R3, R3, R3
+        ADD R0, R0, #0
+        BRzp DIV_SHIFT
+        ADD R3, R3, #1          ; next bit of the dividend
+DIV_SHIFT
+        ADD R0, R0, R0
+        ; The magnitudes are unsigned: a remainder of
+        ; x8000 or more is at least the divisor, and a
+        ; divisor of x8000 is more than a smaller one
+        ADD R3, R3, #0
+        BRn DIV_SUBTRACT
+        ADD R1, R1, #0
+        BRn DIV_NEXT
+        ADD R4, R3, R5
+        BRn DIV_NEXT
+DIV_SUBTRACT
+        ADD R3, R3, R5
+        ADD R2, R2, #1
+DIV_NEXT
+        ADD R7, R7, #-1
+        BRp DIV_LOOP
+        LD R4, DIV_NEGATE
+        BRzp DIV_REMAINDER
+        NOT R2, R2
+        ADD R2, R2, #1
+DIV_REMAINDER
+        LD R4, DIV_DIVIDEND
+        BRzp DIV_STORE
+        NOT R3, R3
+        ADD R3, R3, #1
+DIV_STORE
+        ADD R0, R2, #0
+        ADD R1, R3, #0
+        LD R2, MATH_R2
+        LD R3, MATH_R3
+        LD R4, MATH_R4
+        LD R5, MATH_R5
+        LD R7, MATH_R7
+        RET
+
+MODULO
+        ST R1, MOD_R1
+        ST R7, MOD_R7
+        JSR DIVIDE
+        ADD R0, R1, #0
+        LD R1, MOD_R1
+        LD R7, MOD_R7
+        RET
+
+MATH_R1 .BLKW 1
+MATH_R2 .BLKW 1
+MATH_R3 .BLKW 1
+MATH_R4 .BLKW 1
+MATH_R5 .BLKW 1
+MATH_R7 .BLKW 1
+DIV_DIVIDEND .BLKW 1
+DIV_NEGATE .BLKW 1
+MOD_R1 .BLKW 1
+MOD_R7 .BLKW 1
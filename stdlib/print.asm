@@ -0,0 +1,101 @@
+; Printing numbers on the console
+;
+; PRINT_DECIMAL  print R0 as a signed decimal number
+; PRINT_HEX      print R0 as x followed by 4 hex digits
+;
+; Every register is preserved.
+
+        .INCLUDE <math.asm>
+        .GLOBAL PRINT_DECIMAL, PRINT_HEX
+
+; The digits are the remainders of divisions by
+; 10, stored backwards in front of PD_END
+PRINT_DECIMAL
+        ST R0, PD_R0
+        ST R1, PD_R1
+        ST R2, PD_R2
+        ST R3, PD_R3
+        ST R7, PD_R7
+        ADD R2, R0, #0          ; what is left to print
+        BRzp PD_DIGITS
+        LD R0, PD_MINUS
+        OUT
+PD_DIGITS
+        LEA R3, PD_END
+PD_LOOP
+        ADD R0, R2, #0
+        AND R1, R1, #0
+        ADD R1, R1, #10
+        JSR DIVIDE
+        ADD R2, R0, #0
+        ADD R1, R1, #0
+        BRzp PD_DIGIT
+        NOT R1, R1              ; the remainder of a negative
+        ADD R1, R1, #1          ; number is negative
+PD_DIGIT
+        LD R0, PD_ZERO
+        ADD R0, R0, R1
+        ADD R3, R3, #-1
+        STR R0, R3, #0
+        ADD R2, R2, #0
+        BRnp PD_LOOP
+        ADD R0, R3, #0
+        PUTS
+        LD R0, PD_R0
+        LD R1, PD_R1
+        LD R2, PD_R2
+        LD R3, PD_R3
+        LD R7, PD_R7
+        RET
+
+; Four bits at a time, from the top
+PRINT_HEX
+        ST R0, PD_R0
+        ST R1, PD_R1
+        ST R2, PD_R2
+        ST R3, PD_R3
+        ST R7, PD_R7
+        ADD R1, R0, #0
+        LD R0, PH_X
+        OUT
+        AND R2, R2, #0
+        ADD R2, R2, #4          ; digits left
+PH_DIGIT
+        AND R0, R0, #0
+        AND R3, R3, #0
+        ADD R3, R3, #4          ; bits left
+PH_BIT
+        ADD R0, R0, R0
+        ADD R1, R1, #0
+        BRzp PH_SHIFT
+        ADD R0, R0, #1
+PH_SHIFT
+        ADD R1, R1, R1
+        ADD R3, R3, #-1
+        BRp PH_BIT
+        ADD R3, R0, #-10
+        BRn PH_PRINT
+        ADD R0, R0, #7          ; 'A' is 7 after '9' + 1
+PH_PRINT
+        LD R3, PD_ZERO
+        ADD R0, R0, R3
+        OUT
+        ADD R2, R2, #-1
+        BRp PH_DIGIT
+        LD R0, PD_R0
+        LD R1, PD_R1
+        LD R2, PD_R2
+        LD R3, PD_R3
+        LD R7, PD_R7
+        RET
+
+PD_ZERO .FILL '0'
+PD_MINUS .FILL '-'
+PH_X    .FILL 'x'
+PD_R0   .BLKW 1
+PD_R1   .BLKW 1
+PD_R2   .BLKW 1
+PD_R3   .BLKW 1
+PD_R7   .BLKW 1
+PD_BUFFER .BLKW 5
+PD_END  .FILL 0
@@ -0,0 +1,40 @@
+; A stack in R6, growing down: R6 points to the last
+; pushed word. Set R6 before using it, for example to
+; the end of a .BLKW.
+;
+; STACK_PUSH reg     macro, push a register
+; STACK_POP reg      macro, pop into a register
+; SAVE_REGISTERS     push R0 to R5
+; RESTORE_REGISTERS  pop R5 to R0
+
+        .GLOBAL SAVE_REGISTERS, RESTORE_REGISTERS
+
+.MACRO STACK_PUSH reg
+        ADD R6, R6, #-1
+        STR \reg, R6, #0
+.ENDM
+
+.MACRO STACK_POP reg
+        LDR \reg, R6, #0
+        ADD R6, R6, #1
+.ENDM
+
+SAVE_REGISTERS
+        ADD R6, R6, #-6
+        STR R0, R6, #5
+        STR R1, R6, #4
+        STR R2, R6, #3
+        STR R3, R6, #2
+        STR R4, R6, #1
+        STR R5, R6, #0
+        RET
+
+RESTORE_REGISTERS
+        LDR R0, R6, #5
+        LDR R1, R6, #4
+        LDR R2, R6, #3
+        LDR R3, R6, #2
+        LDR R4, R6, #1
+        LDR R5, R6, #0
+        ADD R6, R6, #6
+        RET
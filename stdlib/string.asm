@@ -0,0 +1,75 @@
+; Zero terminated strings, one character per word
+;
+; STRLEN  R0 = length of the string at R0
+; STRCMP  compare the strings at R0 and R1: R0 is 0 if
+;         they are equal, otherwise the difference of the
+;         first characters that differ
+; STRCPY  copy the string at R1, with its terminator,
+;         to R0
+;
+; The other registers are preserved.
+
+        .GLOBAL STRLEN, STRCMP, STRCPY
+
+STRLEN
+        ST R1, STR_R1
+        ST R2, STR_R2
+        ADD R1, R0, #0
+        AND R0, R0, #0
+STRLEN_LOOP
+        LDR R2, R1, #0
+        BRz STRLEN_DONE
+        ADD R0, R0, #1
+        ADD R1, R1, #1
+        BR STRLEN_LOOP
+STRLEN_DONE
+        LD R1, STR_R1
+        LD R2, STR_R2
+        RET
+
+STRCMP
+        ST R1, STR_R1
+        ST R2, STR_R2
+        ST R3, STR_R3
+        ST R4, STR_R4
+        ADD R2, R0, #0
+STRCMP_LOOP
+        LDR R3, R2, #0
+        LDR R4, R1, #0
+        NOT R0, R4
+        ADD R0, R0, #1
+        ADD R0, R3, R0
+        BRnp STRCMP_DONE
+        ADD R3, R3, #0
+        BRz STRCMP_DONE         ; both ended
+        ADD R2, R2, #1
+        ADD R1, R1, #1
+        BR STRCMP_LOOP
+STRCMP_DONE
+        LD R1, STR_R1
+        LD R2, STR_R2
+        LD R3, STR_R3
+        LD R4, STR_R4
+        RET
+
+STRCPY
+        ST R1, STR_R1
+        ST R2, STR_R2
+        ST R3, STR_R3
+        ADD R2, R0, #0
+STRCPY_LOOP
+        LDR R3, R1, #0
+        STR R3, R2, #0
+        ADD R1, R1, #1
+        ADD R2, R2, #1
+        ADD R3, R3, #0
+        BRnp STRCPY_LOOP
+        LD R1, STR_R1
+        LD R2, STR_R2
+        LD R3, STR_R3
+        RET
+
+STR_R1  .BLKW 1
+STR_R2  .BLKW 1
+STR_R3  .BLKW 1
+STR_R4  .BLKW 1
@@ -0,0 +1,14 @@
+; Every routine of the standard library, for the tests
+        .ORIG x3000
+        LD R6, STACK
+        HALT
+STACK   .FILL x4000
+        .INCLUDE <math.asm>
+        .INCLUDE <print.asm>
+        .INCLUDE <string.asm>
+        .INCLUDE <stack.asm>
+HELLO   .STRINGZ "hello"
+HELP    .STRINGZ "help"
+EMPTY   .STRINGZ ""
+BUFFER  .BLKW 8
+        .END
@@ -0,0 +1,240 @@
+//! Control flow and call graphs of images and
+//! the lints over them
+use little_computer_3::analysis::decode::Instruction;
+use little_computer_3::analysis::lint::{lint, Check};
+use little_computer_3::analysis::{Callee, Cfg, Edge, EdgeKind};
+use little_computer_3::assembler::assemble;
+use little_computer_3::compiler;
+use little_computer_3::loader::Image;
+use std::collections::BTreeMap;
+
+fn image(source: &str) -> Image {
+    let program = assemble(source).unwrap();
+    let mut image = Image::new(program.segments.clone()).unwrap();
+    image.entry = program.entry();
+    image.data = program.data();
+    image.symbols = program.symbols;
+    image.lines = program.lines;
+    image
+}
+
+const MULTIPLY: &str = "\
+        .ORIG x3000
+        LD R0, A
+        LD R1, B
+        JSR MULTIPLY
+        ST R2, PRODUCT
+        HALT
+A       .FILL #6
+B       .FILL #7
+PRODUCT .BLKW 1
+MULTIPLY
+        AND R2, R2, #0
+        ADD R3, R1, #0
+        BRz DONE
+LOOP    ADD R2, R2, R0
+        ADD R3, R3, #-1
+        BRp LOOP
+DONE    RET
+        .END
+";
+
+#[test]
+fn blocks_end_at_branches_and_targets() {
+    let cfg = Cfg::build(&image(MULTIPLY));
+    let starts: Vec<u16> = cfg.blocks.keys().copied().collect();
+    assert_eq!(starts, [0x3000, 0x3008, 0x300B, 0x300E]);
+    // Data after HALT is not code
+    assert_eq!(cfg.blocks[&0x3000].end(), 0x3004);
+    assert_eq!(
+        cfg.blocks[&0x300B].successors,
+        [Edge { to: 0x300B, kind: EdgeKind::Branch }, Edge { to: 0x300E, kind: EdgeKind::Fallthrough }]
+    );
+    assert!(cfg.blocks[&0x300E].successors.is_empty());
+}
+
+#[test]
+fn data_is_not_code() {
+    let source = ".ORIG x3000\nLEA R0, MSG\nPUTS\nMSG .STRINGZ \"Hi\"\nLOOP BR LOOP\n.END\n";
+    let cfg = Cfg::build(&image(source));
+    let starts: Vec<u16> = cfg.blocks.keys().copied().collect();
+    assert_eq!(starts, [0x3000]);
+    assert_eq!(cfg.blocks[&0x3000].successors, [Edge { to: 0x3002, kind: EdgeKind::Fallthrough }]);
+    let dot = cfg.dot();
+    assert!(dot.contains("label=\"x3002 (data)\""), "{}", dot);
+    assert!(!dot.contains("NOP"), "{}", dot);
+}
+
+#[test]
+fn call_targets_are_subroutines() {
+    let cfg = Cfg::build(&image(MULTIPLY));
+    let main = &cfg.subroutines[&0x3000];
+    assert_eq!(main.blocks, [0x3000]);
+    assert_eq!(main.calls, [Callee::Subroutine(0x3008), Callee::Trap(0x25)]);
+    let multiply = &cfg.subroutines[&0x3008];
+    assert_eq!(multiply.name, "MULTIPLY");
+    assert_eq!(multiply.blocks, [0x3008, 0x300B, 0x300E]);
+    assert!(multiply.calls.is_empty());
+}
+
+#[test]
+fn registers_loaded_before_jsrr_are_followed() {
+    // Compiled calls load the address from a pool
+    let program = compiler::build("t.c", "int twice(int n) { return n + n; } int main() { return twice(4); }").unwrap();
+    let mut image = Image::new(program.segments.clone()).unwrap();
+    image.entry = program.entry();
+    image.symbols = program.symbols;
+    let cfg = Cfg::build(&image);
+    assert!(cfg.unresolved.is_empty());
+    let names: Vec<&str> = cfg.subroutines.values().map(|s| s.name.as_str()).collect();
+    assert_eq!(names, ["x3000", "F_twice", "F_main"]);
+}
+
+#[test]
+fn unknown_targets_are_reported() {
+    let cfg = Cfg::build(&image(".ORIG x3000\nLDR R1, R0, #0\nJMP R1\n.END\n"));
+    assert_eq!(cfg.unresolved, [0x3001]);
+    assert!(cfg.blocks[&0x3000].successors.is_empty());
+}
+
+#[test]
+fn graphviz_and_json() {
+    let cfg = Cfg::build(&image(MULTIPLY));
+    let dot = cfg.dot();
+    assert!(dot.contains("\"x3000\" -> \"x3008\" [style=dashed];"), "{}", dot);
+    assert!(dot.contains("\"x300B\" -> \"x300B\" [label=\"taken\"];"), "{}", dot);
+    assert!(dot.contains("LOOP:\\lx300B  ADD R2, R2, R0\\l"), "{}", dot);
+    let calls = cfg.call_dot();
+    assert!(calls.contains("\"x3000\" -> \"MULTIPLY\";"), "{}", calls);
+    assert!(calls.contains("\"x3000\" -> \"HALT\" [style=dashed];"), "{}", calls);
+
+    let json = cfg.json();
+    assert_eq!(json["entry"], "x3000");
+    assert_eq!(json["blocks"][1]["instructions"][2]["text"], "BRz DONE");
+    // Source lines from 1
+    assert_eq!(json["blocks"][0]["instructions"][0]["line"], 2);
+    assert_eq!(json["subroutines"][1]["calls"].as_array().unwrap().len(), 0);
+}
+
+#[test]
+fn instructions_are_written_back_in_assembler_syntax() {
+    let names = BTreeMap::from([(0x3010, "DATA".to_string())]);
+    let cases = [
+        (0x1262, "ADD R1, R1, #2"),
+        (0x5A81, "AND R5, R2, R1"),
+        (0x0E0F, "BRnzp DATA"),
+        (0x0000, "NOP"),
+        (0x2BFE, "LD R5, x2FFF"),
+        (0x6E7F, "LDR R7, R1, #-1"),
+        (0xC1C0, "RET"),
+        (0x4080, "JSRR R2"),
+        (0xF022, "PUTS"),
+        (0xF030, "DREAD"),
+        (0xF040, "TRAP x40"),
+        (0xD000, ".FILL xD000"),
+    ];
+    for (word, text) in cases {
+        assert_eq!(Instruction::decode(0x3000, word).format(&names), text, "x{:04X}", word);
+    }
+}
+
+/// Check and address of every lint
+fn lints(source: &str) -> Vec<(Check, u16)> {
+    lint(&Cfg::build(&image(source))).iter().map(|l| (l.check, l.address)).collect()
+}
+
+#[test]
+fn correct_programs_have_no_lints() {
+    assert!(lints(MULTIPLY).is_empty());
+    let saved = ".ORIG x3000\nJSR SUB\nHALT\nSUB ADD R6, R6, #-1\nSTR R7, R6, #0\nJSR LEAF\nLDR R7, R6, #0\nADD R6, R6, #1\nRET\nLEAF RET\n.END\n";
+    assert!(lints(saved).is_empty());
+}
+
+#[test]
+fn registers_read_before_written() {
+    let source = ".ORIG x3000\nAND R0, R0, #0\nADD R0, R1, #1\nOUT\nHALT\n.END\n";
+    let found = lint(&Cfg::build(&image(source)));
+    assert_eq!(found.len(), 1);
+    assert_eq!((found[0].check, found[0].address, found[0].line), (Check::Uninitialized, 0x3001, Some(3)));
+    assert_eq!(found[0].message, "R1 is read before it is written");
+    // Only on some paths
+    let source = ".ORIG x3000\nGETC\nBRz SKIP\nAND R1, R1, #0\nSKIP ADD R0, R1, #0\nHALT\n.END\n";
+    assert_eq!(lints(source), [(Check::Uninitialized, 0x3003)]);
+}
+
+#[test]
+fn r7_overwritten_before_ret() {
+    let source = ".ORIG x3000\nJSR SUB\nHALT\nSUB AND R0, R0, #0\nOUT\nRET\n.END\n";
+    let found = lint(&Cfg::build(&image(source)));
+    assert_eq!((found[0].check, found[0].address), (Check::ClobberedReturn, 0x3004));
+    assert!(found[0].message.contains("x3003 (OUT)"), "{}", found[0].message);
+}
+
+#[test]
+fn branch_without_condition_bits() {
+    // Only an object file can tell it is not data
+    let source = ".ORIG x3000\n.FILL x0001\nHALT\n.END\n";
+    let mut image = image(source);
+    image.data.clear();
+    let found: Vec<_> = lint(&Cfg::build(&image)).iter().map(|l| (l.check, l.address)).collect();
+    assert_eq!(found, [(Check::NeverTaken, 0x3000)]);
+}
+
+#[test]
+fn unbalanced_stack() {
+    let push = "ADD R6, R6, #-1\nSTR R0, R6, #0\n";
+    let source = format!(".ORIG x3000\nJSR SUB\nHALT\nSUB {}RET\n.END\n", push);
+    assert_eq!(lints(&source), [(Check::UnbalancedStack, 0x3004)]);
+    // The pop is skipped when R0 is zero
+    let source = format!(".ORIG x3000\nJSR SUB\nHALT\nSUB {}ADD R0, R0, #0\nBRz DONE\nADD R6, R6, #1\nDONE RET\n.END\n", push);
+    assert_eq!(lints(&source), [(Check::UnbalancedStack, 0x3007)]);
+    // Compiled C returns with its result pushed
+    let program = compiler::build("t.c", "int f(int n) { return n ? n + f(n - 1) : 0; } int main() { return f(3); }").unwrap();
+    let mut image = Image::new(program.segments.clone()).unwrap();
+    image.entry = program.entry();
+    assert!(lint(&Cfg::build(&image)).is_empty());
+}
+
+#[test]
+fn missing_halt() {
+    let source = ".ORIG x3000\nAND R0, R0, #0\nADD R0, R0, #1\n.END\n";
+    assert_eq!(lints(source), [(Check::MissingHalt, 0x3001)]);
+    let found = lint(&Cfg::build(&image(source)));
+    let rendered = found[0].render("p.asm", Some(source));
+    assert_eq!(
+        rendered,
+        "p.asm:3: x3001: warning[missing-halt]: execution runs past the end of the program at x3002\n  |\n3 | ADD R0, R0, #1\n  = help: end the program with HALT\n"
+    );
+}
+
+#[test]
+fn missing_halt_before_data() {
+    let source = ".ORIG x3000\nLEA R0, MSG\nPUTS\nMSG .STRINGZ \"Hello\"\nBUF .BLKW 3\n.END\n";
+    assert_eq!(lints(source), [(Check::MissingHalt, 0x3002)]);
+    let found = lint(&Cfg::build(&image(source)));
+    assert_eq!((found[0].line, found[0].message.as_str()), (Some(4), "execution runs into data"));
+}
+
+#[test]
+fn missing_halt_before_data_then_a_subroutine() {
+    let source = ".ORIG x3000\nJSR PRINT\nMSG .STRINGZ \"Hi\"\nZERO .BLKW 2\nPRINT LEA R0, MSG\nRET\n.END\n";
+    assert_eq!(lints(source), [(Check::MissingHalt, 0x3001)]);
+    let cfg = Cfg::build(&image(source));
+    assert_eq!(cfg.subroutines[&0x3006].blocks, [0x3006]);
+}
+
+#[test]
+fn unknown_arguments_are_rejected() {
+    use little_computer_3::analysis;
+    let temp = |name: &str| std::env::temp_dir().join(format!("lc3-cfg-{}-{}", std::process::id(), name));
+    let (program, graph) = (temp("typo.asm"), temp("typo.json"));
+    std::fs::write(&program, ".ORIG x3000\nHALT\n.END\n").unwrap();
+    let (program, graph) = (program.display().to_string(), graph.display().to_string());
+    let code = |args: &[&str]| analysis::main(args.iter().map(|a| a.to_string()).collect());
+    assert_eq!(code(&["--format", "json", "-o", &graph, &program]), 1);
+    assert_eq!(code(&["--json", "-o", &graph, &program, &program]), 1);
+    assert!(!std::path::Path::new(&graph).exists());
+    assert_eq!(code(&["--json", "-o", &graph, &program]), 0);
+    std::fs::remove_file(&graph).unwrap();
+    std::fs::remove_file(&program).unwrap();
+}
@@ -0,0 +1,575 @@
+//! Assembler features beyond plain instructions
+mod common;
+
+use common::{load, machine, run};
+use little_computer_3::assembler::{assemble, assemble_file, assemble_with, Expansion, Options, Program, Severity};
+
+fn words(source: &str) -> Vec<u16> {
+    let program = assemble(source).unwrap_or_else(|errors| {
+        panic!("{}", errors.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("\n"))
+    });
+    program.segments.into_iter().flat_map(|s| s.words).collect()
+}
+
+const PUSH_POP: &str = "\
+.MACRO PUSH reg
+    ADD R6, R6, #-1
+    STR \\reg, R6, #0
+.ENDM
+.macro pop reg
+    LDR \\reg, R6, #0
+    ADD R6, R6, #1
+.endm
+";
+
+#[test]
+fn macro_expands_with_arguments() {
+    let source = format!("{}.ORIG x3000\nPUSH R1\npop R2\n.END\n", PUSH_POP);
+    let plain = ".ORIG x3000\nADD R6, R6, #-1\nSTR R1, R6, #0\nLDR R2, R6, #0\nADD R6, R6, #1\n.END\n";
+    assert_eq!(words(&source), words(plain));
+}
+
+#[test]
+fn label_on_macro_call_names_first_word() {
+    let source = format!("{}.ORIG x3000\nHALT\nSAVE PUSH R0\nBR SAVE\n.END\n", PUSH_POP);
+    let program = assemble(&source).unwrap();
+    assert_eq!(program.symbols["SAVE"], 0x3001);
+    // BR back 3 words
+    assert_eq!(program.segments[0].words[3], 0x0FFD);
+}
+
+#[test]
+fn local_labels_are_unique_per_expansion() {
+    let source = "\
+.MACRO WAIT n
+    AND R0, R0, #0
+    ADD R0, R0, \\n
+@loop ADD R0, R0, #-1
+    BRp @loop
+.ENDM
+.ORIG x3000
+WAIT #3
+WAIT #5
+.END
+";
+    let words = words(source);
+    assert_eq!(words.len(), 8);
+    // Both branches go back one word to their own loop
+    assert_eq!(words[3], 0x03FE);
+    assert_eq!(words[7], 0x03FE);
+}
+
+#[test]
+fn macros_call_other_macros() {
+    let source = format!(
+        "{}.MACRO SWAP a b\nPUSH \\a\nPUSH \\b\npop \\a\npop \\b\n.ENDM\n.ORIG x3000\nSWAP R1, R2\n.END\n",
+        PUSH_POP
+    );
+    assert_eq!(words(&source).len(), 8);
+}
+
+#[test]
+fn string_arguments_keep_escapes() {
+    let source = ".MACRO MSG text\n.STRINGZ \\text\n.ENDM\n.ORIG x3000\nMSG \"a\\\"b\\n\"\n.END\n";
+    assert_eq!(words(source), vec![b'a' as u16, b'"' as u16, b'b' as u16, b'\n' as u16, 0]);
+}
+
+#[test]
+fn conditional_assembly() {
+    let source = "\
+.ORIG x3000
+.IF 1
+    ADD R0, R0, #1
+.ELSE
+    ADD R0, R0, #2
+.ENDIF
+.IF #0
+    ADD R0, R0, #3
+    .IF 1
+        ADD R0, R0, #4
+    .ENDIF
+.ENDIF
+.END
+";
+    assert_eq!(words(source), vec![0x1021]);
+}
+
+#[test]
+fn conditionals_use_macro_arguments() {
+    let source = "\
+.MACRO CLEAR reg, keep
+.IF \\keep
+.ELSE
+    AND \\reg, \\reg, #0
+.ENDIF
+.ENDM
+.ORIG x3000
+CLEAR R1, 0
+CLEAR R2, 1
+.END
+";
+    assert_eq!(words(source), vec![0x5260]);
+}
+
+#[test]
+fn conditions_are_expressions() {
+    let source = "\
+DEBUG   .EQU 1
+SIZE    .EQU DEBUG * 4
+.MACRO NEED a
+.IF \\a - 2
+    ADD R0, R0, #\\a
+.ENDIF
+.ENDM
+.ORIG x3000
+.IF DEBUG
+    ADD R1, R1, #1
+.ENDIF
+.IF SIZE - 4
+    ADD R1, R1, #2
+.ENDIF
+NEED 2
+NEED 3
+.END
+";
+    assert_eq!(words(source), vec![0x1261, 0x1023]);
+    let errors = assemble(".ORIG x3000\n.IF LATER\n.ENDIF\nLATER .EQU 1\n.END\n").unwrap_err();
+    assert_eq!(errors[0].message, "`LATER` is not a constant");
+}
+
+#[test]
+fn errors_inside_macros_point_at_the_call() {
+    let source = ".MACRO BAD\n    ADD R0, R0, #99\n.ENDM\n.ORIG x3000\nBAD\n.END\n";
+    let errors = assemble(source).unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].line, 2);
+    assert_eq!(
+        errors[0].expansion,
+        vec![Expansion {
+            name: "BAD".to_string(),
+            line: 5,
+            include: false
+        }]
+    );
+    assert!(errors[0].to_string().contains("in macro BAD called at line 5"));
+}
+
+#[test]
+fn macro_errors() {
+    let cases = [
+        (".MACRO M a\n.ENDM\n.ORIG x3000\nM\n.END\n", "expects 1 argument"),
+        (".MACRO M\nADD \\x, R0, R0\n.ENDM\n.ORIG x3000\nM\n.END\n", "unknown parameter"),
+        (".MACRO M\nM\n.ENDM\n.ORIG x3000\nM\n.END\n", "too deeply"),
+        (".MACRO ADD\n.ENDM\n", "invalid macro name"),
+        (".MACRO M\n", "no .ENDM"),
+        (".IF 1\n", "no .ENDIF"),
+        (".ENDIF\n", "without .IF"),
+        (".ORIG x3000\n@x ADD R0, R0, R0\n.END\n", "invalid label"),
+    ];
+    for (source, message) in cases {
+        let errors = assemble(source).unwrap_err();
+        assert!(
+            errors.iter().any(|e| e.message.contains(message)),
+            "{:?} should report `{}`, got {:?}",
+            source,
+            message,
+            errors
+        );
+    }
+}
+
+#[test]
+fn constant_expressions() {
+    let source = "\
+SIZE .EQU 4
+NEWLINE .EQU '\\n'
+.ORIG x3000
+    ADD R0, R0, (SIZE*2)-1
+    ADD R1, R1, -SIZE
+    LD R2, DATA+1
+    TRAP x20 + 5
+DATA .FILL 'A'
+    .FILL NEWLINE
+    .FILL b1010 | 0x100
+    .FILL END-DATA
+    .BLKW SIZE/2, ~0
+END .FILL DATA+1
+.END
+";
+    assert_eq!(
+        words(source),
+        vec![
+            0x1027, 0x127C, 0x2402, 0xF025, 0x0041, 0x000A, 0x010A, 0x0006, 0xFFFF, 0xFFFF,
+            0x3005
+        ]
+    );
+}
+
+#[test]
+fn equ_may_use_later_symbols() {
+    let source = ".ORIG x3000\nLEN .EQU END-START\nSTART .BLKW 3\nEND ADD R0, R0, LEN\n.END\n";
+    let program = assemble(source).unwrap();
+    assert_eq!(program.segments[0].words[3], 0x1023);
+    // Constants are not labels
+    assert!(!program.symbols.contains_key("LEN"));
+}
+
+#[test]
+fn label_offsets_are_relative_numbers_are_not() {
+    let source = ".ORIG x3000\nSTART BR START+1\nBR #1\nBR 1\n.END\n";
+    assert_eq!(words(source), vec![0x0E00, 0x0E01, 0x0E01]);
+}
+
+#[test]
+fn operands_may_be_separated_by_spaces() {
+    let plain = ".ORIG x3000\nADD R0, R1, #-1\nADD R0, R1, #4\nAND R2, R2, #0\n.END\n";
+    let spaced = ".ORIG x3000\nADD R0 R1 -1\nADD R0 R1 (2 + 2)\nAND R2 R2 0\n.END\n";
+    assert_eq!(words(spaced), words(plain));
+}
+
+#[test]
+fn device_traps_have_names() {
+    let source = ".ORIG x3000\nDREAD\ndwrite\nRAND\nHALT\n.END\n";
+    assert_eq!(extended(source).segments[0].words, vec![0xF030, 0xF031, 0xF032, 0xF025]);
+    // They are labels in strict mode
+    assert_eq!(words(".ORIG x3000\nRAND ADD R0, R0, #1\nJSR RAND\n.END\n"), [0x1021, 0x4FFE]);
+    let errors = assemble(".ORIG x3000\nSEED RAND\n.END\n").unwrap_err();
+    assert_eq!(errors[0].help.as_deref(), Some("`RAND` is a device trap, assemble with --extended"));
+}
+
+#[test]
+fn character_literals_in_macros() {
+    let source = ".MACRO PUT reg\nLD \\reg, @c\nBR @n\n@c .FILL ';'\n@n OUT\n.ENDM\n.ORIG x3000\nPUT R0\n.END\n";
+    assert_eq!(words(source), vec![0x2001, 0x0E01, 0x003B, 0xF021]);
+}
+
+#[test]
+fn range_errors_name_the_field() {
+    let cases = [
+        (".ORIG x3000\nADD R0, R0, #16\n.END\n", "16 does not fit in imm5 (-16 to 15)"),
+        (".ORIG x3000\nLDR R0, R1, 32\n.END\n", "32 does not fit in offset6"),
+        (".ORIG x3000\nBR #-257\n.END\n", "-257 does not fit in PCoffset9"),
+        (".ORIG x3000\nJSR #1024\n.END\n", "1024 does not fit in PCoffset11"),
+        (".ORIG x3000\nLD R0, FAR\n.BLKW 300\nFAR .FILL 0\n.END\n", "`FAR` is too far: offset 300 does not fit in PCoffset9"),
+        (".ORIG x3000\nTRAP x100\n.END\n", "TRAP expects a vector"),
+        (".ORIG x3000\n.FILL x10000\n.END\n", "does not fit in 16 bits"),
+        (".ORIG x3000\n.FILL 1/0\n.END\n", "division by zero"),
+        (".ORIG x3000\n.FILL (1\n.END\n", "missing `)`"),
+        ("A .EQU B\nB .EQU A\n", "circular .EQU"),
+        ("A .EQU MISSING\n", "undefined label `MISSING`"),
+        (".EQU 3\n", ".EQU needs a name"),
+    ];
+    for (source, message) in cases {
+        let errors = assemble(source).unwrap_err();
+        assert!(
+            errors.iter().any(|e| e.message.contains(message)),
+            "{:?} should report `{}`, got {:?}",
+            source,
+            message,
+            errors
+        );
+    }
+}
+
+#[test]
+fn listing_shows_every_line() {
+    let source = "\
+SIZE .EQU 4
+.ORIG x3000
+START ADD R0, R0, SIZE
+    BRnzp START
+MSG .STRINGZ \"A\"
+.END
+";
+    let program = assemble(source).unwrap();
+    let mut out = Vec::new();
+    program.listing.write(&mut out, source).unwrap();
+    let listing = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = listing.lines().collect();
+    assert_eq!(lines[3], "   3   x3000  x1024  0001 000 000 1 00100  START ADD R0, R0, SIZE  [SIZE=#4]");
+    assert_eq!(lines[4], "   4   x3001  x0FFE  0000 111 111111110    BRnzp START  [START=x3000]");
+    assert_eq!(lines[5], "   5   x3002  x0041  0000000001000001      MSG .STRINGZ \"A\"");
+    assert_eq!(lines[6], "       x3003  x0000  0000000000000000");
+    assert!(lines.contains(&"SIZE                  #4       1        3"));
+    assert!(lines.contains(&"START                 x3000    3        4"));
+    assert!(lines.contains(&"MSG                   x3002    5"));
+}
+
+#[test]
+fn listing_cross_references_conditions() {
+    let source = "\
+DEBUG .EQU 1
+.ORIG x3000
+.IF DEBUG
+    ADD R0, R0, DEBUG
+.ENDIF
+    HALT
+.END
+";
+    let program = assemble(source).unwrap();
+    let mut out = Vec::new();
+    program.listing.write(&mut out, source).unwrap();
+    let listing = String::from_utf8(out).unwrap();
+    assert!(listing.lines().any(|line| line == "DEBUG                 #1       1        3, 4"));
+}
+
+#[test]
+fn listing_follows_macro_calls() {
+    let source = format!("{}.ORIG x3000\nPUSH R1\n.END\n", PUSH_POP);
+    let program = assemble(&source).unwrap();
+    let mut out = Vec::new();
+    program.listing.write(&mut out, &source).unwrap();
+    let listing = String::from_utf8(out).unwrap();
+    assert!(listing.contains("  10   x3000                               PUSH R1\n  10+  x3000  x1DBF"));
+    assert!(listing.contains("  10+  x3001  x7380  0111 001 110 000000   STR R1, R6, #0"));
+}
+
+#[test]
+fn instruction_fields() {
+    use little_computer_3::assembler::listing::fields;
+    assert_eq!(fields(0x1042), "0001 000 001 0 00 010");
+    assert_eq!(fields(0x4801), "0100 1 00000000001");
+    assert_eq!(fields(0xF025), "1111 0000 00100101");
+    assert_eq!(fields(0x8000), "1000 000000000000");
+}
+
+#[test]
+fn every_error_is_reported_with_its_column() {
+    let source = ".ORIG x3000\nLOOP ADD R0, R0, #1\n    BRnzp LOOOP\n    ADD R8, R0, #1\n    LD R0, FAR\n    HALT\n.BLKW 300\nFAR .FILL 0\n.END\n";
+    let errors = assemble(source).unwrap_err();
+    let found: Vec<_> = errors
+        .iter()
+        .map(|e| (e.line, e.columns.clone(), e.help.as_deref()))
+        .collect();
+    assert_eq!(
+        found,
+        vec![
+            (3, Some(11..16), Some("did you mean `LOOP`?")),
+            (4, Some(9..11), Some("registers are R0 to R7")),
+            (5, Some(12..15), Some("use LDI or LEA+LDR")),
+        ]
+    );
+    assert_eq!(errors[0].to_string(), "3:11: error: undefined label `LOOOP`");
+}
+
+#[test]
+fn errors_render_with_a_snippet() {
+    let source = ".ORIG x3000\n\tBRnzp LOOOP\nLOOP HALT\n.END\n";
+    let errors = assemble(source).unwrap_err();
+    assert_eq!(
+        errors[0].render("loop.asm", source),
+        "loop.asm:2:8: error: undefined label `LOOOP`\n  |\n2 | \tBRnzp LOOOP\n  | \t      ^^^^^\n  = help: did you mean `LOOP`?\n"
+    );
+}
+
+#[test]
+fn offsets_out_of_range_suggest_alternatives() {
+    let cases = [
+        ("ST R0, #300", "use STI or LEA+STR"),
+        ("JSR #2000", "use JSRR"),
+        ("BRz #-300", "JMP"),
+        ("LDR R0, R1, #40", "add to the base register first"),
+    ];
+    for (line, help) in cases {
+        let source = format!(".ORIG x3000\n{}\n.END\n", line);
+        let errors = assemble(&source).unwrap_err();
+        assert!(errors[0].help.as_deref().unwrap().contains(help), "{}: {:?}", line, errors);
+    }
+}
+
+#[test]
+fn unreachable_code_is_a_warning() {
+    let source = "\
+.ORIG x3000
+    BRz SKIP
+    HALT
+    ADD R0, R0, #1
+    ADD R0, R0, #2
+SKIP RET
+NEXT BRnzp #1
+    ADD R1, R1, #1
+    JMP R0
+TABLE .FILL x3009
+    NOT R0, R0
+.END
+";
+    let program = assemble(source).unwrap();
+    let warnings: Vec<_> = program.warnings.iter().map(|w| (w.line, w.severity)).collect();
+    // The BR #1 target and the address in TABLE are reachable
+    assert_eq!(warnings, vec![(4, Severity::Warning), (8, Severity::Warning)]);
+    assert!(program.warnings[0].message.contains("after HALT"));
+}
+
+/// A directory with `files`, removed when dropped
+struct Files(std::path::PathBuf);
+
+impl Files {
+    fn new(name: &str, files: &[(&str, &str)]) -> Files {
+        let directory = std::env::temp_dir().join(format!("lc3-assembler-{}-{}", std::process::id(), name));
+        for (path, text) in files {
+            let path = directory.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, text).unwrap();
+        }
+        Files(directory)
+    }
+
+    fn path(&self, name: &str) -> String {
+        self.0.join(name).to_string_lossy().into_owned()
+    }
+}
+
+impl Drop for Files {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[test]
+fn includes_are_relative_to_the_including_file() {
+    let files = Files::new(
+        "relative",
+        &[
+            ("main.asm", ".ORIG x3000\n.INCLUDE \"lib/double.asm\"\n.INCLUDE \"lib/double.asm\"\n.END\n"),
+            ("lib/double.asm", ".INCLUDE \"twice.asm\"\nDOUBLE ADD R0, R0, R0\n"),
+            ("lib/twice.asm", ".INCLUDE \"double.asm\"\n.FILL DOUBLE\n"),
+        ],
+    );
+    let main = files.path("main.asm");
+    let program = assemble_file(&main, &std::fs::read_to_string(&main).unwrap()).unwrap();
+    // Every file once, whatever includes it
+    assert_eq!(program.segments[0].words, [0x3001, 0x1000]);
+}
+
+#[test]
+fn errors_in_included_files_name_the_file() {
+    let files = Files::new("errors", &[("bad.asm", "\n  ADD R0, R0, NOPE\n")]);
+    let source = ".ORIG x3000\n.INCLUDE \"bad.asm\"\n.INCLUDE \"missing.asm\"\n.END\n";
+    let errors = assemble_file(&files.path("main.asm"), source).unwrap_err();
+    let bad = files.path("bad.asm");
+    assert_eq!(errors[0].file.as_deref(), Some(bad.as_str()));
+    assert_eq!(
+        errors[0].to_string(),
+        format!("2:15: error: undefined label `NOPE` (in {} included at line 2)", bad)
+    );
+    assert!(errors[0].render("main.asm", source).contains("2 |   ADD R0, R0, NOPE\n"));
+    assert!(errors[1].to_string().starts_with(&format!("3: error: cannot include `{}`", files.path("missing.asm"))));
+}
+
+#[test]
+fn standard_library_is_built_in() {
+    let source = ".ORIG x3000\nJSR MULTIPLY\nHALT\n.INCLUDE <math.asm>\n.INCLUDE <nothing.asm>\n.END\n";
+    let errors = assemble(source).unwrap_err();
+    assert_eq!(
+        errors.iter().map(|e| e.to_string()).collect::<Vec<_>>(),
+        ["5: error: cannot include `<nothing.asm>`: no such file in the standard library"]
+    );
+    let program = assemble(&source.replace(".INCLUDE <nothing.asm>\n", "")).unwrap();
+    assert_eq!(program.symbols["MULTIPLY"], 0x3002);
+    // Included lines follow the .INCLUDE in the listing
+    let mut listing = Vec::new();
+    program.listing.write(&mut listing, source).unwrap();
+    let listing = String::from_utf8(listing).unwrap();
+    assert!(listing.contains("   4+  x3002  x3253  0011 001 001010011    ST R1, MATH_R1"));
+}
+
+fn extended(source: &str) -> Program {
+    let options = Options { extended: true };
+    assemble_with(source, None, options).unwrap_or_else(|errors| {
+        panic!("{}", errors.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("\n"))
+    })
+}
+
+#[test]
+fn pseudo_instructions_are_opt_in() {
+    let errors = assemble(".ORIG x3000\nPUSH R1\n.END\n").unwrap_err();
+    assert_eq!(errors[0].help.as_deref(), Some("`PUSH` is a pseudo-instruction, assemble with --extended"));
+    // Their names are still labels in strict mode
+    assert_eq!(words(".ORIG x3000\nCLR ADD R0, R0, #0\nBR CLR\n.END\n"), [0x1020, 0x0FFE]);
+}
+
+#[test]
+fn pseudo_instructions_expand_to_instructions() {
+    let pseudo = ".ORIG x3000\nPUSH R1\nPOP R2\nMOV R3, R4\nCLR R5\nSUB R0, R1, #3\n.END\n";
+    let plain = ".ORIG x3000\nADD R6, R6, #-1\nSTR R1, R6, #0\nLDR R2, R6, #0\nADD R6, R6, #1\n\
+                 ADD R3, R4, #0\nAND R5, R5, #0\nADD R0, R1, #-3\n.END\n";
+    let program = extended(pseudo);
+    assert_eq!(program.segments[0].words, words(plain));
+    let errors = assemble_with(".ORIG x3000\nPOP R6\nSUB R0, R0, #20\n.END\n", None, Options { extended: true }).unwrap_err();
+    let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+    assert_eq!(
+        messages,
+        [
+            "2:5: error: POP cannot use R6, the stack pointer",
+            "3:13: error: SUB immediate 20 is out of range (-15 to 16)"
+        ]
+    );
+}
+
+#[test]
+fn sub_works_with_any_registers() {
+    let cases = [
+        ("SUB R0, R1, R2", 0, 7),
+        ("SUB R1, R1, R2", 1, 7),
+        ("SUB R2, R1, R2", 2, 7),
+        ("SUB R2, R2, R1", 2, (-7i16) as u16),
+        ("SUB R1, R1, R1", 1, 0),
+    ];
+    for (line, register, expected) in cases {
+        let source = format!(".ORIG x3000\nAND R1, R1, #0\nADD R1, R1, #10\nAND R2, R2, #0\nADD R2, R2, #3\n{}\n.END\n", line);
+        let program = extended(&source);
+        let (mut vm, _) = machine("");
+        load(&mut vm, 0x3000, &program.segments[0].words);
+        vm.registers.pc = 0x3000;
+        run(&mut vm, program.segments[0].words.len());
+        assert_eq!(vm.registers.get(register), expected, "{}", line);
+    }
+}
+
+#[test]
+fn ldimm_uses_a_literal_pool() {
+    let source = "\
+.ORIG x3000
+    LDIMM R0, #1234
+    LDIMM R1, x8000
+    LDIMM R2, #1234
+    BR NEXT
+    .POOL
+NEXT LDIMM R3, NEXT
+    HALT
+.END
+";
+    let program = extended(source);
+    // The second #1234 shares the first word
+    assert_eq!(
+        program.segments[0].words,
+        [0x2003, 0x2203, 0x2401, 0x0E02, 1234, 0x8000, 0x2601, 0xF025, 0x3006]
+    );
+    let mut out = Vec::new();
+    program.listing.write(&mut out, source).unwrap();
+    let listing = String::from_utf8(out).unwrap();
+    assert!(listing.contains("   6   x3004  x04D2  0000010011010010      .FILL #1234"), "{}", listing);
+    assert!(listing.contains("   9   x3008  x3006  0011000000000110      .FILL NEXT"), "{}", listing);
+
+    let far = ".ORIG x3000\nLDIMM R0, #1\nHALT\n.BLKW 300\n.END\n";
+    let errors = assemble_with(far, None, Options { extended: true }).unwrap_err();
+    assert_eq!(errors[0].to_string(), "2:1: error: the literal pool is too far: offset 301 does not fit in PCoffset9 (-256 to 255)");
+    assert_eq!(errors[0].help.as_deref(), Some("add a .POOL after a nearby HALT, RET or branch"));
+}
+
+#[test]
+fn command_line_typos_are_rejected() {
+    use little_computer_3::assembler;
+    let path = std::env::temp_dir().join(format!("lc3-assembler-{}-typo.asm", std::process::id()));
+    std::fs::write(&path, ".ORIG x3000\nHALT\n.END\n").unwrap();
+    let path = path.display().to_string();
+    let object = path.replace(".asm", ".obj");
+    let code = |args: &[&str]| assembler::main(args.iter().map(|a| a.to_string()).collect());
+    assert_eq!(code(&[&path, "--extnded"]), 1);
+    assert_eq!(code(&["--extnded", &path]), 1);
+    assert_eq!(code(&[&path, &path]), 1);
+    assert!(!std::path::Path::new(&object).exists());
+    assert_eq!(code(&["--extended", &path]), 0);
+    std::fs::remove_file(&object).unwrap();
+    std::fs::remove_file(&path).unwrap();
+}
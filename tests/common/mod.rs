@@ -0,0 +1,135 @@
+#![allow(dead_code)]
+use little_computer_3::hardware::register::ConditionFlag;
+use little_computer_3::hardware::vm::VM;
+use little_computer_3::runner::SharedBuffer;
+use std::io::Cursor;
+
+pub const N: u16 = ConditionFlag::NEG as u16;
+pub const Z: u16 = ConditionFlag::ZRO as u16;
+pub const P: u16 = ConditionFlag::POS as u16;
+
+/// A VM reading `input` from the keyboard,
+/// with the display output captured
+pub fn machine(input: &str) -> (VM, SharedBuffer) {
+    let output = SharedBuffer::default();
+    let vm = VM::with_console(
+        Box::new(Cursor::new(input.as_bytes().to_vec())),
+        Box::new(output.clone()),
+    );
+    (vm, output)
+}
+
+/// A VM with `words` loaded at x3000
+pub fn program(words: &[u16]) -> VM {
+    let (mut vm, _) = machine("");
+    load_program(&mut vm, words);
+    vm
+}
+
+/// Load `words` at x3000 and start there
+pub fn load_program(vm: &mut VM, words: &[u16]) {
+    load(vm, 0x3000, words);
+    vm.registers.pc = 0x3000;
+}
+
+pub fn load(vm: &mut VM, address: u16, words: &[u16]) {
+    for (i, &word) in words.iter().enumerate() {
+        vm.write_memory(address.wrapping_add(i as u16) as usize, word);
+    }
+}
+
+/// Execute `n` instructions
+pub fn run(vm: &mut VM, n: usize) {
+    for _ in 0..n {
+        vm.step();
+    }
+}
+
+pub fn output(buffer: &SharedBuffer) -> String {
+    String::from_utf8(buffer.contents()).unwrap()
+}
+
+// Encoders, straight from the instruction set table
+
+fn bits(value: i32, n: u32) -> u16 {
+    (value as u16) & ((1 << n) - 1) as u16
+}
+
+pub fn add(dr: u16, sr1: u16, sr2: u16) -> u16 {
+    0x1000 | (dr << 9) | (sr1 << 6) | sr2
+}
+
+pub fn add_imm(dr: u16, sr1: u16, imm5: i32) -> u16 {
+    0x1000 | (dr << 9) | (sr1 << 6) | 0x20 | bits(imm5, 5)
+}
+
+pub fn and(dr: u16, sr1: u16, sr2: u16) -> u16 {
+    0x5000 | (dr << 9) | (sr1 << 6) | sr2
+}
+
+pub fn and_imm(dr: u16, sr1: u16, imm5: i32) -> u16 {
+    0x5000 | (dr << 9) | (sr1 << 6) | 0x20 | bits(imm5, 5)
+}
+
+pub fn not(dr: u16, sr: u16) -> u16 {
+    0x9000 | (dr << 9) | (sr << 6) | 0x3F
+}
+
+/// `nzp` is the 3-bit condition mask
+pub fn br(nzp: u16, offset9: i32) -> u16 {
+    (nzp << 9) | bits(offset9, 9)
+}
+
+pub fn jmp(base: u16) -> u16 {
+    0xC000 | (base << 6)
+}
+
+pub fn ret() -> u16 {
+    jmp(7)
+}
+
+pub fn jsr(offset11: i32) -> u16 {
+    0x4800 | bits(offset11, 11)
+}
+
+pub fn jsrr(base: u16) -> u16 {
+    0x4000 | (base << 6)
+}
+
+pub fn ld(dr: u16, offset9: i32) -> u16 {
+    0x2000 | (dr << 9) | bits(offset9, 9)
+}
+
+pub fn ldi(dr: u16, offset9: i32) -> u16 {
+    0xA000 | (dr << 9) | bits(offset9, 9)
+}
+
+pub fn ldr(dr: u16, base: u16, offset6: i32) -> u16 {
+    0x6000 | (dr << 9) | (base << 6) | bits(offset6, 6)
+}
+
+pub fn lea(dr: u16, offset9: i32) -> u16 {
+    0xE000 | (dr << 9) | bits(offset9, 9)
+}
+
+pub fn st(sr: u16, offset9: i32) -> u16 {
+    0x3000 | (sr << 9) | bits(offset9, 9)
+}
+
+pub fn sti(sr: u16, offset9: i32) -> u16 {
+    0xB000 | (sr << 9) | bits(offset9, 9)
+}
+
+pub fn str(sr: u16, base: u16, offset6: i32) -> u16 {
+    0x7000 | (sr << 9) | (base << 6) | bits(offset6, 6)
+}
+
+pub fn trap(vector: u16) -> u16 {
+    0xF000 | vector
+}
+
+pub fn rti() -> u16 {
+    0x8000
+}
+
+pub const RES: u16 = 0xD000;
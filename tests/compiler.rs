@@ -0,0 +1,188 @@
+//! Compiling C and running the result
+mod common;
+
+use common::{load, machine};
+use little_computer_3::compiler::{build, compile};
+use little_computer_3::hardware::device::framebuffer;
+use little_computer_3::runner;
+
+/// Compile `source`, run it to HALT with `input`
+/// and return the output and `main`'s result
+fn run(source: &str, input: &str) -> (String, i16) {
+    let program = build("test.c", source).unwrap_or_else(|e| panic!("{}", e));
+    let (mut vm, output) = machine(input);
+    for segment in &program.segments {
+        load(&mut vm, segment.origin, &segment.words);
+    }
+    vm.registers.pc = program.segments[0].origin;
+    let mut steps = 0;
+    while vm.is_running() {
+        vm.step();
+        steps += 1;
+        assert!(steps < 1_000_000, "the program does not halt");
+    }
+    let text = common::output(&output).replace("HALT detected\n", "");
+    (text, vm.registers.r0 as i16)
+}
+
+fn errors(source: &str) -> Vec<String> {
+    compile(source).unwrap_err().iter().map(|e| e.to_string()).collect()
+}
+
+#[test]
+fn main_returns_in_r0() {
+    assert_eq!(run("int main() { return 6 * 7; }", "").1, 42);
+    assert_eq!(run("int main() { return -17 / 5 + -17 % 5; }", "").1, -5);
+}
+
+#[test]
+fn recursion_uses_the_stack() {
+    let source = "
+        int fib(int n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }
+        int fact(int n) { return n <= 1 ? 1 : n * fact(n - 1); }
+        int main() { print_int(fact(7)); putchar(' '); return fib(12); }
+    ";
+    assert_eq!(run(source, ""), ("5040 ".to_string(), 144));
+}
+
+#[test]
+fn arrays_and_pointers() {
+    let source = "
+        int table[5] = {5, 3, 9, 1, 7};
+        void sort(int *a, int n) {
+            for (int i = 0; i < n; i++)
+                for (int j = i + 1; j < n; j++)
+                    if (a[j] < a[i]) { int t = a[i]; a[i] = a[j]; a[j] = t; }
+        }
+        int main() {
+            int *p = table;
+            sort(table, sizeof(table) / sizeof(int));
+            while (p < table + 5) { print_int(*p++); putchar(' '); }
+            return &table[4] - table;
+        }
+    ";
+    assert_eq!(run(source, ""), ("1 3 5 7 9 ".to_string(), 4));
+}
+
+#[test]
+fn strings_and_characters() {
+    let source = r#"
+        char *greeting = "hello";
+        int length(char *s) { int n = 0; while (*s++) n++; return n; }
+        int main() {
+            char name[10] = "world";
+            name[0] = getchar();
+            print_string(greeting);
+            putchar(' ');
+            puts(name);
+            return length(name) + length("");
+        }
+    "#;
+    assert_eq!(run(source, "W"), ("hello World\n".to_string(), 5));
+}
+
+#[test]
+fn control_flow() {
+    let source = "
+        int counter;
+        int main() {
+            int i = 0;
+            do { i++; if (i == 3) continue; if (i > 8) break; counter += i; } while (1);
+            while (i > 0) i -= 2;
+            return counter * 10 + (!i && (i || 1));
+        }
+    ";
+    // 1 + 2 + 4 + ... + 8 = 33, then i ends at -1
+    assert_eq!(run(source, "").1, 330);
+}
+
+#[test]
+fn comparisons_do_not_overflow() {
+    let source = "
+        int main() {
+            int a = 30000, b = -30000, min = -32767 - 1;
+            print_int(a < b); print_int(b < a); print_int(a <= b); print_int(b >= a);
+            print_int(min < 1); print_int(1 > min); print_int(min <= 0); print_int(0 < min);
+            return (a > b) + (b > a);
+        }
+    ";
+    assert_eq!(run(source, ""), ("01001110".to_string(), 1));
+}
+
+#[test]
+fn pointers_compare_unsigned() {
+    let source = "
+        int main() {
+            int *low = 0x1000, *high = 0xF000;
+            print_int(low < high); print_int(high < low); print_int(high >= low);
+            return (high > low) + (low == low);
+        }
+    ";
+    assert_eq!(run(source, ""), ("101".to_string(), 2));
+}
+
+#[test]
+fn long_functions_get_literal_pools() {
+    let calls = "print_int(i); ".repeat(120);
+    let source = format!("int main() {{ int i = 1; {} return i; }}", calls);
+    assert_eq!(run(&source, "").0, "1".repeat(120));
+}
+
+#[test]
+fn long_expressions_get_literal_pools() {
+    let terms = vec!["(x * i)"; 39].join(" + ");
+    let source = format!("int main() {{ int x = 2; int i = 3; return {}; }}", terms);
+    assert_eq!(run(&source, "").1, 39 * 6);
+}
+
+#[test]
+fn long_bodies_reach_their_labels() {
+    let body = "x = x * 3 + y * 5 - x / 7; ".repeat(40);
+    let source = format!(
+        "int main() {{ int x = 1; int y = 2; int i; for (i = 0; i < 3; i++) {{ if (x != y) {{ {} }} }} return x; }}",
+        body
+    );
+    let mut x: i16 = 1;
+    for _ in 0..3 * 40 {
+        x = x.wrapping_mul(3).wrapping_add(10).wrapping_sub(x / 7);
+    }
+    assert_eq!(run(&source, ""), (String::new(), x));
+}
+
+#[test]
+fn stack_is_below_the_framebuffer() {
+    let assembly = compile("int main() { return 0; }").unwrap();
+    assert!(assembly.contains(&format!("LDIMM R6, x{:04X}", framebuffer::VIDEO_START)), "{}", assembly);
+}
+
+#[test]
+fn compiled_assembly_runs() {
+    let dir = std::env::temp_dir();
+    let program = dir.join(format!("lc3-compiled-{}.asm", std::process::id()));
+    let report = dir.join(format!("lc3-compiled-{}.json", std::process::id()));
+    let assembly = compile("int main() { print_int(6 * 7); return 0; }").unwrap();
+    std::fs::write(&program, assembly).unwrap();
+    let args = ["--report", report.to_str().unwrap(), program.to_str().unwrap()];
+    let code = runner::main(args.iter().map(|a| a.to_string()).collect());
+    let json: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&report).unwrap()).unwrap();
+    std::fs::remove_file(program).unwrap();
+    std::fs::remove_file(report).unwrap();
+    assert_eq!(code, runner::EXIT_HALTED, "{}", json);
+    assert_eq!(json["output"], "42HALT detected\n");
+}
+
+#[test]
+fn errors_have_positions() {
+    assert_eq!(errors("int main() {\n  return x;\n}"), ["2:10: error: undefined variable `x`"]);
+    assert_eq!(errors("int main() { return 1 }"), ["1:23: error: expected `;`, found `}`"]);
+    // Semantic errors are all reported
+    let found = errors("int main() {\n  y = 1;\n  f();\n}");
+    assert_eq!(found.len(), 2, "{:?}", found);
+}
+
+#[test]
+fn rendered_errors_point_at_the_column() {
+    let source = "int main() {\n  return @;\n}";
+    let rendered = compile(source).unwrap_err()[0].render("test.c", source);
+    assert_eq!(rendered, "test.c:2:10: error: unexpected character `@`\n  |\n2 |   return @;\n  |          ^\n");
+}
@@ -0,0 +1,415 @@
+//! Memory mapped devices seen from programs
+mod common;
+
+use common::*;
+use little_computer_3::assembler::{assemble_with, Options};
+use little_computer_3::hardware::bus::{Device, Dma, Interrupt};
+use little_computer_3::hardware::device::disk::{Disk, DISK_ERROR, DISK_READ, DISK_READY, SECTOR_SIZE};
+use little_computer_3::hardware::device::framebuffer::{Framebuffer, HEIGHT, WIDTH};
+use little_computer_3::hardware::device::keyboard::Keyboard;
+use little_computer_3::hardware::device::rng::{Rng, RNG_DETERMINISTIC};
+use little_computer_3::hardware::device::timer::{TIMER_ENABLE, TIMER_EXPIRED, TIMER_INTERRUPT_ENABLE, TIMER_PRIORITY};
+use little_computer_3::hardware::replay::{self, InputLog, ReplayError};
+use little_computer_3::hardware::vm::{Fault, MemoryMappedReg, VM};
+use little_computer_3::runner::{self, HaltReason, SharedBuffer};
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+
+fn run_to_halt(vm: &mut VM) {
+    assert_eq!(runner::run(vm, 10_000), HaltReason::Halted);
+}
+
+fn keyboard(vm: &mut VM) -> &mut Keyboard {
+    vm.bus.device_mut::<Keyboard>().unwrap()
+}
+
+/// Echo two characters, after `delay` instructions
+fn echo(delay: usize) -> Vec<u16> {
+    let mut words = vec![add_imm(1, 1, 1); delay];
+    words.extend([trap(0x20), trap(0x21), trap(0x20), trap(0x21), trap(0x25)]);
+    words
+}
+
+/// Run `echo(0)` on `input` and return the log
+fn record(input: &str) -> InputLog {
+    let (mut vm, _) = machine(input);
+    load_program(&mut vm, &echo(0));
+    let log = SharedBuffer::default();
+    replay::write_header(&mut log.clone(), Some(7)).unwrap();
+    keyboard(&mut vm).record(Box::new(log.clone()));
+    run_to_halt(&mut vm);
+    InputLog::read(&log.contents()[..]).unwrap()
+}
+
+#[test]
+fn recorded_input_replays() {
+    let log = record("hi");
+    assert_eq!(log.seed, Some(7));
+    // Each GETC polls KBSR, then reads KBDR
+    let first = log.events[0];
+    assert_eq!((first.instruction, first.address, first.value), (0, 0xFE00, 0x8000));
+    assert_eq!((log.events[1].address, log.events[1].value), (0xFE02, 'h' as u16));
+    assert_eq!(log.events.back().unwrap().instruction, 2);
+
+    let (mut vm, output) = machine("");
+    load_program(&mut vm, &echo(0));
+    keyboard(&mut vm).replay(log);
+    run_to_halt(&mut vm);
+    assert!(keyboard(&mut vm).error().is_none());
+    assert_eq!(common::output(&output), "hiHALT detected\n");
+    assert_eq!(vm.registers.r0, 'i' as u16);
+}
+
+#[test]
+fn replay_reports_divergence() {
+    let (mut vm, _) = machine("");
+    load_program(&mut vm, &echo(1));
+    keyboard(&mut vm).replay(record("hi"));
+    run_to_halt(&mut vm);
+    match keyboard(&mut vm).error() {
+        Some(ReplayError::Diverged { instruction, address }) => assert_eq!((*instruction, *address), (1, 0xFE00)),
+        other => panic!("expected a divergence, got {:?}", other),
+    }
+}
+
+struct Broken;
+
+impl Write for Broken {
+    fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+        Err(io::Error::other("disk full"))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn recording_errors_stop_the_recording() {
+    let (mut vm, output) = machine("hi");
+    load_program(&mut vm, &echo(0));
+    keyboard(&mut vm).record(Box::new(Broken));
+    run_to_halt(&mut vm);
+    // The input still reaches the program
+    assert_eq!(common::output(&output), "hiHALT detected\n");
+    let error = keyboard(&mut vm).error().unwrap();
+    assert!(matches!(error, ReplayError::Io(_)));
+    assert_eq!(error.to_string(), "Unable to write input log: disk full");
+}
+
+#[test]
+fn framebuffer_pixels_are_dumped() {
+    // Red at (5, 2), blue in the bottom right corner
+    let mut vm = common::program(&[ld(0, 4), sti(0, 4), ld(0, 4), sti(0, 4), trap(0x25), 0x7C00, 0xC105, 0x001F, 0xFDFF]);
+    run_to_halt(&mut vm);
+    assert_eq!(vm.read_memory(0xC105), 0x7C00);
+    // The device claims the range, memory is untouched
+    assert_eq!(vm.memory[0xC105], 0);
+    let framebuffer = vm.bus.device::<Framebuffer>().unwrap();
+    assert_eq!(framebuffer.pixel(5, 2), 0x7C00);
+    assert_eq!(framebuffer.rgb(WIDTH - 1, HEIGHT - 1), [0, 0, 255]);
+
+    let mut ppm = Vec::new();
+    framebuffer.write_ppm(&mut ppm).unwrap();
+    let header = b"P6\n128 124\n255\n";
+    assert_eq!(&ppm[..header.len()], header);
+    let pixels = &ppm[header.len()..];
+    assert_eq!(pixels.len(), WIDTH * HEIGHT * 3);
+    assert_eq!(&pixels[(2 * WIDTH + 5) * 3..][..3], [255, 0, 0]);
+    assert_eq!(&pixels[pixels.len() - 3..], [0, 0, 255]);
+    assert!(pixels[..15].iter().all(|&c| c == 0));
+
+    let path = std::env::temp_dir().join(format!("lc3-devices-{}.png", std::process::id()));
+    framebuffer.save(&path).unwrap();
+    let png = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+    assert_eq!(&png[12..16], b"IHDR");
+    assert_eq!(png[16..20], (WIDTH as u32).to_be_bytes());
+    assert_eq!(png[20..24], (HEIGHT as u32).to_be_bytes());
+    // Rows are stored uncompressed after the chunk,
+    // zlib and block headers, each behind a filter byte
+    assert_eq!(&png[37..41], b"IDAT");
+    let rows = &png[41 + 2 + 5..];
+    let at = |x: usize, y: usize| &rows[y * (1 + WIDTH * 3) + 1 + x * 3..][..3];
+    assert_eq!(at(5, 2), [255, 0, 0]);
+    assert_eq!(at(4, 2), [0, 0, 0]);
+    assert_eq!(at(WIDTH - 1, HEIGHT - 1), [0, 0, 255]);
+}
+
+fn disk_image(name: &str, words: &[u16]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("lc3-devices-{}-{}", std::process::id(), name));
+    std::fs::write(&path, words.iter().flat_map(|w| w.to_be_bytes()).collect::<Vec<u8>>()).unwrap();
+    path
+}
+
+#[test]
+fn disk_dma_goes_through_the_bus() {
+    // Sector 1 is a white line
+    let mut sectors = vec![0; SECTOR_SIZE];
+    sectors.extend([0x7FFF; SECTOR_SIZE]);
+    let path = disk_image("dma.img", &sectors);
+    let (mut vm, _) = machine("");
+    vm.bus.attach(Box::new(Disk::open(&path).unwrap()));
+    // Read sector 1 to the screen, write the screen
+    // back to sector 2
+    let source = ".ORIG x3000\nAND R0, R0, #0\nADD R0, R0, #1\nLD R1, SCREEN\nDREAD\nADD R2, R0, #0\n\
+        ADD R0, R0, #2\nDWRITE\nHALT\nSCREEN .FILL xC000\n.END\n";
+    let program = assemble_with(source, None, Options { extended: true }).unwrap();
+    load_program(&mut vm, &program.segments[0].words);
+    run_to_halt(&mut vm);
+    let image = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!((vm.registers.r2, vm.registers.r0), (0, 0));
+    let framebuffer = vm.bus.device::<Framebuffer>().unwrap();
+    assert_eq!(framebuffer.pixel(0, 0), 0x7FFF);
+    assert_eq!(framebuffer.pixel(WIDTH - 1, 1), 0x7FFF);
+    assert_eq!(framebuffer.pixel(0, 2), 0);
+    assert_eq!(vm.memory[0xC000], 0);
+    assert_eq!(image.len(), 3 * SECTOR_SIZE * 2);
+    assert!(image[2 * SECTOR_SIZE * 2..].chunks(2).all(|w| w == [0x7F, 0xFF]));
+}
+
+#[test]
+fn disk_registers_report_errors() {
+    let path = disk_image("error.img", &[]);
+    let (mut vm, _) = machine("");
+    vm.bus.attach(Box::new(Disk::open(&path).unwrap()));
+    // An unknown command, then poll DSKSR
+    load_program(&mut vm, &[sti(0, 3), ldi(1, 3), br(0b111, -2), trap(0x25), 0xFE16, 0xFE10]);
+    vm.registers.r0 = 7;
+    run(&mut vm, 2);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(vm.registers.r1, DISK_READY | DISK_ERROR);
+
+    // A later command clears the error
+    let path = disk_image("read.img", &[1, 2, 3]);
+    let mut disk = Disk::open(&path).unwrap();
+    let mut memory = vec![0; 0x10000];
+    disk.write(MemoryMappedReg::Dskadr as u16, 0x4000);
+    disk.write(MemoryMappedReg::Dskcmd as u16, DISK_READ);
+    assert_eq!(disk.read(MemoryMappedReg::Dsksr as u16), 0);
+    disk.execute(&mut Dma::new(&mut memory));
+    assert_eq!(disk.read(MemoryMappedReg::Dsksr as u16), DISK_READY);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(memory[0x4000..0x4004], [1, 2, 3, 0]);
+}
+
+/// Remembers the last write, reads return the
+/// address plus the number of reads
+#[derive(Default)]
+struct Probe {
+    reads: u16,
+    written: Option<(u16, u16)>,
+}
+
+impl Device for Probe {
+    fn name(&self) -> &str {
+        "probe"
+    }
+
+    fn range(&self) -> RangeInclusive<u16> {
+        0x4000..=0x4001
+    }
+
+    fn read(&mut self, address: u16) -> u16 {
+        self.reads += 1;
+        address + self.reads
+    }
+
+    fn write(&mut self, address: u16, value: u16) {
+        self.written = Some((address, value));
+    }
+}
+
+#[test]
+fn bus_routes_claimed_addresses() {
+    let mut vm = common::program(&[ldi(0, 2), sti(0, 2), trap(0x25), 0x4001, 0x4000]);
+    vm.bus.attach(Box::<Probe>::default());
+    assert!(vm.bus.claims(0x4001));
+    assert!(!vm.bus.claims(0x4002));
+    run_to_halt(&mut vm);
+    assert_eq!(vm.registers.r0, 0x4002);
+    let probe = vm.bus.device::<Probe>().unwrap();
+    assert_eq!((probe.reads, probe.written), (1, Some((0x4000, 0x4002))));
+    // Memory behind the device is never touched
+    assert_eq!(vm.memory[0x4000..0x4002], [0, 0]);
+
+    // Unclaimed addresses are plain memory
+    vm.write_memory(0x4002, 7);
+    assert_eq!((vm.memory[0x4002], vm.read_memory(0x4002)), (7, 7));
+    assert_eq!(vm.bus.device_named_mut("probe").unwrap().read(0x4000), 0x4002);
+    assert!(vm.bus.device_named_mut("printer").is_none());
+}
+
+#[test]
+#[should_panic(expected = "Device clash overlaps with keyboard at xFE00-xFE03")]
+fn bus_rejects_overlapping_devices() {
+    struct Clash;
+    impl Device for Clash {
+        fn name(&self) -> &str {
+            "clash"
+        }
+        fn range(&self) -> RangeInclusive<u16> {
+            0xFE02..=0xFE02
+        }
+        fn read(&mut self, _: u16) -> u16 {
+            0
+        }
+        fn write(&mut self, _: u16, _: u16) {}
+    }
+    let (mut vm, _) = machine("");
+    vm.bus.attach(Box::new(Clash));
+}
+
+#[test]
+fn interrupts_without_a_handler_fault() {
+    /// Always requests an interrupt at vector x90
+    struct Alarm;
+    impl Device for Alarm {
+        fn name(&self) -> &str {
+            "alarm"
+        }
+        fn range(&self) -> RangeInclusive<u16> {
+            0x4000..=0x4000
+        }
+        fn read(&mut self, _: u16) -> u16 {
+            0
+        }
+        fn write(&mut self, _: u16, _: u16) {}
+        fn tick(&mut self, _: &mut Dma) -> Option<Interrupt> {
+            Some(Interrupt { vector: 0x90, priority: 1 })
+        }
+    }
+    let mut vm = common::program(&nops(20));
+    vm.bus.attach(Box::new(Alarm));
+    assert_eq!(runner::run(&mut vm, 10), HaltReason::Fault);
+    assert_eq!(vm.fault, Some(Fault::Exception { vector: 0x90, pc: 0x3001 }));
+    assert_eq!(vm.registers.priority, 0);
+    assert!(vm.registers.user_mode);
+}
+
+/// `n` instructions that do nothing
+fn nops(n: usize) -> Vec<u16> {
+    vec![br(0, 0); n]
+}
+
+#[test]
+fn timer_counts_instructions() {
+    let mut vm = common::program(&nops(20));
+    assert_eq!(vm.read_memory(0xFE08), TIMER_PRIORITY << 8);
+    vm.write_memory(0xFE0A, 5);
+    assert_eq!(vm.read_memory(0xFE0C), 5);
+    run(&mut vm, 3);
+    // Nothing happens until it is enabled
+    assert_eq!(vm.read_memory(0xFE0C), 5);
+    vm.write_memory(0xFE08, TIMER_ENABLE);
+    run(&mut vm, 4);
+    assert_eq!(vm.read_memory(0xFE0C), 1);
+    assert_eq!(vm.read_memory(0xFE08) & TIMER_EXPIRED, 0);
+    run(&mut vm, 1);
+    // Expired and reloaded, polled without interrupt
+    assert_eq!(vm.read_memory(0xFE08), TIMER_EXPIRED | TIMER_ENABLE);
+    assert_eq!(vm.read_memory(0xFE0C), 5);
+    assert_eq!(vm.registers.pc, 0x3008);
+    vm.write_memory(0xFE08, TIMER_EXPIRED | TIMER_ENABLE);
+    assert_eq!(vm.read_memory(0xFE08), TIMER_ENABLE);
+    run(&mut vm, 5);
+    assert_ne!(vm.read_memory(0xFE08) & TIMER_EXPIRED, 0);
+}
+
+#[test]
+fn timer_interrupts_use_its_vector_and_priority() {
+    let mut vm = common::program(&nops(20));
+    vm.memory[0x0190] = 0x1000;
+    vm.write_memory(0xFE0E, 0x90);
+    vm.write_memory(0xFE0A, 2);
+    // Priority 0 never interrupts a program at level 0
+    vm.write_memory(0xFE08, TIMER_INTERRUPT_ENABLE | TIMER_ENABLE);
+    run(&mut vm, 4);
+    assert_eq!(vm.registers.pc, 0x3004);
+    assert_ne!(vm.read_memory(0xFE08) & TIMER_EXPIRED, 0);
+    // The request is still pending at a higher priority
+    vm.write_memory(0xFE08, TIMER_INTERRUPT_ENABLE | (1 << 8) | TIMER_ENABLE);
+    run(&mut vm, 1);
+    assert_eq!(vm.registers.pc, 0x1000);
+    assert_eq!(vm.registers.priority, 1);
+    assert!(!vm.registers.user_mode);
+}
+
+#[test]
+fn timer_interrupts_need_a_handler() {
+    let mut vm = common::program(&nops(20));
+    vm.write_memory(0xFE0A, 10);
+    vm.write_memory(0xFE08, TIMER_INTERRUPT_ENABLE | (TIMER_PRIORITY << 8) | TIMER_ENABLE);
+    assert_eq!(runner::run(&mut vm, 100), HaltReason::Fault);
+    assert_eq!(vm.fault, Some(Fault::Exception { vector: 0x81, pc: 0x300A }));
+    assert_eq!(vm.registers.priority, 0);
+}
+
+#[test]
+fn halted_machines_take_no_interrupts() {
+    let mut vm = common::program(&[trap(0x25)]);
+    vm.memory[0x0181] = 0x1000;
+    vm.write_memory(0xFE0A, 1);
+    vm.write_memory(0xFE08, TIMER_INTERRUPT_ENABLE | (1 << 8) | TIMER_ENABLE);
+    run_to_halt(&mut vm);
+    assert_eq!(vm.registers.pc, 0x3001);
+    assert_eq!(vm.registers.priority, 0);
+    assert!(vm.registers.user_mode);
+}
+
+#[test]
+fn keyboard_registers_are_read_only() {
+    let (mut vm, _) = machine("a");
+    vm.write_memory(MemoryMappedReg::Kbsr as usize, 1 << 14);
+    vm.write_memory(MemoryMappedReg::Kbdr as usize, 7);
+    assert_eq!(vm.peek_memory(MemoryMappedReg::Kbsr as u16), 0);
+    assert_eq!(vm.peek_memory(MemoryMappedReg::Kbdr as u16), 0);
+}
+
+#[test]
+fn peeking_leaves_devices_alone() {
+    let (mut vm, _) = machine("a");
+    *vm.bus.device_mut::<Rng>().unwrap() = Rng::seeded(0);
+    // The input is not polled
+    assert_eq!(vm.peek_memory(MemoryMappedReg::Kbsr as u16), 0);
+    assert_eq!(vm.read_memory(MemoryMappedReg::Kbsr as u16), 0x8000);
+    assert_eq!(vm.peek_memory(MemoryMappedReg::Kbdr as u16), b'a' as u16);
+    assert_eq!(vm.peek_memory(MemoryMappedReg::Kbsr as u16), 0x8000);
+    // The sequence does not advance
+    assert_eq!(vm.peek_memory(MemoryMappedReg::Rngdr as u16), 0xE220);
+    assert_eq!(vm.read_memory(MemoryMappedReg::Rngdr as u16), 0xE220);
+    assert_eq!(vm.peek_memory(MemoryMappedReg::Rngdr as u16), 0x6E78);
+    // Plain memory and devices without side effects
+    vm.memory[0x4000] = 7;
+    assert_eq!(vm.peek_memory(0x4000), 7);
+    assert_eq!(vm.peek_memory(MemoryMappedReg::Dsr as u16), 0x8000);
+}
+
+#[test]
+fn rng_sequence_depends_only_on_the_seed() {
+    // splitmix64, the top 16 bits of every output
+    let mut rng = Rng::seeded(0);
+    assert_eq!([rng.next_word(), rng.next_word(), rng.next_word()], [0xE220, 0x6E78, 0x06C4]);
+
+    // Seed from a program, then draw two words
+    let mut vm = common::program(&[ld(0, 4), sti(0, 4), ldi(1, 4), ldi(2, 3), trap(0x25), 1234, 0xFE1C, 0xFE18]);
+    run_to_halt(&mut vm);
+    assert_eq!((vm.registers.r1, vm.registers.r2), (0xBB0C, 0x97C7));
+    assert_eq!(vm.read_memory(0xFE1A), RNG_DETERMINISTIC);
+    assert_eq!(vm.read_memory(0xFE1C), 1234);
+
+    // Switching the mode on again restarts the sequence
+    vm.write_memory(0xFE1A, RNG_DETERMINISTIC);
+    assert_eq!(vm.read_memory(0xFE18), 0xBB0C);
+    // Clearing the bit selects the host entropy
+    vm.write_memory(0xFE1A, 0);
+    assert_eq!(vm.read_memory(0xFE1A), 0);
+    // and back again, still from the programmed seed
+    vm.write_memory(0xFE1A, RNG_DETERMINISTIC);
+    assert_eq!(vm.read_memory(0xFE1C), 1234);
+    assert_eq!(vm.read_memory(0xFE18), 0xBB0C);
+}
@@ -0,0 +1,508 @@
+//! Conformance tests for every opcode, written
+//! against the LC-3 ISA specification.
+//! The instructions are encoded by hand so that
+//! the tests do not depend on the assembler.
+mod common;
+
+use common::*;
+use little_computer_3::hardware::instruction::{get_op_code, sign_extend, OpCode};
+use little_computer_3::hardware::vm::{
+    Fault, ILLEGAL_OPCODE_EXCEPTION, INTERRUPT_TABLE, PRIVILEGE_EXCEPTION,
+};
+
+#[test]
+fn op_code_discriminants_match_encoding() {
+    let op_codes = [
+        OpCode::BR,
+        OpCode::ADD,
+        OpCode::LD,
+        OpCode::ST,
+        OpCode::JSR,
+        OpCode::AND,
+        OpCode::LDR,
+        OpCode::STR,
+        OpCode::RTI,
+        OpCode::NOT,
+        OpCode::LDI,
+        OpCode::STI,
+        OpCode::JMP,
+        OpCode::RES,
+        OpCode::LEA,
+        OpCode::TRAP,
+    ];
+    for (bits, op_code) in op_codes.into_iter().enumerate() {
+        assert_eq!(op_code as u16, bits as u16);
+        assert_eq!(get_op_code(&((bits as u16) << 12)), Some(op_code));
+        assert_eq!(
+            get_op_code(&(((bits as u16) << 12) | 0x0FFF)),
+            Some(op_code)
+        );
+    }
+}
+
+#[test]
+fn sign_extend_boundaries() {
+    assert_eq!(sign_extend(0x0F, 5), 15);
+    assert_eq!(sign_extend(0x10, 5), (-16i16) as u16);
+    assert_eq!(sign_extend(0x1F, 5), 0xFFFF);
+    assert_eq!(sign_extend(0x1F, 6), 31);
+    assert_eq!(sign_extend(0x20, 6), (-32i16) as u16);
+    assert_eq!(sign_extend(0x0FF, 9), 255);
+    assert_eq!(sign_extend(0x100, 9), (-256i16) as u16);
+    assert_eq!(sign_extend(0x3FF, 11), 1023);
+    assert_eq!(sign_extend(0x400, 11), (-1024i16) as u16);
+    assert_eq!(sign_extend(0, 5), 0);
+}
+
+#[test]
+fn add_register_mode() {
+    let mut vm = program(&[add(0, 1, 2)]);
+    vm.registers.r1 = 1200;
+    vm.registers.r2 = 34;
+    run(&mut vm, 1);
+    assert_eq!(vm.registers.r0, 1234);
+    assert_eq!(vm.registers.cond, P);
+    assert_eq!(vm.registers.pc, 0x3001);
+}
+
+#[test]
+fn add_immediate_boundaries() {
+    let mut vm = program(&[add_imm(0, 1, 15), add_imm(2, 1, -16)]);
+    vm.registers.r1 = 100;
+    run(&mut vm, 2);
+    assert_eq!(vm.registers.r0, 115);
+    assert_eq!(vm.registers.r2, 84);
+}
+
+#[test]
+fn add_wraps_around() {
+    let mut vm = program(&[add_imm(0, 0, 1), add_imm(1, 1, 1)]);
+    vm.registers.r0 = 0x7FFF;
+    vm.registers.r1 = 0xFFFF;
+    run(&mut vm, 1);
+    assert_eq!(vm.registers.r0, 0x8000);
+    assert_eq!(vm.registers.cond, N);
+    run(&mut vm, 1);
+    assert_eq!(vm.registers.r1, 0);
+    assert_eq!(vm.registers.cond, Z);
+}
+
+#[test]
+fn add_same_register_as_source_and_destination() {
+    let mut vm = program(&[add(3, 3, 3)]);
+    vm.registers.r3 = 21;
+    run(&mut vm, 1);
+    assert_eq!(vm.registers.r3, 42);
+}
+
+#[test]
+fn and_register_and_immediate() {
+    let mut vm = program(&[
+        and(0, 1, 2),
+        and_imm(3, 1, 0x0F),
+        and_imm(4, 1, -1),
+        and_imm(5, 1, 0),
+    ]);
+    vm.registers.r1 = 0xF0F5;
+    vm.registers.r2 = 0x8F0F;
+    run(&mut vm, 1);
+    assert_eq!(vm.registers.r0, 0x8005);
+    assert_eq!(vm.registers.cond, N);
+    run(&mut vm, 1);
+    assert_eq!(vm.registers.r3, 0x0005);
+    assert_eq!(vm.registers.cond, P);
+    run(&mut vm, 1);
+    // -1 is sign extended to xFFFF
+    assert_eq!(vm.registers.r4, 0xF0F5);
+    run(&mut vm, 1);
+    assert_eq!(vm.registers.r5, 0);
+    assert_eq!(vm.registers.cond, Z);
+}
+
+#[test]
+fn not_complements() {
+    let mut vm = program(&[not(0, 1), not(2, 2)]);
+    vm.registers.r1 = 0x00FF;
+    vm.registers.r2 = 0xFFFF;
+    run(&mut vm, 1);
+    assert_eq!(vm.registers.r0, 0xFF00);
+    assert_eq!(vm.registers.cond, N);
+    run(&mut vm, 1);
+    assert_eq!(vm.registers.r2, 0);
+    assert_eq!(vm.registers.cond, Z);
+}
+
+#[test]
+fn br_every_mask_against_every_condition() {
+    for cond in [N, Z, P] {
+        for mask in 0..8 {
+            let mut vm = program(&[br(mask, 10)]);
+            vm.registers.cond = cond;
+            run(&mut vm, 1);
+            let expected = if mask & cond != 0 { 0x300B } else { 0x3001 };
+            assert_eq!(
+                vm.registers.pc, expected,
+                "mask {:03b} cond {:03b}",
+                mask, cond
+            );
+        }
+    }
+}
+
+#[test]
+fn br_does_not_change_condition_codes() {
+    let mut vm = program(&[br(0b111, 0)]);
+    vm.registers.cond = N;
+    run(&mut vm, 1);
+    assert_eq!(vm.registers.cond, N);
+}
+
+#[test]
+fn br_offset_boundaries() {
+    let mut vm = program(&[br(0b111, 255)]);
+    run(&mut vm, 1);
+    assert_eq!(vm.registers.pc, 0x3001 + 255);
+
+    let mut vm = program(&[br(0b111, -256)]);
+    run(&mut vm, 1);
+    assert_eq!(vm.registers.pc, 0x3001 - 256);
+}
+
+#[test]
+fn br_wraps_around_address_space() {
+    let (mut vm, _) = machine("");
+    load(&mut vm, 0x0000, &[br(0b111, -2)]);
+    vm.registers.pc = 0x0000;
+    run(&mut vm, 1);
+    assert_eq!(vm.registers.pc, 0xFFFF);
+
+    let (mut vm, _) = machine("");
+    load(&mut vm, 0xFDF0, &[br(0b111, 255)]);
+    vm.registers.pc = 0xFDF0;
+    run(&mut vm, 1);
+    assert_eq!(vm.registers.pc, 0xFDF0 + 256);
+}
+
+#[test]
+fn fetch_wraps_from_xffff_to_x0000() {
+    let (mut vm, _) = machine("");
+    vm.memory[0xFFFF] = add_imm(0, 0, 1);
+    vm.registers.pc = 0xFFFF;
+    run(&mut vm, 1);
+    assert_eq!(vm.registers.pc, 0x0000);
+    assert_eq!(vm.registers.r0, 1);
+}
+
+#[test]
+fn jmp_and_ret() {
+    let mut vm = program(&[jmp(2)]);
+    vm.registers.r2 = 0x4567;
+    run(&mut vm, 1);
+    assert_eq!(vm.registers.pc, 0x4567);
+
+    let mut vm = program(&[ret()]);
+    vm.registers.r7 = 0x1234;
+    run(&mut vm, 1);
+    assert_eq!(vm.registers.pc, 0x1234);
+}
+
+#[test]
+fn jsr_offset_boundaries() {
+    let mut vm = program(&[jsr(1023)]);
+    run(&mut vm, 1);
+    assert_eq!(vm.registers.pc, 0x3001 + 1023);
+    assert_eq!(vm.registers.r7, 0x3001);
+
+    let mut vm = program(&[jsr(-1024)]);
+    run(&mut vm, 1);
+    assert_eq!(vm.registers.pc, 0x3001 - 1024);
+    assert_eq!(vm.registers.r7, 0x3001);
+}
+
+#[test]
+fn jsrr_jumps_to_base_register() {
+    let mut vm = program(&[jsrr(3)]);
+    vm.registers.r3 = 0x5000;
+    vm.registers.cond = P;
+    run(&mut vm, 1);
+    assert_eq!(vm.registers.pc, 0x5000);
+    assert_eq!(vm.registers.r7, 0x3001);
+    assert_eq!(vm.registers.cond, P);
+}
+
+#[test]
+fn jsrr_r7_uses_old_value() {
+    let mut vm = program(&[jsrr(7)]);
+    vm.registers.r7 = 0x5000;
+    run(&mut vm, 1);
+    assert_eq!(vm.registers.pc, 0x5000);
+    assert_eq!(vm.registers.r7, 0x3001);
+}
+
+#[test]
+fn ld_offset_boundaries_and_condition_codes() {
+    let mut vm = program(&[ld(0, 255)]);
+    vm.memory[0x3001 + 255] = 0x8000;
+    run(&mut vm, 1);
+    assert_eq!(vm.registers.r0, 0x8000);
+    assert_eq!(vm.registers.cond, N);
+
+    let mut vm = program(&[ld(1, -256)]);
+    vm.memory[0x3001 - 256] = 7;
+    run(&mut vm, 1);
+    assert_eq!(vm.registers.r1, 7);
+    assert_eq!(vm.registers.cond, P);
+
+    let mut vm = program(&[ld(2, 1)]);
+    vm.registers.r2 = 99;
+    run(&mut vm, 1);
+    assert_eq!(vm.registers.r2, 0);
+    assert_eq!(vm.registers.cond, Z);
+}
+
+#[test]
+fn ld_wraps_around_address_space() {
+    let (mut vm, _) = machine("");
+    load(&mut vm, 0x0010, &[ld(0, -256)]);
+    vm.memory[0xFF11] = 0x2222;
+    vm.registers.pc = 0x0010;
+    run(&mut vm, 1);
+    assert_eq!(vm.registers.r0, 0x2222);
+}
+
+#[test]
+fn ldi_loads_through_pointer() {
+    let mut vm = program(&[ldi(0, 1)]);
+    vm.memory[0x3002] = 0x4000;
+    vm.memory[0x4000] = 0xFFFE;
+    run(&mut vm, 1);
+    assert_eq!(vm.registers.r0, 0xFFFE);
+    assert_eq!(vm.registers.cond, N);
+}
+
+#[test]
+fn ldi_offset_boundaries() {
+    let mut vm = program(&[ldi(0, -256)]);
+    vm.memory[0x3001 - 256] = 0x4000;
+    vm.memory[0x4000] = 5;
+    run(&mut vm, 1);
+    assert_eq!(vm.registers.r0, 5);
+
+    let mut vm = program(&[ldi(0, 255)]);
+    vm.memory[0x3001 + 255] = 0x4001;
+    vm.memory[0x4001] = 6;
+    run(&mut vm, 1);
+    assert_eq!(vm.registers.r0, 6);
+}
+
+#[test]
+fn ldr_offset_boundaries() {
+    let mut vm = program(&[ldr(0, 1, 31), ldr(2, 1, -32)]);
+    vm.registers.r1 = 0x4000;
+    vm.memory[0x4000 + 31] = 31;
+    vm.memory[0x4000 - 32] = 0;
+    run(&mut vm, 1);
+    assert_eq!(vm.registers.r0, 31);
+    assert_eq!(vm.registers.cond, P);
+    vm.registers.r2 = 1;
+    run(&mut vm, 1);
+    assert_eq!(vm.registers.r2, 0);
+    assert_eq!(vm.registers.cond, Z);
+}
+
+#[test]
+fn ldr_wraps_around_address_space() {
+    let mut vm = program(&[ldr(0, 1, -1), ldr(2, 3, 1)]);
+    vm.registers.r1 = 0x0000;
+    vm.registers.r3 = 0xFFFF;
+    vm.memory[0xFFFF] = 0xAAAA;
+    vm.memory[0x0000] = 0x5555;
+    run(&mut vm, 2);
+    assert_eq!(vm.registers.r0, 0xAAAA);
+    assert_eq!(vm.registers.r2, 0x5555);
+}
+
+#[test]
+fn lea_computes_address() {
+    let mut vm = program(&[lea(0, 255), lea(1, -256)]);
+    run(&mut vm, 1);
+    assert_eq!(vm.registers.r0, 0x3001 + 255);
+    assert_eq!(vm.registers.cond, P);
+    run(&mut vm, 1);
+    assert_eq!(vm.registers.r1, 0x3002 - 256);
+}
+
+#[test]
+fn lea_does_not_read_memory() {
+    let mut vm = program(&[lea(0, 0)]);
+    vm.memory[0x3001] = 0xFFFF;
+    run(&mut vm, 1);
+    assert_eq!(vm.registers.r0, 0x3001);
+}
+
+#[test]
+fn lea_sets_condition_codes() {
+    let (mut vm, _) = machine("");
+    load(&mut vm, 0x8000, &[lea(0, 0)]);
+    vm.registers.pc = 0x8000;
+    run(&mut vm, 1);
+    assert_eq!(vm.registers.r0, 0x8001);
+    assert_eq!(vm.registers.cond, N);
+}
+
+#[test]
+fn st_offset_boundaries() {
+    let mut vm = program(&[st(0, 255), st(0, -256)]);
+    vm.registers.r0 = 0xBEEF;
+    vm.registers.cond = P;
+    run(&mut vm, 2);
+    assert_eq!(vm.memory[0x3001 + 255], 0xBEEF);
+    assert_eq!(vm.memory[0x3002 - 256], 0xBEEF);
+    assert_eq!(vm.registers.cond, P);
+}
+
+#[test]
+fn sti_stores_through_pointer() {
+    let mut vm = program(&[sti(5, 1)]);
+    vm.memory[0x3002] = 0x4321;
+    vm.registers.r5 = 77;
+    run(&mut vm, 1);
+    assert_eq!(vm.memory[0x4321], 77);
+}
+
+#[test]
+fn str_offset_boundaries() {
+    let mut vm = program(&[str(0, 6, 31), str(0, 6, -32)]);
+    vm.registers.r0 = 0x1111;
+    vm.registers.r6 = 0x4000;
+    run(&mut vm, 2);
+    assert_eq!(vm.memory[0x4000 + 31], 0x1111);
+    assert_eq!(vm.memory[0x4000 - 32], 0x1111);
+}
+
+#[test]
+fn str_wraps_around_address_space() {
+    let mut vm = program(&[str(0, 1, 1)]);
+    vm.registers.r0 = 0x1234;
+    vm.registers.r1 = 0xFFFF;
+    run(&mut vm, 1);
+    assert_eq!(vm.memory[0x0000], 0x1234);
+}
+
+#[test]
+fn stores_reach_memory_mapped_devices() {
+    let (mut vm, output) = machine("");
+    load(&mut vm, 0x3000, &[sti(0, 0), 0xFE06]);
+    vm.registers.r0 = b'!' as u16;
+    run(&mut vm, 1);
+    assert_eq!(common::output(&output), "!");
+}
+
+#[test]
+fn illegal_opcode_without_handler_faults() {
+    let mut vm = program(&[RES]);
+    run(&mut vm, 1);
+    assert!(!vm.is_running());
+    assert_eq!(
+        vm.fault,
+        Some(Fault::Exception {
+            vector: ILLEGAL_OPCODE_EXCEPTION,
+            pc: 0x3000
+        })
+    );
+}
+
+#[test]
+fn illegal_opcode_enters_handler_on_supervisor_stack() {
+    let mut vm = program(&[RES]);
+    vm.memory[(INTERRUPT_TABLE + ILLEGAL_OPCODE_EXCEPTION as u16) as usize] = 0x1000;
+    vm.registers.r6 = 0xF000;
+    vm.registers.saved_ssp = 0x2FF0;
+    vm.registers.cond = P;
+    run(&mut vm, 1);
+    assert!(vm.is_running());
+    assert_eq!(vm.registers.pc, 0x1000);
+    assert!(!vm.registers.user_mode);
+    assert_eq!(vm.registers.r6, 0x2FEE);
+    assert_eq!(vm.registers.saved_usp, 0xF000);
+    assert_eq!(vm.memory[0x2FEE], 0x3001);
+    assert_eq!(vm.memory[0x2FEF], 0x8000 | P);
+}
+
+#[test]
+fn rti_in_user_mode_is_a_privilege_violation() {
+    let mut vm = program(&[rti()]);
+    run(&mut vm, 1);
+    assert_eq!(
+        vm.fault,
+        Some(Fault::Exception {
+            vector: PRIVILEGE_EXCEPTION,
+            pc: 0x3000
+        })
+    );
+}
+
+#[test]
+fn rti_returns_to_user_mode() {
+    let (mut vm, _) = machine("");
+    load(&mut vm, 0x1000, &[rti()]);
+    vm.registers.pc = 0x1000;
+    vm.registers.user_mode = false;
+    vm.registers.priority = 4;
+    vm.registers.r6 = 0x2FFE;
+    vm.registers.saved_usp = 0xF000;
+    vm.memory[0x2FFE] = 0x3456;
+    vm.memory[0x2FFF] = 0x8000 | N;
+    run(&mut vm, 1);
+    assert_eq!(vm.registers.pc, 0x3456);
+    assert!(vm.registers.user_mode);
+    assert_eq!(vm.registers.priority, 0);
+    assert_eq!(vm.registers.cond, N);
+    assert_eq!(vm.registers.r6, 0xF000);
+    assert_eq!(vm.registers.saved_ssp, 0x3000);
+}
+
+#[test]
+fn rti_stays_in_supervisor_mode() {
+    let (mut vm, _) = machine("");
+    load(&mut vm, 0x1000, &[rti()]);
+    vm.registers.pc = 0x1000;
+    vm.registers.user_mode = false;
+    vm.registers.r6 = 0x2FFE;
+    vm.memory[0x2FFE] = 0x0500;
+    vm.memory[0x2FFF] = 0x0302;
+    run(&mut vm, 1);
+    assert_eq!(vm.registers.pc, 0x0500);
+    assert!(!vm.registers.user_mode);
+    assert_eq!(vm.registers.priority, 3);
+    assert_eq!(vm.registers.cond, Z);
+    assert_eq!(vm.registers.r6, 0x3000);
+}
+
+#[test]
+fn timer_interrupt_round_trip() {
+    // Count in R0 while the timer fires once
+    let mut vm = program(&[add_imm(0, 0, 1), br(0b111, -2)]);
+    load(
+        &mut vm,
+        0x1000,
+        &[add_imm(1, 1, 1), sti(2, 1), rti(), 0xFE08],
+    );
+    vm.memory[(INTERRUPT_TABLE + 0x81) as usize] = 0x1000;
+    // Acknowledge the expired bit, disable the timer
+    vm.registers.r2 = 0x8000;
+    vm.write_memory(0xFE0A, 3);
+    vm.write_memory(0xFE08, 0x4000 | (6 << 8) | 1);
+
+    run(&mut vm, 3);
+    assert_eq!(vm.registers.pc, 0x1000);
+    assert!(!vm.registers.user_mode);
+    assert_eq!(vm.registers.priority, 6);
+    run(&mut vm, 3);
+    assert_eq!(vm.registers.r1, 1);
+    assert!(vm.registers.user_mode);
+    assert_eq!(vm.registers.r6, 0);
+    assert_eq!(vm.registers.pc, 0x3001);
+    run(&mut vm, 20);
+    assert_eq!(vm.registers.r1, 1);
+}
@@ -0,0 +1,194 @@
+//! Separate assembly and linking
+mod common;
+
+use common::{machine, output};
+use little_computer_3::assembler::{assemble, Program};
+use little_computer_3::linker::{self, object, Field, LinkError, Object, Options, Target};
+use little_computer_3::loader::{self, LoadError};
+
+fn program(source: &str) -> Program {
+    assemble(source).unwrap_or_else(|errors| {
+        panic!("{}", errors.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("\n"))
+    })
+}
+
+fn object(name: &str, source: &str) -> Object {
+    program(source).object(Some(name))
+}
+
+const MAIN: &str = "\
+.EXTERNAL PRINT, MESSAGE
+.SECTION CODE
+START LEA R0, MESSAGE
+    JSR PRINT
+    HALT
+";
+
+const LIBRARY: &str = "\
+.GLOBAL PRINT, MESSAGE
+.SECTION CODE
+PRINT ST R7, SAVE
+    PUTS
+    LD R7, SAVE
+    RET
+SAVE .BLKW 1
+.SECTION DATA
+MESSAGE .STRINGZ \"linked\"
+";
+
+#[test]
+fn externals_become_relocations() {
+    let main = object("main.asm", MAIN);
+    assert_eq!(main.externals, ["MESSAGE", "PRINT"]);
+    let code = &main.sections[0];
+    assert_eq!(code.name.as_deref(), Some("CODE"));
+    assert_eq!(code.words, [0xE000, 0x4800, 0xF025]);
+    let relocations: Vec<_> = code.relocations.iter().map(|r| (r.offset, r.field, &r.target)).collect();
+    assert_eq!(
+        relocations,
+        [
+            (0, Field::PcOffset9, &Target::Symbol("MESSAGE".to_string())),
+            (1, Field::PcOffset11, &Target::Symbol("PRINT".to_string())),
+        ]
+    );
+}
+
+#[test]
+fn linked_program_runs() {
+    let objects = [object("main.asm", MAIN), object("library.asm", LIBRARY)];
+    let image = linker::link(&objects, &Options::default()).unwrap();
+    // CODE of both files first, then DATA
+    assert_eq!(image.symbols["START"], 0x3000);
+    assert_eq!(image.symbols["PRINT"], 0x3003);
+    assert_eq!(image.symbols["MESSAGE"], 0x3008);
+    assert_eq!(image.entry, Some(0x3000));
+
+    let (mut vm, display) = machine("");
+    image.load(&mut vm);
+    vm.registers.pc = image.entry.unwrap();
+    while vm.is_running() {
+        vm.step();
+    }
+    assert_eq!(output(&display), "linkedHALT detected\n");
+}
+
+#[test]
+fn readme_example_runs() {
+    // The two files side by side in the README
+    let readme = std::fs::read_to_string("README.md").unwrap();
+    let start = readme.find("; main.asm").unwrap();
+    let example = &readme[start..start + readme[start..].find("```").unwrap()];
+    let (mut main, mut print) = (String::new(), String::new());
+    for line in example.lines() {
+        let (left, right) = line.split_at(line.len().min(32));
+        main.push_str(left.trim_end());
+        main.push('\n');
+        print.push_str(right);
+        print.push('\n');
+    }
+    let objects = [object("main.asm", &main), object("print.asm", &print)];
+    let image = linker::link(&objects, &Options::default()).unwrap();
+    let (mut vm, display) = machine("");
+    image.load(&mut vm);
+    vm.registers.pc = image.entry.unwrap();
+    while vm.is_running() {
+        vm.step();
+    }
+    assert_eq!(output(&display), "HelloHALT detected\n");
+}
+
+#[test]
+fn sections_can_be_placed() {
+    let objects = [object("main.asm", MAIN), object("library.asm", LIBRARY)];
+    let options = Options {
+        placements: [("DATA".to_string(), 0x3100)].into(),
+        entry: Some("START".to_string()),
+        ..Options::default()
+    };
+    let image = linker::link(&objects, &options).unwrap();
+    assert_eq!(image.symbols["MESSAGE"], 0x3100);
+    let unknown = Options {
+        placements: [("BSS".to_string(), 0x4000)].into(),
+        ..Options::default()
+    };
+    assert!(matches!(
+        linker::link(&objects, &unknown).unwrap_err().as_slice(),
+        [LinkError::UnknownSection(name)] if name == "BSS"
+    ));
+}
+
+#[test]
+fn offsets_out_of_range_are_reported() {
+    let objects = [object("main.asm", MAIN), object("library.asm", LIBRARY)];
+    let options = Options {
+        placements: [("DATA".to_string(), 0x5000)].into(),
+        ..Options::default()
+    };
+    let errors = linker::link(&objects, &options).unwrap_err();
+    assert_eq!(
+        errors.iter().map(|e| e.to_string()).collect::<Vec<_>>(),
+        ["main.asm: x3000: offset 8191 to `MESSAGE` does not fit in PCoffset9 (-256 to 255)"]
+    );
+}
+
+#[test]
+fn missing_and_duplicate_symbols_are_reported() {
+    let errors = linker::link(&[object("main.asm", MAIN)], &Options::default()).unwrap_err();
+    let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+    assert_eq!(
+        messages,
+        ["main.asm: undefined external `MESSAGE`", "main.asm: undefined external `PRINT`"]
+    );
+
+    let objects = [object("a.asm", LIBRARY), object("b.asm", LIBRARY)];
+    let errors = linker::link(&objects, &Options::default()).unwrap_err();
+    assert_eq!(errors[0].to_string(), "`MESSAGE` is exported by both a.asm and b.asm");
+}
+
+#[test]
+fn orig_blocks_stay_in_place() {
+    let vectors = "\
+.EXTERNAL HANDLER
+.ORIG x0180
+.FILL HANDLER
+.END
+";
+    let handler = ".GLOBAL HANDLER\n.SECTION CODE\nNOP ADD R0, R0, #0\nHANDLER RTI\n";
+    let objects = [object("vectors.asm", vectors), object("handler.asm", handler)];
+    let image = linker::link(&objects, &Options::default()).unwrap();
+    let segments: Vec<_> = image.segments().iter().map(|s| (s.origin, s.words.clone())).collect();
+    assert_eq!(segments, [(0x0180, vec![0x3001]), (0x3000, vec![0x1020, 0x8000])]);
+}
+
+#[test]
+fn relocatable_addresses_cannot_be_numbers() {
+    let errors = assemble(".SECTION CODE\nHERE ADD R0, R0, HERE\n").unwrap_err();
+    assert_eq!(
+        errors[0].to_string(),
+        "2:18: error: `HERE` is relocatable, its address is only known after linking"
+    );
+    let errors = assemble(".GLOBAL MISSING\n.ORIG x3000\nHALT\n").unwrap_err();
+    assert_eq!(errors[0].to_string(), "1:9: error: undefined label `MISSING`");
+}
+
+#[test]
+fn object_round_trip() {
+    let main = object("main.asm", MAIN);
+    let mut bytes = Vec::new();
+    object::write(&main, &mut bytes).unwrap();
+    assert_eq!(&bytes[..4], b"LC3R");
+    assert_eq!(object::parse(&bytes).unwrap(), main);
+    assert!(matches!(object::parse(&bytes[..bytes.len() - 1]), Err(LoadError::Truncated)));
+    // Only the linker reads relocatable objects
+    assert!(matches!(loader::parse(&bytes), Err(LoadError::Relocatable)));
+}
+
+#[test]
+fn linked_image_keeps_the_entry_point() {
+    let image = linker::link(&[object("library.asm", LIBRARY)], &Options::default()).unwrap();
+    let mut bytes = Vec::new();
+    image.write_object(&mut bytes).unwrap();
+    let parsed = loader::parse(&bytes).unwrap();
+    assert_eq!(parsed.entry, Some(0x3000));
+    assert_eq!(parsed.symbols["MESSAGE"], image.symbols["MESSAGE"]);
+}
@@ -0,0 +1,262 @@
+//! Reading and validation of program images
+mod common;
+
+use little_computer_3::assembler::assemble;
+use little_computer_3::loader::{self, Image, LoadError, Segment};
+
+fn segment(origin: u16, length: usize) -> Segment {
+    Segment {
+        origin,
+        words: vec![0; length],
+    }
+}
+
+#[test]
+fn parses_origin_and_words() {
+    let image = loader::parse(&[0x30, 0x00, 0xF0, 0x25, 0x12, 0x34]).unwrap();
+    assert_eq!(
+        image.segments(),
+        &[Segment {
+            origin: 0x3000,
+            words: vec![0xF025, 0x1234]
+        }]
+    );
+}
+
+#[test]
+fn origin_without_words_is_empty() {
+    let image = loader::parse(&[0x30, 0x00]).unwrap();
+    assert!(image.segments()[0].words.is_empty());
+}
+
+#[test]
+fn rejects_truncated_images() {
+    assert!(matches!(loader::parse(&[]), Err(LoadError::MissingOrigin)));
+    assert!(matches!(loader::parse(&[0x30]), Err(LoadError::MissingOrigin)));
+    assert!(matches!(loader::parse(&[0x30, 0x00, 0xF0]), Err(LoadError::OddLength(3))));
+}
+
+#[test]
+fn rejects_images_past_the_end_of_memory() {
+    let mut bytes = vec![0xFF, 0xF0];
+    bytes.resize(2 + 2 * 0x20, 0);
+    assert!(matches!(
+        loader::parse(&bytes),
+        Err(LoadError::PastEndOfMemory {
+            origin: 0xFFF0,
+            length: 0x20
+        })
+    ));
+}
+
+#[test]
+fn rejects_images_over_the_device_registers() {
+    assert!(Image::new(vec![segment(0xFD00, 0x100)]).is_ok());
+    assert!(matches!(
+        Image::new(vec![segment(0xFD00, 0x101)]),
+        Err(LoadError::IoPage { origin: 0xFD00, .. })
+    ));
+}
+
+#[test]
+fn rejects_overlapping_segments() {
+    assert!(Image::new(vec![segment(0x3000, 0x10), segment(0x3010, 1)]).is_ok());
+    assert!(matches!(
+        Image::new(vec![segment(0x3008, 4), segment(0x3000, 0x10)]),
+        Err(LoadError::Overlap {
+            first: 0x3000,
+            second: 0x3008
+        })
+    ));
+}
+
+const SOURCE: &str = "\
+.ORIG x4000
+DATA .FILL x1234
+.END
+.ORIG x3000
+MAIN LD R0, DATA2
+     HALT
+DATA2 .FILL #5
+.END
+";
+
+fn object() -> Vec<u8> {
+    let program = assemble(SOURCE).unwrap();
+    let mut bytes = Vec::new();
+    program.write_object(&mut bytes, Some("main.asm")).unwrap();
+    bytes
+}
+
+#[test]
+fn object_round_trip() {
+    let image = loader::parse(&object()).unwrap();
+    assert_eq!(
+        image.segments(),
+        &[
+            Segment {
+                origin: 0x4000,
+                words: vec![0x1234]
+            },
+            Segment {
+                origin: 0x3000,
+                words: vec![0x2001, 0xF025, 5]
+            },
+        ]
+    );
+    assert_eq!(image.entry, Some(0x3000));
+    assert_eq!(image.source.as_deref(), Some("main.asm"));
+    assert_eq!(image.symbols["DATA"], 0x4000);
+    assert_eq!(image.symbols["MAIN"], 0x3000);
+    assert_eq!(image.lines[&0x4000], 2);
+    assert_eq!(image.lines[&0x3001], 6);
+}
+
+#[test]
+fn object_loads_into_memory() {
+    let (mut vm, _) = common::machine("");
+    loader::parse(&object()).unwrap().load(&mut vm);
+    assert_eq!(vm.memory[0x4000], 0x1234);
+    assert_eq!(vm.memory[0x3002], 5);
+}
+
+#[test]
+fn rejects_truncated_objects() {
+    let bytes = object();
+    for length in 4..bytes.len() {
+        assert!(
+            matches!(loader::parse(&bytes[..length]), Err(LoadError::Truncated)),
+            "length {}",
+            length
+        );
+    }
+}
+
+#[test]
+fn rejects_bad_objects() {
+    let mut bytes = object();
+    bytes.push(0);
+    assert!(matches!(loader::parse(&bytes), Err(LoadError::TrailingData(1))));
+
+    let mut bytes = object();
+    bytes[5] = 9;
+    assert!(matches!(loader::parse(&bytes), Err(LoadError::UnsupportedVersion(9))));
+}
+
+#[test]
+fn legacy_writer_holds_one_segment() {
+    let program = assemble(".ORIG x3000\nHALT\n.END").unwrap();
+    let mut bytes = Vec::new();
+    program.write_legacy(&mut bytes).unwrap();
+    assert_eq!(bytes, [0x30, 0x00, 0xF0, 0x25]);
+    assert!(assemble(SOURCE).unwrap().write_legacy(&mut Vec::new()).is_err());
+}
+
+fn write_temp(name: &str, bytes: &[u8]) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("lc3-loader-{}-{}", std::process::id(), name));
+    std::fs::write(&path, bytes).unwrap();
+    path
+}
+
+#[test]
+fn loads_several_files() {
+    let os = write_temp("os.obj", &[0x02, 0x00, 0xF0, 0x25]);
+    let user = write_temp("user.obj", &[0x30, 0x00, 0x12, 0x34]);
+    let (mut vm, _) = common::machine("");
+    let images = loader::load_files(&mut vm, &[&os, &user]).unwrap();
+    assert_eq!(images.last().unwrap().entry, Some(0x3000));
+    assert_eq!(vm.memory[0x0200], 0xF025);
+    assert_eq!(vm.memory[0x3000], 0x1234);
+    std::fs::remove_file(os).unwrap();
+    std::fs::remove_file(user).unwrap();
+}
+
+#[test]
+fn rejects_overlapping_files() {
+    let first = write_temp("first.obj", &[0x30, 0x00, 0, 1, 0, 2, 0, 3]);
+    let second = write_temp("second.obj", &[0x30, 0x02, 0, 4]);
+    let result = loader::read_all(&[&first, &second]);
+    std::fs::remove_file(&first).unwrap();
+    std::fs::remove_file(&second).unwrap();
+    match result {
+        Err(LoadError::FileOverlap { address, .. }) => assert_eq!(address, 0x3002),
+        other => panic!("expected an overlap, got {:?}", other),
+    }
+}
+
+#[test]
+fn parses_text_images() {
+    let hex = loader::parse_hex("3000\nF025\n\n1234\n").unwrap();
+    assert_eq!(
+        hex.segments(),
+        &[Segment {
+            origin: 0x3000,
+            words: vec![0xF025, 0x1234]
+        }]
+    );
+    assert_eq!(hex.entry, Some(0x3000));
+
+    let bin = loader::parse_bin("0011000000000000\r\n1111000000100101\r\n").unwrap();
+    assert_eq!(
+        bin.segments(),
+        &[Segment {
+            origin: 0x3000,
+            words: vec![0xF025]
+        }]
+    );
+}
+
+#[test]
+fn rejects_invalid_text_images() {
+    assert!(matches!(loader::parse_hex(""), Err(LoadError::MissingOrigin)));
+    assert!(matches!(
+        loader::parse_hex("3000\nF02\n"),
+        Err(LoadError::InvalidLine { line: 2, .. })
+    ));
+    assert!(matches!(
+        loader::parse_bin("0011000000000000\n2111000000100101\n"),
+        Err(LoadError::InvalidLine { line: 2, .. })
+    ));
+}
+
+const SYMBOLS: &str = "\
+// Symbol table
+// Scope level 0:
+//\tSymbol Name       Page Address
+//\t----------------  ------------
+//\tMAIN              3000
+//\tDATA              x3002
+
+";
+
+#[test]
+fn parses_symbol_tables() {
+    let symbols = loader::parse_symbols(SYMBOLS).unwrap();
+    assert_eq!(symbols.len(), 2);
+    assert_eq!(symbols["MAIN"], 0x3000);
+    assert_eq!(symbols["DATA"], 0x3002);
+    assert!(matches!(
+        loader::parse_symbols("//\tMAIN 3000 extra\n"),
+        Err(LoadError::InvalidLine { line: 1, .. })
+    ));
+}
+
+#[test]
+fn reads_symbols_next_to_the_image() {
+    let image = write_temp("sym.hex", b"3000\nF025\n");
+    let symbols = write_temp("sym.sym", SYMBOLS.as_bytes());
+    let result = loader::read(&image);
+    std::fs::remove_file(&image).unwrap();
+    std::fs::remove_file(&symbols).unwrap();
+    assert_eq!(result.unwrap().symbols["DATA"], 0x3002);
+}
+
+#[test]
+fn images_without_an_entry_start_at_x3000() {
+    let (mut vm, _) = common::machine("");
+    assert_eq!(vm.registers.pc, 0x3000);
+    let image = Image::new(vec![Segment { origin: 0x4000, words: vec![0xF025] }]).unwrap();
+    assert_eq!(image.entry, None);
+    image.load(&mut vm);
+    assert_eq!(vm.registers.pc, 0x3000);
+}
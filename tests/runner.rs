@@ -0,0 +1,99 @@
+//! Batch runs and their JSON report
+use little_computer_3::runner;
+use serde_json::Value;
+use std::path::PathBuf;
+
+fn temp(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("lc3-runner-{}-{}", std::process::id(), name))
+}
+
+/// Run `source` saved as `name` with the extra
+/// `args`, returns the exit code and the report
+fn run(name: &str, source: &str, args: &[&str]) -> (i32, Value) {
+    let (program, report) = (temp(name), temp(&format!("{}.json", name)));
+    std::fs::write(&program, source).unwrap();
+    let mut arguments: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+    arguments.extend(["--report".to_string(), report.display().to_string(), program.display().to_string()]);
+    let code = runner::main(arguments);
+    let json = std::fs::read_to_string(&report).map_or(Value::Null, |text| serde_json::from_str(&text).unwrap());
+    std::fs::remove_file(program).unwrap();
+    let _ = std::fs::remove_file(report);
+    (code, json)
+}
+
+#[test]
+fn pseudo_instructions_need_extended() {
+    let source = ".ORIG x3000\nLDIMM R0, x1234\nMOV R1, R0\nHALT\n.POOL\n.END\n";
+    assert_eq!(run("plain.asm", source, &[]).0, runner::EXIT_ERROR);
+    let (code, json) = run("extended.asm", source, &["--extended"]);
+    assert_eq!(code, runner::EXIT_HALTED);
+    assert_eq!(json["registers"]["r1"], 0x1234);
+}
+
+#[test]
+fn report_has_registers_output_and_memory() {
+    let source = "\
+        .ORIG x3000
+        LEA R0, TEXT
+        PUTS
+        GETC
+        STI R0, CELL
+        HALT
+TEXT    .STRINGZ \"hi \"
+CELL    .FILL x4001
+        .END
+";
+    let input = temp("input.txt");
+    std::fs::write(&input, "k").unwrap();
+    let args = ["--input", input.to_str().unwrap(), "--memory", "x4000:2", "--memory", "xFE04"];
+    let (code, json) = run("report.asm", source, &args);
+    std::fs::remove_file(input).unwrap();
+    assert_eq!(code, runner::EXIT_HALTED);
+    assert_eq!(json["halt_reason"], "halted");
+    assert_eq!(json["message"], Value::Null);
+    assert_eq!(json["instructions"], 5);
+    assert_eq!(json["output"], "hi HALT detected\n");
+    let registers = json["registers"].as_object().unwrap();
+    let names: Vec<&str> = registers.keys().map(|k| k.as_str()).collect();
+    assert_eq!(names, ["pc", "psr", "r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7"]);
+    assert_eq!(registers["r0"], 'k' as u16);
+    assert_eq!(json["memory"], serde_json::json!({"x4000": 0, "x4001": 'k' as u16, "xFE04": 0x8000}));
+}
+
+#[test]
+fn memory_dump_has_no_side_effects() {
+    let input = temp("peek.txt");
+    std::fs::write(&input, "kz").unwrap();
+    // Reading KBSR would take the `z`
+    let args = ["--input", input.to_str().unwrap(), "--memory", "xFE00:3"];
+    let (code, json) = run("peek.asm", ".ORIG x3000\nGETC\nHALT\n.END\n", &args);
+    std::fs::remove_file(input).unwrap();
+    assert_eq!(code, runner::EXIT_HALTED);
+    assert_eq!(json["memory"], serde_json::json!({"xFE00": 0, "xFE01": 0, "xFE02": 'k' as u16}));
+}
+
+#[test]
+fn exit_codes() {
+    let (code, json) = run("halt.asm", ".ORIG x3000\nHALT\n.END\n", &[]);
+    assert_eq!((code, &json["halt_reason"]), (runner::EXIT_HALTED, &Value::from("halted")));
+
+    let (code, json) = run("loop.asm", ".ORIG x3000\nLOOP BR LOOP\n.END\n", &["--max-instructions", "100"]);
+    assert_eq!((code, &json["halt_reason"]), (runner::EXIT_TIMEOUT, &Value::from("timeout")));
+    assert_eq!(json["instructions"], 100);
+    assert_eq!(json["message"], "Instruction limit of 100 reached at x3000");
+
+    let (code, json) = run("fault.asm", ".ORIG x3000\n.FILL xD000\n.END\n", &[]);
+    assert_eq!((code, &json["halt_reason"]), (runner::EXIT_FAULT, &Value::from("fault")));
+    assert!(json["message"].is_string());
+}
+
+#[test]
+fn unknown_arguments_are_usage_errors() {
+    let (code, json) = run("typo.asm", ".ORIG x3000\nHALT\n.END\n", &["--memroy", "x4000"]);
+    assert_eq!(code, runner::EXIT_ERROR);
+    assert_eq!(json, Value::Null);
+    // Only one program is run
+    let (code, json) = run("second.asm", ".ORIG x3000\nHALT\n.END\n", &["first.asm"]);
+    assert_eq!(code, runner::EXIT_ERROR);
+    assert_eq!(json, Value::Null);
+}
@@ -0,0 +1,94 @@
+//! Saving and restoring the whole machine
+mod common;
+
+use common::*;
+use little_computer_3::hardware::device::keyboard::Keyboard;
+use little_computer_3::hardware::device::rng::Rng;
+use little_computer_3::hardware::replay::{self, InputLog};
+use little_computer_3::hardware::vm::{Fault, VM};
+use little_computer_3::runner::{self, HaltReason, SharedBuffer};
+
+/// Read a character, print it with a random number
+/// in R1, twice, then halt
+fn program() -> Vec<u16> {
+    let once = [trap(0x20), trap(0x21), add(1, 0, 0), trap(0x32), add(1, 1, 0)];
+    [once, once].concat().into_iter().chain([st(1, 1), trap(0x25), 0]).collect()
+}
+
+fn registers(vm: &VM) -> [u16; 12] {
+    let r = &vm.registers;
+    [r.r0, r.r1, r.r2, r.r3, r.r4, r.r5, r.r6, r.r7, r.pc, r.psr(), r.saved_ssp, r.saved_usp]
+}
+
+fn devices(vm: &VM) -> Vec<(String, Vec<u16>)> {
+    vm.bus.devices().map(|d| (d.name().to_string(), d.save())).collect()
+}
+
+fn seeded(input: &str) -> (VM, SharedBuffer) {
+    let (mut vm, output) = machine(input);
+    *vm.bus.device_mut::<Rng>().unwrap() = Rng::seeded(9);
+    load_program(&mut vm, &program());
+    (vm, output)
+}
+
+#[test]
+fn restored_machine_continues_identically() {
+    let (mut recording, _) = seeded("ok");
+    let log = SharedBuffer::default();
+    replay::write_header(&mut log.clone(), None).unwrap();
+    recording.bus.device_mut::<Keyboard>().unwrap().record(Box::new(log.clone()));
+    assert_eq!(runner::run(&mut recording, 1000), HaltReason::Halted);
+
+    // Save between the two reads of the replay
+    let (mut original, output) = seeded("");
+    let log = InputLog::read(&log.contents()[..]).unwrap();
+    original.bus.device_mut::<Keyboard>().unwrap().replay(log);
+    run(&mut original, 5);
+    let mut snapshot = Vec::new();
+    original.save_snapshot(&mut snapshot).unwrap();
+    let printed = common::output(&output).len();
+
+    let (mut restored, restored_output) = machine("");
+    restored.load_snapshot(&mut &snapshot[..]).unwrap();
+    assert_eq!(registers(&restored), registers(&original));
+    assert_eq!(restored.instructions, 5);
+    assert_eq!(restored.memory, original.memory);
+    assert_eq!(devices(&restored), devices(&original));
+
+    for vm in [&mut original, &mut restored] {
+        assert_eq!(runner::run(vm, 1000), HaltReason::Halted);
+        assert!(vm.bus.device::<Keyboard>().unwrap().error().is_none());
+    }
+    assert_eq!(registers(&restored), registers(&original));
+    assert_eq!(restored.instructions, original.instructions);
+    assert_eq!(restored.memory, original.memory);
+    assert_eq!(devices(&restored), devices(&original));
+    assert_eq!(common::output(&restored_output), &common::output(&output)[printed..]);
+    assert_eq!(common::output(&restored_output), "kHALT detected\n");
+}
+
+#[test]
+fn faults_are_saved() {
+    let mut vm = common::program(&[add_imm(0, 0, 1), RES]);
+    assert_eq!(runner::run(&mut vm, 10), HaltReason::Fault);
+    let mut snapshot = Vec::new();
+    vm.save_snapshot(&mut snapshot).unwrap();
+
+    let (mut restored, _) = machine("");
+    restored.load_snapshot(&mut &snapshot[..]).unwrap();
+    assert_eq!(restored.fault, Some(Fault::Exception { vector: 0x01, pc: 0x3001 }));
+    assert_eq!(restored.instructions, 2);
+    assert!(!restored.is_running());
+}
+
+#[test]
+fn invalid_snapshots_leave_the_machine_alone() {
+    let vm = common::program(&[add_imm(0, 0, 1)]);
+    let mut snapshot = Vec::new();
+    vm.save_snapshot(&mut snapshot).unwrap();
+    // An unknown fault kind
+    snapshot[6 + 24 + 8 + 1] = 7;
+    let (mut other, _) = machine("");
+    assert!(other.load_snapshot(&mut &snapshot[..]).is_err());
+    assert_eq!(other.memory[0x3000], 0);
+}
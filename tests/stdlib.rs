@@ -0,0 +1,39 @@
+//! The standard library routines, through the
+//! test files in `stdlib/tests/`
+use little_computer_3::assembler::{assemble, stdlib};
+use little_computer_3::testcase;
+use std::path::Path;
+
+#[test]
+fn every_routine_passes_its_tests() {
+    let mut failures = Vec::new();
+    for name in ["math", "print", "string", "stack"] {
+        let path = Path::new("stdlib/tests").join(format!("{}.toml", name));
+        for case in testcase::run_file(&path).unwrap() {
+            if !case.failures.is_empty() {
+                failures.push(format!("{}: {}: {}", name, case.name, case.failures.join(", ")));
+            }
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn every_file_is_tested_and_assembles_alone() {
+    for (name, _) in stdlib::FILES {
+        assert!(Path::new("stdlib/tests").join(name.replace(".asm", ".toml")).exists(), "{}", name);
+        let program = assemble(&format!(".ORIG x3000\n.INCLUDE <{}>\n.END\n", name)).unwrap();
+        assert!(program.warnings.is_empty(), "{}: {:?}", name, program.warnings);
+    }
+}
+
+#[test]
+fn library_can_be_linked() {
+    let program = assemble(&stdlib::linkable()).unwrap();
+    let object = program.object(None);
+    assert_eq!(object.sections.len(), 1);
+    let globals: Vec<&str> = object.symbols.iter().filter(|s| s.global).map(|s| s.name.as_str()).collect();
+    for routine in ["MULTIPLY", "DIVIDE", "PRINT_DECIMAL", "STRCMP", "SAVE_REGISTERS"] {
+        assert!(globals.contains(&routine), "{}", routine);
+    }
+}
@@ -0,0 +1,122 @@
+//! Declarative test files run by `cargo run test`
+use little_computer_3::runner;
+use little_computer_3::testcase::{self, CaseResult};
+use std::path::{Path, PathBuf};
+
+const ECHO: &str = "\
+        .ORIG x3000
+        GETC
+        OUT
+        ST R0, LAST
+        HALT
+LAST    .BLKW 1
+; R1 = R0 + 1
+INC     ADD R1, R0, #1
+        RET
+LOOP    BR LOOP
+        .END
+";
+
+/// Write `ECHO` and the test file `toml` to a new
+/// directory, returns the path of the test file
+fn write(name: &str, toml: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("lc3-testcase-{}-{}", std::process::id(), name));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("echo.asm"), ECHO).unwrap();
+    std::fs::write(dir.join("echo.toml"), toml).unwrap();
+    dir.join("echo.toml")
+}
+
+fn run(name: &str, toml: &str) -> Vec<CaseResult> {
+    let path = write(name, toml);
+    let results = testcase::run_file(&path);
+    std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    results.unwrap()
+}
+
+#[test]
+fn example_file_passes() {
+    let results = testcase::run_file(Path::new("examples/multiply.toml")).unwrap();
+    let names: Vec<&str> = results.iter().map(|r| r.name.as_str()).collect();
+    assert_eq!(names, ["whole program stores 6 * 7", "3 * 4", "negative times positive", "anything times zero"]);
+    assert!(results.iter().all(|r| r.failures.is_empty()));
+}
+
+#[test]
+fn cases_set_up_the_machine() {
+    let toml = r##"
+        program = "echo.asm"
+
+        [[case]]
+        name = "whole program"
+        input = "q"
+        expect = { registers = { r0 = "x71" }, memory = { LAST = 113 }, output = "qHALT detected\n" }
+
+        [[case]]
+        name = "subroutine by label"
+        subroutine = "INC"
+        registers = { r0 = "#-2" }
+        expect = { registers = { r1 = -1, r6 = "xC000", pc = "x0200" } }
+
+        [[case]]
+        name = "subroutine by address, memory set up"
+        subroutine = "x3005"
+        memory = { "x4000" = 5, LAST = "LOOP" }
+        registers = { r0 = 9 }
+        expect = { registers = { r1 = 10 }, memory = { "x4000" = 5, LAST = "x3007" } }
+    "##;
+    for result in run("setup", toml) {
+        assert!(result.failures.is_empty(), "{}: {:?}", result.name, result.failures);
+    }
+}
+
+#[test]
+fn failures_are_described() {
+    let toml = r#"
+        program = "echo.asm"
+        max_instructions = 50
+
+        [[case]]
+        name = "wrong values"
+        input = "a"
+        expect = { registers = { r0 = 98 }, memory = { LAST = -1 }, output = "b" }
+
+        [[case]]
+        name = "endless"
+        subroutine = "LOOP"
+
+        [[case]]
+        name = "errors in the file"
+        registers = { r9 = 1 }
+
+        [[case]]
+        name = "unknown label"
+        subroutine = "NOWHERE"
+    "#;
+    let results = run("failures", toml);
+    assert_eq!(
+        results[0].failures,
+        [
+            "r0: expected x0062 (98), found x0061 (97)",
+            "LAST: expected xFFFF (-1), found x0061 (97)",
+            "output: expected \"b\", found \"aHALT detected\\n\"",
+        ]
+    );
+    assert_eq!(results[1].failures, ["did not halt within 50 instructions (PC x3007)"]);
+    assert_eq!(results[2].failures, ["unknown register `r9`"]);
+    assert_eq!(results[3].failures, ["unknown label `NOWHERE`"]);
+}
+
+#[test]
+fn exit_code_counts_failures() {
+    let passing = write("pass", "program = \"echo.asm\"\n[[case]]\nname = \"halts\"\n");
+    let failing = write("fail", "program = \"echo.asm\"\n[[case]]\nname = \"r0\"\nexpect = { registers = { r0 = 1 } }\n");
+    let code = |paths: &[&PathBuf]| testcase::main(paths.iter().map(|p| p.display().to_string()).collect());
+    assert_eq!(code(&[&passing]), 0);
+    assert_eq!(code(&[&passing, &failing]), runner::EXIT_ERROR);
+    assert_eq!(testcase::main(vec!["missing.toml".to_string()]), runner::EXIT_ERROR);
+    assert_eq!(testcase::main(Vec::new()), runner::EXIT_ERROR);
+    for path in [passing, failing] {
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
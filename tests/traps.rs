@@ -0,0 +1,136 @@
+//! Conformance tests for the trap routines
+mod common;
+
+use common::*;
+use little_computer_3::hardware::device::disk::Disk;
+use little_computer_3::hardware::device::rng::Rng;
+use little_computer_3::hardware::vm::Fault;
+
+#[test]
+fn trap_saves_return_address() {
+    let mut vm = program(&[trap(0x21)]);
+    run(&mut vm, 1);
+    assert_eq!(vm.registers.r7, 0x3001);
+    assert_eq!(vm.registers.pc, 0x3001);
+}
+
+#[test]
+fn getc_reads_without_echo() {
+    let (mut vm, output) = machine("a");
+    load(&mut vm, 0x3000, &[trap(0x20)]);
+    vm.registers.r0 = 0xFF00;
+    run(&mut vm, 1);
+    assert_eq!(vm.registers.r0, b'a' as u16);
+    assert_eq!(common::output(&output), "");
+}
+
+#[test]
+fn getc_at_end_of_input_returns_zero() {
+    let (mut vm, _) = machine("");
+    load(&mut vm, 0x3000, &[trap(0x20)]);
+    vm.registers.r0 = 5;
+    run(&mut vm, 1);
+    assert_eq!(vm.registers.r0, 0);
+}
+
+#[test]
+fn out_writes_low_byte() {
+    let (mut vm, output) = machine("");
+    load(&mut vm, 0x3000, &[trap(0x21)]);
+    vm.registers.r0 = 0x4100 | b'Z' as u16;
+    run(&mut vm, 1);
+    assert_eq!(common::output(&output), "Z");
+}
+
+#[test]
+fn puts_writes_one_character_per_word() {
+    let (mut vm, output) = machine("");
+    load(&mut vm, 0x3000, &[trap(0x22)]);
+    load(&mut vm, 0x4000, &[b'h' as u16, b'i' as u16, 0, b'!' as u16]);
+    vm.registers.r0 = 0x4000;
+    run(&mut vm, 1);
+    assert_eq!(common::output(&output), "hi");
+}
+
+#[test]
+fn in_prompts_and_reads() {
+    let (mut vm, output) = machine("q");
+    load(&mut vm, 0x3000, &[trap(0x23)]);
+    run(&mut vm, 1);
+    assert_eq!(vm.registers.r0, b'q' as u16);
+    assert_eq!(common::output(&output), "Enter a  character : q");
+}
+
+#[test]
+fn putsp_writes_two_characters_per_word() {
+    let (mut vm, output) = machine("");
+    load(&mut vm, 0x3000, &[trap(0x24)]);
+    load(&mut vm, 0x4000, &[0x6548, 0x6C6C, 0x006F, 0]);
+    vm.registers.r0 = 0x4000;
+    run(&mut vm, 1);
+    assert_eq!(common::output(&output), "Hello");
+}
+
+#[test]
+fn halt_stops_the_machine() {
+    let (mut vm, output) = machine("");
+    load(&mut vm, 0x3000, &[trap(0x25), add_imm(0, 0, 1)]);
+    run(&mut vm, 1);
+    assert!(!vm.is_running());
+    assert_eq!(vm.fault, None);
+    assert_eq!(vm.registers.pc, 0x3001);
+    assert_eq!(common::output(&output), "HALT detected\n");
+}
+
+#[test]
+fn invalid_trap_faults() {
+    let mut vm = program(&[trap(0x26)]);
+    run(&mut vm, 1);
+    assert!(!vm.is_running());
+    assert_eq!(
+        vm.fault,
+        Some(Fault::InvalidTrap {
+            vector: 0x26,
+            pc: 0x3000
+        })
+    );
+}
+
+#[test]
+fn rand_is_deterministic_with_a_seed() {
+    let mut expected = Rng::seeded(42);
+    let mut vm = program(&[trap(0x32), add(1, 0, 0), trap(0x32)]);
+    *vm.bus.device_mut::<Rng>().unwrap() = Rng::seeded(42);
+    run(&mut vm, 3);
+    assert_eq!(vm.registers.r1, expected.next_word().wrapping_mul(2));
+    assert_eq!(vm.registers.r0, expected.next_word());
+}
+
+#[test]
+fn disk_traps_without_disk_fail() {
+    let mut vm = program(&[trap(0x30), add_imm(2, 0, 0), trap(0x31)]);
+    run(&mut vm, 3);
+    assert_eq!(vm.registers.r2, 0xFFFF);
+    assert_eq!(vm.registers.r0, 0xFFFF);
+}
+
+#[test]
+fn disk_traps_round_trip() {
+    let path = std::env::temp_dir().join(format!("lc3-traps-{}.img", std::process::id()));
+    let mut vm = program(&[trap(0x31), and_imm(0, 0, 0), add_imm(0, 0, 1), trap(0x30)]);
+    vm.bus.attach(Box::new(Disk::open(&path).unwrap()));
+    for i in 0..256 {
+        vm.memory[0x4000 + i] = i as u16 * 3;
+    }
+    vm.registers.r0 = 1;
+    vm.registers.r1 = 0x4000;
+    run(&mut vm, 1);
+    assert_eq!(vm.registers.r0, 0);
+
+    // Read the sector back somewhere else
+    vm.registers.r1 = 0x5000;
+    run(&mut vm, 3);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(vm.registers.r0, 0);
+    assert_eq!(&vm.memory[0x5000..0x5100], &vm.memory[0x4000..0x4100]);
+}